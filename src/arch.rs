@@ -0,0 +1,81 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::{VmError, Result};
+
+/// UEFI images for aarch64 guests, by distribution
+const AARCH64_FIRMWARE: &[&str] = &[
+    "/usr/share/AAVMF/AAVMF_CODE.fd",
+    "/usr/share/edk2/aarch64/QEMU_EFI-pflash.raw",
+    "/usr/share/qemu-efi-aarch64/QEMU_EFI.fd",
+    "/usr/share/edk2-armvirt/aarch64/QEMU_CODE.fd",
+];
+
+/// S-mode U-Boot for riscv64 guests; QEMU's bundled OpenSBI hands over to it
+const RISCV64_FIRMWARE: &[&str] = &[
+    "/usr/lib/u-boot/qemu-riscv64_smode/u-boot.bin",
+    "/usr/share/u-boot-qemu-bin/qemu-riscv64_smode/uboot.elf",
+    "/usr/share/uboot/qemu-riscv64_smode/u-boot.bin",
+];
+
+/// How firmware is handed to the guest
+#[derive(Debug, Clone, PartialEq)]
+pub enum Firmware {
+    /// Read-only pflash loader (UEFI)
+    Loader(PathBuf),
+    /// Loaded as the kernel on top of QEMU's default firmware (OpenSBI + U-Boot)
+    Kernel(PathBuf),
+}
+
+/// Emulator, machine and firmware for a guest architecture
+#[derive(Debug, Clone)]
+pub struct ArchProfile {
+    pub arch: String,
+    pub emulator: PathBuf,
+    pub machine: &'static str,
+    /// CPU model for TCG; KVM guests use host-passthrough instead
+    pub cpu_model: &'static str,
+    pub firmware: Option<Firmware>,
+}
+
+impl ArchProfile {
+    /// Looks up the profile for `arch`, checking that its emulator is installed
+    pub fn for_arch(arch: &str) -> Result<Self> {
+        let (machine, cpu_model, firmware, package) = match arch {
+            "x86_64" => ("q35", "qemu64", None, "qemu-system-x86"),
+            "aarch64" => ("virt", "cortex-a72", find(AARCH64_FIRMWARE).map(Firmware::Loader), "qemu-system-arm"),
+            "riscv64" => ("virt", "rv64", find(RISCV64_FIRMWARE).map(Firmware::Kernel), "qemu-system-misc"),
+            _ => return Err(VmError::InvalidInput(format!(
+                "Unsupported architecture '{}'. Use x86_64, aarch64 or riscv64", arch
+            ))),
+        };
+
+        let binary = format!("qemu-system-{}", arch);
+        let emulator = ["/usr/bin", "/usr/local/bin"].iter()
+            .map(|dir| Path::new(dir).join(&binary))
+            .find(|path| path.exists())
+            .ok_or_else(|| VmError::QemuError(format!(
+                "{} not found. Install the {} package", binary, package
+            )))?;
+
+        if firmware.is_none() && arch != "x86_64" {
+            tracing::warn!("No firmware found for {}; the guest will need a kernel to boot", arch);
+        }
+
+        Ok(Self {
+            arch: arch.to_string(),
+            emulator,
+            machine,
+            cpu_model,
+            firmware,
+        })
+    }
+}
+
+/// Architecture of this host in QEMU naming
+pub fn host_arch() -> &'static str {
+    std::env::consts::ARCH
+}
+
+fn find(candidates: &[&str]) -> Option<PathBuf> {
+    candidates.iter().map(PathBuf::from).find(|path| path.exists())
+}
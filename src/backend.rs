@@ -0,0 +1,283 @@
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    capabilities::HostCapabilities,
+    error::{VmError, Result},
+    events::EventStream,
+    image::Customization,
+    privilege::Privileges,
+    secret::SecretInfo,
+    stats::DomainStats,
+    storage::{self, PoolInfo, VolumeInfo},
+    utils::CopyProgress,
+    vm::{SnapshotInfo, VmInfo, VmState},
+};
+
+/// How to attach to a domain's serial console
+#[derive(Debug, Clone, Copy)]
+pub struct ConsoleOptions<'a> {
+    /// Also save the output here as an asciicast
+    pub record: Option<&'a Path>,
+    /// Control character that ends the session, such as 0x1d for Ctrl+]
+    pub escape: u8,
+    /// Take the console over from a session already holding it
+    pub force: bool,
+}
+
+/// Hypervisor operations `VmManager` is built on.
+///
+/// `LibvirtClient` is the production implementation; `mock::MockBackend`
+/// keeps everything in memory so VM flows can be tested without a host.
+#[async_trait]
+pub trait Backend: Send + Sync {
+    /// Lists domains; `all` includes inactive ones, `fast` skips disk/interface lookups
+    async fn list_domains(&self, all: bool, fast: bool) -> Result<Vec<VmInfo>>;
+    async fn get_domain_info(&self, name: &str) -> Result<VmInfo>;
+    async fn get_domain_state(&self, name: &str) -> Result<VmState>;
+    async fn domain_exists(&self, name: &str) -> Result<bool>;
+    async fn get_domain_xml(&self, name: &str) -> Result<String>;
+
+    /// The persistent definition, without live state such as device aliases.
+    /// Backends that keep only one definition return it unchanged.
+    async fn get_inactive_domain_xml(&self, name: &str) -> Result<String> {
+        self.get_domain_xml(name).await
+    }
+
+    async fn start_domain(&self, name: &str) -> Result<()>;
+    /// Requests a graceful ACPI shutdown
+    async fn shutdown_domain(&self, name: &str) -> Result<()>;
+    /// Powers the domain off immediately
+    async fn destroy_domain(&self, name: &str) -> Result<()>;
+    /// Stops the running domain's vCPUs; it keeps its memory and devices
+    async fn pause_domain(&self, name: &str) -> Result<()>;
+    /// Lets a paused domain run again
+    async fn resume_domain(&self, name: &str) -> Result<()>;
+    /// Defines a new domain, or redefines an existing one, from libvirt XML
+    async fn define_domain(&self, xml: &str) -> Result<()>;
+    async fn undefine_domain(&self, name: &str) -> Result<()>;
+
+    /// Swaps the media in a CD-ROM drive (`target`, e.g. `sda`); `None` ejects it.
+    /// Applies to the running VM and its persistent definition.
+    async fn change_media(&self, name: &str, target: &str, media: Option<&str>) -> Result<()>;
+
+    /// Networks as (name, active, bridge, autostart)
+    async fn list_networks(&self) -> Result<Vec<(String, bool, String, bool)>>;
+
+    /// Creates an empty qcow2 disk image
+    async fn create_disk(&self, path: &Path, size_bytes: u64) -> Result<()>;
+    /// Copies a disk image to a new, independent qcow2 image
+    async fn clone_disk(&self, source: &Path, target: &Path, progress: CopyProgress<'_>) -> Result<()>;
+    /// Brings in an image built elsewhere (qcow2, raw, ...) as a new qcow2 image,
+    /// copied or, with `link`, as an overlay backed by `source`
+    async fn import_disk(&self, source: &Path, target: &Path, link: bool, progress: CopyProgress<'_>) -> Result<()>;
+    /// Installs packages, runs commands and adds SSH keys inside a disk image
+    async fn customize_image(&self, image: &Path, customization: &Customization) -> Result<()>;
+
+    /// Takes a snapshot; with `memory` it must also hold the running VM's RAM so
+    /// reverting resumes the guest where it was
+    async fn create_snapshot(&self, name: &str, snapshot: &str, description: Option<&str>, memory: bool) -> Result<()>;
+    async fn list_snapshots(&self, name: &str) -> Result<Vec<SnapshotInfo>>;
+    async fn revert_snapshot(&self, name: &str, snapshot: &str) -> Result<()>;
+    async fn delete_snapshot(&self, name: &str, snapshot: &str) -> Result<()>;
+
+    /// Attaches the terminal to the domain's serial console until the escape key
+    /// is pressed or the domain goes away
+    async fn connect_console(&self, name: &str, options: ConsoleOptions<'_>) -> Result<()>;
+    /// Asks the guest's balloon driver to shrink or grow the running domain to `memory` MiB
+    async fn set_memory_target(&self, name: &str, memory: u64) -> Result<()>;
+
+    /// Writes the running domain's memory to a managed save image and stops it;
+    /// its next start resumes from there
+    async fn managed_save(&self, _name: &str) -> Result<()> {
+        Err(VmError::InvalidInput("Managed saves need the libvirt backend".to_string()))
+    }
+
+    /// Names of the domains that have a managed save image; none on a backend
+    /// that can't make them
+    async fn list_managed_saves(&self) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    /// Replaces a device of the running domain with `device_xml` (matched by type),
+    /// e.g. to change a console password; the persistent definition is left alone
+    async fn update_device(&self, _name: &str, _device_xml: &str) -> Result<()> {
+        Err(VmError::InvalidInput("Live device updates need the libvirt backend".to_string()))
+    }
+
+    /// Hot-plugs `device_xml` into the running domain; the persistent definition is left alone
+    async fn attach_device(&self, _name: &str, _device_xml: &str) -> Result<()> {
+        Err(VmError::InvalidInput("Hot-plugging devices needs the libvirt backend".to_string()))
+    }
+
+    /// Applies average bandwidth limits in KiB/s (0 = unlimited) to an interface
+    /// of the running domain; the persistent definition is left alone
+    async fn set_interface_bandwidth(&self, _name: &str, _mac: &str, _inbound: u64, _outbound: u64) -> Result<()> {
+        Err(VmError::InvalidInput("Live bandwidth limits need the libvirt backend".to_string()))
+    }
+
+    /// Sets the running domain's CPU weight and per-vCPU quota (µs per `period`, -1 = uncapped);
+    /// the persistent definition is left alone
+    async fn set_cpu_tune(&self, _name: &str, _shares: u64, _period: u64, _quota: i64) -> Result<()> {
+        Err(VmError::InvalidInput("Live CPU tuning needs the libvirt backend".to_string()))
+    }
+
+    /// Merges the overlays of disk `target` (e.g. `vda`) down into its base image and
+    /// switches the running domain to it, showing progress
+    async fn block_commit(&self, _name: &str, _target: &str) -> Result<()> {
+        Err(VmError::InvalidInput("Live block jobs need the libvirt backend".to_string()))
+    }
+
+    /// Copies the backing chain's data into disk `target` of the running domain so
+    /// it no longer needs its backing files, showing progress
+    async fn block_pull(&self, _name: &str, _target: &str) -> Result<()> {
+        Err(VmError::InvalidInput("Live block jobs need the libvirt backend".to_string()))
+    }
+
+    /// Cumulative CPU, memory, disk and network counters of every running domain
+    async fn domain_stats(&self) -> Result<Vec<DomainStats>> {
+        Err(VmError::InvalidInput("Live statistics need the libvirt backend".to_string()))
+    }
+
+    /// Sends a QEMU guest agent command and returns its `return` value
+    async fn agent_command(&self, _name: &str, _command: &serde_json::Value) -> Result<serde_json::Value> {
+        Err(VmError::InvalidInput("The guest agent is only reachable through libvirt".to_string()))
+    }
+
+    /// Sends a QMP command to the domain's QEMU monitor and returns its `return` value
+    async fn monitor_command(&self, _name: &str, _command: &serde_json::Value) -> Result<serde_json::Value> {
+        Err(VmError::InvalidInput("This backend has no QEMU monitor to send commands to".to_string()))
+    }
+
+    /// Machine types, CPU models and firmware the host's hypervisor supports
+    async fn capabilities(&self) -> Result<HostCapabilities> {
+        Err(VmError::InvalidInput("Capability queries need the libvirt backend".to_string()))
+    }
+
+    /// Defines a secret from `xml` and stores `value` in it, returning its UUID
+    async fn create_secret(&self, _xml: &str, _value: &[u8]) -> Result<String> {
+        Err(VmError::InvalidInput("Secrets need the libvirt backend".to_string()))
+    }
+
+    async fn list_secrets(&self) -> Result<Vec<SecretInfo>> {
+        Err(VmError::InvalidInput("Secrets need the libvirt backend".to_string()))
+    }
+
+    async fn delete_secret(&self, _uuid: &str) -> Result<()> {
+        Err(VmError::InvalidInput("Secrets need the libvirt backend".to_string()))
+    }
+
+    /// Replaces the host's vmtools nftables table with what `ruleset` (an
+    /// `nft -f` script) describes
+    async fn apply_firewall(&self, _ruleset: &str) -> Result<()> {
+        Err(VmError::InvalidInput("Firewall rules need the libvirt backend".to_string()))
+    }
+
+    /// The host's filter rules (`iptables-save -t filter` and `nft list ruleset`),
+    /// where libvirt's rejects for its networks are; `None` when they can't be read
+    async fn host_filter_rules(&self) -> Option<String> {
+        None
+    }
+
+    /// Defines a virtual network from `xml`, then starts and autostarts it
+    async fn define_network(&self, _xml: &str) -> Result<()> {
+        Err(VmError::InvalidInput("Virtual networks need the libvirt backend".to_string()))
+    }
+
+    /// Defines a storage pool from `xml`, then builds, starts and autostarts it
+    async fn define_pool(&self, _xml: &str) -> Result<()> {
+        Err(VmError::InvalidInput("Storage pools need the libvirt backend".to_string()))
+    }
+
+    async fn list_pools(&self) -> Result<Vec<PoolInfo>> {
+        Err(VmError::InvalidInput("Storage pools need the libvirt backend".to_string()))
+    }
+
+    /// Stops and undefines a pool; its volumes are left alone
+    async fn delete_pool(&self, _name: &str) -> Result<()> {
+        Err(VmError::InvalidInput("Storage pools need the libvirt backend".to_string()))
+    }
+
+    async fn list_volumes(&self, _pool: &str) -> Result<Vec<VolumeInfo>> {
+        Err(VmError::InvalidInput("Storage pools need the libvirt backend".to_string()))
+    }
+
+    /// Creates a qcow2 volume of `size_bytes` in `pool`
+    async fn create_volume(&self, _pool: &str, _name: &str, _size_bytes: u64) -> Result<()> {
+        Err(VmError::InvalidInput("Storage pools need the libvirt backend".to_string()))
+    }
+
+    /// The pool and name of the volume at `path`, if a storage pool holds it
+    async fn volume_at(&self, _path: &Path) -> Result<Option<(String, String)>> {
+        Ok(None)
+    }
+
+    /// Deletes a volume through its pool, which keeps the pool's view current
+    async fn delete_volume(&self, _pool: &str, _name: &str) -> Result<()> {
+        Err(VmError::InvalidInput("Storage pools need the libvirt backend".to_string()))
+    }
+
+    /// IQNs of the targets an iSCSI portal offers
+    async fn discover_iscsi_targets(&self, _host: &str) -> Result<Vec<String>> {
+        Err(VmError::InvalidInput("iSCSI discovery needs the libvirt backend".to_string()))
+    }
+
+    /// Sets the password of `user` inside the shut-off VM's disks with libguestfs
+    async fn set_guest_password(&self, _name: &str, _user: &str, _password: &str) -> Result<()> {
+        Err(VmError::InvalidInput("Offline password resets need the libvirt backend".to_string()))
+    }
+
+    /// Allocates a thin LV of `size_bytes` in `vg/thin_pool`, returning its device path
+    async fn create_thin_volume(&self, _vg: &str, _thin_pool: &str, _name: &str, _size_bytes: u64) -> Result<PathBuf> {
+        Err(VmError::InvalidInput("LVM volumes need the libvirt backend".to_string()))
+    }
+
+    /// Creates a sparse zvol `dataset/name` of `size_bytes`, returning its device path
+    async fn create_zvol(&self, _dataset: &str, _name: &str, _size_bytes: u64, _volblocksize: &str, _compression: &str) -> Result<PathBuf> {
+        Err(VmError::InvalidInput("ZFS volumes need the libvirt backend".to_string()))
+    }
+
+    /// `zfs clone` of the zvol `device` as `name` in the same dataset
+    async fn clone_zvol(&self, _device: &Path, _name: &str) -> Result<PathBuf> {
+        Err(VmError::InvalidInput("ZFS volumes need the libvirt backend".to_string()))
+    }
+
+    /// Snapshot named `snapshot` of the thin LV or zvol `device`
+    async fn snapshot_thin_volume(&self, device: &Path, _snapshot: &str) -> Result<()> {
+        Err(volumes_unsupported(device))
+    }
+
+    /// Snapshots of the thin LV or zvol `device` as (name, creation time)
+    async fn list_thin_snapshots(&self, device: &Path) -> Result<Vec<(String, String)>> {
+        Err(volumes_unsupported(device))
+    }
+
+    /// Rolls the thin LV or zvol `device` back to `snapshot`, keeping the snapshot
+    async fn revert_thin_volume(&self, device: &Path, _snapshot: &str) -> Result<()> {
+        Err(volumes_unsupported(device))
+    }
+
+    async fn delete_thin_snapshot(&self, device: &Path, _snapshot: &str) -> Result<()> {
+        Err(volumes_unsupported(device))
+    }
+
+    /// Removes the thin LV or zvol `device` with its snapshots; anything else is refused
+    async fn remove_thin_volume(&self, device: &Path) -> Result<()> {
+        Err(volumes_unsupported(device))
+    }
+
+    /// Subscribes to lifecycle events for one domain, or all when `None`
+    fn subscribe_events(&self, domain: Option<&str>) -> Result<EventStream>;
+
+    /// libvirt credentials for host tools run outside the backend, such as
+    /// the network fixers; `None` when the backend isn't libvirt
+    fn privileges(&self) -> Option<&Privileges> {
+        None
+    }
+}
+
+/// What the thin LV and zvol defaults answer, naming the storage `device` is on
+fn volumes_unsupported(device: &Path) -> VmError {
+    let kind = if storage::zvol_name(device).is_ok() { "ZFS" } else { "LVM" };
+    VmError::InvalidInput(format!("{} volumes need the libvirt backend", kind))
+}
@@ -0,0 +1,127 @@
+use serde::Serialize;
+
+use crate::domain::{attribute, element_text, element_texts};
+
+/// A machine type the emulator accepts
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MachineType {
+    pub name: String,
+    /// The versioned type an alias such as `q35` stands for
+    pub canonical: Option<String>,
+    pub max_cpus: Option<u32>,
+}
+
+/// What the host and its KVM emulator support, from `virsh capabilities`
+/// and `virsh domcapabilities`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HostCapabilities {
+    pub arch: String,
+    /// Host CPU model and vendor in libvirt's naming
+    pub cpu_model: Option<String>,
+    pub cpu_vendor: Option<String>,
+    pub machines: Vec<MachineType>,
+    pub max_vcpus: Option<u32>,
+    /// Supported `<cpu mode=...>` values
+    pub cpu_modes: Vec<String>,
+    /// Model libvirt expands `host-model` to
+    pub host_model: Option<String>,
+    /// Named CPU models usable on this host
+    pub cpu_models: Vec<String>,
+    /// UEFI firmware images libvirt can load
+    pub firmware: Vec<String>,
+    /// Whether guests of the host architecture can use KVM
+    pub kvm: bool,
+}
+
+impl HostCapabilities {
+    /// Combines the output of `virsh capabilities` and `virsh domcapabilities`
+    pub fn parse(capabilities: &str, domcapabilities: &str) -> Self {
+        let host = section(capabilities, "<host>", "</host>").unwrap_or_default();
+        let host_cpu = section(host, "<cpu>", "</cpu>").unwrap_or_default();
+        let arch = element_text(host_cpu, "arch").unwrap_or_else(|| std::env::consts::ARCH.to_string());
+
+        // One <guest> per emulator; keep the one for the host architecture
+        let guest = capabilities.match_indices("<guest>")
+            .filter_map(|(pos, _)| section(&capabilities[pos..], "<guest>", "</guest>"))
+            .find(|guest| guest.contains(&format!("<arch name='{}'>", arch)))
+            .unwrap_or_default();
+
+        let machines = guest.match_indices("<machine")
+            .filter_map(|(pos, _)| {
+                let element = &guest[pos..];
+                let head = &element[..element.find('>')?];
+                let name = element_text(element, "machine")?;
+                Some(MachineType {
+                    name,
+                    canonical: attribute(head, "canonical='"),
+                    max_cpus: attribute(head, "maxCpus='").and_then(|n| n.parse().ok()),
+                })
+            })
+            .collect();
+
+        let cpu = section(domcapabilities, "<cpu>", "</cpu>").unwrap_or_default();
+        let cpu_modes = cpu.match_indices("<mode name='")
+            .filter_map(|(pos, _)| {
+                let head = &cpu[pos..pos + cpu[pos..].find('>')?];
+                head.contains("supported='yes'").then(|| attribute(head, "name='")).flatten()
+            })
+            .collect();
+        let host_model = section(cpu, "<mode name='host-model'", "</mode>")
+            .and_then(|mode| element_text(mode, "model"));
+        let cpu_models = section(cpu, "<mode name='custom'", "</mode>")
+            .map(|mode| mode.match_indices("<model usable='yes'")
+                .filter_map(|(pos, _)| element_text(&mode[pos..], "model"))
+                .collect())
+            .unwrap_or_default();
+
+        Self {
+            cpu_model: element_text(host_cpu, "model"),
+            cpu_vendor: element_text(host_cpu, "vendor"),
+            machines,
+            max_vcpus: attribute(domcapabilities, "<vcpu max='").and_then(|n| n.parse().ok()),
+            cpu_modes,
+            host_model,
+            cpu_models,
+            firmware: section(domcapabilities, "<loader", "</loader>")
+                .map(|loader| element_texts(loader, "value"))
+                .unwrap_or_default(),
+            kvm: guest.contains("<domain type='kvm'"),
+            arch,
+        }
+    }
+
+    /// `wanted` when the emulator has it, otherwise the newest machine type of the
+    /// same family (`pc-q35-7.0` → what `q35` currently stands for)
+    pub fn best_machine(&self, wanted: &str) -> Option<String> {
+        if self.machines.iter().any(|machine| machine.name == wanted) {
+            return Some(wanted.to_string());
+        }
+
+        let prefix = wanted.rsplit_once('-').map_or(wanted, |(prefix, _)| prefix);
+        let family = prefix.strip_prefix("pc-").unwrap_or(prefix);
+        if let Some(canonical) = self.machines.iter()
+            .find(|machine| machine.name == family)
+            .and_then(|alias| alias.canonical.clone())
+        {
+            return Some(canonical);
+        }
+
+        // No alias (e.g. i440fx, which is just `pc`): compare versions numerically
+        let version = |name: &str| -> Vec<u32> {
+            name.rsplit_once('-')
+                .map(|(_, version)| version.split('.').filter_map(|part| part.parse().ok()).collect())
+                .unwrap_or_default()
+        };
+        self.machines.iter()
+            .filter(|machine| machine.name.rsplit_once('-').is_some_and(|(p, _)| p == prefix))
+            .max_by_key(|machine| version(&machine.name))
+            .map(|machine| machine.name.clone())
+    }
+}
+
+/// The text from `start` up to and including the next `end`
+fn section<'a>(xml: &'a str, start: &str, end: &str) -> Option<&'a str> {
+    let from = xml.find(start)?;
+    let to = from + xml[from..].find(end)? + end.len();
+    Some(&xml[from..to])
+}
@@ -1,4 +1,18 @@
-use clap::{Parser, Subcommand};
+use clap::{builder::BoolishValueParser, Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+use vmtools_core::{
+    config::{AudioBackend, CpuModel},
+    firewall::{PortForward, Protocol},
+    health::HealthCheck,
+    image::SshInject,
+    logging::LogStyle,
+    optimize::Profile,
+    osinfo::OsVariant,
+    stats::LogFormat,
+    storage::DiskSource,
+    vm::{DiskBus, DomainKind, ListColumn, ListFilter, ListSort, TopSort, VmState},
+};
 
 #[derive(Parser)]
 #[command(name = "vmtools")]
@@ -6,6 +20,15 @@ use clap::{Parser, Subcommand};
 #[command(version = "0.1.0")]
 #[command(author = "VM-Tools Contributors")]
 pub struct Cli {
+    /// Write every event of this run, down to each virsh and qemu-img command
+    /// and its output, to a trace file for a bug report
+    #[arg(long, global = true)]
+    pub debug: bool,
+
+    /// Format of log lines on stderr and in the trace file: text or json
+    #[arg(long, global = true, default_value = "text")]
+    pub log_format: LogStyle,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -21,54 +44,188 @@ pub enum Commands {
         /// Show only running VMs
         #[arg(short, long)]
         running: bool,
+        
+        /// Sort by field (name, memory, cpus, uptime, created, started)
+        #[arg(long)]
+        sort: Option<ListSort>,
+        
+        /// Show only VMs in this state (running, stopped, paused, suspended)
+        #[arg(long)]
+        state: Option<VmState>,
+        
+        /// Filter by name (name~substring or name=exact), may be repeated
+        #[arg(long)]
+        filter: Vec<ListFilter>,
+        
+        /// Columns to show (name, state, memory, cpus, uptime, ip, autostart, uuid, created, started, health, snapshots, backup)
+        #[arg(long, value_delimiter = ',')]
+        columns: Vec<ListColumn>,
+        
+        /// Skip per-VM disk, network and usage queries for faster output
+        #[arg(long)]
+        fast: bool,
     },
     
     /// Start a virtual machine
     Start {
         /// Name of the VM to start
-        name: String,
+        #[arg(required_unless_present_any = ["all", "group"])]
+        name: Option<String>,
+        
+        /// Boot once from this ISO, then go back to the saved boot order
+        #[arg(long, conflicts_with_all = ["all", "group"])]
+        boot_iso: Option<PathBuf>,
+        
+        /// Attach to the serial console as soon as the VM is running
+        #[arg(long, conflicts_with_all = ["all", "group"])]
+        console: bool,
+        
+        /// Start every stopped VM at once
+        #[arg(long, conflicts_with = "name")]
+        all: bool,
+        
+        /// With --all, only VMs with this tag
+        #[arg(long, requires = "all")]
+        tag: Option<String>,
+        
+        /// Start the members of a group, dependencies first
+        #[arg(long, conflicts_with_all = ["name", "all"])]
+        group: Option<String>,
+    },
+    
+    /// Start the VMs with a tag, dependencies first
+    StartGroup {
+        /// Tag set with `adopt --tag`
+        tag: String,
+        
+        /// Seconds to wait for each dependency to pass its health checks
+        #[arg(long, default_value = "300")]
+        timeout: u64,
     },
     
     /// Stop a virtual machine
     Stop {
         /// Name of the VM to stop
-        name: String,
+        #[arg(required_unless_present_any = ["all", "group"])]
+        name: Option<String>,
         
         /// Force stop (equivalent to pulling power)
         #[arg(short, long)]
         force: bool,
+        
+        /// Stop every running VM at once
+        #[arg(long, conflicts_with = "name")]
+        all: bool,
+        
+        /// With --all, only VMs with this tag
+        #[arg(long, requires = "all")]
+        tag: Option<String>,
+        
+        /// Stop the members of a group at once
+        #[arg(long, conflicts_with_all = ["name", "all"])]
+        group: Option<String>,
     },
     
     /// Get status of a virtual machine
     Status {
+        /// Name of the VM
+        #[arg(required_unless_present_any = ["group", "all"])]
+        name: Option<String>,
+        
+        /// Show the members of a group instead
+        #[arg(long, conflicts_with = "name")]
+        group: Option<String>,
+        
+        /// Summarize the whole host instead: VMs by state, allocated and used
+        /// resources, storage pools and detected issues
+        #[arg(long, conflicts_with_all = ["name", "group"])]
+        all: bool,
+    },
+    
+    /// Manage groups of VMs, kept as tags in each VM's metadata
+    Group {
+        #[command(subcommand)]
+        action: GroupAction,
+    },
+    
+    /// Run a VM's health checks, or change them with --add/--remove/--clear
+    Health {
+        /// Name of the VM
+        name: String,
+        
+        /// Check to add (repeatable): ping, agent, tcp:<port> or an http(s):// URL,
+        /// where {ip} stands for the guest's address
+        #[arg(long = "add", value_name = "CHECK")]
+        add: Vec<HealthCheck>,
+        
+        /// Check to remove (repeatable)
+        #[arg(long = "remove", value_name = "CHECK")]
+        remove: Vec<HealthCheck>,
+        
+        /// Remove all checks before adding
+        #[arg(long)]
+        clear: bool,
+    },
+    
+    /// Show the VMs a VM depends on, or change them with --add/--remove/--clear
+    Depends {
         /// Name of the VM
         name: String,
+        
+        /// VM that has to be up and healthy first (repeatable)
+        #[arg(long = "add", value_name = "VM")]
+        add: Vec<String>,
+        
+        /// Dependency to remove (repeatable)
+        #[arg(long = "remove", value_name = "VM")]
+        remove: Vec<String>,
+        
+        /// Remove all dependencies before adding
+        #[arg(long)]
+        clear: bool,
     },
     
     /// Create a new virtual machine
-    Create {
-        /// Name of the new VM
+    Create(Box<CreateArgs>),
+    
+    /// Bring a domain defined outside vmtools under management
+    Adopt {
+        /// Name of the libvirt domain
         name: String,
         
-        /// Memory in MB
-        #[arg(short, long, default_value = "2048")]
-        memory: u64,
+        /// Template the VM corresponds to
+        #[arg(long)]
+        template: Option<String>,
         
-        /// Number of CPUs
-        #[arg(short, long, default_value = "2")]
-        cpus: u32,
+        /// Tag to record (repeatable)
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+    },
+    
+    /// Create a VM from an existing qcow2 or raw disk image
+    ImportDisk {
+        /// Disk image to import
+        path: PathBuf,
+        
+        /// Name of the new VM
+        #[arg(long)]
+        name: String,
         
-        /// Disk size in GB
-        #[arg(short, long, default_value = "20")]
-        disk_size: u64,
+        /// Guest OS, as in virt-install (e.g. ubuntu24.04, win11)
+        #[arg(long)]
+        os_variant: Option<String>,
         
-        /// Path to ISO file for installation
+        /// Memory in MB (default: from the config defaults or template)
         #[arg(short, long)]
-        iso_path: Option<String>,
+        memory: Option<u64>,
         
-        /// VM template to use
+        /// Number of CPUs
         #[arg(short, long)]
-        template: Option<String>,
+        cpus: Option<u32>,
+        
+        /// Use the image as a backing file instead of copying it
+        #[arg(long)]
+        link: bool,
     },
     
     /// Delete a virtual machine
@@ -79,6 +236,16 @@ pub enum Commands {
         /// Force delete without confirmation
         #[arg(short, long)]
         force: bool,
+        
+        /// Move disks and definition to the trash instead of deleting them
+        #[arg(long)]
+        trash: bool,
+    },
+    
+    /// Restore a VM deleted with --trash
+    Undelete {
+        /// Name of the VM to restore (omit to list the trash)
+        name: Option<String>,
     },
     
     /// Clone a virtual machine
@@ -90,34 +257,323 @@ pub enum Commands {
         target: String,
     },
     
-    /// Monitor VM performance and resources
+    /// Set, rotate or clear the SPICE/VNC console password
+    VncPasswd {
+        /// Name of the VM
+        name: String,
+        
+        /// Read the new password from stdin instead of generating one
+        #[arg(long, conflicts_with = "clear")]
+        password_stdin: bool,
+        
+        /// Remove the password
+        #[arg(long)]
+        clear: bool,
+    },
+    
+    /// Reset a guest account's password on a stopped VM (edits its disks with libguestfs)
+    ResetPassword {
+        /// Name of the VM
+        name: String,
+        
+        /// Account whose password to reset
+        #[arg(long, default_value = "root")]
+        user: String,
+        
+        /// Read the new password from stdin instead of generating one
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    
+    /// Compare a VM's configuration with a template
+    Diff {
+        /// Name of the VM
+        name: String,
+        
+        /// Template to compare against
+        #[arg(short, long)]
+        template: String,
+    },
+    
+    /// Print a VM's domain XML
+    ExportXml {
+        /// Name of the VM
+        name: String,
+        
+        /// Print the persistent definition rather than the running configuration
+        #[arg(long)]
+        inactive: bool,
+    },
+    
+    /// Define a VM from a domain XML file
+    ImportXml {
+        /// Path to the XML file
+        file: PathBuf,
+        
+        /// Import under a different name
+        #[arg(long)]
+        rename: Option<String>,
+        
+        /// Give the VM a new UUID and MAC addresses
+        #[arg(long)]
+        regenerate_ids: bool,
+    },
+    
+    /// Change memory and CPUs of a stopped VM
+    Set {
+        /// Name of the VM
+        name: String,
+        
+        /// Memory in MB
+        #[arg(short, long)]
+        memory: Option<u64>,
+        
+        /// Number of CPUs
+        #[arg(short, long)]
+        cpus: Option<u32>,
+    },
+    
+    /// Resize a running VM's memory through the balloon, or change balloon options
+    Balloon {
+        /// Name of the VM
+        name: String,
+        
+        /// Memory the guest should keep, in MB
+        target: Option<u64>,
+        
+        /// Deflate the balloon instead of letting the guest run out of memory (on/off)
+        #[arg(long, value_parser = BoolishValueParser::new())]
+        autodeflate: Option<bool>,
+        
+        /// Return freed guest pages to the host continuously (on/off)
+        #[arg(long, value_parser = BoolishValueParser::new())]
+        free_page_reporting: Option<bool>,
+    },
+    
+    /// Tune network interfaces
+    Nic {
+        #[command(subcommand)]
+        action: NicAction,
+    },
+    
+    /// Manage storage pools on NFS exports and iSCSI targets
+    Pool {
+        #[command(subcommand)]
+        action: PoolAction,
+    },
+    
+    /// Attach disks and flatten disk backing chains
+    Disk {
+        #[command(subcommand)]
+        action: DiskAction,
+    },
+    
+    /// Measure storage performance
+    Bench {
+        #[command(subcommand)]
+        action: BenchAction,
+    },
+    
+    /// Tune CPU scheduling
+    Cpu {
+        #[command(subcommand)]
+        action: CpuAction,
+    },
+    
+    /// Set the boot device order
+    Boot {
+        /// Name of the VM
+        name: String,
+        
+        /// Devices in boot priority (hd, cdrom, network, fd)
+        #[arg(long, value_delimiter = ',', required = true)]
+        order: Vec<String>,
+    },
+    
+    /// Create and resize VMs to match a YAML manifest, reporting drift
+    Apply {
+        /// Manifest listing the VMs (name, template, resources, networks, cloud-init)
+        manifest: PathBuf,
+    },
+    
+    /// Show what apply would change for a manifest, without changing anything
+    Plan {
+        /// Manifest listing the VMs
+        manifest: PathBuf,
+    },
+    
+    /// Eject or insert CD-ROM media
+    Media {
+        /// Name of the VM
+        name: String,
+        
+        #[command(subcommand)]
+        action: MediaAction,
+    },
+    
+    /// Manage VM snapshots
+    Snapshot {
+        #[command(subcommand)]
+        action: SnapshotAction,
+    },
+    
+    /// Show disk usage across all VMs (virtual vs actual size, backing chains, snapshots)
+    Du,
+    
+    /// Show what the host's hypervisor supports (machine types, CPU models, vCPUs, firmware)
+    Capabilities,
+    
+    /// Prepare disk images with libguestfs
+    Image {
+        #[command(subcommand)]
+        action: ImageAction,
+    },
+    
+    /// Manage libvirt secrets (Ceph keys, LUKS passphrases, iSCSI CHAP passwords)
+    Secret {
+        #[command(subcommand)]
+        action: SecretAction,
+    },
+    
+    /// Save the memory of every running VM and stop it (before a host shutdown)
+    SuspendAll,
+    
+    /// Start every VM saved by suspend-all, continuing where it was
+    ResumeAll,
+    
+    /// Install systemd units: suspend-all at shutdown and resume-all at boot, and the daemon
+    InstallUnits {
+        /// Directory to write the unit to
+        #[arg(long, default_value = "/etc/systemd/system")]
+        dir: PathBuf,
+    },
+    
+    /// Run background policies: suspend idle VMs ([idle]) and keep automatic snapshots ([snapshot_policies])
+    Daemon,
+    
+    /// Live CPU, memory, disk and network use of every running VM
+    Top {
+        /// Sort by cpu, memory, disk or net
+        #[arg(long, default_value = "cpu")]
+        sort: TopSort,
+    },
+    
+    /// Monitor VM performance and resources; q quits, p pauses or resumes, s snapshots
     Monitor {
         /// Name of the VM to monitor
         name: String,
+        
+        /// Seconds between refreshes
+        #[arg(long, default_value = "2", value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+        
+        /// Also append every sample, with a timestamp, to this file
+        #[arg(long)]
+        log: Option<PathBuf>,
+        
+        /// Format of the log file: csv (default) or jsonl
+        #[arg(long, requires = "log")]
+        format: Option<LogFormat>,
     },
     
-    /// Connect to VM console
+    /// Connect to VM console; Ctrl+] (or console.escape in the config) detaches
     Console {
         /// Name of the VM
         name: String,
+        
+        /// Save the session's output to this file (asciinema .cast format)
+        #[arg(long)]
+        record: Option<PathBuf>,
+        
+        /// Take the console over from another session holding it
+        #[arg(long)]
+        force: bool,
+    },
+    
+    /// Send a raw QMP command to a running VM's QEMU monitor and print the result
+    ///
+    /// Commands that change the VM behind libvirt's back can leave its view stale;
+    /// libvirt marks the domain as tainted.
+    Qmp {
+        /// Name of the VM
+        name: String,
+        
+        /// The command as QMP JSON, e.g. '{"execute": "query-block"}', or just its name
+        command: String,
+    },
+    
+    /// Send a command to a running VM's QEMU guest agent and print the result
+    Ga {
+        /// Name of the VM
+        name: String,
+        
+        /// guest-info, guest-get-osinfo, another command name or raw JSON,
+        /// e.g. '{"execute": "guest-get-fsinfo"}'
+        command: String,
     },
     
     /// List available networks
     Networks,
     
+    /// Forward host ports to guests on NAT networks (nftables)
+    Firewall {
+        #[command(subcommand)]
+        action: FirewallAction,
+    },
+    
+    /// Create virtual networks from [network_templates]
+    Network {
+        #[command(subcommand)]
+        action: NetworkAction,
+    },
+    
+    /// Watch VM lifecycle events (started, stopped, crashed, migrated)
+    Events {
+        /// Only show events for this VM
+        #[arg(long)]
+        vm: Option<String>,
+        
+        /// Print events as JSON lines
+        #[arg(long)]
+        json: bool,
+    },
+    
+    /// Probe this host (KVM, libvirt, pools, networks) and write a config that fits it
+    Setup,
+    
+    /// Create the configured storage directories and check they can hold disk images
+    StorageInit,
+    
+    /// Host settings that affect VMs
+    Host {
+        #[command(subcommand)]
+        action: HostAction,
+    },
+    
     /// Configuration management
     Config {
         /// Show current configuration
         #[arg(long)]
         show: bool,
         
-        /// Set a configuration value (key=value)
+        /// Set a configuration value (key=value, e.g. templates.ubuntu.memory=4096)
         #[arg(short = 's', long, value_parser = parse_key_val)]
         set: Option<(String, String)>,
         
         /// Get a configuration value
         #[arg(short, long)]
         get: Option<String>,
+        
+        /// Encrypt a sensitive value at rest (libvirt.uri, webhooks.urls, notifications.smtp_url)
+        #[arg(long, value_name = "KEY")]
+        encrypt: Option<String>,
+        
+        /// Keep the encrypted value in the desktop keyring instead of an age-encrypted field
+        #[arg(long, requires = "encrypt")]
+        keyring: bool,
+        
+        #[command(subcommand)]
+        action: Option<ConfigAction>,
     },
     
     /// Fix network configuration issues for a VM
@@ -128,12 +584,29 @@ pub enum Commands {
         /// Automatically apply fixes (default: analyze only)
         #[arg(long)]
         auto: bool,
+        
+        /// Test the running VM's path out hop by hop instead: bridge, gateway, DHCP
+        /// lease, forwarding and NAT on the host, gateway and DNS from the guest
+        #[arg(long, conflicts_with = "auto")]
+        diagnose: bool,
     },
     
     /// Optimize VM configuration based on libvirt environment
     Optimize {
         /// Name of the VM to optimize
         name: String,
+        
+        /// Make the suggested changes to the VM definition, after showing them as a diff
+        #[arg(long)]
+        apply: bool,
+        
+        /// Apply without asking for confirmation
+        #[arg(short, long, requires = "apply")]
+        yes: bool,
+        
+        /// Also tune for a kind of guest: windows, realtime
+        #[arg(long)]
+        profile: Option<Profile>,
     },
     
     /// Fix clipboard and SPICE integration issues
@@ -153,6 +626,606 @@ pub enum Commands {
     },
 }
 
+/// Arguments of `create`, boxed in `Commands` as they outweigh every other command
+#[derive(Args)]
+pub struct CreateArgs {
+    /// Name of the new VM
+    pub name: String,
+    
+    /// Memory in MB
+    #[arg(short, long, default_value = "2048")]
+    pub memory: u64,
+    
+    /// Number of CPUs
+    #[arg(short, long, default_value = "2")]
+    pub cpus: u32,
+    
+    /// Disk size in GB
+    #[arg(short, long, default_value = "20")]
+    pub disk_size: u64,
+    
+    /// Path to ISO file for installation; with a new blank disk it boots first until the VM's first shutdown
+    #[arg(short, long)]
+    pub iso_path: Option<String>,
+    
+    /// Guest OS as an osinfo short ID (e.g. ubuntu24.04, win11); picks disk bus, NIC
+    /// model and Windows tuning. Detected from --iso-path when osinfo-detect knows it
+    #[arg(long)]
+    pub os_variant: Option<OsVariant>,
+    
+    /// Start the VM right after it is defined
+    #[arg(long)]
+    pub start: bool,
+    
+    /// VM template to use
+    #[arg(short, long)]
+    pub template: Option<String>,
+    
+    /// What to create: vm, or container (needs an lxc:/// URI)
+    #[arg(long = "type", default_value = "vm")]
+    pub kind: DomainKind,
+    
+    /// Root filesystem directory for --type container
+    #[arg(long, required_if_eq("kind", "container"))]
+    pub rootfs: Option<PathBuf>,
+    
+    /// Guest architecture (x86_64, aarch64, riscv64)
+    #[arg(long)]
+    pub arch: Option<String>,
+    
+    /// Emulate the CPU with TCG instead of KVM (needed for foreign architectures)
+    #[arg(long)]
+    pub emulated: bool,
+    
+    /// USB redirection channels for the SPICE viewer (default from config)
+    #[arg(long)]
+    pub usb_redirect: Option<u32>,
+    
+    /// Audio backend: spice, pulseaudio, pipewire or none (default from config)
+    #[arg(long)]
+    pub audio: Option<AudioBackend>,
+    
+    /// Console listen address (default from config, normally 127.0.0.1)
+    #[arg(long)]
+    pub listen: Option<String>,
+    
+    /// Require TLS for the SPICE console
+    #[arg(long)]
+    pub tls: bool,
+    
+    /// Fixed SPICE TLS port (implies --tls)
+    #[arg(long)]
+    pub tls_port: Option<u16>,
+    
+    /// Directory with the console's x509 certificates
+    #[arg(long)]
+    pub x509_dir: Option<PathBuf>,
+    
+    /// Bus for the system disk: virtio, or virtio-scsi (multiqueue, many disks)
+    #[arg(long, default_value = "virtio")]
+    pub disk_bus: DiskBus,
+    
+    /// virtio-net queue pairs (default: one per vCPU)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=256))]
+    pub net_queues: Option<u32>,
+    
+    /// Dedicated disk I/O threads (default: from the template, 0 disables)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(0..=64))]
+    pub iothreads: Option<u32>,
+    
+    /// CPU the guest sees: host-passthrough, host-model or a named model such as
+    /// Skylake-Client (default: defaults.cpu_model); only named models live-migrate
+    /// between different hosts
+    #[arg(long)]
+    pub cpu_model: Option<CpuModel>,
+    
+    /// Run from other storage instead of a new local qcow2 file: rbd:<pool>/<image>
+    /// (cluster and credentials from the [ceph] config), pool:<pool>/<volume>
+    /// (created with --disk-size if missing and the pool allows it),
+    /// lvm:<vg>/<thin-pool> or zfs:<dataset> (a new thin LV or zvol of --disk-size
+    /// named after the VM)
+    #[arg(long)]
+    pub disk: Option<DiskSource>,
+    
+    /// Boot this kernel image (e.g. a locally built bzImage) instead of the
+    /// disk's bootloader; the libvirt/qemu user must be able to read it
+    #[arg(long, conflicts_with = "rootfs")]
+    pub kernel: Option<PathBuf>,
+    
+    /// Initramfs for --kernel
+    #[arg(long, requires = "kernel")]
+    pub initrd: Option<PathBuf>,
+    
+    /// Kernel command line for --kernel (e.g. "console=ttyS0 root=/dev/vda rw")
+    #[arg(long, requires = "kernel")]
+    pub cmdline: Option<String>,
+    
+    /// Copy sizing and devices from this VM, with an empty disk of the same size
+    /// in place of each of its disks (boots --iso-path first if given)
+    #[arg(long, value_name = "VM", conflicts_with_all = [
+        "memory", "cpus", "disk_size", "os_variant", "template", "kind", "arch", "emulated",
+        "usb_redirect", "audio", "listen", "tls", "tls_port", "x509_dir", "disk_bus",
+        "net_queues", "iothreads", "cpu_model", "disk", "kernel",
+    ])]
+    pub like: Option<String>,
+}
+
+#[derive(Subcommand)]
+pub enum MediaAction {
+    /// Remove the media from the CD-ROM drive
+    Eject,
+    
+    /// Insert an ISO, replacing any current media
+    Insert {
+        /// Path to the ISO file
+        iso: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum NicAction {
+    /// Cap an interface's average bandwidth (e.g. 100mbit, 1gbit, 0 = unlimited)
+    Limit {
+        /// Name of the VM
+        name: String,
+        
+        /// MAC address of the interface
+        mac: String,
+        
+        /// Limit for traffic received by the guest
+        #[arg(long)]
+        inbound: Option<String>,
+        
+        /// Limit for traffic sent by the guest
+        #[arg(long)]
+        outbound: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ImageAction {
+    /// Install packages, run commands and add SSH keys inside an image, in that order
+    Customize {
+        /// Disk image to change in place
+        image: PathBuf,
+        
+        /// Packages to install (comma-separated)
+        #[arg(long, value_delimiter = ',')]
+        install: Vec<String>,
+        
+        /// Shell command to run inside the image as root (repeatable)
+        #[arg(long)]
+        run_command: Vec<String>,
+        
+        /// Public key to authorize for a user, as <user>:<key file> (repeatable)
+        #[arg(long)]
+        ssh_inject: Vec<SshInject>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SecretAction {
+    /// Store a key in a new secret; the value is read from stdin unless --value-file is given
+    Create {
+        /// What the secret unlocks
+        #[arg(value_parser = ["ceph", "volume", "iscsi", "tls", "vtpm"])]
+        kind: String,
+        
+        /// Usage name disks refer to (e.g. "client.libvirt secret", a volume path or an iSCSI target)
+        usage: String,
+        
+        /// Read the raw value from this file
+        #[arg(long)]
+        value_file: Option<PathBuf>,
+        
+        /// Free-form description
+        #[arg(long)]
+        description: Option<String>,
+    },
+    
+    /// List secrets (values are never shown)
+    List,
+    
+    /// Delete a secret by UUID or usage name
+    Delete {
+        /// UUID or usage name
+        id: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum FirewallAction {
+    /// Forward a host port to the guest, following its address as leases change
+    Allow(PortArgs),
+    
+    /// Stop forwarding a host port to the guest
+    Remove(PortArgs),
+    
+    /// List port forwards and the addresses they point at
+    List,
+    
+    /// Rebuild the rules from the guests' current addresses
+    Sync,
+}
+
+#[derive(Args)]
+pub struct PortArgs {
+    /// Name of the VM
+    pub name: String,
+    
+    /// Port the service listens on in the guest
+    #[arg(long)]
+    pub port: u16,
+    
+    /// Port on the host, when it differs from the guest's
+    #[arg(long)]
+    pub host_port: Option<u16>,
+    
+    /// tcp or udp
+    #[arg(long, default_value = "tcp")]
+    pub protocol: Protocol,
+}
+
+impl PortArgs {
+    pub fn forward(&self) -> PortForward {
+        PortForward {
+            host_port: self.host_port.unwrap_or(self.port),
+            guest_port: self.port,
+            protocol: self.protocol,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum NetworkAction {
+    /// Define, start and autostart a network from a template
+    Create {
+        /// Name of the new network
+        name: String,
+        
+        /// Template from [network_templates] in the config
+        #[arg(long)]
+        template: String,
+        
+        /// Also give the network this IPv6 /64, e.g. fd00:150::/64, with DHCPv6
+        /// and router advertisements
+        #[arg(long, value_name = "PREFIX")]
+        ipv6: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum PoolAction {
+    /// List storage pools
+    List,
+    
+    /// List the volumes of a pool
+    Volumes {
+        /// Name of the pool
+        pool: String,
+    },
+    
+    /// List the targets an iSCSI portal offers
+    Discover {
+        /// Portal host name or address
+        host: String,
+    },
+    
+    /// Add a pool on an NFS export, mounted under the images directory
+    AddNfs {
+        /// Name of the new pool
+        name: String,
+        
+        /// NFS server
+        #[arg(long)]
+        host: String,
+        
+        /// Exported directory, e.g. /volume1/vms
+        #[arg(long)]
+        export: String,
+    },
+    
+    /// Add a pool for the LUNs of an iSCSI target
+    AddIscsi {
+        /// Name of the new pool
+        name: String,
+        
+        /// Portal host name or address
+        #[arg(long)]
+        host: String,
+        
+        /// Target IQN (see pool discover)
+        #[arg(long)]
+        target: String,
+        
+        /// CHAP user; the password is the iscsi secret named after the target
+        #[arg(long)]
+        chap_user: Option<String>,
+    },
+    
+    /// Remove a pool definition (data on the server is kept)
+    Remove {
+        /// Name of the pool
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum DiskAction {
+    /// Add a data disk, hot-plugging it into a running VM
+    Attach {
+        /// Name of the VM
+        name: String,
+        
+        /// Disk to attach: rbd:<pool>/<image>, pool:<pool>/<volume>, lvm:<vg>/<thin-pool>
+        /// or zfs:<dataset>
+        source: DiskSource,
+        
+        /// Size in GB of a new thin LV or zvol, or of a pool volume that doesn't exist yet
+        #[arg(long)]
+        size: Option<u64>,
+    },
+    
+    /// Merge a running VM's overlays down into the base image (blockcommit)
+    Commit {
+        /// Name of the VM
+        name: String,
+        
+        /// Only this disk (e.g. vda); default is every disk with a backing file
+        #[arg(long)]
+        disk: Option<String>,
+    },
+    
+    /// Copy backing data into a VM's disks so they stand alone (blockpull);
+    /// works offline too
+    Pull {
+        /// Name of the VM
+        name: String,
+        
+        /// Only this disk (e.g. vda); default is every disk with a backing file
+        #[arg(long)]
+        disk: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum BenchAction {
+    /// Run qemu-img bench against a VM's disk and report IOPS, throughput and latency
+    Disk {
+        /// Name of the VM
+        name: String,
+        
+        /// Disk to measure (e.g. vda); default is the first one
+        #[arg(long)]
+        disk: Option<String>,
+        
+        /// Host cache modes to compare (none, writeback, writethrough, directsync, unsafe)
+        #[arg(long, value_delimiter = ',', default_value = "none")]
+        cache: Vec<String>,
+        
+        /// AIO modes to compare (threads, native, io_uring)
+        #[arg(long, value_delimiter = ',', default_value = "threads")]
+        aio: Vec<String>,
+        
+        /// Also measure writes, on a scratch image next to the disk
+        #[arg(long)]
+        write: bool,
+        
+        /// Also run fio inside the running guest through the guest agent
+        #[arg(long)]
+        guest: bool,
+        
+        /// Number of requests per run
+        #[arg(long, default_value = "100000")]
+        requests: u64,
+        
+        /// Requests in flight
+        #[arg(long, default_value = "64", value_parser = clap::value_parser!(u32).range(1..=1024))]
+        depth: u32,
+        
+        /// Request size in bytes
+        #[arg(long, default_value = "4096")]
+        block_size: u64,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CpuAction {
+    /// Weight a VM against others and cap its vCPUs
+    Limit {
+        /// Name of the VM
+        name: String,
+        
+        /// Relative CPU weight (default 1024; 0 resets)
+        #[arg(long)]
+        shares: Option<u64>,
+        
+        /// Cap on each vCPU's time, e.g. 50% (0 removes the cap)
+        #[arg(long)]
+        quota: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SnapshotAction {
+    /// Take a snapshot of a VM
+    Create {
+        /// Name of the VM
+        vm: String,
+        
+        /// Name of the snapshot
+        name: String,
+        
+        /// Free-form description stored with the snapshot
+        #[arg(long)]
+        description: Option<String>,
+        
+        /// Include the running VM's memory so reverting resumes it exactly
+        #[arg(long)]
+        memory: bool,
+    },
+    
+    /// List snapshots of a VM
+    List {
+        /// Name of the VM
+        vm: String,
+    },
+    
+    /// Show the disk space each snapshot and backing image of a VM takes
+    Du {
+        /// Name of the VM
+        vm: String,
+    },
+    
+    /// Revert a VM to a snapshot
+    Revert {
+        /// Name of the VM
+        vm: String,
+        
+        /// Name of the snapshot
+        name: String,
+    },
+    
+    /// Delete a snapshot
+    Delete {
+        /// Name of the VM
+        vm: String,
+        
+        /// Name of the snapshot
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    /// Check the config file for syntax errors, wrong types, unknown keys and bad paths
+    Validate {
+        /// File to check instead of ~/.config/vmtools/config.toml
+        file: Option<PathBuf>,
+    },
+    
+    /// List every setting with its effective value, as keys for --get and --set
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum HostAction {
+    /// Check KSM, swappiness, transparent hugepages, the CPU governor and nested
+    /// virtualization against values that suit VMs
+    Tune {
+        /// Write the recommended values (until the next reboot)
+        #[arg(long)]
+        apply: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum GroupAction {
+    /// Create a group from one or more VMs
+    Create {
+        /// Name of the group
+        name: String,
+        
+        /// VMs to put in the group
+        #[arg(required = true)]
+        vms: Vec<String>,
+    },
+    
+    /// Add VMs to a group
+    Add {
+        /// Name of the group
+        name: String,
+        
+        /// VMs to add
+        #[arg(required = true)]
+        vms: Vec<String>,
+    },
+    
+    /// Remove VMs from a group
+    Remove {
+        /// Name of the group
+        name: String,
+        
+        /// VMs to remove
+        #[arg(required = true)]
+        vms: Vec<String>,
+    },
+    
+    /// List groups and their members
+    List,
+}
+
+impl Commands {
+    /// Operation name and target VM reported to webhooks once the command finishes
+    pub fn operation(&self) -> Option<(&'static str, Option<String>)> {
+        match self {
+            Commands::Start { all: true, .. } => Some(("start-all", None)),
+            Commands::Start { group: Some(_), .. } => Some(("start-group", None)),
+            // With --console, start_vm_with_console reports the start before the session begins
+            Commands::Start { name, console: false, .. } => Some(("start", name.clone())),
+            Commands::StartGroup { .. } => Some(("start-group", None)),
+            Commands::Stop { all: true, .. } => Some(("stop-all", None)),
+            Commands::Stop { group: Some(_), .. } => Some(("stop-group", None)),
+            Commands::Stop { name, .. } => Some(("stop", name.clone())),
+            Commands::SuspendAll => Some(("suspend-all", None)),
+            Commands::ResumeAll => Some(("resume-all", None)),
+            Commands::Create(args) => Some(("create", Some(args.name.clone()))),
+            Commands::ImportDisk { name, .. } => Some(("import-disk", Some(name.clone()))),
+            Commands::Image { action: ImageAction::Customize { .. } } => Some(("image-customize", None)),
+            Commands::Adopt { name, .. } => Some(("adopt", Some(name.clone()))),
+            Commands::Health { name, add, remove, clear } if !add.is_empty() || !remove.is_empty() || *clear => {
+                Some(("health", Some(name.clone())))
+            }
+            Commands::Depends { name, add, remove, clear } if !add.is_empty() || !remove.is_empty() || *clear => {
+                Some(("depends", Some(name.clone())))
+            }
+            Commands::Delete { name, .. } => Some(("delete", Some(name.clone()))),
+            Commands::Undelete { name: Some(name) } => Some(("undelete", Some(name.clone()))),
+            Commands::Clone { target, .. } => Some(("clone", Some(target.clone()))),
+            Commands::ImportXml { file, rename, .. } => Some((
+                "import-xml",
+                rename.clone().or_else(|| file.file_stem().map(|s| s.to_string_lossy().into_owned())),
+            )),
+            Commands::VncPasswd { name, .. } => Some(("vnc-passwd", Some(name.clone()))),
+            Commands::ResetPassword { name, .. } => Some(("reset-password", Some(name.clone()))),
+            Commands::Balloon { name, .. } => Some(("balloon", Some(name.clone()))),
+            Commands::Set { name, .. } => Some(("set", Some(name.clone()))),
+            Commands::Boot { name, .. } => Some(("boot", Some(name.clone()))),
+            Commands::Nic { action: NicAction::Limit { name, .. } } => Some(("nic-limit", Some(name.clone()))),
+            Commands::Cpu { action: CpuAction::Limit { name, .. } } => Some(("cpu-limit", Some(name.clone()))),
+            Commands::Disk { action: DiskAction::Attach { name, .. } } => Some(("disk-attach", Some(name.clone()))),
+            Commands::Disk { action: DiskAction::Commit { name, .. } } => Some(("disk-commit", Some(name.clone()))),
+            Commands::Disk { action: DiskAction::Pull { name, .. } } => Some(("disk-pull", Some(name.clone()))),
+            Commands::Media { name, .. } => Some(("media", Some(name.clone()))),
+            Commands::Pool { action: PoolAction::AddNfs { name, .. } | PoolAction::AddIscsi { name, .. } } => {
+                Some(("pool-add", Some(name.clone())))
+            }
+            Commands::Pool { action: PoolAction::Remove { name } } => Some(("pool-remove", Some(name.clone()))),
+            Commands::Firewall { action: FirewallAction::Allow(args) } => Some(("firewall-allow", Some(args.name.clone()))),
+            Commands::Firewall { action: FirewallAction::Remove(args) } => Some(("firewall-remove", Some(args.name.clone()))),
+            Commands::Network { action: NetworkAction::Create { name, .. } } => Some(("network-create", Some(name.clone()))),
+            Commands::StorageInit => Some(("storage-init", None)),
+            Commands::Host { action: HostAction::Tune { apply: true } } => Some(("host-tune", None)),
+            Commands::Secret { action: SecretAction::Create { .. } } => Some(("secret-create", None)),
+            Commands::Secret { action: SecretAction::Delete { .. } } => Some(("secret-delete", None)),
+            Commands::Apply { .. } => Some(("apply", None)),
+            Commands::Snapshot { action } => match action {
+                SnapshotAction::Create { vm, .. } => Some(("snapshot-create", Some(vm.clone()))),
+                SnapshotAction::Revert { vm, .. } => Some(("snapshot-revert", Some(vm.clone()))),
+                SnapshotAction::Delete { vm, .. } => Some(("snapshot-delete", Some(vm.clone()))),
+                SnapshotAction::List { .. } | SnapshotAction::Du { .. } => None,
+            },
+            Commands::Group { action } => match action {
+                GroupAction::Create { .. } => Some(("group-create", None)),
+                GroupAction::Add { .. } => Some(("group-add", None)),
+                GroupAction::Remove { .. } => Some(("group-remove", None)),
+                GroupAction::List => None,
+            },
+            _ => None,
+        }
+    }
+}
+
 fn parse_key_val(s: &str) -> Result<(String, String), String> {
     let parts: Vec<&str> = s.splitn(2, '=').collect();
     if parts.len() != 2 {
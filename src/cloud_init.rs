@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::{
+    error::{VmError, Result},
+    runner::{CommandRunner, Invocation},
+};
+
+/// Volume label cloud-init's NoCloud datasource looks for
+const SEED_LABEL: &str = "cidata";
+
+/// First-boot configuration handed to the guest on a NoCloud seed ISO
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CloudInit {
+    /// `#cloud-config` file used verbatim as user-data; the options below are
+    /// only used when it is absent
+    #[serde(default)]
+    pub user_data: Option<PathBuf>,
+    /// Keys for the image's default user
+    #[serde(default)]
+    pub ssh_authorized_keys: Vec<String>,
+    /// Packages installed on first boot
+    #[serde(default)]
+    pub packages: Vec<String>,
+    /// Network configuration (version 1 or 2) put on the seed as `network-config`;
+    /// without it the guest uses DHCP on its first interface
+    #[serde(default)]
+    pub network_config: Option<PathBuf>,
+}
+
+impl CloudInit {
+    /// The user-data document for a guest called `hostname`
+    pub fn user_data(&self, hostname: &str) -> Result<String> {
+        if let Some(path) = &self.user_data {
+            return std::fs::read_to_string(path).map_err(|e| VmError::ConfigError(format!(
+                "Cannot read cloud-init user-data {}: {}", path.display(), e
+            )));
+        }
+
+        let mut user_data = format!("#cloud-config\nhostname: {}\n", hostname);
+        let mut list = |key: &str, items: &[String]| {
+            if !items.is_empty() {
+                user_data.push_str(&format!("{}:\n", key));
+                for item in items {
+                    user_data.push_str(&format!("  - {}\n", item));
+                }
+            }
+        };
+        list("ssh_authorized_keys", &self.ssh_authorized_keys);
+        list("packages", &self.packages);
+        Ok(user_data)
+    }
+
+    fn network_config(&self) -> Result<Option<String>> {
+        self.network_config.as_ref()
+            .map(|path| std::fs::read_to_string(path).map_err(|e| VmError::ConfigError(format!(
+                "Cannot read cloud-init network-config {}: {}", path.display(), e
+            ))))
+            .transpose()
+    }
+
+    /// Writes a seed ISO for the guest `name` to `path` with `genisoimage`, run through `runner`
+    pub async fn build_seed(&self, runner: &dyn CommandRunner, name: &str, path: &Path, temp_dir: &Path) -> Result<()> {
+        let work = temp_dir.join(format!("vmtools_cidata_{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&work).await?;
+
+        let result = async {
+            tokio::fs::write(work.join("user-data"), self.user_data(name)?).await?;
+            tokio::fs::write(
+                work.join("meta-data"),
+                format!("instance-id: {}\nlocal-hostname: {}\n", name, name),
+            ).await?;
+
+            let mut command = Invocation::new("genisoimage")
+                .args(["-output", &path.to_string_lossy(), "-volid", SEED_LABEL, "-joliet", "-rock"])
+                .arg(work.join("user-data"))
+                .arg(work.join("meta-data"));
+            if let Some(network_config) = self.network_config()? {
+                tokio::fs::write(work.join("network-config"), network_config).await?;
+                command = command.arg(work.join("network-config"));
+            }
+            let output = runner.output(&command)
+                .await
+                .map_err(|e| VmError::CommandError(format!(
+                    "Cannot run genisoimage (install genisoimage or cdrkit): {}", e
+                )))?;
+
+            if !output.status.success() {
+                let error = String::from_utf8_lossy(&output.stderr);
+                return Err(VmError::CommandError(format!("Failed to build cloud-init seed: {}", error.trim())));
+            }
+            Ok(())
+        }.await;
+
+        let _ = tokio::fs::remove_dir_all(&work).await;
+        result
+    }
+}
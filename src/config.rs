@@ -4,7 +4,16 @@ use std::fs;
 use std::path::PathBuf;
 use std::fmt;
 
-use crate::error::{VmError, Result};
+use crate::{
+    cloud_init::CloudInit,
+    error::{VmError, Result},
+    runner::CommandRunner,
+    validation::{self, ConfigIssue},
+    vault,
+};
+
+/// Settings that may hold credentials or tokens, and so can be encrypted at rest
+pub const SENSITIVE_KEYS: &[&str] = &["libvirt.uri", "webhooks.urls", "notifications.smtp_url"];
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -14,6 +23,425 @@ pub struct Config {
     pub system: SystemConfig,
     pub templates: HashMap<String, VmTemplate>,
     pub defaults: DefaultsConfig,
+    #[serde(default)]
+    pub webhooks: WebhookConfig,
+    #[serde(default)]
+    pub backend: BackendConfig,
+    #[serde(default)]
+    pub desktop: DesktopConfig,
+    #[serde(default)]
+    pub graphics: GraphicsConfig,
+    #[serde(default)]
+    pub console: ConsoleConfig,
+    #[serde(default)]
+    pub ceph: CephConfig,
+    #[serde(default)]
+    pub zfs: ZfsConfig,
+    #[serde(default)]
+    pub idle: IdleConfig,
+    #[serde(default)]
+    pub notifications: NotificationConfig,
+    /// Automatic snapshots per VM, taken and pruned by `vmtools daemon`
+    #[serde(default)]
+    pub snapshot_policies: HashMap<String, SnapshotPolicy>,
+    /// Virtual networks `vmtools network create --template` defines, keyed by template name
+    #[serde(default)]
+    pub network_templates: HashMap<String, NetworkTemplate>,
+    /// Encrypted form of each decrypted value, keyed by the value, so `save` writes
+    /// back what was loaded rather than the plain text
+    #[serde(skip)]
+    sealed: HashMap<String, String>,
+}
+
+/// Which hypervisor layer VMs are managed through
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// libvirtd via virsh (default)
+    Libvirt,
+    /// `qemu-system-*` processes launched directly, for hosts without libvirtd
+    Qemu,
+}
+
+impl std::str::FromStr for BackendKind {
+    type Err = VmError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "libvirt" => Ok(BackendKind::Libvirt),
+            "qemu" => Ok(BackendKind::Qemu),
+            _ => Err(VmError::InvalidInput(format!("Unknown backend '{}' (expected libvirt or qemu)", s))),
+        }
+    }
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendKind::Libvirt => write!(f, "libvirt"),
+            BackendKind::Qemu => write!(f, "qemu"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackendConfig {
+    #[serde(rename = "type", default = "default_backend_kind")]
+    pub kind: BackendKind,
+    /// Where the qemu backend keeps VM definitions, PID files and QMP sockets
+    #[serde(default = "default_qemu_state_dir")]
+    pub qemu_state_dir: PathBuf,
+}
+
+fn default_backend_kind() -> BackendKind {
+    BackendKind::Libvirt
+}
+
+fn default_qemu_state_dir() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("/tmp"))
+        .join("vmtools")
+        .join("qemu")
+}
+
+impl Default for BackendConfig {
+    fn default() -> Self {
+        Self {
+            kind: default_backend_kind(),
+            qemu_state_dir: default_qemu_state_dir(),
+        }
+    }
+}
+
+/// CPU a KVM guest sees
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum CpuModel {
+    /// The host CPU exactly; fastest, but the VM only migrates to identical hosts
+    HostPassthrough,
+    /// The closest named model plus the host's extra features, fixed at start
+    HostModel,
+    /// A named model such as `Skylake-Client` or `EPYC`, migratable to any host that has it
+    Named(String),
+}
+
+impl std::str::FromStr for CpuModel {
+    type Err = VmError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "host-passthrough" => Ok(CpuModel::HostPassthrough),
+            "host-model" => Ok(CpuModel::HostModel),
+            name if !name.is_empty() && name.len() <= 64
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) => {
+                Ok(CpuModel::Named(name.to_string()))
+            }
+            _ => Err(VmError::InvalidInput(format!(
+                "Invalid CPU model '{}' (expected host-passthrough, host-model or a model name)", s
+            ))),
+        }
+    }
+}
+
+impl TryFrom<String> for CpuModel {
+    type Error = VmError;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<CpuModel> for String {
+    fn from(model: CpuModel) -> Self {
+        model.to_string()
+    }
+}
+
+impl fmt::Display for CpuModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuModel::HostPassthrough => write!(f, "host-passthrough"),
+            CpuModel::HostModel => write!(f, "host-model"),
+            CpuModel::Named(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Where guest sound is played
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioBackend {
+    /// Streamed to the SPICE viewer (default)
+    Spice,
+    /// Played by QEMU itself; QEMU must be able to reach the user's sound server,
+    /// which usually means qemu:///session
+    Pulseaudio,
+    Pipewire,
+    /// No sound device
+    None,
+}
+
+impl std::str::FromStr for AudioBackend {
+    type Err = VmError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "spice" => Ok(AudioBackend::Spice),
+            "pulseaudio" => Ok(AudioBackend::Pulseaudio),
+            "pipewire" => Ok(AudioBackend::Pipewire),
+            "none" => Ok(AudioBackend::None),
+            _ => Err(VmError::InvalidInput(format!(
+                "Unknown audio backend '{}' (expected spice, pulseaudio, pipewire or none)", s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for AudioBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AudioBackend::Spice => write!(f, "spice"),
+            AudioBackend::Pulseaudio => write!(f, "pulseaudio"),
+            AudioBackend::Pipewire => write!(f, "pipewire"),
+            AudioBackend::None => write!(f, "none"),
+        }
+    }
+}
+
+/// Devices for desktop guests viewed over SPICE
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DesktopConfig {
+    /// USB redirection channels, i.e. how many USB devices the viewer can pass through at once
+    #[serde(default = "default_usb_redirect")]
+    pub usb_redirect: u32,
+    #[serde(default = "default_audio")]
+    pub audio: AudioBackend,
+}
+
+fn default_usb_redirect() -> u32 {
+    2
+}
+
+fn default_audio() -> AudioBackend {
+    AudioBackend::Spice
+}
+
+impl Default for DesktopConfig {
+    fn default() -> Self {
+        Self {
+            usb_redirect: default_usb_redirect(),
+            audio: default_audio(),
+        }
+    }
+}
+
+/// Where the SPICE/VNC console of new VMs listens and whether it requires TLS
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphicsConfig {
+    /// Listen address; anything but loopback exposes the console to the network
+    #[serde(default = "default_graphics_listen")]
+    pub listen: String,
+    /// Require TLS for SPICE channels (VNC TLS is switched on host-wide in qemu.conf)
+    #[serde(default)]
+    pub tls: bool,
+    /// Fixed SPICE TLS port; unset lets libvirt pick one
+    #[serde(default)]
+    pub tls_port: Option<u16>,
+    /// Directory with ca-cert.pem, server-cert.pem and server-key.pem; unset uses
+    /// libvirt's /etc/pki/libvirt-spice or /etc/pki/libvirt-vnc
+    #[serde(default)]
+    pub x509_dir: Option<PathBuf>,
+}
+
+fn default_graphics_listen() -> String {
+    "127.0.0.1".to_string()
+}
+
+impl Default for GraphicsConfig {
+    fn default() -> Self {
+        Self {
+            listen: default_graphics_listen(),
+            tls: false,
+            tls_port: None,
+            x509_dir: None,
+        }
+    }
+}
+
+/// How `vmtools console` attaches to a VM's serial console
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsoleConfig {
+    /// Key ending a session, in caret notation: `^]` is Ctrl+]
+    #[serde(default = "default_console_escape")]
+    pub escape: String,
+}
+
+fn default_console_escape() -> String {
+    "^]".to_string()
+}
+
+impl Default for ConsoleConfig {
+    fn default() -> Self {
+        Self { escape: default_console_escape() }
+    }
+}
+
+impl ConsoleConfig {
+    /// The control character a caret-notation key such as `^]` sends
+    pub fn escape_byte(escape: &str) -> Result<u8> {
+        match escape.as_bytes() {
+            [b'^', key] if (b'@'..=b'_').contains(&key.to_ascii_uppercase()) => Ok(key.to_ascii_uppercase() ^ 0x40),
+            _ => Err(VmError::InvalidInput(format!(
+                "Invalid console escape '{}' (a control key in caret notation, such as ^] or ^O)", escape
+            ))),
+        }
+    }
+}
+
+/// Cluster access for `rbd:<pool>/<image>` disks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CephConfig {
+    /// Monitors as `host` or `host:port`; empty uses the host's /etc/ceph/ceph.conf
+    #[serde(default)]
+    pub monitors: Vec<String>,
+    /// cephx user (without `client.`); empty disables authentication
+    #[serde(default = "default_ceph_user")]
+    pub user: String,
+}
+
+fn default_ceph_user() -> String {
+    "libvirt".to_string()
+}
+
+impl Default for CephConfig {
+    fn default() -> Self {
+        Self {
+            monitors: Vec::new(),
+            user: default_ceph_user(),
+        }
+    }
+}
+
+impl CephConfig {
+    /// Usage name of the libvirt secret holding the user's key, as Ceph's docs name it
+    pub fn secret_usage(&self) -> String {
+        format!("client.{} secret", self.user)
+    }
+}
+
+/// Properties of zvols created for `zfs:<dataset>` disks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZfsConfig {
+    /// Block size of new zvols; fixed once the zvol exists
+    #[serde(default = "default_volblocksize")]
+    pub volblocksize: String,
+    /// `compression` property of new zvols (e.g. lz4, zstd, off)
+    #[serde(default = "default_zfs_compression")]
+    pub compression: String,
+}
+
+fn default_volblocksize() -> String {
+    "16K".to_string()
+}
+
+fn default_zfs_compression() -> String {
+    "lz4".to_string()
+}
+
+impl Default for ZfsConfig {
+    fn default() -> Self {
+        Self {
+            volblocksize: default_volblocksize(),
+            compression: default_zfs_compression(),
+        }
+    }
+}
+
+/// How many automatic snapshots of each period to keep of one VM; 0 takes none
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotPolicy {
+    #[serde(default)]
+    pub hourly: u32,
+    #[serde(default)]
+    pub daily: u32,
+    #[serde(default)]
+    pub weekly: u32,
+    #[serde(default)]
+    pub monthly: u32,
+}
+
+/// How guests on a templated network reach the outside
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkMode {
+    /// Guests reach the outside through the host, masqueraded (default)
+    #[default]
+    Nat,
+    /// Guests only reach the host and each other
+    Isolated,
+}
+
+/// A virtual network every lab host can recreate the same way
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkTemplate {
+    /// IPv4 subnet in CIDR form, e.g. 192.168.150.0/24; the host takes its first address
+    pub subnet: String,
+    /// First address DHCP leases; unset with `dhcp_end` leases the upper half of the subnet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dhcp_start: Option<String>,
+    /// Last address DHCP leases
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dhcp_end: Option<String>,
+    #[serde(default)]
+    pub mode: NetworkMode,
+    /// DNS domain guests are named under, e.g. lab.internal
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+}
+
+/// Suspending VMs nobody uses, done by `vmtools daemon`. Off by default: a VM
+/// that disappears is a surprise on a server, but frees RAM on a laptop.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdleConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// CPU use in percent of one host CPU below which a VM counts as idle
+    #[serde(default = "default_idle_cpu_threshold")]
+    pub cpu_threshold: f64,
+    /// Network traffic in bytes per second, both ways together, below which a VM
+    /// counts as serving no connections
+    #[serde(default = "default_idle_net_threshold")]
+    pub net_threshold: u64,
+    /// Minutes a VM must stay idle before it is suspended
+    #[serde(default = "default_idle_minutes")]
+    pub minutes: u64,
+    /// VMs never suspended
+    #[serde(default)]
+    pub allowlist: Vec<String>,
+}
+
+fn default_idle_cpu_threshold() -> f64 {
+    5.0
+}
+
+fn default_idle_net_threshold() -> u64 {
+    2048
+}
+
+fn default_idle_minutes() -> u64 {
+    30
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cpu_threshold: default_idle_cpu_threshold(),
+            net_threshold: default_idle_net_threshold(),
+            minutes: default_idle_minutes(),
+            allowlist: Vec::new(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,6 +449,13 @@ pub struct LibvirtConfig {
     pub uri: String,
     pub socket_path: Option<String>,
     pub timeout: u64,
+    /// Fall back to qemu:///session when the system URI isn't accessible
+    #[serde(default = "default_session_fallback")]
+    pub session_fallback: bool,
+}
+
+fn default_session_fallback() -> bool {
+    true
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -29,6 +464,13 @@ pub struct StorageConfig {
     pub vm_images_path: PathBuf,
     pub iso_path: PathBuf,
     pub backup_path: PathBuf,
+    /// Days deleted VMs stay in `backup_path/trash` before being purged (0 = forever)
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u64,
+}
+
+fn default_trash_retention_days() -> u64 {
+    7
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -47,6 +489,9 @@ pub struct SystemConfig {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VmTemplate {
+    /// Template this one starts from; only the settings that differ need listing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
     pub memory: u64,
     pub cpus: u32,
     pub disk_size: u64,
@@ -55,6 +500,36 @@ pub struct VmTemplate {
     pub machine_type: String,
     pub boot_order: Vec<String>,
     pub features: Vec<String>,
+    /// Dedicated disk I/O threads; 0 leaves disk I/O on QEMU's main loop
+    #[serde(default = "default_iothreads")]
+    pub iothreads: u32,
+    /// `#cloud-config` user-data (default user, SSH keys, packages, ...) that VMs
+    /// made from this template get on a cloud-init seed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_data_path: Option<PathBuf>,
+    /// Network configuration put on the same seed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network_config: Option<PathBuf>,
+}
+
+impl VmTemplate {
+    /// The cloud-init seed VMs made from this template get, if it sets one up;
+    /// relative paths are taken from the vmtools config directory
+    pub fn cloud_init(&self) -> Result<Option<CloudInit>> {
+        if self.user_data_path.is_none() && self.network_config.is_none() {
+            return Ok(None);
+        }
+        let base = Config::config_dir()?;
+        Ok(Some(CloudInit {
+            user_data: self.user_data_path.as_ref().map(|path| base.join(path)),
+            network_config: self.network_config.as_ref().map(|path| base.join(path)),
+            ..Default::default()
+        }))
+    }
+}
+
+fn default_iothreads() -> u32 {
+    1
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -65,6 +540,158 @@ pub struct DefaultsConfig {
     pub disk_format: String,
     pub network: String,
     pub graphics: String,
+    /// CPU model for new KVM guests
+    #[serde(default = "default_cpu_model")]
+    pub cpu_model: CpuModel,
+}
+
+fn default_cpu_model() -> CpuModel {
+    CpuModel::HostPassthrough
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    /// URLs that receive a JSON POST for every dispatched event
+    #[serde(default)]
+    pub urls: Vec<String>,
+    /// Event kinds to forward (e.g. "crashed", "operation"); empty forwards everything
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// Per-request timeout in seconds
+    #[serde(default = "default_webhook_timeout")]
+    pub timeout: u64,
+}
+
+fn default_webhook_timeout() -> u64 {
+    10
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            urls: Vec::new(),
+            events: Vec::new(),
+            timeout: default_webhook_timeout(),
+        }
+    }
+}
+
+/// Desktop and email notices when long operations finish; webhooks get these
+/// too, as `operation` events
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationConfig {
+    /// Show a desktop notification through D-Bus (`notify-send`)
+    #[serde(default)]
+    pub desktop: bool,
+    /// Mail server to send through, e.g. `smtps://mail.example.com:465`; curl
+    /// reads the login from ~/.netrc. Unset sends no email.
+    #[serde(default)]
+    pub smtp_url: Option<String>,
+    #[serde(default)]
+    pub email_from: String,
+    #[serde(default)]
+    pub email_to: Vec<String>,
+    /// Operations worth a notice, by command name
+    #[serde(default = "default_notify_operations")]
+    pub operations: Vec<String>,
+    /// Operations that finish sooner than this many seconds stay quiet
+    #[serde(default = "default_notify_min_seconds")]
+    pub min_seconds: u64,
+}
+
+fn default_notify_operations() -> Vec<String> {
+    ["create", "clone", "import-disk", "image-customize", "disk-commit", "disk-pull", "apply", "suspend-all"]
+        .iter()
+        .map(|operation| operation.to_string())
+        .collect()
+}
+
+fn default_notify_min_seconds() -> u64 {
+    30
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self {
+            desktop: false,
+            smtp_url: None,
+            email_from: String::new(),
+            email_to: Vec::new(),
+            operations: default_notify_operations(),
+            min_seconds: default_notify_min_seconds(),
+        }
+    }
+}
+
+/// Merges each template that `extends` another over its parent, parents first, so
+/// the deserialized templates are complete
+fn resolve_extends(content: &str, templates: &mut toml::Table) -> std::result::Result<(), ConfigIssue> {
+    let mut resolved = toml::Table::new();
+    for name in templates.keys() {
+        resolve_template(content, name, templates, &mut resolved, &mut Vec::new())?;
+    }
+    *templates = resolved;
+    Ok(())
+}
+
+fn resolve_template(
+    content: &str,
+    name: &str,
+    raw: &toml::Table,
+    resolved: &mut toml::Table,
+    chain: &mut Vec<String>,
+) -> std::result::Result<toml::Table, ConfigIssue> {
+    if let Some(toml::Value::Table(template)) = resolved.get(name) {
+        return Ok(template.clone());
+    }
+    let key = format!("templates.{}", name);
+    let Some(toml::Value::Table(template)) = raw.get(name) else {
+        return Err(ConfigIssue::new(content, &key, "a template must be a table")
+            .with_suggestion(format!("write it as [templates.{}]", name)));
+    };
+
+    let key = format!("{}.extends", key);
+    let merged = match template.get("extends") {
+        None => template.clone(),
+        Some(toml::Value::String(parent)) => {
+            if !raw.contains_key(parent) {
+                let mut names: Vec<&String> = raw.keys().collect();
+                names.sort();
+                return Err(ConfigIssue::new(content, &key, format!("extends unknown template '{}'", parent))
+                    .with_suggestion(format!("templates: {}", names.iter().map(|n| n.as_str()).collect::<Vec<_>>().join(", "))));
+            }
+            chain.push(name.to_string());
+            if chain.contains(parent) {
+                return Err(ConfigIssue::new(content, &key, format!(
+                    "template inheritance cycle: {} → {}", chain.join(" → "), parent
+                )));
+            }
+            let mut merged = resolve_template(content, parent, raw, resolved, chain)?;
+            chain.pop();
+            merged.remove("extends");
+            merged.extend(template.clone());
+            merged
+        }
+        Some(_) => {
+            return Err(ConfigIssue::new(content, &key, "extends must be a template name")
+                .with_suggestion("put the name in double quotes"));
+        }
+    };
+    resolved.insert(name.to_string(), toml::Value::Table(merged.clone()));
+    Ok(merged)
+}
+
+/// The reverse of `resolve_extends`: drops each extending template's settings that
+/// equal its parent's, so saving keeps only the overrides
+fn strip_inherited(templates: &mut toml::Table) {
+    let complete = templates.clone();
+    for (_, template) in templates.iter_mut() {
+        let toml::Value::Table(template) = template else { continue };
+        let Some(toml::Value::Table(parent)) = template.get("extends")
+            .and_then(|parent| parent.as_str())
+            .and_then(|parent| complete.get(parent)) else { continue };
+        template.retain(|key, value| key == "extends" || parent.get(key) != Some(value));
+    }
 }
 
 impl Default for Config {
@@ -81,6 +708,10 @@ impl Default for Config {
             machine_type: "pc-q35-7.0".to_string(),
             boot_order: vec!["hd".to_string(), "cdrom".to_string()],
             features: vec!["acpi".to_string(), "apic".to_string(), "pae".to_string()],
+            iothreads: 1,
+            user_data_path: None,
+            network_config: None,
+            extends: None,
         });
         
         // Windows template
@@ -93,6 +724,10 @@ impl Default for Config {
             machine_type: "pc-q35-7.0".to_string(),
             boot_order: vec!["hd".to_string(), "cdrom".to_string()],
             features: vec!["acpi".to_string(), "apic".to_string(), "hyperv".to_string()],
+            iothreads: 2,
+            user_data_path: None,
+            network_config: None,
+            extends: None,
         });
         
         Self {
@@ -100,12 +735,14 @@ impl Default for Config {
                 uri: "qemu:///system".to_string(),
                 socket_path: Some("/var/run/libvirt/libvirt-sock".to_string()),
                 timeout: 30,
+                session_fallback: default_session_fallback(),
             },
             storage: StorageConfig {
                 default_pool: "default".to_string(),
                 vm_images_path: PathBuf::from("/var/lib/libvirt/images"),
                 iso_path: PathBuf::from("/var/lib/libvirt/images/iso"),
                 backup_path: PathBuf::from("/var/lib/libvirt/backup"),
+                trash_retention_days: default_trash_retention_days(),
             },
             network: NetworkConfig {
                 default_network: "default".to_string(),
@@ -125,21 +762,40 @@ impl Default for Config {
                 disk_format: "qcow2".to_string(),
                 network: "default".to_string(),
                 graphics: "spice".to_string(),
+                cpu_model: default_cpu_model(),
             },
+            webhooks: WebhookConfig::default(),
+            backend: BackendConfig::default(),
+            desktop: DesktopConfig::default(),
+            graphics: GraphicsConfig::default(),
+            console: ConsoleConfig::default(),
+            ceph: CephConfig::default(),
+            zfs: ZfsConfig::default(),
+            idle: IdleConfig::default(),
+            notifications: NotificationConfig::default(),
+            snapshot_policies: HashMap::new(),
+            network_templates: HashMap::new(),
+            sealed: HashMap::new(),
         }
     }
 }
 
 impl Config {
-    pub fn load() -> Result<Self> {
+    /// Reads the config file, creating a default one on first use, and decrypts
+    /// its sealed values with the secret helpers `runner` starts
+    pub async fn load(runner: &dyn CommandRunner) -> Result<Self> {
         let config_path = Self::config_path()?;
         
         if config_path.exists() {
             let content = fs::read_to_string(&config_path)
                 .map_err(|e| VmError::ConfigError(format!("Failed to read config file: {}", e)))?;
             
-            let config: Config = toml::from_str(&content)
-                .map_err(|e| VmError::ConfigError(format!("Failed to parse config: {}", e)))?;
+            let (mut config, issues) = Self::parse_with_issues(&content)
+                .map_err(|issue| VmError::ConfigError(format!("Failed to parse {}: {}", config_path.display(), issue)))?;
+            for issue in issues {
+                eprintln!("⚠️  {}: {}", config_path.display(), issue);
+            }
+            config.unseal(runner).await?;
             
             Ok(config)
         } else {
@@ -157,20 +813,163 @@ impl Config {
                 .map_err(|e| VmError::ConfigError(format!("Failed to create config directory: {}", e)))?;
         }
         
-        let content = toml::to_string_pretty(self)
-            .map_err(|e| VmError::ConfigError(format!("Failed to serialize config: {}", e)))?;
-        
-        fs::write(&config_path, content)
+        fs::write(&config_path, self.to_toml()?)
             .map_err(|e| VmError::ConfigError(format!("Failed to write config file: {}", e)))?;
         
         Ok(())
     }
     
-    fn config_path() -> Result<PathBuf> {
+    /// Reads a config file's contents, templates merged over the ones they extend
+    pub fn parse(content: &str) -> Result<Self> {
+        Self::parse_with_issues(content)
+            .map(|(config, _)| config)
+            .map_err(|issue| VmError::ConfigError(format!("Failed to parse config: {}", issue)))
+    }
+    
+    /// Like `parse`, also returning what doesn't stop the config from loading:
+    /// unknown keys and paths that can't work
+    pub fn parse_with_issues(content: &str) -> std::result::Result<(Self, Vec<ConfigIssue>), ConfigIssue> {
+        let raw: toml::Table = toml::from_str(content)
+            .map_err(|e| ConfigIssue::from_toml(content, &e))?;
+        let mut table = raw.clone();
+        if let Some(toml::Value::Table(templates)) = table.get_mut("templates") {
+            resolve_extends(content, templates)?;
+        }
+        let config: Config = toml::Value::Table(table).try_into()
+            .map_err(|e| ConfigIssue::from_toml(content, &e))?;
+        
+        let serialized = |config: &Config| toml::Table::try_from(config).unwrap_or_default();
+        let (loaded, defaults) = (serialized(&config), serialized(&Config::default()));
+        let mut issues = validation::unknown_keys(content, &raw, &[&loaded, &defaults]);
+        issues.extend(validation::path_issues(content, &config));
+        Ok((config, issues))
+    }
+    
+    /// Checks the config file at `path`, or the one `load` reads; a file that
+    /// doesn't parse has that as its only issue
+    pub fn validate_file(path: Option<&std::path::Path>) -> Result<(PathBuf, Vec<ConfigIssue>)> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => Self::config_path()?,
+        };
+        let content = fs::read_to_string(&path)
+            .map_err(|e| VmError::ConfigError(format!("Cannot read {}: {}", path.display(), e)))?;
+        let issues = match Self::parse_with_issues(&content) {
+            Ok((_, issues)) => issues,
+            Err(issue) => vec![issue],
+        };
+        Ok((path, issues))
+    }
+    
+    /// The config file contents for these settings, with encrypted values as stored
+    /// and extending templates reduced to their overrides
+    pub fn to_toml(&self) -> Result<String> {
+        let mut stored = self.clone();
+        let sealed = std::mem::take(&mut stored.sealed);
+        for key in SENSITIVE_KEYS {
+            for value in stored.sensitive_values(key)? {
+                if let Some(encrypted) = sealed.get(value.as_str()) {
+                    *value = encrypted.clone();
+                }
+            }
+        }
+        
+        let mut table = toml::Table::try_from(&stored)
+            .map_err(|e| VmError::ConfigError(format!("Failed to serialize config: {}", e)))?;
+        if let Some(toml::Value::Table(templates)) = table.get_mut("templates") {
+            strip_inherited(templates);
+        }
+        toml::to_string_pretty(&table)
+            .map_err(|e| VmError::ConfigError(format!("Failed to serialize config: {}", e)))
+    }
+    
+    /// `~/.config/vmtools/config.toml`
+    pub fn config_path() -> Result<PathBuf> {
+        Ok(Self::config_dir()?.join("config.toml"))
+    }
+    
+    /// `~/.config/vmtools`, where the config file lives
+    pub fn config_dir() -> Result<PathBuf> {
         let config_dir = dirs::config_dir()
             .ok_or_else(|| VmError::ConfigError("Cannot determine config directory".to_string()))?;
         
-        Ok(config_dir.join("vmtools").join("config.toml"))
+        Ok(config_dir.join("vmtools"))
+    }
+    
+    /// Switches to a per-user libvirt URI, moving storage paths that still point
+    /// at the system-wide defaults to `~/.local/share/vmtools`
+    pub fn use_session_uri(&mut self, uri: &str) {
+        self.libvirt.uri = uri.to_string();
+        
+        let defaults = Config::default();
+        let Some(base) = dirs::data_dir().map(|dir| dir.join("vmtools")) else {
+            return;
+        };
+        
+        if self.storage.vm_images_path == defaults.storage.vm_images_path {
+            self.storage.vm_images_path = base.join("images");
+        }
+        if self.storage.iso_path == defaults.storage.iso_path {
+            self.storage.iso_path = base.join("iso");
+        }
+        if self.storage.backup_path == defaults.storage.backup_path {
+            self.storage.backup_path = base.join("backup");
+        }
+    }
+    
+    /// The values of a sensitive setting, one per list entry
+    fn sensitive_values(&mut self, key: &str) -> Result<Vec<&mut String>> {
+        match key {
+            "libvirt.uri" => Ok(vec![&mut self.libvirt.uri]),
+            "webhooks.urls" => Ok(self.webhooks.urls.iter_mut().collect()),
+            "notifications.smtp_url" => Ok(self.notifications.smtp_url.iter_mut().collect()),
+            _ => Err(VmError::InvalidInput(format!(
+                "'{}' can't be encrypted; sensitive settings are {}", key, SENSITIVE_KEYS.join(", ")
+            ))),
+        }
+    }
+    
+    /// Decrypts sensitive values stored as `age:` or `keyring:` references
+    async fn unseal(&mut self, runner: &dyn CommandRunner) -> Result<()> {
+        let mut sealed = HashMap::new();
+        for key in SENSITIVE_KEYS {
+            for value in self.sensitive_values(key)? {
+                if vault::is_sealed(value) {
+                    let plain = vault::unseal(runner, value).await
+                        .map_err(|e| VmError::ConfigError(format!("Cannot decrypt {}: {}", key, e)))?;
+                    sealed.insert(plain.clone(), std::mem::replace(value, plain));
+                }
+            }
+        }
+        self.sealed = sealed;
+        Ok(())
+    }
+    
+    /// Encrypts the current values of a sensitive setting, with the local age identity
+    /// or into the keyring; returns how many values were newly encrypted
+    pub async fn seal_value(&mut self, key: &str, keyring: bool, runner: &dyn CommandRunner) -> Result<usize> {
+        let mut sealed = std::mem::take(&mut self.sealed);
+        let result = async {
+            let values = self.sensitive_values(key)?;
+            let entries = values.len();
+            let mut count = 0;
+            for (index, value) in values.into_iter().enumerate() {
+                if value.is_empty() || sealed.contains_key(value.as_str()) {
+                    continue;
+                }
+                let encrypted = if keyring {
+                    let entry = if entries > 1 { format!("{}.{}", key, index) } else { key.to_string() };
+                    vault::seal_keyring(runner, &entry, value).await?
+                } else {
+                    vault::seal_age(runner, value).await?
+                };
+                sealed.insert(value.clone(), encrypted);
+                count += 1;
+            }
+            Ok(count)
+        }.await;
+        self.sealed = sealed;
+        result
     }
     
     pub fn get_template(&self, name: &str) -> Option<&VmTemplate> {
@@ -180,11 +979,19 @@ impl Config {
     pub fn set_value(&mut self, key: &str, value: &str) -> Result<()> {
         match key {
             "libvirt.uri" => self.libvirt.uri = value.to_string(),
+            "libvirt.session_fallback" => {
+                self.libvirt.session_fallback = value.parse()
+                    .map_err(|_| VmError::InvalidInput(format!("Invalid boolean value: {}", value)))?;
+            }
             "libvirt.timeout" => {
                 self.libvirt.timeout = value.parse()
                     .map_err(|_| VmError::InvalidInput(format!("Invalid timeout value: {}", value)))?;
             }
             "storage.default_pool" => self.storage.default_pool = value.to_string(),
+            "storage.trash_retention_days" => {
+                self.storage.trash_retention_days = value.parse()
+                    .map_err(|_| VmError::InvalidInput(format!("Invalid retention value: {}", value)))?;
+            }
             "network.default_network" => self.network.default_network = value.to_string(),
             "defaults.memory" => {
                 self.defaults.memory = value.parse()
@@ -194,20 +1001,261 @@ impl Config {
                 self.defaults.cpus = value.parse()
                     .map_err(|_| VmError::InvalidInput(format!("Invalid CPU count: {}", value)))?;
             }
-            _ => return Err(VmError::InvalidInput(format!("Unknown config key: {}", key))),
+            "webhooks.urls" => {
+                self.webhooks.urls = value.split(',')
+                    .map(|url| url.trim().to_string())
+                    .filter(|url| !url.is_empty())
+                    .collect();
+            }
+            "backend.type" => self.backend.kind = value.parse()?,
+            "backend.qemu_state_dir" => self.backend.qemu_state_dir = PathBuf::from(value),
+            "desktop.usb_redirect" => {
+                self.desktop.usb_redirect = value.parse()
+                    .map_err(|_| VmError::InvalidInput(format!("Invalid channel count: {}", value)))?;
+            }
+            "desktop.audio" => self.desktop.audio = value.parse()?,
+            "defaults.cpu_model" => self.defaults.cpu_model = value.parse()?,
+            "defaults.graphics" => match value {
+                "spice" | "vnc" => self.defaults.graphics = value.to_string(),
+                _ => return Err(VmError::InvalidInput(format!("Invalid graphics type: {} (expected spice or vnc)", value))),
+            },
+            "graphics.listen" => {
+                value.parse::<std::net::IpAddr>()
+                    .map_err(|_| VmError::InvalidInput(format!("Invalid listen address: {}", value)))?;
+                self.graphics.listen = value.to_string();
+            }
+            "graphics.tls" => {
+                self.graphics.tls = value.parse()
+                    .map_err(|_| VmError::InvalidInput(format!("Invalid boolean value: {}", value)))?;
+            }
+            "graphics.tls_port" => {
+                self.graphics.tls_port = match value {
+                    "" | "auto" => None,
+                    port => Some(port.parse()
+                        .map_err(|_| VmError::InvalidInput(format!("Invalid port: {}", value)))?),
+                };
+            }
+            "graphics.x509_dir" => {
+                self.graphics.x509_dir = (!value.is_empty()).then(|| PathBuf::from(value));
+            }
+            "console.escape" => {
+                ConsoleConfig::escape_byte(value)?;
+                self.console.escape = value.to_string();
+            }
+            "ceph.monitors" => {
+                if value.chars().any(|c| c.is_whitespace() && c != ' ' || matches!(c, '<' | '>' | '&' | '\'' | '"')) {
+                    return Err(VmError::InvalidInput(format!("Invalid Ceph monitors: {}", value)));
+                }
+                self.ceph.monitors = value.split(',')
+                    .map(|monitor| monitor.trim().to_string())
+                    .filter(|monitor| !monitor.is_empty())
+                    .collect();
+            }
+            "ceph.user" => {
+                if !value.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) {
+                    return Err(VmError::InvalidInput(format!("Invalid Ceph user: {}", value)));
+                }
+                self.ceph.user = value.trim_start_matches("client.").to_string();
+            }
+            "zfs.volblocksize" => {
+                let valid = value.strip_suffix(['K', 'k'])
+                    .and_then(|kib| kib.parse::<u32>().ok())
+                    .is_some_and(|kib| kib.is_power_of_two() && (1..=128).contains(&kib));
+                if !valid {
+                    return Err(VmError::InvalidInput(format!(
+                        "Invalid volblocksize '{}' (a power of two from 1K to 128K)", value
+                    )));
+                }
+                self.zfs.volblocksize = value.to_uppercase();
+            }
+            "zfs.compression" => {
+                if value.is_empty() || !value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                    return Err(VmError::InvalidInput(format!("Invalid ZFS compression: {}", value)));
+                }
+                self.zfs.compression = value.to_string();
+            }
+            "idle.enabled" => {
+                self.idle.enabled = value.parse()
+                    .map_err(|_| VmError::InvalidInput(format!("Invalid boolean value: {}", value)))?;
+            }
+            "idle.cpu_threshold" => {
+                self.idle.cpu_threshold = value.parse().ok()
+                    .filter(|percent: &f64| *percent >= 0.0)
+                    .ok_or_else(|| VmError::InvalidInput(format!("Invalid CPU threshold: {}", value)))?;
+            }
+            "idle.net_threshold" => {
+                self.idle.net_threshold = value.parse()
+                    .map_err(|_| VmError::InvalidInput(format!("Invalid network threshold: {}", value)))?;
+            }
+            "idle.minutes" => {
+                self.idle.minutes = value.parse().ok()
+                    .filter(|minutes| *minutes > 0)
+                    .ok_or_else(|| VmError::InvalidInput(format!("Invalid idle time: {}", value)))?;
+            }
+            "idle.allowlist" => {
+                self.idle.allowlist = value.split(',')
+                    .map(|name| name.trim().to_string())
+                    .filter(|name| !name.is_empty())
+                    .collect();
+            }
+            "notifications.desktop" => {
+                self.notifications.desktop = value.parse()
+                    .map_err(|_| VmError::InvalidInput(format!("Invalid boolean value: {}", value)))?;
+            }
+            "notifications.smtp_url" => {
+                if !(value.is_empty() || value.starts_with("smtp://") || value.starts_with("smtps://")) {
+                    return Err(VmError::InvalidInput(format!("Invalid SMTP URL (smtp:// or smtps://): {}", value)));
+                }
+                self.notifications.smtp_url = (!value.is_empty()).then(|| value.to_string());
+            }
+            "notifications.email_from" => {
+                validate_email(value)?;
+                self.notifications.email_from = value.to_string();
+            }
+            "notifications.email_to" => {
+                let addresses: Vec<String> = value.split(',')
+                    .map(|address| address.trim().to_string())
+                    .filter(|address| !address.is_empty())
+                    .collect();
+                for address in &addresses {
+                    validate_email(address)?;
+                }
+                self.notifications.email_to = addresses;
+            }
+            "notifications.operations" => {
+                self.notifications.operations = value.split(',')
+                    .map(|operation| operation.trim().to_string())
+                    .filter(|operation| !operation.is_empty())
+                    .collect();
+            }
+            "notifications.min_seconds" => {
+                self.notifications.min_seconds = value.parse()
+                    .map_err(|_| VmError::InvalidInput(format!("Invalid duration: {}", value)))?;
+            }
+            _ => self.set_path(key, value)?,
+        }
+        Ok(())
+    }
+    
+    /// Sets any other setting by its dotted path in the config file, the value read
+    /// as the type the setting already has (lists comma-separated)
+    fn set_path(&mut self, key: &str, value: &str) -> Result<()> {
+        let mut table = toml::Table::try_from(&*self)
+            .map_err(|e| VmError::ConfigError(format!("Failed to serialize config: {}", e)))?;
+        let (parent, leaf) = key.rsplit_once('.').unwrap_or(("", key));
+        let mut node = &mut table;
+        for part in parent.split('.').filter(|part| !part.is_empty()) {
+            node = match node.get_mut(part) {
+                Some(toml::Value::Table(child)) => child,
+                _ => return Err(self.unknown_key(key)),
+            };
+        }
+        let new = match node.get(leaf) {
+            Some(toml::Value::Table(child)) => return Err(table_error(key, child)),
+            Some(current) => typed_value(key, current, value)?,
+            // An optional setting that is unset: take the value as TOML, else as a string
+            None => toml::from_str::<toml::Table>(&format!("value = {}", value)).ok()
+                .and_then(|mut parsed| parsed.remove("value"))
+                .unwrap_or_else(|| toml::Value::String(value.to_string())),
+        };
+        node.insert(leaf.to_string(), new);
+        
+        let mut updated: Config = toml::Value::Table(table).try_into().map_err(|e| {
+            let issue = ConfigIssue::from_toml("", &e);
+            VmError::InvalidInput(match issue.suggestion {
+                Some(hint) => format!("Invalid value for {}: {} ({})", key, issue.message, hint),
+                None => format!("Invalid value for {}: {}", key, issue.message),
+            })
+        })?;
+        // Keys no setting reads don't survive the round trip
+        if updated.get_path(key).is_err() {
+            return Err(self.unknown_key(key));
         }
+        updated.sealed = std::mem::take(&mut self.sealed);
+        *self = updated;
         Ok(())
     }
     
     pub fn get_value(&self, key: &str) -> Result<String> {
         match key {
             "libvirt.uri" => Ok(self.libvirt.uri.clone()),
+            "libvirt.session_fallback" => Ok(self.libvirt.session_fallback.to_string()),
             "libvirt.timeout" => Ok(self.libvirt.timeout.to_string()),
             "storage.default_pool" => Ok(self.storage.default_pool.clone()),
+            "storage.trash_retention_days" => Ok(self.storage.trash_retention_days.to_string()),
             "network.default_network" => Ok(self.network.default_network.clone()),
             "defaults.memory" => Ok(self.defaults.memory.to_string()),
             "defaults.cpus" => Ok(self.defaults.cpus.to_string()),
-            _ => Err(VmError::InvalidInput(format!("Unknown config key: {}", key))),
+            "webhooks.urls" => Ok(self.webhooks.urls.join(",")),
+            "backend.type" => Ok(self.backend.kind.to_string()),
+            "backend.qemu_state_dir" => Ok(self.backend.qemu_state_dir.display().to_string()),
+            "desktop.usb_redirect" => Ok(self.desktop.usb_redirect.to_string()),
+            "desktop.audio" => Ok(self.desktop.audio.to_string()),
+            "defaults.graphics" => Ok(self.defaults.graphics.clone()),
+            "defaults.cpu_model" => Ok(self.defaults.cpu_model.to_string()),
+            "graphics.listen" => Ok(self.graphics.listen.clone()),
+            "graphics.tls" => Ok(self.graphics.tls.to_string()),
+            "graphics.tls_port" => Ok(self.graphics.tls_port.map(|p| p.to_string()).unwrap_or_else(|| "auto".to_string())),
+            "ceph.monitors" => Ok(self.ceph.monitors.join(",")),
+            "ceph.user" => Ok(self.ceph.user.clone()),
+            "zfs.volblocksize" => Ok(self.zfs.volblocksize.clone()),
+            "zfs.compression" => Ok(self.zfs.compression.clone()),
+            "graphics.x509_dir" => Ok(self.graphics.x509_dir.as_ref().map(|d| d.display().to_string()).unwrap_or_default()),
+            "console.escape" => Ok(self.console.escape.clone()),
+            "idle.enabled" => Ok(self.idle.enabled.to_string()),
+            "idle.cpu_threshold" => Ok(self.idle.cpu_threshold.to_string()),
+            "idle.net_threshold" => Ok(self.idle.net_threshold.to_string()),
+            "idle.minutes" => Ok(self.idle.minutes.to_string()),
+            "idle.allowlist" => Ok(self.idle.allowlist.join(",")),
+            "notifications.desktop" => Ok(self.notifications.desktop.to_string()),
+            "notifications.smtp_url" => Ok(self.notifications.smtp_url.clone().unwrap_or_default()),
+            "notifications.email_from" => Ok(self.notifications.email_from.clone()),
+            "notifications.email_to" => Ok(self.notifications.email_to.join(",")),
+            "notifications.operations" => Ok(self.notifications.operations.join(",")),
+            "notifications.min_seconds" => Ok(self.notifications.min_seconds.to_string()),
+            _ => self.get_path(key),
+        }
+    }
+    
+    fn get_path(&self, key: &str) -> Result<String> {
+        let table = toml::Table::try_from(self)
+            .map_err(|e| VmError::ConfigError(format!("Failed to serialize config: {}", e)))?;
+        let (parent, leaf) = key.rsplit_once('.').unwrap_or(("", key));
+        let mut node = &table;
+        for part in parent.split('.').filter(|part| !part.is_empty()) {
+            node = match node.get(part) {
+                Some(toml::Value::Table(child)) => child,
+                _ => return Err(self.unknown_key(key)),
+            };
+        }
+        match node.get(leaf) {
+            Some(toml::Value::Table(child)) => Err(table_error(key, child)),
+            Some(value) => Ok(display_value(value)),
+            None => Err(self.unknown_key(key)),
+        }
+    }
+    
+    /// Every setting as a dotted key and its value, sorted by key; encrypted
+    /// values are shown as such rather than decrypted
+    pub fn entries(&self) -> Result<Vec<(String, String)>> {
+        let table = toml::Table::try_from(self)
+            .map_err(|e| VmError::ConfigError(format!("Failed to serialize config: {}", e)))?;
+        let mut entries = Vec::new();
+        flatten("", &table, &mut entries);
+        for (key, value) in &mut entries {
+            if SENSITIVE_KEYS.contains(&key.as_str()) && self.sealed.keys().any(|plain| value.contains(plain.as_str())) {
+                *value = "(encrypted)".to_string();
+            }
+        }
+        entries.sort();
+        Ok(entries)
+    }
+    
+    fn unknown_key(&self, key: &str) -> VmError {
+        let entries = self.entries().unwrap_or_default();
+        match validation::closest(key, entries.iter().map(|(known, _)| known.as_str())) {
+            Some(known) => VmError::InvalidInput(format!("Unknown config key: {} (did you mean {}?)", key, known)),
+            None => VmError::InvalidInput(format!("Unknown config key: {}; see vmtools config list", key)),
         }
     }
 }
@@ -216,6 +1264,7 @@ impl fmt::Display for Config {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "VM Tools Configuration:")?;
         writeln!(f, "=======================")?;
+        writeln!(f, "Backend: {}", self.backend.kind)?;
         writeln!(f, "Libvirt URI: {}", self.libvirt.uri)?;
         writeln!(f, "Timeout: {}s", self.libvirt.timeout)?;
         writeln!(f, "Default Pool: {}", self.storage.default_pool)?;
@@ -224,11 +1273,77 @@ impl fmt::Display for Config {
         writeln!(f, "Default Network: {}", self.network.default_network)?;
         writeln!(f, "Default Memory: {}MB", self.defaults.memory)?;
         writeln!(f, "Default CPUs: {}", self.defaults.cpus)?;
+        writeln!(f, "Default CPU Model: {}", self.defaults.cpu_model)?;
         writeln!(f, "Default Disk: {}GB", self.defaults.disk_size)?;
+        writeln!(f, "Graphics: {} on {}{}", self.defaults.graphics, self.graphics.listen,
+                 if self.graphics.tls { " (TLS)" } else { "" })?;
+        writeln!(f, "USB Redirection: {} channel(s)", self.desktop.usb_redirect)?;
+        writeln!(f, "Audio: {}", self.desktop.audio)?;
+        writeln!(f, "Webhooks: {} configured", self.webhooks.urls.len())?;
+        if !self.sealed.is_empty() {
+            writeln!(f, "Encrypted Values: {}", self.sealed.len())?;
+        }
         writeln!(f, "\nAvailable Templates:")?;
         for (name, template) in &self.templates {
             writeln!(f, "  - {}: {}MB, {} CPUs, {}GB disk", name, template.memory, template.cpus, template.disk_size)?;
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// `value` parsed as the same type as `current`, the setting's present value
+fn typed_value(key: &str, current: &toml::Value, value: &str) -> Result<toml::Value> {
+    let invalid = |expected: &str| VmError::InvalidInput(format!("Invalid value for {}: {} (expected {})", key, value, expected));
+    Ok(match current {
+        toml::Value::String(_) => toml::Value::String(value.to_string()),
+        toml::Value::Integer(_) => toml::Value::Integer(value.parse().map_err(|_| invalid("a whole number"))?),
+        toml::Value::Float(_) => toml::Value::Float(value.parse().map_err(|_| invalid("a number"))?),
+        toml::Value::Boolean(_) => toml::Value::Boolean(value.parse().map_err(|_| invalid("true or false"))?),
+        toml::Value::Array(items) => {
+            let element = items.first().cloned().unwrap_or_else(|| toml::Value::String(String::new()));
+            toml::Value::Array(value.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| typed_value(key, &element, item))
+                .collect::<Result<_>>()?)
+        }
+        _ => return Err(invalid("a value vmtools config --set can write")),
+    })
+}
+
+/// How `config --get` shows a value: strings bare and lists comma-separated
+fn display_value(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(text) => text.clone(),
+        toml::Value::Array(items) if items.iter().all(|item| !item.is_table() && !item.is_array()) => {
+            items.iter().map(display_value).collect::<Vec<_>>().join(",")
+        }
+        other => other.to_string(),
+    }
+}
+
+fn flatten(prefix: &str, table: &toml::Table, entries: &mut Vec<(String, String)>) {
+    for (name, value) in table {
+        let key = if prefix.is_empty() { name.clone() } else { format!("{}.{}", prefix, name) };
+        match value {
+            toml::Value::Table(child) => flatten(&key, child, entries),
+            value => entries.push((key, display_value(value))),
+        }
+    }
+}
+
+fn table_error(key: &str, table: &toml::Table) -> VmError {
+    VmError::InvalidInput(format!(
+        "{} is a table; pick one of its keys: {}", key, table.keys().map(String::as_str).collect::<Vec<_>>().join(", ")
+    ))
+}
+
+/// An address for email headers: one `@`, no whitespace or header separators
+fn validate_email(address: &str) -> Result<()> {
+    let valid = address.split_once('@').is_some_and(|(user, domain)| !user.is_empty() && domain.contains('.'))
+        && !address.chars().any(|c| c.is_whitespace() || c.is_control() || matches!(c, '<' | '>' | ',' | ';'));
+    if !valid {
+        return Err(VmError::InvalidInput(format!("Invalid email address: {}", address)));
+    }
+    Ok(())
+}
@@ -0,0 +1,194 @@
+use std::net::Ipv4Addr;
+
+use crate::{
+    domain,
+    network::Ipv4Subnet,
+    privilege::Privileges,
+    runner::Invocation,
+};
+
+/// How one step of a network diagnosis went
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    Pass,
+    Fail,
+    /// Couldn't be checked, e.g. without root or a guest agent
+    Skip,
+}
+
+/// One hop of the path a guest's packets take, checked
+#[derive(Debug, Clone)]
+pub struct DiagnosisStep {
+    /// What was checked, e.g. "Guest has a DHCP lease"
+    pub check: String,
+    pub verdict: Verdict,
+    /// What was found: an address, a command's complaint, why it was skipped
+    pub detail: String,
+    /// What to do about a failure
+    pub hint: Option<String>,
+}
+
+impl DiagnosisStep {
+    pub fn pass(check: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self { check: check.into(), verdict: Verdict::Pass, detail: detail.into(), hint: None }
+    }
+
+    pub fn fail(check: impl Into<String>, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self { check: check.into(), verdict: Verdict::Fail, detail: detail.into(), hint: Some(hint.into()) }
+    }
+
+    pub fn skip(check: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self { check: check.into(), verdict: Verdict::Skip, detail: detail.into(), hint: None }
+    }
+}
+
+/// The step packets die at: the first one that failed
+pub fn first_failure(steps: &[DiagnosisStep]) -> Option<&DiagnosisStep> {
+    steps.iter().find(|step| step.verdict == Verdict::Fail)
+}
+
+/// The host's IPv4 address on a libvirt network and its subnet, from `net-dumpxml`
+pub fn network_ipv4(xml: &str) -> Option<(Ipv4Addr, Ipv4Subnet)> {
+    xml.split("<ip ").skip(1)
+        .map(|block| &block[..block.find('>').unwrap_or(block.len())])
+        .filter(|tag| !tag.contains("family='ipv6'"))
+        .find_map(|tag| {
+            let address: Ipv4Addr = domain::attribute(tag, "address='")?.parse().ok()?;
+            let prefix = match domain::attribute(tag, "prefix='") {
+                Some(prefix) => prefix.parse().ok()?,
+                None => u32::from(domain::attribute(tag, "netmask='")?.parse::<Ipv4Addr>().ok()?).count_ones() as u8,
+            };
+            Some((address, Ipv4Subnet::containing(address, prefix)))
+        })
+}
+
+/// How a libvirt network reaches the outside (`nat`, `route`, `bridge`...);
+/// `None` for an isolated network
+pub fn forward_mode(xml: &str) -> Option<String> {
+    let forward = &xml[xml.find("<forward")?..];
+    let tag = &forward[..forward.find('>').unwrap_or(forward.len())];
+    Some(domain::attribute(tag, "mode='").unwrap_or_else(|| "nat".to_string()))
+}
+
+/// The IPv4 address leased to `mac`, from `net-dhcp-leases`
+pub fn lease_address(output: &str, mac: &str) -> Option<String> {
+    output.lines()
+        .filter(|line| line.to_lowercase().contains(&mac.to_lowercase()))
+        .find_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let position = fields.iter().position(|field| *field == "ipv4")?;
+            let address = fields.get(position + 1)?;
+            Some(address.split('/').next().unwrap_or(address).to_string())
+        })
+}
+
+/// Whether `rules` (`iptables-save -t nat` or `nft list ruleset`) masquerade
+/// traffic leaving `subnet`
+pub fn masquerades(rules: &str, subnet: &Ipv4Subnet) -> bool {
+    let subnet = subnet.to_string();
+    rules.lines().any(|line| line.contains(&subnet) && line.to_lowercase().contains("masquerade"))
+}
+
+/// Whether `ip -o link show` reports the link administratively up
+pub fn link_is_up(output: &str) -> bool {
+    output.split_once('<')
+        .and_then(|(_, rest)| rest.split_once('>'))
+        .is_some_and(|(flags, _)| flags.split(',').any(|flag| flag == "UP"))
+}
+
+/// The host's NAT rules, or `None` when neither iptables nor nftables could be
+/// read, which takes root
+pub async fn nat_rules(privileges: &Privileges) -> Option<String> {
+    host_rules(privileges, "nat").await
+}
+
+/// The host's `table` rules from iptables, followed by the whole nftables
+/// ruleset, or `None` when neither could be read
+pub async fn host_rules(privileges: &Privileges, table: &str) -> Option<String> {
+    let mut rules = String::new();
+    let mut read = false;
+    for command in [Invocation::new("iptables-save").args(["-t", table]), Invocation::new("nft").args(["list", "ruleset"])] {
+        if let Ok(output) = privileges.runner().output(&command).await {
+            if output.status.success() {
+                rules.push_str(&String::from_utf8_lossy(&output.stdout));
+                read = true;
+            }
+        }
+    }
+    read.then_some(rules)
+}
+
+/// Checks the host's half of the path out of a guest on libvirt network `network`,
+/// whose XML is `xml`, for the interface with `mac`: the bridge, the gateway
+/// address, the guest's lease, forwarding and NAT
+pub async fn host_steps(privileges: &Privileges, network: &str, xml: &str, bridge: &str, mac: &str) -> Vec<DiagnosisStep> {
+    let mut steps = Vec::new();
+    let run = |invocation: Invocation| async move {
+        privileges.runner().output(&invocation).await.ok()
+    };
+
+    let link = run(Invocation::new("ip").args(["-o", "link", "show", "dev", bridge])).await;
+    match link {
+        Some(output) if output.status.success() && link_is_up(&String::from_utf8_lossy(&output.stdout)) => {
+            steps.push(DiagnosisStep::pass(format!("Bridge {} is up", bridge), ""));
+        }
+        Some(output) if output.status.success() => steps.push(DiagnosisStep::fail(
+            format!("Bridge {} is up", bridge), "the link is down",
+            format!("sudo ip link set {} up, or restart the network: virsh net-destroy {} && virsh net-start {}", bridge, network, network),
+        )),
+        _ => steps.push(DiagnosisStep::fail(
+            format!("Bridge {} is up", bridge), "no such device",
+            format!("Restart the network: virsh net-destroy {} && virsh net-start {}", network, network),
+        )),
+    }
+
+    let Some((gateway, subnet)) = network_ipv4(xml) else {
+        steps.push(DiagnosisStep::skip("Host reaches the gateway", format!("network '{}' has no IPv4 address", network)));
+        return steps;
+    };
+    let ping = run(Invocation::new("ping").args(["-c", "1", "-W", "1", &gateway.to_string()])).await;
+    steps.push(match ping {
+        Some(output) if output.status.success() => DiagnosisStep::pass("Host reaches the gateway", gateway.to_string()),
+        _ => DiagnosisStep::fail(
+            "Host reaches the gateway", format!("no reply from {}", gateway),
+            format!("The bridge lost its address; restart the network: virsh net-destroy {} && virsh net-start {}", network, network),
+        ),
+    });
+
+    let leases = match privileges.virsh_read(&["net-dhcp-leases", network, "--mac", mac]) {
+        Ok(invocation) => run(invocation).await,
+        Err(_) => None,
+    };
+    steps.push(match leases.map(|output| lease_address(&String::from_utf8_lossy(&output.stdout), mac)) {
+        Some(Some(address)) => DiagnosisStep::pass("Guest has a DHCP lease", address),
+        _ => DiagnosisStep::fail(
+            "Guest has a DHCP lease", format!("no lease for {}", mac),
+            "Check that the guest runs a DHCP client on this interface and that dnsmasq runs for the network",
+        ),
+    });
+
+    let Some(mode) = forward_mode(xml) else {
+        steps.push(DiagnosisStep::skip("Host forwards and masquerades", "the network is isolated, guests only reach the host"));
+        return steps;
+    };
+    let forwarding = run(Invocation::new("sysctl").args(["-n", "net.ipv4.ip_forward"])).await;
+    steps.push(match forwarding.map(|output| String::from_utf8_lossy(&output.stdout).trim() == "1") {
+        Some(true) => DiagnosisStep::pass("Host forwards IPv4", "net.ipv4.ip_forward = 1"),
+        _ => DiagnosisStep::fail(
+            "Host forwards IPv4", "net.ipv4.ip_forward is off",
+            "sudo sysctl -w net.ipv4.ip_forward=1 (libvirt turns it on when a NAT network starts)",
+        ),
+    });
+
+    if mode == "nat" {
+        steps.push(match nat_rules(privileges).await {
+            Some(rules) if masquerades(&rules, &subnet) => DiagnosisStep::pass(format!("NAT masquerades {}", subnet), ""),
+            Some(_) => DiagnosisStep::fail(
+                format!("NAT masquerades {}", subnet), "no MASQUERADE rule for the subnet",
+                "A firewall reload dropped libvirt's rules; 'sudo vmtools fix-network <vm> --auto' restores them without restarting the network",
+            ),
+            None => DiagnosisStep::skip(format!("NAT masquerades {}", subnet), "reading the NAT rules needs root; run with sudo"),
+        });
+    }
+    steps
+}
@@ -0,0 +1,1102 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use crate::{
+    config::VmTemplate,
+    error::{VmError, Result},
+    utils,
+};
+
+/// A disk device in a domain definition
+#[derive(Debug, Clone, PartialEq)]
+pub struct DomainDisk {
+    /// Target device name, e.g. `vda`
+    pub target: String,
+    /// `virtio`, `scsi`, `sata`, ...
+    pub bus: String,
+    pub path: String,
+    pub format: String,
+    /// I/O thread serving a virtio disk, if pinned to one
+    pub iothread: Option<u32>,
+}
+
+/// A CD-ROM drive, which may be empty
+#[derive(Debug, Clone, PartialEq)]
+pub struct CdromDrive {
+    /// Target device name, e.g. `sda`
+    pub target: String,
+    pub media: Option<String>,
+}
+
+/// Devices libvirt adds or numbers on its own, left out of comparisons
+const IMPLICIT_DEVICES: &[&str] = &["controller", "input", "memballoon", "emulator"];
+
+/// One setting that differs between two definitions
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpecDifference {
+    pub field: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// Settings of the virtio memory balloon
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemBalloon {
+    /// Give memory back to the guest instead of letting it hit the OOM killer
+    pub autodeflate: bool,
+    /// Return pages the guest frees to the host as they are freed
+    pub free_page_reporting: bool,
+}
+
+/// Average bandwidth limits of one interface in KiB/s, 0 meaning unlimited
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Bandwidth {
+    pub inbound: u64,
+    pub outbound: u64,
+}
+
+/// Scheduler period for CPU quotas, in microseconds (the kernel's default)
+pub const CPU_PERIOD: u64 = 100_000;
+
+/// cgroup CPU weight and cap of a domain
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CpuTune {
+    /// Relative weight against other VMs; 0 leaves libvirt's default of 1024
+    pub shares: u64,
+    /// Share of each vCPU's time the guest may use, in percent; 0 means uncapped
+    pub quota: u32,
+}
+
+/// Namespace of the entry vmtools keeps in a domain's `<metadata>`
+pub const METADATA_NAMESPACE: &str = "https://github.com/FabulaNox/VM-Tools/xmlns/vm/1.0";
+
+/// What vmtools records about a VM in its domain definition, so the
+/// information travels with the domain. Template names and tags are
+/// written as-is; check them with `utils::validate_label` first.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VmMetadata {
+    /// Unix time the VM was created, or first seen when adopted
+    pub created_at: Option<u64>,
+    /// Unix time vmtools last started the VM
+    pub last_started: Option<u64>,
+    /// Template the VM was created from or matched to
+    pub template: Option<String>,
+    pub tags: Vec<String>,
+    /// The domain was defined outside vmtools and adopted later
+    pub adopted: bool,
+    /// Health check specs (`ping`, `tcp:22`, ...), see `health::HealthCheck`
+    pub health_checks: Vec<String>,
+    /// VMs that have to be up and healthy before this one starts in a group
+    pub depends_on: Vec<String>,
+    /// Boots from the installer ISO until vmtools first starts it; after that the
+    /// disk goes first from the next cold boot on
+    pub iso_first_boot: bool,
+    /// The guest OS as an osinfo short ID (`ubuntu24.04`, `win11`), when known
+    pub os_variant: Option<String>,
+    /// Host ports forwarded to the guest (`443/tcp`, `8443:443/tcp`), see `firewall::PortForward`
+    pub port_forwards: Vec<String>,
+}
+
+impl VmMetadata {
+    /// Reads the vmtools entry of `xml`; `None` when the domain has none
+    pub fn parse(xml: &str) -> Option<Self> {
+        let body = element_text(xml, "vmtools:vm")?;
+        Some(Self {
+            created_at: element_text(&body, "vmtools:created_at").and_then(|t| t.parse().ok()),
+            last_started: element_text(&body, "vmtools:last_started").and_then(|t| t.parse().ok()),
+            template: element_text(&body, "vmtools:template"),
+            tags: element_texts(&body, "vmtools:tag"),
+            adopted: body.contains("<vmtools:adopted/>"),
+            health_checks: element_texts(&body, "vmtools:health").iter()
+                .map(|spec| spec.replace("&amp;", "&"))
+                .collect(),
+            depends_on: element_texts(&body, "vmtools:depends_on"),
+            iso_first_boot: body.contains("<vmtools:iso_first_boot/>"),
+            os_variant: element_text(&body, "vmtools:os_variant"),
+            port_forwards: element_texts(&body, "vmtools:forward"),
+        })
+    }
+
+    fn to_xml(&self) -> String {
+        let mut lines = vec![format!("<vmtools:vm xmlns:vmtools='{}'>", METADATA_NAMESPACE)];
+        if let Some(created_at) = self.created_at {
+            lines.push(format!("  <vmtools:created_at>{}</vmtools:created_at>", created_at));
+        }
+        if let Some(last_started) = self.last_started {
+            lines.push(format!("  <vmtools:last_started>{}</vmtools:last_started>", last_started));
+        }
+        if let Some(template) = &self.template {
+            lines.push(format!("  <vmtools:template>{}</vmtools:template>", template));
+        }
+        for tag in &self.tags {
+            lines.push(format!("  <vmtools:tag>{}</vmtools:tag>", tag));
+        }
+        if self.adopted {
+            lines.push("  <vmtools:adopted/>".to_string());
+        }
+        for check in &self.health_checks {
+            lines.push(format!("  <vmtools:health>{}</vmtools:health>", check.replace('&', "&amp;")));
+        }
+        for dependency in &self.depends_on {
+            lines.push(format!("  <vmtools:depends_on>{}</vmtools:depends_on>", dependency));
+        }
+        if self.iso_first_boot {
+            lines.push("  <vmtools:iso_first_boot/>".to_string());
+        }
+        if let Some(os_variant) = &self.os_variant {
+            lines.push(format!("  <vmtools:os_variant>{}</vmtools:os_variant>", os_variant));
+        }
+        for forward in &self.port_forwards {
+            lines.push(format!("  <vmtools:forward>{}</vmtools:forward>", forward));
+        }
+        lines.push("</vmtools:vm>".to_string());
+        lines.join("\n    ")
+    }
+}
+
+/// Returns `xml` with its vmtools `<metadata>` entry replaced by `metadata`,
+/// leaving entries of other applications alone
+pub fn set_metadata(xml: &str, metadata: &VmMetadata) -> Result<String> {
+    let mut xml = xml.to_string();
+    if let Some(start) = xml.find("<vmtools:vm") {
+        let close = "</vmtools:vm>";
+        let end = start + xml[start..].find(close)
+            .ok_or_else(|| VmError::InvalidInput("Malformed vmtools metadata".to_string()))? + close.len();
+        // Take the indentation before the entry with it
+        let start = xml[..start].rfind('\n').unwrap_or(start);
+        xml.replace_range(start..end, "");
+    }
+
+    let entry = metadata.to_xml();
+    if let Some(end) = xml.find("</metadata>") {
+        let start = xml[..end].rfind('\n').map_or(end, |pos| pos + 1);
+        xml.insert_str(start, &format!("    {}\n", entry));
+        return Ok(xml);
+    }
+    if let Some(pos) = xml.find("<metadata/>") {
+        xml.replace_range(pos..pos + "<metadata/>".len(), &format!("<metadata>\n    {}\n  </metadata>", entry));
+        return Ok(xml);
+    }
+
+    // libvirt keeps <metadata> right after the identity elements
+    let anchor = ["</description>", "</title>", "</uuid>", "</name>"].iter()
+        .find_map(|tag| xml.find(tag).map(|pos| pos + tag.len()))
+        .ok_or_else(|| VmError::InvalidInput("Domain XML has no <name>".to_string()))?;
+    xml.insert_str(anchor, &format!("\n  <metadata>\n    {}\n  </metadata>", entry));
+    Ok(xml)
+}
+
+/// The parts of a libvirt domain definition vmtools generates and reads back.
+///
+/// This is not a general XML parser: it understands the flat layout produced
+/// by `VmManager` so backends without libvirt can act on the same definitions.
+#[derive(Debug, Clone)]
+pub struct DomainSpec {
+    pub name: String,
+    pub uuid: String,
+    /// Memory in MiB
+    pub memory: u64,
+    pub cpus: u32,
+    /// Number of dedicated disk I/O threads
+    pub iothreads: u32,
+    pub arch: String,
+    pub machine_type: String,
+    pub os_type: String,
+    pub boot_order: Vec<String>,
+    pub features: Vec<String>,
+    pub disks: Vec<DomainDisk>,
+    pub cdrom: Option<CdromDrive>,
+    pub mac_address: Option<String>,
+    /// Virtual networks of the interfaces, in order
+    pub networks: Vec<String>,
+    /// UEFI loader, kernel or `firmware=` autoselection, if any
+    pub firmware: Option<String>,
+    /// Top-level devices as `kind:type`, e.g. `disk:cdrom` or `graphics:spice`
+    pub devices: Vec<String>,
+    /// `None` when the domain has no virtio balloon
+    pub balloon: Option<MemBalloon>,
+}
+
+impl DomainSpec {
+    pub fn parse(xml: &str) -> Result<Self> {
+        let name = element_text(xml, "name")
+            .ok_or_else(|| VmError::InvalidInput("Domain XML has no <name>".to_string()))?;
+
+        let memory = element_text(xml, "memory")
+            .and_then(|m| m.parse::<u64>().ok())
+            .map(|m| match attribute(xml, "<memory unit='").as_deref() {
+                Some("KiB") | None => m / 1024,
+                Some("GiB") => m * 1024,
+                _ => m,
+            })
+            .unwrap_or(0);
+
+        let (arch, machine_type) = match xml.find("<type ") {
+            Some(pos) => (
+                attribute(&xml[pos..], "arch='").unwrap_or_default(),
+                attribute(&xml[pos..], "machine='").unwrap_or_default(),
+            ),
+            None => (String::new(), String::new()),
+        };
+
+        let features = element_text(xml, "features")
+            .map(|body| body.split('<')
+                .filter_map(|tag| tag.split(['/', '>', ' ', '\n']).next())
+                .filter(|tag| !tag.is_empty())
+                .map(|tag| tag.to_string())
+                .collect())
+            .unwrap_or_default();
+
+        let mut disks = Vec::new();
+        let mut cdrom = None;
+        for block in xml.split("<disk ").skip(1) {
+            let block = &block[..block.find("</disk>").unwrap_or(block.len())];
+            let path = attribute(block, "<source file='");
+
+            if block.contains("device='cdrom'") {
+                cdrom = cdrom.or(Some(CdromDrive {
+                    target: attribute(block, "<target dev='").unwrap_or_default(),
+                    media: path,
+                }));
+            } else if let (true, Some(path)) = (block.contains("device='disk'"), path) {
+                disks.push(DomainDisk {
+                    target: attribute(block, "<target dev='").unwrap_or_default(),
+                    bus: block.find("<target ")
+                        .and_then(|pos| attribute(&block[pos..], " bus='"))
+                        .unwrap_or_else(|| "virtio".to_string()),
+                    path,
+                    format: attribute(block, "<driver name='qemu' type='")
+                        .unwrap_or_else(|| "qcow2".to_string()),
+                    iothread: attribute(block, " iothread='").and_then(|id| id.parse().ok()),
+                });
+            }
+        }
+
+        Ok(Self {
+            name,
+            uuid: element_text(xml, "uuid").unwrap_or_default(),
+            memory,
+            cpus: element_text(xml, "vcpu").and_then(|c| c.parse().ok()).unwrap_or(1),
+            iothreads: element_text(xml, "iothreads").and_then(|n| n.parse().ok()).unwrap_or(0),
+            arch: if arch.is_empty() { "x86_64".to_string() } else { arch },
+            machine_type,
+            os_type: element_text(xml, "type").unwrap_or_else(|| "hvm".to_string()),
+            boot_order: attributes(xml, "<boot dev='"),
+            features,
+            disks,
+            cdrom,
+            mac_address: attribute(xml, "<mac address='"),
+            networks: attributes(xml, "<source network='"),
+            firmware: element_text(xml, "loader")
+                .or_else(|| element_text(xml, "kernel"))
+                .or_else(|| attribute(xml, "<os firmware='")),
+            devices: element_text(xml, "devices").map(|body| devices(&body)).unwrap_or_default(),
+            balloon: opening_tag(xml, "memballoon")
+                .filter(|tag| tag.contains("model='virtio"))
+                .map(|tag| MemBalloon {
+                    autodeflate: tag.contains("autodeflate='on'"),
+                    free_page_reporting: tag.contains("freePageReporting='on'"),
+                }),
+        })
+    }
+
+    /// Settings where this definition differs from `expected`
+    pub fn diff(&self, expected: &DomainSpec) -> Vec<SpecDifference> {
+        let mut differences = Vec::new();
+        let mut compare = |field: &str, expected: String, actual: String| {
+            if expected != actual {
+                differences.push(SpecDifference { field: field.to_string(), expected, actual });
+            }
+        };
+        let list = |items: &[String]| if items.is_empty() { "-".to_string() } else { items.join(", ") };
+
+        compare("memory", format!("{} MB", expected.memory), format!("{} MB", self.memory));
+        compare("cpus", expected.cpus.to_string(), self.cpus.to_string());
+        compare("iothreads", expected.iothreads.to_string(), self.iothreads.to_string());
+        compare("arch", expected.arch.clone(), self.arch.clone());
+        // libvirt expands aliases such as q35 to the versioned pc-q35-8.2
+        if !self.machine_type.starts_with(&format!("pc-{}-", expected.machine_type)) {
+            compare("machine", expected.machine_type.clone(), self.machine_type.clone());
+        }
+        compare("boot order", list(&expected.boot_order), list(&self.boot_order));
+
+        let mut features = self.features.clone();
+        let mut expected_features = expected.features.clone();
+        features.sort();
+        expected_features.sort();
+        compare("features", list(&expected_features), list(&features));
+
+        compare(
+            "firmware",
+            expected.firmware.clone().unwrap_or_else(|| "BIOS".to_string()),
+            self.firmware.clone().unwrap_or_else(|| "BIOS".to_string()),
+        );
+
+        let count = |devices: &[String], kind: &str| devices.iter().filter(|d| *d == kind).count();
+        let mut kinds: Vec<&String> = expected.devices.iter().chain(&self.devices).collect();
+        kinds.sort();
+        kinds.dedup();
+        for kind in kinds {
+            compare(
+                &format!("device {}", kind),
+                count(&expected.devices, kind).to_string(),
+                count(&self.devices, kind).to_string(),
+            );
+        }
+
+        differences
+    }
+
+    /// The hardware settings of this domain as a template
+    pub fn template(&self) -> VmTemplate {
+        VmTemplate {
+            memory: self.memory,
+            cpus: self.cpus,
+            disk_size: 0,
+            os_type: self.os_type.clone(),
+            arch: self.arch.clone(),
+            machine_type: self.machine_type.clone(),
+            boot_order: self.boot_order.clone(),
+            features: self.features.clone(),
+            iothreads: self.iothreads,
+            user_data_path: None,
+            network_config: None,
+            extends: None,
+        }
+    }
+}
+
+/// Returns `xml` with the first CD-ROM drive's media replaced, or removed when `media` is `None`
+pub fn set_cdrom_media(xml: &str, media: Option<&str>) -> Result<String> {
+    let marker = xml.find("device='cdrom'")
+        .ok_or_else(|| VmError::InvalidInput("VM has no CD-ROM drive".to_string()))?;
+    let start = xml[..marker].rfind("<disk ")
+        .ok_or_else(|| VmError::InvalidInput("Malformed CD-ROM definition".to_string()))?;
+    let end = start + xml[start..].find("</disk>")
+        .ok_or_else(|| VmError::InvalidInput("Malformed CD-ROM definition".to_string()))?;
+
+    let mut lines: Vec<String> = xml[start..end].lines()
+        .filter(|line| !line.trim_start().starts_with("<source "))
+        .map(|line| line.to_string())
+        .collect();
+
+    if let Some(path) = media {
+        // Keep libvirt's usual order: <driver>, then <source>
+        let at = lines.iter().position(|line| line.trim_start().starts_with("<driver ")).map_or(1, |i| i + 1);
+        let indent: String = lines.get(at).or(lines.last())
+            .map(|line| line.chars().take_while(|c| c.is_whitespace()).collect())
+            .unwrap_or_default();
+        lines.insert(at, format!("{}<source file='{}'/>", indent, path));
+    }
+
+    Ok(format!("{}{}{}", &xml[..start], lines.join("\n"), &xml[end..]))
+}
+
+/// Returns `xml` with the CD-ROM drive holding `media`, adding a drive if there is none
+pub fn with_cdrom(xml: &str, media: &str) -> Result<String> {
+    if xml.contains("device='cdrom'") {
+        return set_cdrom_media(xml, Some(media));
+    }
+
+    // The virt machines have no SATA controller
+    let bus = if xml.contains("machine='virt") { "scsi" } else { "sata" };
+    // SATA and SCSI share the sd* names, and a virtio-scsi system disk may hold sda
+    let target = ('a'..='z')
+        .map(|letter| format!("sd{}", letter))
+        .find(|dev| !xml.contains(&format!("<target dev='{}'", dev)))
+        .ok_or_else(|| VmError::InvalidInput("No free sd* target for a CD-ROM drive".to_string()))?;
+    add_device(xml, &format!(r#"<disk type='file' device='cdrom'>
+      <driver name='qemu' type='raw'/>
+      <source file='{}'/>
+      <target dev='{}' bus='{}'/>
+      <readonly/>
+    </disk>"#, media, target, bus))
+}
+
+/// Returns `xml` with `device` appended to `<devices>`; `device` is indented for that level
+pub fn add_device(xml: &str, device: &str) -> Result<String> {
+    let end = xml.rfind("</devices>")
+        .ok_or_else(|| VmError::InvalidInput("Domain XML has no <devices>".to_string()))?;
+    Ok(format!("{}  {}\n  {}", &xml[..end], device, &xml[end..]))
+}
+
+/// The `<shares>` and per-vCPU `<quota>` of the domain's `<cputune>`
+pub fn cpu_tune(xml: &str) -> CpuTune {
+    let Some(body) = element_text(xml, "cputune") else {
+        return CpuTune::default();
+    };
+    let number = |tag: &str| element_text(&body, tag).and_then(|value| value.parse::<i64>().ok());
+    let period = number("period").filter(|period| *period > 0).unwrap_or(CPU_PERIOD as i64);
+    CpuTune {
+        shares: number("shares").map_or(0, |shares| shares.max(0) as u64),
+        quota: number("quota")
+            .filter(|quota| *quota > 0)
+            .map_or(0, |quota| (quota * 100 / period) as u32),
+    }
+}
+
+/// Returns `xml` with the shares, period and quota of its `<cputune>` set to `tune`,
+/// keeping other tuning such as vCPU pinning
+pub fn set_cpu_tune(xml: &str, tune: CpuTune) -> Result<String> {
+    let mut settings = Vec::new();
+    if tune.shares > 0 {
+        settings.push(format!("<shares>{}</shares>", tune.shares));
+    }
+    if tune.quota > 0 {
+        settings.push(format!("<period>{}</period>", CPU_PERIOD));
+        settings.push(format!("<quota>{}</quota>", CPU_PERIOD * tune.quota as u64 / 100));
+    }
+
+    settings.extend(cputune_lines(xml, &["<shares>", "<period>", "<quota>"]));
+    write_cputune(xml, settings)
+}
+
+/// Pins vCPU `i` to host CPU `vcpus[i]` and the emulator and I/O threads to
+/// `housekeeping`, replacing any pinning `xml` had and keeping shares and quotas
+pub fn set_cpu_pinning(xml: &str, vcpus: &[u32], housekeeping: &str, iothreads: u32) -> Result<String> {
+    let mut settings = cputune_lines(xml, &["<vcpupin ", "<emulatorpin ", "<iothreadpin "]);
+    settings.extend(vcpus.iter().enumerate()
+        .map(|(vcpu, cpu)| format!("<vcpupin vcpu='{}' cpuset='{}'/>", vcpu, cpu)));
+    settings.push(format!("<emulatorpin cpuset='{}'/>", housekeeping));
+    settings.extend((1..=iothreads).map(|id| format!("<iothreadpin iothread='{}' cpuset='{}'/>", id, housekeeping)));
+    write_cputune(xml, settings)
+}
+
+/// The lines of `<cputune>` except those starting with one of `dropped`
+fn cputune_lines(xml: &str, dropped: &[&str]) -> Vec<String> {
+    element_text(xml, "cputune")
+        .map(|body| body.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .filter(|line| !dropped.iter().any(|tag| line.starts_with(tag)))
+            .map(|line| line.to_string())
+            .collect())
+        .unwrap_or_default()
+}
+
+/// Returns `xml` with `<cputune>` holding `settings`, dropped when there are none
+fn write_cputune(xml: &str, settings: Vec<String>) -> Result<String> {
+    let element = if settings.is_empty() {
+        String::new()
+    } else {
+        format!("<cputune>\n    {}\n  </cputune>", settings.join("\n    "))
+    };
+
+    if let Some(start) = xml.find("<cputune>") {
+        let end = start + xml[start..].find("</cputune>")
+            .ok_or_else(|| VmError::InvalidInput("Malformed <cputune>".to_string()))? + "</cputune>".len();
+        // Drop the element's own line when it goes away entirely
+        let (start, end) = if element.is_empty() {
+            (xml[..start].rfind('\n').unwrap_or(start), end)
+        } else {
+            (start, end)
+        };
+        return Ok(format!("{}{}{}", &xml[..start], element, &xml[end..]));
+    }
+    if element.is_empty() {
+        return Ok(xml.to_string());
+    }
+
+    // Goes after <vcpu> (and <iothreads>, if present) as in libvirt's own output
+    let anchor = xml.find("</iothreads>").map(|pos| pos + "</iothreads>".len())
+        .or_else(|| xml.find("</vcpu>").map(|pos| pos + "</vcpu>".len()))
+        .ok_or_else(|| VmError::InvalidInput("Domain XML has no <vcpu>".to_string()))?;
+    Ok(format!("{}\n  {}{}", &xml[..anchor], element, &xml[anchor..]))
+}
+
+/// The bandwidth limits of the interface with MAC address `mac`
+pub fn interface_bandwidth(xml: &str, mac: &str) -> Result<Bandwidth> {
+    let block = &xml[interface_range(xml, mac)?];
+    let average = |direction: &str| attribute(block, &format!("<{} average='", direction))
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0);
+    Ok(Bandwidth { inbound: average("inbound"), outbound: average("outbound") })
+}
+
+/// Returns `xml` with the `<bandwidth>` of the interface with MAC address `mac`
+/// replaced by `bandwidth`; unlimited directions are left out
+pub fn set_interface_bandwidth(xml: &str, mac: &str, bandwidth: Bandwidth) -> Result<String> {
+    let range = interface_range(xml, mac)?;
+    let mut in_bandwidth = false;
+    let mut lines: Vec<String> = xml[range.clone()].lines()
+        .filter(|line| {
+            let line = line.trim_start();
+            if line.starts_with("<bandwidth>") {
+                in_bandwidth = true;
+            }
+            let keep = !in_bandwidth && !line.starts_with("<bandwidth/>");
+            if line.starts_with("</bandwidth>") {
+                in_bandwidth = false;
+            }
+            keep
+        })
+        .map(|line| line.to_string())
+        .collect();
+
+    // The last line is the indentation before </interface>
+    let indent = lines.pop().unwrap_or_default();
+    if bandwidth != Bandwidth::default() {
+        lines.push(format!("{}  <bandwidth>", indent));
+        for (direction, average) in [("inbound", bandwidth.inbound), ("outbound", bandwidth.outbound)] {
+            if average > 0 {
+                lines.push(format!("{}    <{} average='{}'/>", indent, direction, average));
+            }
+        }
+        lines.push(format!("{}  </bandwidth>", indent));
+    }
+    lines.push(indent);
+
+    Ok(format!("{}{}{}", &xml[..range.start], lines.join("\n"), &xml[range.end..]))
+}
+
+/// Returns `xml` with the interface with MAC address `mac` set to `queues` queue pairs
+pub fn set_interface_queues(xml: &str, mac: &str, queues: u32) -> Result<String> {
+    let range = interface_range(xml, mac)?;
+    let block = &xml[range.clone()];
+    let updated = if opening_tag(block, "driver").is_some() {
+        set_attribute(block, "driver", "queues", &queues.to_string())
+    } else {
+        let model = block.find("<model ")
+            .ok_or_else(|| VmError::InvalidInput(format!("Interface {} has no <model>", mac)))?;
+        let end = model + block[model..].find('>').unwrap_or(0) + 1;
+        let indent = &block[block[..model].rfind('\n').map_or(0, |pos| pos + 1)..model];
+        format!("{}\n{}<driver queues='{}'/>{}", &block[..end], indent, queues, &block[end..])
+    };
+    Ok(format!("{}{}{}", &xml[..range.start], updated, &xml[range.end..]))
+}
+
+/// Each interface as its MAC address, model and queue pairs (1 without a `queues=`)
+pub fn interfaces(xml: &str) -> Vec<(String, String, u32)> {
+    xml.split("<interface ").skip(1)
+        .filter_map(|block| {
+            let block = &block[..block.find("</interface>").unwrap_or(block.len())];
+            let mac = attribute(block, "<mac address='")?;
+            let model = attribute(block, "<model type='").unwrap_or_default();
+            let queues = attribute(block, " queues='").and_then(|q| q.parse().ok()).unwrap_or(1);
+            Some((mac, model, queues))
+        })
+        .collect()
+}
+
+/// From `<interface` up to its closing tag, for the interface with MAC address `mac`
+fn interface_range(xml: &str, mac: &str) -> Result<std::ops::Range<usize>> {
+    let not_found = || VmError::InvalidInput(format!("VM has no network interface with MAC address {}", mac));
+    // MAC addresses are case-insensitive; lowercasing ASCII keeps byte offsets intact
+    let marker = xml.to_ascii_lowercase()
+        .find(&format!("<mac address='{}'", mac.to_ascii_lowercase()))
+        .ok_or_else(not_found)?;
+    let start = xml[..marker].rfind("<interface ").ok_or_else(not_found)?;
+    let end = marker + xml[marker..].find("</interface>").ok_or_else(not_found)?;
+    Ok(start..end)
+}
+
+/// Returns `xml` with the balloon's autodeflate and free page reporting switched
+/// on or off; `None` leaves a setting as it is
+pub fn set_balloon_options(xml: &str, autodeflate: Option<bool>, free_page_reporting: Option<bool>) -> Result<String> {
+    if opening_tag(xml, "memballoon").is_none_or(|tag| !tag.contains("model='virtio")) {
+        return Err(VmError::InvalidInput("VM has no virtio memory balloon".to_string()));
+    }
+
+    let on_off = |enabled: bool| if enabled { "on" } else { "off" };
+    let mut xml = xml.to_string();
+    if let Some(enabled) = autodeflate {
+        xml = set_attribute(&xml, "memballoon", "autodeflate", on_off(enabled));
+    }
+    if let Some(enabled) = free_page_reporting {
+        xml = set_attribute(&xml, "memballoon", "freePageReporting", on_off(enabled));
+    }
+    Ok(xml)
+}
+
+/// Returns `xml` with its `<os>` boot devices replaced by `order`
+pub fn set_boot_order(xml: &str, order: &[String]) -> Result<String> {
+    let start = xml.find("<os>")
+        .ok_or_else(|| VmError::InvalidInput("Domain XML has no <os> section".to_string()))?;
+    let end = start + xml[start..].find("</os>")
+        .ok_or_else(|| VmError::InvalidInput("Domain XML has no <os> section".to_string()))?;
+
+    let mut lines: Vec<String> = xml[start..end].lines()
+        .filter(|line| !line.trim_start().starts_with("<boot dev="))
+        .map(|line| line.to_string())
+        .collect();
+    // The last line is the indentation before </os>
+    let indent = lines.pop().unwrap_or_default();
+    lines.extend(order.iter().map(|dev| format!("{}  <boot dev='{}'/>", indent, dev)));
+    lines.push(indent);
+
+    Ok(format!("{}{}{}", &xml[..start], lines.join("\n"), &xml[end..]))
+}
+
+/// A kernel QEMU loads directly instead of booting the disk's bootloader
+#[derive(Debug, Clone, PartialEq)]
+pub struct KernelBoot {
+    pub kernel: PathBuf,
+    pub initrd: Option<PathBuf>,
+    /// Kernel command line, e.g. `console=ttyS0 root=/dev/vda rw`
+    pub cmdline: Option<String>,
+}
+
+/// Returns `xml` with `<kernel>`, `<initrd>` and `<cmdline>` in its `<os>` section,
+/// replacing any it had
+pub fn set_kernel_boot(xml: &str, boot: &KernelBoot) -> Result<String> {
+    let start = xml.find("<os>")
+        .ok_or_else(|| VmError::InvalidInput("Domain XML has no <os> section".to_string()))?;
+    let end = start + xml[start..].find("</os>")
+        .ok_or_else(|| VmError::InvalidInput("Domain XML has no <os> section".to_string()))?;
+
+    let mut lines: Vec<String> = xml[start..end].lines()
+        .filter(|line| !["<kernel>", "<initrd>", "<cmdline>"].iter().any(|tag| line.trim_start().starts_with(tag)))
+        .map(|line| line.to_string())
+        .collect();
+    let indent = lines.last().cloned().unwrap_or_default();
+    let escape = |text: &str| text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+
+    let mut elements = vec![format!("{}  <kernel>{}</kernel>", indent, escape(&boot.kernel.to_string_lossy()))];
+    if let Some(initrd) = &boot.initrd {
+        elements.push(format!("{}  <initrd>{}</initrd>", indent, escape(&initrd.to_string_lossy())));
+    }
+    if let Some(cmdline) = &boot.cmdline {
+        elements.push(format!("{}  <cmdline>{}</cmdline>", indent, escape(cmdline)));
+    }
+    // Right after <type>, where libvirt writes them
+    let position = lines.iter().position(|line| line.trim_start().starts_with("<type")).map_or(1, |i| i + 1);
+    lines.splice(position..position, elements);
+
+    Ok(format!("{}{}{}", &xml[..start], lines.join("\n"), &xml[end..]))
+}
+
+/// Returns `xml` with its `<cpu>` element replaced by `cpu`, or `cpu` added after
+/// `<vcpu>` when it has none
+pub fn set_cpu(xml: &str, cpu: &str) -> Result<String> {
+    if let Some(replaced) = replace_element(xml, "cpu", cpu).filter(|_| xml.contains("<cpu>")) {
+        return Ok(replaced);
+    }
+    if let Some(range) = opening_tag_range(xml, "cpu") {
+        let end = if xml[range.end..].starts_with("/>") {
+            range.end + 2
+        } else {
+            range.end + xml[range.end..].find("</cpu>")
+                .ok_or_else(|| VmError::InvalidInput("Malformed <cpu>".to_string()))? + "</cpu>".len()
+        };
+        return Ok(format!("{}{}{}", &xml[..range.start], cpu, &xml[end..]));
+    }
+    let anchor = xml.find("</vcpu>").map(|pos| pos + "</vcpu>".len())
+        .ok_or_else(|| VmError::InvalidInput("Domain XML has no <vcpu>".to_string()))?;
+    Ok(format!("{}\n  {}{}", &xml[..anchor], cpu, &xml[anchor..]))
+}
+
+/// The `mode` of the domain's `<cpu>`, `custom` when it only names a model
+pub fn cpu_mode(xml: &str) -> Option<String> {
+    opening_tag(xml, "cpu").map(|tag| attribute(tag, "mode='").unwrap_or_else(|| "custom".to_string()))
+}
+
+/// Returns `xml` with the machine type of its `<os><type>` set to `machine`
+pub fn set_machine(xml: &str, machine: &str) -> Result<String> {
+    if opening_tag(xml, "type").is_none() {
+        return Err(VmError::InvalidInput("Domain XML has no <os> type".to_string()));
+    }
+    Ok(set_attribute(xml, "type", "machine", machine))
+}
+
+/// Returns `xml` with guest memory backed by the host's huge pages
+pub fn set_hugepages(xml: &str) -> Result<String> {
+    if let Some(start) = xml.find("<memoryBacking>") {
+        if element_text(xml, "memoryBacking").is_some_and(|body| body.contains("<hugepages")) {
+            return Ok(xml.to_string());
+        }
+        let at = start + "<memoryBacking>".len();
+        return Ok(format!("{}\n    <hugepages/>{}", &xml[..at], &xml[at..]));
+    }
+    // After the memory sizes, as libvirt orders it
+    let anchor = ["</currentMemory>", "</memory>"].iter()
+        .find_map(|tag| xml.find(tag).map(|pos| pos + tag.len()))
+        .ok_or_else(|| VmError::InvalidInput("Domain XML has no <memory>".to_string()))?;
+    Ok(format!("{}\n  <memoryBacking>\n    <hugepages/>\n  </memoryBacking>{}", &xml[..anchor], &xml[anchor..]))
+}
+
+/// Disks naming a storage pool volume (`<disk type='volume'>`) rather than a file,
+/// as (target, bus, pool, volume); `DomainSpec::disks` leaves them out
+pub fn volume_disks(xml: &str) -> Vec<(String, String, String, String)> {
+    xml.split("<disk type='volume' device='disk'>").skip(1)
+        .filter_map(|block| {
+            let block = &block[..block.find("</disk>").unwrap_or(block.len())];
+            let target = block.find("<target ").map(|pos| &block[pos..])?;
+            Some((
+                attribute(target, "dev='")?,
+                attribute(target, " bus='").unwrap_or_else(|| "virtio".to_string()),
+                attribute(block, "<source pool='")?,
+                attribute(block, " volume='")?,
+            ))
+        })
+        .collect()
+}
+
+/// Returns `xml` with the disk at target `target` moved to the virtio bus as `dev`;
+/// its drive address goes too, libvirt assigns a PCI one
+pub fn set_disk_virtio(xml: &str, target: &str, dev: &str) -> Result<String> {
+    set_disk_bus(xml, target, "virtio", dev)
+}
+
+/// Returns `xml` with the disk at target `target` moved to `bus` as `dev`, without
+/// its old address so libvirt assigns one on the new bus
+pub fn set_disk_bus(xml: &str, target: &str, bus: &str, dev: &str) -> Result<String> {
+    let range = disk_range(xml, target)?;
+    let block: Vec<String> = xml[range.clone()].lines()
+        .filter(|line| !line.trim_start().starts_with("<address "))
+        .map(|line| match line.find("<target ") {
+            Some(pos) => format!("{}<target dev='{}' bus='{}'/>", &line[..pos], dev, bus),
+            None => line.to_string(),
+        })
+        .collect();
+    Ok(format!("{}{}{}", &xml[..range.start], block.join("\n"), &xml[range.end..]))
+}
+
+/// Returns `xml` with the disk at target `target` backed by the qcow2 file at `path`
+/// instead of its old image or pool volume and whatever that was layered on
+pub fn set_disk_image(xml: &str, target: &str, path: &str) -> Result<String> {
+    let range = disk_range(xml, target)?;
+    let block: Vec<String> = xml[range.clone()].lines()
+        .filter(|line| !line.trim_start().starts_with("<backingStore"))
+        .map(|line| match line.find("<source ") {
+            Some(pos) => format!("{}<source file='{}'/>", &line[..pos], path),
+            None if line.starts_with("<disk ") => set_attribute(line, "disk", "type", "file"),
+            None if line.trim_start().starts_with("<driver ") => set_attribute(line, "driver", "type", "qcow2"),
+            None => line.to_string(),
+        })
+        .collect();
+    Ok(format!("{}{}{}", &xml[..range.start], block.join("\n"), &xml[range.end..]))
+}
+
+/// Byte range of the `<disk>` element at target `target`, up to its closing tag
+fn disk_range(xml: &str, target: &str) -> Result<std::ops::Range<usize>> {
+    let marker = xml.find(&format!("<target dev='{}'", target))
+        .ok_or_else(|| VmError::InvalidInput(format!("VM has no disk {}", target)))?;
+    let start = xml[..marker].rfind("<disk ")
+        .ok_or_else(|| VmError::InvalidInput(format!("VM has no disk {}", target)))?;
+    let end = marker + xml[marker..].find("</disk>")
+        .ok_or_else(|| VmError::InvalidInput("Malformed <disk>".to_string()))?;
+    Ok(start..end)
+}
+
+/// Returns `xml` with the model of the interface with MAC address `mac` set to `model`
+pub fn set_interface_model(xml: &str, mac: &str, model: &str) -> Result<String> {
+    let range = interface_range(xml, mac)?;
+    let block = &xml[range.clone()];
+    if opening_tag(block, "model").is_none() {
+        return Err(VmError::InvalidInput(format!("Interface {} has no <model>", mac)));
+    }
+    Ok(format!("{}{}{}", &xml[..range.start], set_attribute(block, "model", "type", model), &xml[range.end..]))
+}
+
+/// Returns `xml` with `element` in place of the `<features>` entry `tag`, or added
+/// to `<features>` when it has none
+pub fn set_feature(xml: &str, tag: &str, element: &str) -> Result<String> {
+    let start = xml.find("<features>")
+        .ok_or_else(|| VmError::InvalidInput("Domain XML has no <features>".to_string()))?;
+    let end = start + xml[start..].find("</features>")
+        .ok_or_else(|| VmError::InvalidInput("Malformed <features>".to_string()))?;
+    if let Some(range) = element_range(&xml[start..end], tag) {
+        return Ok(format!("{}{}{}", &xml[..start + range.start], element, &xml[start + range.end..]));
+    }
+    Ok(format!("{}  {}\n  {}", &xml[..end], element, &xml[end..]))
+}
+
+/// The `<features>` entry `tag` as written, e.g. the whole `<hyperv>` block
+pub fn feature(xml: &str, tag: &str) -> Option<String> {
+    let body = element_text(xml, "features")?;
+    element_range(&body, tag).map(|range| body[range].to_string())
+}
+
+/// Returns `xml` with the `<clock>` timer `name` replaced by `element`, or `element`
+/// added to `<clock>`
+pub fn set_timer(xml: &str, name: &str, element: &str) -> Result<String> {
+    let start = xml.find("<clock ")
+        .ok_or_else(|| VmError::InvalidInput("Domain XML has no <clock>".to_string()))?;
+    let end = start + xml[start..].find("</clock>")
+        .ok_or_else(|| VmError::InvalidInput("Domain XML has no <clock> timers".to_string()))?;
+    if let Some(timer) = xml[start..end].find(&format!("<timer name='{}'", name)) {
+        let timer = start + timer;
+        let timer_end = timer + xml[timer..].find("/>").unwrap_or(0) + 2;
+        return Ok(format!("{}{}{}", &xml[..timer], element, &xml[timer_end..]));
+    }
+    Ok(format!("{}  {}\n  {}", &xml[..end], element, &xml[end..]))
+}
+
+/// The `<timer>` named `name` as written, e.g. `<timer name='hpet' present='no'/>`
+pub fn timer(xml: &str, name: &str) -> Option<String> {
+    let start = xml.find(&format!("<timer name='{}'", name))?;
+    let end = start + xml[start..].find("/>")? + 2;
+    Some(xml[start..end].to_string())
+}
+
+/// The `offset` of the domain's `<clock>`
+pub fn clock_offset(xml: &str) -> Option<String> {
+    opening_tag(xml, "clock").and_then(|tag| attribute(tag, "offset='"))
+}
+
+/// Returns `xml` with its `<clock>` offset set to `offset` (`utc`, `localtime`)
+pub fn set_clock_offset(xml: &str, offset: &str) -> Result<String> {
+    if opening_tag(xml, "clock").is_none() {
+        return Err(VmError::InvalidInput("Domain XML has no <clock>".to_string()));
+    }
+    Ok(set_attribute(xml, "clock", "offset", offset))
+}
+
+/// Returns `xml` with memory and current memory set to `memory` MiB
+pub fn set_memory(xml: &str, memory: u64) -> Result<String> {
+    let xml = replace_element(xml, "memory", &format!("<memory unit='MiB'>{}</memory>", memory))
+        .ok_or_else(|| VmError::InvalidInput("Domain XML has no <memory>".to_string()))?;
+    // Without balloon tweaks currentMemory tracks memory, as in the generated definitions
+    Ok(replace_element(&xml, "currentMemory", &format!("<currentMemory unit='MiB'>{}</currentMemory>", memory))
+        .unwrap_or(xml))
+}
+
+/// Returns `xml` with `cpus` vCPUs, all of them online
+pub fn set_cpus(xml: &str, cpus: u32) -> Result<String> {
+    replace_element(xml, "vcpu", &format!("<vcpu placement='static'>{}</vcpu>", cpus))
+        .ok_or_else(|| VmError::InvalidInput("Domain XML has no <vcpu>".to_string()))
+}
+
+/// Returns `xml` with every console password set to `password`, or removed when `None`
+pub fn set_graphics_password(xml: &str, password: Option<&str>) -> Result<String> {
+    let mut updated = String::with_capacity(xml.len());
+    let mut rest = xml;
+    let mut found = false;
+
+    while let Some(pos) = rest.find("<graphics ") {
+        found = true;
+        let end = pos + rest[pos..].find('>')
+            .ok_or_else(|| VmError::InvalidInput("Malformed <graphics> element".to_string()))?;
+        let self_closing = rest[..end].ends_with('/');
+        let tag_end = if self_closing { end - 1 } else { end };
+
+        let mut tag = rest[pos..tag_end].trim_end().to_string();
+        if let Some(start) = tag.find(" passwd='") {
+            let close = start + " passwd='".len() + tag[start + " passwd='".len()..].find('\'').unwrap_or(0) + 1;
+            tag.replace_range(start..close, "");
+        }
+        if let Some(password) = password {
+            tag.push_str(&format!(" passwd='{}'", password));
+        }
+
+        updated.push_str(&rest[..pos]);
+        updated.push_str(&tag);
+        updated.push_str(if self_closing { "/>" } else { ">" });
+        rest = &rest[end + 1..];
+    }
+
+    if !found {
+        return Err(VmError::InvalidInput("VM has no SPICE or VNC console".to_string()));
+    }
+    updated.push_str(rest);
+    Ok(updated)
+}
+
+/// Every whole `<graphics>` element, in document order
+pub fn graphics_elements(xml: &str) -> Vec<String> {
+    xml.match_indices("<graphics ")
+        .filter_map(|(pos, _)| {
+            let open_end = pos + xml[pos..].find('>')?;
+            if xml[..open_end].ends_with('/') {
+                return Some(xml[pos..=open_end].to_string());
+            }
+            let end = pos + xml[pos..].find("</graphics>")? + "</graphics>".len();
+            Some(xml[pos..end].to_string())
+        })
+        .collect()
+}
+
+/// Returns `xml` with the domain renamed
+pub fn set_name(xml: &str, name: &str) -> Result<String> {
+    replace_element(xml, "name", &format!("<name>{}</name>", name))
+        .ok_or_else(|| VmError::InvalidInput("Domain XML has no <name>".to_string()))
+}
+
+/// Returns `xml` with a fresh UUID and fresh MAC addresses, so it can be defined next to the original
+pub fn regenerate_ids(xml: &str) -> String {
+    let uuid = format!("<uuid>{}</uuid>", uuid::Uuid::new_v4());
+    let mut xml = replace_element(xml, "uuid", &uuid).unwrap_or_else(|| xml.to_string());
+
+    let prefix = "<mac address='";
+    let mut search = 0;
+    while let Some(pos) = xml[search..].find(prefix) {
+        let start = search + pos + prefix.len();
+        let Some(len) = xml[start..].find('\'') else { break };
+        xml.replace_range(start..start + len, &utils::generate_mac_address());
+        search = start;
+    }
+    xml
+}
+
+/// Returns `xml` with every MAC address found in `taken`, or used twice within it,
+/// swapped for a fresh one, along with the (old, new) pairs swapped. MAC
+/// addresses compare case-insensitively; `taken` holds them in lowercase.
+pub fn avoid_macs(xml: &str, taken: &HashSet<String>) -> (String, Vec<(String, String)>) {
+    let mut xml = xml.to_string();
+    let mut used = taken.clone();
+    let mut swapped = Vec::new();
+    let prefix = "<mac address='";
+    let mut search = 0;
+    while let Some(pos) = xml[search..].find(prefix) {
+        let start = search + pos + prefix.len();
+        let Some(len) = xml[start..].find('\'') else { break };
+        let mac = xml[start..start + len].to_string();
+        if !used.insert(mac.to_ascii_lowercase()) {
+            let fresh = std::iter::repeat_with(utils::generate_mac_address)
+                .find(|fresh| !used.contains(fresh))
+                .expect("an endless supply of MAC addresses");
+            used.insert(fresh.clone());
+            xml.replace_range(start..start + len, &fresh);
+            swapped.push((mac, fresh));
+        }
+        search = start;
+    }
+    (xml, swapped)
+}
+
+/// Returns `xml` without the VM's UEFI variable store, so libvirt gives it a
+/// fresh one from the firmware's template
+pub fn drop_nvram(xml: &str) -> String {
+    match element_range(xml, "nvram") {
+        Some(range) => {
+            // The whole line, with its indentation
+            let start = xml[..range.start].rfind('\n').unwrap_or(range.start);
+            format!("{}{}", &xml[..start], &xml[range.end..])
+        }
+        None => xml.to_string(),
+    }
+}
+
+/// Byte range of the first whole `<tag>` element, self-closing or up to its closing tag
+fn element_range(xml: &str, tag: &str) -> Option<std::ops::Range<usize>> {
+    let mut search = 0;
+    loop {
+        let open = search + xml[search..].find(&format!("<{}", tag))?;
+        let after = &xml[open + tag.len() + 1..];
+        if after.starts_with(['>', ' ', '/']) {
+            let tag_end = open + xml[open..].find('>')? + 1;
+            if xml[..tag_end].ends_with("/>") {
+                return Some(open..tag_end);
+            }
+            let close = format!("</{}>", tag);
+            return Some(open..tag_end + xml[tag_end..].find(&close)? + close.len());
+        }
+        search = open + 1;
+    }
+}
+
+/// Byte range of the first `<tag ...>` opening tag, without the closing `>` or `/>`
+fn opening_tag_range(xml: &str, tag: &str) -> Option<std::ops::Range<usize>> {
+    let start = xml.find(&format!("<{} ", tag))?;
+    let end = start + xml[start..].find('>')?;
+    Some(start..start + xml[start..end].trim_end_matches('/').len())
+}
+
+fn opening_tag<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    opening_tag_range(xml, tag).map(|range| &xml[range])
+}
+
+/// Sets `name='value'` on the first `<tag ...>`, replacing any existing value
+fn set_attribute(xml: &str, tag: &str, name: &str, value: &str) -> String {
+    let Some(range) = opening_tag_range(xml, tag) else {
+        return xml.to_string();
+    };
+    let (start, current) = (range.start, &xml[range]);
+    let prefix = format!(" {}='", name);
+
+    let updated = match current.find(&prefix) {
+        Some(pos) => {
+            let value_start = pos + prefix.len();
+            let value_end = value_start + current[value_start..].find('\'').unwrap_or(0);
+            format!("{}{}{}", &current[..value_start], value, &current[value_end..])
+        }
+        None => format!("{}{}{}'", current.trim_end(), prefix, value),
+    };
+
+    format!("{}{}{}", &xml[..start], updated, &xml[start + current.len()..])
+}
+
+/// Replaces the first whole `<tag ...>text</tag>` element
+fn replace_element(xml: &str, tag: &str, replacement: &str) -> Option<String> {
+    let mut search = 0;
+    loop {
+        let open = search + xml[search..].find(&format!("<{}", tag))?;
+        let after = &xml[open + tag.len() + 1..];
+        if after.starts_with('>') || after.starts_with(' ') {
+            let close = format!("</{}>", tag);
+            let end = open + xml[open..].find(&close)? + close.len();
+            return Some(format!("{}{}{}", &xml[..open], replacement, &xml[end..]));
+        }
+        search = open + 1;
+    }
+}
+
+/// Top-level elements of a `<devices>` body as `kind:type`, skipping implicit ones
+fn devices(body: &str) -> Vec<String> {
+    let mut found = Vec::new();
+    let mut depth = 0usize;
+    for tag in body.split('<').skip(1) {
+        let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+        if tag.starts_with('/') {
+            depth = depth.saturating_sub(1);
+            continue;
+        }
+        if depth == 0 {
+            let kind = tag.split([' ', '/']).next().unwrap_or_default();
+            if !IMPLICIT_DEVICES.contains(&kind) {
+                // Disks are told apart by device (disk, cdrom), everything else by type
+                let subtype = attribute(tag, "device='").or_else(|| attribute(tag, "type='"));
+                found.push(match subtype {
+                    Some(subtype) => format!("{}:{}", kind, subtype),
+                    None => kind.to_string(),
+                });
+            }
+        }
+        if !tag.ends_with('/') {
+            depth += 1;
+        }
+    }
+    found
+}
+
+/// Text of the first `<tag ...>text</tag>` element
+pub(crate) fn element_text(xml: &str, tag: &str) -> Option<String> {
+    let mut search = 0;
+    loop {
+        let open = search + xml[search..].find(&format!("<{}", tag))?;
+        let after = &xml[open + tag.len() + 1..];
+        // Skip longer tags sharing the prefix, e.g. <name> vs <namespace>
+        if after.starts_with('>') || after.starts_with(' ') {
+            let start = open + xml[open..].find('>')? + 1;
+            let end = start + xml[start..].find(&format!("</{}>", tag))?;
+            return Some(xml[start..end].trim().to_string());
+        }
+        search = open + 1;
+    }
+}
+
+/// Quoted value following `prefix`, e.g. `<source file='` → the path
+pub(crate) fn attribute(xml: &str, prefix: &str) -> Option<String> {
+    let start = xml.find(prefix)? + prefix.len();
+    let end = start + xml[start..].find('\'')?;
+    Some(xml[start..end].to_string())
+}
+
+/// The text of every `<tag>` element, in document order
+pub(crate) fn element_texts(xml: &str, tag: &str) -> Vec<String> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    xml.match_indices(&open)
+        .filter_map(|(pos, _)| {
+            let start = pos + open.len();
+            xml[start..].find(&close).map(|end| xml[start..start + end].trim().to_string())
+        })
+        .collect()
+}
+
+/// Every quoted value following `prefix`, in document order
+pub(crate) fn attributes(xml: &str, prefix: &str) -> Vec<String> {
+    xml.match_indices(prefix)
+        .filter_map(|(pos, _)| attribute(&xml[pos..], prefix))
+        .collect()
+}
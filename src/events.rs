@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdout, Command as AsyncCommand};
+
+use crate::error::{VmError, Result};
+
+/// Domain lifecycle event kinds reported by libvirt
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Defined,
+    Undefined,
+    Started,
+    Suspended,
+    Resumed,
+    Stopped,
+    Shutdown,
+    Crashed,
+    Migrated,
+    Other(String),
+}
+
+impl std::fmt::Display for EventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventKind::Defined => write!(f, "defined"),
+            EventKind::Undefined => write!(f, "undefined"),
+            EventKind::Started => write!(f, "started"),
+            EventKind::Suspended => write!(f, "suspended"),
+            EventKind::Resumed => write!(f, "resumed"),
+            EventKind::Stopped => write!(f, "stopped"),
+            EventKind::Shutdown => write!(f, "shutdown"),
+            EventKind::Crashed => write!(f, "crashed"),
+            EventKind::Migrated => write!(f, "migrated"),
+            EventKind::Other(kind) => write!(f, "{}", kind.to_lowercase()),
+        }
+    }
+}
+
+/// A single lifecycle event for a domain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DomainEvent {
+    pub timestamp: String,
+    pub domain: String,
+    pub kind: EventKind,
+    pub detail: String,
+}
+
+impl DomainEvent {
+    /// Parses a line of `virsh event --event lifecycle --timestamp` output, e.g.
+    /// `2024-05-01 10:00:00.123+0000: event 'lifecycle' for domain 'web': Started Booted`
+    pub fn parse(line: &str) -> Option<Self> {
+        let (timestamp, rest) = match line.find(": event '") {
+            Some(pos) => (line[..pos].trim().to_string(), &line[pos + 2..]),
+            None if line.starts_with("event '") => (chrono::Utc::now().to_rfc3339(), line),
+            None => return None,
+        };
+
+        let rest = rest.strip_prefix("event 'lifecycle' for domain '")?;
+        let (domain, rest) = rest.split_once("': ")?;
+
+        let mut words = rest.split_whitespace();
+        let kind_str = words.next()?;
+        let detail = words.collect::<Vec<_>>().join(" ");
+
+        // libvirt reports migrations as Started/Stopped with a "Migrated" detail
+        let kind = if detail.eq_ignore_ascii_case("migrated") {
+            EventKind::Migrated
+        } else {
+            match kind_str {
+                "Defined" => EventKind::Defined,
+                "Undefined" => EventKind::Undefined,
+                "Started" => EventKind::Started,
+                "Suspended" => EventKind::Suspended,
+                "Resumed" => EventKind::Resumed,
+                "Stopped" if detail.eq_ignore_ascii_case("crashed") => EventKind::Crashed,
+                "Stopped" => EventKind::Stopped,
+                "Shutdown" => EventKind::Shutdown,
+                "Crashed" => EventKind::Crashed,
+                other => EventKind::Other(other.to_string()),
+            }
+        };
+
+        Some(DomainEvent {
+            timestamp,
+            domain: domain.to_string(),
+            kind,
+            detail,
+        })
+    }
+}
+
+/// Running `virsh event` subscription yielding parsed lifecycle events
+pub struct EventStream {
+    child: Child,
+    lines: Lines<BufReader<ChildStdout>>,
+}
+
+impl EventStream {
+    /// Starts the subscription on `virsh`, a prepared virsh command for the target URI
+    pub fn spawn(mut virsh: AsyncCommand, domain: Option<&str>) -> Result<Self> {
+        let mut args = vec!["event", "--event", "lifecycle", "--loop", "--timestamp"];
+        match domain {
+            Some(domain) => args.extend(["--domain", domain]),
+            None => args.push("--all"),
+        }
+
+        let mut child = virsh
+            .args(&args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| VmError::LibvirtError(format!("Failed to subscribe to events: {}", e)))?;
+
+        let stdout = child.stdout.take()
+            .ok_or_else(|| VmError::LibvirtError("Failed to capture event stream".to_string()))?;
+
+        Ok(Self {
+            child,
+            lines: BufReader::new(stdout).lines(),
+        })
+    }
+
+    /// Waits for the next lifecycle event; returns `None` when the subscription ends
+    pub async fn next(&mut self) -> Result<Option<DomainEvent>> {
+        while let Some(line) = self.lines.next_line().await? {
+            if let Some(event) = DomainEvent::parse(&line) {
+                return Ok(Some(event));
+            }
+        }
+
+        let _ = self.child.wait().await;
+        Ok(None)
+    }
+}
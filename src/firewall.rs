@@ -0,0 +1,189 @@
+use crate::error::{Result, VmError};
+use crate::network::Ipv4Subnet;
+use std::fmt;
+use std::net::Ipv4Addr;
+
+/// The nftables table vmtools owns; nothing else is touched
+pub const TABLE: &str = "vmtools";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+        })
+    }
+}
+
+impl std::str::FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s.to_lowercase().as_str() {
+            "tcp" => Ok(Protocol::Tcp),
+            "udp" => Ok(Protocol::Udp),
+            _ => Err(format!("Unknown protocol '{}': use tcp or udp", s)),
+        }
+    }
+}
+
+/// Connections to `host_port` on the host go to `guest_port` on the guest.
+/// Written as `443/tcp`, or `8443:443/tcp` when the ports differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PortForward {
+    pub host_port: u16,
+    pub guest_port: u16,
+    pub protocol: Protocol,
+}
+
+impl fmt::Display for PortForward {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.host_port != self.guest_port {
+            write!(f, "{}:", self.host_port)?;
+        }
+        write!(f, "{}/{}", self.guest_port, self.protocol)
+    }
+}
+
+impl std::str::FromStr for PortForward {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        let (ports, protocol) = match s.split_once('/') {
+            Some((ports, protocol)) => (ports, protocol.parse()?),
+            None => (s, Protocol::Tcp),
+        };
+        let port = |port: &str| match port.parse::<u16>() {
+            Ok(port) if port > 0 => Ok(port),
+            _ => Err(format!("Invalid port '{}' in '{}'", port, s)),
+        };
+        let (host_port, guest_port) = match ports.split_once(':') {
+            Some((host, guest)) => (port(host)?, port(guest)?),
+            None => (port(ports)?, port(ports)?),
+        };
+        Ok(Self { host_port, guest_port, protocol })
+    }
+}
+
+/// A VM's port forward, with the address it currently points at
+#[derive(Debug, Clone, PartialEq)]
+pub struct FirewallRule {
+    pub vm: String,
+    pub forward: PortForward,
+    /// The guest's IPv4 address; `None` while it has none, which leaves the forward out
+    pub address: Option<Ipv4Addr>,
+    /// The bridge whose libvirt reject rule drops the forwarded connections, so
+    /// they never reach the guest
+    pub rejected_on: Option<String>,
+}
+
+impl FirewallRule {
+    /// Why the forward doesn't reach the guest, when libvirt rejects it
+    pub fn rejection(&self) -> Option<String> {
+        let bridge = self.rejected_on.as_deref()?;
+        Some(format!(
+            "libvirt rejects new connections into {} on VM '{}''s network, so port {}/{} never reaches it; \
+             put the VM on a routed or bridged network to take inbound connections",
+            bridge, self.vm, self.forward.host_port, self.forward.protocol
+        ))
+    }
+}
+
+/// Fails when two rules claim the same host port
+pub fn check_conflicts(rules: &[FirewallRule]) -> Result<()> {
+    for (index, rule) in rules.iter().enumerate() {
+        let taken = rules[..index].iter().find(|other| {
+            other.forward.host_port == rule.forward.host_port && other.forward.protocol == rule.forward.protocol
+        });
+        if let Some(other) = taken {
+            return Err(VmError::InvalidInput(format!(
+                "Host port {}/{} already goes to VM '{}'",
+                rule.forward.host_port, rule.forward.protocol, other.vm
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The `nft -f` script replacing the vmtools table with one forwarding `rules`,
+/// in a single transaction, so a guest with a new lease only needs it rebuilt.
+/// New connections to a host port are rewritten to the guest in prerouting and
+/// let through in forward; replies follow the connection back out.
+pub fn ruleset(rules: &[FirewallRule]) -> String {
+    // Declaring the table first makes the delete succeed on the first run too
+    let mut script = format!("table ip {table}\ndelete table ip {table}\n", table = TABLE);
+    let rules: Vec<(&FirewallRule, Ipv4Addr)> = rules.iter()
+        .filter_map(|rule| Some((rule, rule.address?)))
+        .collect();
+    if rules.is_empty() {
+        return script;
+    }
+
+    script.push_str(&format!("table ip {} {{\n", TABLE));
+    script.push_str("\tchain prerouting {\n\t\ttype nat hook prerouting priority dstnat; policy accept;\n");
+    for (rule, address) in &rules {
+        let PortForward { host_port, guest_port, protocol } = rule.forward;
+        script.push_str(&format!(
+            "\t\tfib daddr type local {} dport {} dnat to {}:{} comment \"{}\"\n",
+            protocol, host_port, address, guest_port, rule.vm
+        ));
+    }
+    script.push_str("\t}\n");
+    script.push_str("\tchain forward {\n\t\ttype filter hook forward priority filter - 1; policy accept;\n");
+    for (rule, address) in &rules {
+        script.push_str(&format!(
+            "\t\tip daddr {} {} dport {} ct status dnat accept comment \"{}\"\n",
+            address, rule.forward.protocol, rule.forward.guest_port, rule.vm
+        ));
+    }
+    script.push_str("\t}\n}\n");
+    script
+}
+
+/// The bridge on which libvirt's own rules in `host_rules` (`iptables-save -t filter`
+/// or `nft list ruleset`) reject connections forwarded to `address`. libvirt lets
+/// only replies into a NAT network and rejects the rest, and an accept in the
+/// vmtools table can't override a reject in another table or chain.
+pub fn rejecting_bridge(host_rules: &str, address: Ipv4Addr) -> Option<String> {
+    let rules: Vec<LibvirtRule> = host_rules.lines().filter_map(LibvirtRule::parse).collect();
+    let bridge = rules.iter()
+        .find(|rule| rule.subnet.is_some_and(|subnet| Ipv4Subnet::containing(address, subnet.prefix) == subnet))?
+        .bridge;
+    // Routed and open networks accept new connections ahead of the reject
+    let opened = rules.iter().any(|rule| rule.bridge == bridge && rule.subnet.is_some() && rule.accepts_new);
+    let rejected = rules.iter().any(|rule| rule.bridge == bridge && rule.subnet.is_none() && rule.rejects);
+    (rejected && !opened).then(|| bridge.to_string())
+}
+
+/// A rule on traffic leaving through a bridge, as libvirt writes them for iptables
+/// (`-A LIBVIRT_FWI -o virbr0 -j REJECT`) and nftables (`oif "virbr0" reject`)
+struct LibvirtRule<'a> {
+    bridge: &'a str,
+    subnet: Option<Ipv4Subnet>,
+    accepts_new: bool,
+    rejects: bool,
+}
+
+impl<'a> LibvirtRule<'a> {
+    fn parse(line: &'a str) -> Option<Self> {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let after = |keys: &[&str]| tokens.windows(2)
+            .find(|pair| keys.contains(&pair[0]))
+            .map(|pair| pair[1].trim_matches('"'));
+        let bridge = after(&["-o", "oif", "oifname"])?;
+        let stateful = tokens.iter().any(|token| *token == "--ctstate" || *token == "state");
+        let accepts = tokens.iter().any(|token| token.eq_ignore_ascii_case("accept"));
+        Some(Self {
+            bridge,
+            subnet: after(&["-d", "daddr"]).and_then(|subnet| subnet.parse().ok()),
+            accepts_new: accepts && !stateful,
+            rejects: tokens.iter().any(|token| token.eq_ignore_ascii_case("reject")),
+        })
+    }
+}
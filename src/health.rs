@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::{
+    backend::Backend,
+    error::{VmError, Result},
+    runner::{CommandRunner, Invocation},
+};
+
+/// How long a single TCP connect may take before the check fails
+const TCP_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// A probe telling whether a running VM actually serves what it should
+#[derive(Debug, Clone, PartialEq)]
+pub enum HealthCheck {
+    /// The guest answers ICMP echo requests
+    Ping,
+    /// Something accepts connections on this TCP port
+    Tcp(u16),
+    /// The QEMU guest agent answers `guest-ping`
+    Agent,
+    /// The URL answers with a 2xx/3xx status; `{ip}` is replaced by the guest's address
+    Http(String),
+}
+
+impl HealthCheck {
+    pub fn needs_ip(&self) -> bool {
+        match self {
+            HealthCheck::Ping | HealthCheck::Tcp(_) => true,
+            HealthCheck::Agent => false,
+            HealthCheck::Http(url) => url.contains("{ip}"),
+        }
+    }
+
+    /// Runs the check against the VM `name`, reachable at `ip`; ping and curl go through `runner`
+    pub async fn run(&self, backend: &dyn Backend, runner: &dyn CommandRunner, name: &str, ip: Option<&str>) -> HealthResult {
+        let outcome = match (self.needs_ip(), ip) {
+            (true, None) => Err(VmError::NetworkError("no IP address".to_string())),
+            _ => self.probe(backend, runner, name, ip.unwrap_or_default()).await,
+        };
+        HealthResult {
+            check: self.to_string(),
+            passed: outcome.is_ok(),
+            error: outcome.err().map(|e| e.to_string()),
+        }
+    }
+
+    async fn probe(&self, backend: &dyn Backend, runner: &dyn CommandRunner, name: &str, ip: &str) -> Result<()> {
+        match self {
+            HealthCheck::Ping => {
+                command_succeeds(runner, "ping", &["-c", "1", "-W", "2", ip]).await
+            }
+            HealthCheck::Tcp(port) => {
+                match tokio::time::timeout(TCP_TIMEOUT, tokio::net::TcpStream::connect((ip, *port))).await {
+                    Ok(Ok(_)) => Ok(()),
+                    Ok(Err(e)) => Err(VmError::NetworkError(format!("port {}: {}", port, e))),
+                    Err(_) => Err(VmError::Timeout(format!("port {} did not answer", port))),
+                }
+            }
+            HealthCheck::Agent => {
+                backend.agent_command(name, &serde_json::json!({ "execute": "guest-ping" })).await?;
+                Ok(())
+            }
+            HealthCheck::Http(url) => {
+                let url = url.replace("{ip}", ip);
+                command_succeeds(runner, "curl", &["-fsS", "-o", "/dev/null", "--max-time", "5", &url]).await
+            }
+        }
+    }
+}
+
+async fn command_succeeds(runner: &dyn CommandRunner, program: &str, args: &[&str]) -> Result<()> {
+    let output = runner.output(&Invocation::new(program).args(args))
+        .await
+        .map_err(|e| VmError::CommandError(format!("Failed to execute {}: {}", program, e)))?;
+
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        let error = error.trim();
+        return Err(VmError::NetworkError(if error.is_empty() {
+            format!("{} failed", program)
+        } else {
+            error.to_string()
+        }));
+    }
+    Ok(())
+}
+
+impl std::fmt::Display for HealthCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            HealthCheck::Ping => write!(f, "ping"),
+            HealthCheck::Tcp(port) => write!(f, "tcp:{}", port),
+            HealthCheck::Agent => write!(f, "agent"),
+            HealthCheck::Http(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+impl std::str::FromStr for HealthCheck {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.starts_with("http://") || s.starts_with("https://") {
+            return if s.chars().any(|c| c.is_whitespace() || matches!(c, '<' | '>' | '"' | '\'')) {
+                Err(format!("Invalid health check URL '{}'", s))
+            } else {
+                Ok(HealthCheck::Http(s.to_string()))
+            };
+        }
+        match s.to_lowercase().as_str() {
+            "ping" | "icmp" => Ok(HealthCheck::Ping),
+            "agent" | "guest-agent" => Ok(HealthCheck::Agent),
+            spec => spec.strip_prefix("tcp:")
+                .and_then(|port| port.parse::<u16>().ok())
+                .filter(|port| *port > 0)
+                .map(HealthCheck::Tcp)
+                .ok_or_else(|| format!(
+                    "Invalid health check '{}'. Use ping, agent, tcp:<port> or an http(s):// URL", s
+                )),
+        }
+    }
+}
+
+/// Outcome of one health check
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HealthResult {
+    /// The check as written in its spec, e.g. `tcp:22`
+    pub check: String,
+    pub passed: bool,
+    pub error: Option<String>,
+}
+
+/// One-word summary of a VM's check results, as shown in the HEALTH column
+pub fn summary(results: &[HealthResult]) -> String {
+    let failed = results.iter().filter(|result| !result.passed).count();
+    match (results.len(), failed) {
+        (0, _) => "-".to_string(),
+        (_, 0) => "healthy".to_string(),
+        (total, failed) => format!("unhealthy ({}/{})", failed, total),
+    }
+}
@@ -0,0 +1,156 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::{VmError, Result};
+
+/// A host kernel setting that matters for running VMs
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostSetting {
+    pub name: &'static str,
+    /// Files holding the setting; one per CPU for the frequency governor
+    pub paths: Vec<PathBuf>,
+    /// The value in effect, or the distinct values joined by `,` when files disagree
+    pub current: String,
+    /// Values that work well for VMs; the first is what `apply` writes
+    pub recommended: &'static [&'static str],
+    pub reason: &'static str,
+    /// How to change it where writing the files can't, e.g. a module parameter
+    pub manual: Option<String>,
+}
+
+impl HostSetting {
+    pub fn deviates(&self) -> bool {
+        !self.recommended.contains(&self.current.as_str())
+    }
+
+    /// Writes the first recommended value to every file of the setting; this
+    /// lasts until the next reboot
+    pub fn apply(&self) -> Result<()> {
+        if let Some(manual) = &self.manual {
+            return Err(VmError::OperationError(format!("{} can't be changed at runtime: {}", self.name, manual)));
+        }
+        for path in &self.paths {
+            std::fs::write(path, self.recommended[0]).map_err(|e| match e.kind() {
+                std::io::ErrorKind::PermissionDenied => VmError::PermissionDenied(format!(
+                    "Cannot write {}: run 'sudo vmtools host tune --apply'", path.display()
+                )),
+                _ => VmError::OperationError(format!("Failed to write {}: {}", path.display(), e)),
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// The virtualization-related settings of this host, skipping those it doesn't have
+pub fn inspect() -> Vec<HostSetting> {
+    inspect_at(Path::new("/"))
+}
+
+/// Like `inspect`, reading `/proc` and `/sys` below `root`
+pub fn inspect_at(root: &Path) -> Vec<HostSetting> {
+    let mut settings = Vec::new();
+    let mut setting = |name, paths: Vec<PathBuf>, recommended, reason, manual| {
+        let mut values: Vec<String> = paths.iter().filter_map(|path| read_value(path)).collect();
+        values.dedup();
+        if !values.is_empty() {
+            settings.push(HostSetting { name, paths, current: values.join(","), recommended, reason, manual });
+        }
+    };
+
+    setting(
+        "KSM",
+        vec![root.join("sys/kernel/mm/ksm/run")],
+        &["1"],
+        "merges identical guest pages, fitting more VMs into RAM (leave off where guests must not share pages)",
+        None,
+    );
+    setting(
+        "vm.swappiness",
+        vec![root.join("proc/sys/vm/swappiness")],
+        &["10", "1", "0"],
+        "keeps guest memory in RAM instead of swapping it out under pressure",
+        None,
+    );
+    setting(
+        "Transparent hugepages",
+        vec![root.join("sys/kernel/mm/transparent_hugepage/enabled")],
+        &["madvise", "always"],
+        "lets QEMU back guest RAM with huge pages, cutting TLB misses",
+        None,
+    );
+    let mut governors: Vec<PathBuf> = std::fs::read_dir(root.join("sys/devices/system/cpu"))
+        .map(|entries| entries.flatten()
+            .filter(|entry| entry.file_name().to_string_lossy().strip_prefix("cpu")
+                .is_some_and(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_digit())))
+            .map(|entry| entry.path().join("cpufreq/scaling_governor"))
+            .filter(|path| path.exists())
+            .collect())
+        .unwrap_or_default();
+    governors.sort();
+    setting(
+        "CPU governor",
+        governors,
+        &["performance", "schedutil"],
+        "keeps vCPUs from waiting on the host CPU to clock up",
+        None,
+    );
+    for (module, vendor) in [("kvm_intel", "Intel"), ("kvm_amd", "AMD")] {
+        setting(
+            "Nested virtualization",
+            vec![root.join(format!("sys/module/{}/parameters/nested", module))],
+            &["Y"],
+            "lets guests run their own VMs",
+            Some(format!(
+                "echo 'options {} nested=1' | sudo tee /etc/modprobe.d/kvm-nested.conf, then reload {} with \
+                 all VMs stopped (sudo modprobe -r {} && sudo modprobe {}) [{}]",
+                module, module, module, module, vendor
+            )),
+        );
+    }
+    settings
+}
+
+/// The value in a sysfs or procfs file: the selected one of `always [madvise] never`,
+/// nested virtualization's `1`/`0` as `Y`/`N`, anything else as written
+fn read_value(path: &Path) -> Option<String> {
+    let content = std::fs::read_to_string(path).ok()?;
+    let content = content.trim();
+    if let Some((_, selected)) = content.split_once('[') {
+        return selected.split(']').next().map(String::from);
+    }
+    Some(match content {
+        "1" if path.ends_with("parameters/nested") => "Y".to_string(),
+        "0" if path.ends_with("parameters/nested") => "N".to_string(),
+        value => value.to_string(),
+    })
+}
+
+/// QEMU processes on this host as (PID, guest name), from their `-name` argument
+pub fn qemu_processes() -> Vec<(u32, String)> {
+    qemu_processes_at(Path::new("/"))
+}
+
+/// Like `qemu_processes`, reading `/proc` below `root`
+pub fn qemu_processes_at(root: &Path) -> Vec<(u32, String)> {
+    let Ok(entries) = std::fs::read_dir(root.join("proc")) else {
+        return Vec::new();
+    };
+    let mut processes: Vec<(u32, String)> = entries.flatten()
+        .filter_map(|entry| {
+            let pid = entry.file_name().to_string_lossy().parse().ok()?;
+            let cmdline = std::fs::read(entry.path().join("cmdline")).ok()?;
+            let args: Vec<String> = cmdline.split(|byte| *byte == 0)
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect();
+            let program = Path::new(args.first()?).file_name()?.to_string_lossy().into_owned();
+            if !program.starts_with("qemu-system") {
+                return None;
+            }
+            // libvirt passes `guest=web,debug-threads=on`, vmtools' own backend just `web`
+            let name = args.iter().skip_while(|arg| *arg != "-name").nth(1)?;
+            let name = name.strip_prefix("guest=").unwrap_or(name);
+            Some((pid, name.split(',').next()?.to_string()))
+        })
+        .collect();
+    processes.sort();
+    processes
+}
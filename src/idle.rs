@@ -0,0 +1,40 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::{config::IdleConfig, stats::VmUsage};
+
+/// Follows how long each running VM has been idle under an `IdleConfig`
+#[derive(Debug, Default)]
+pub struct IdleTracker {
+    idle_since: HashMap<String, Instant>,
+}
+
+impl IdleTracker {
+    /// Records one sampling interval ending at `now` and returns the VMs idle for
+    /// at least `policy.minutes`, counted from the first interval that found them
+    /// idle. Any activity starts the count over; VMs missing from `usage` have
+    /// stopped and are forgotten.
+    pub fn observe(&mut self, usage: &[VmUsage], policy: &IdleConfig, now: Instant) -> Vec<String> {
+        self.idle_since.retain(|name, _| usage.iter().any(|vm| &vm.name == name));
+
+        let threshold = Duration::from_secs(policy.minutes * 60);
+        let mut due = Vec::new();
+        for vm in usage {
+            let idle = vm.cpu_percent < policy.cpu_threshold && vm.net_rate() < policy.net_threshold;
+            if !idle || policy.allowlist.contains(&vm.name) {
+                self.idle_since.remove(&vm.name);
+                continue;
+            }
+            let since = *self.idle_since.entry(vm.name.clone()).or_insert(now);
+            if now.duration_since(since) >= threshold {
+                due.push(vm.name.clone());
+            }
+        }
+        due
+    }
+
+    /// Forgets a VM, e.g. once it has been suspended
+    pub fn reset(&mut self, name: &str) {
+        self.idle_since.remove(name);
+    }
+}
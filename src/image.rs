@@ -0,0 +1,105 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    error::{VmError, Result},
+    runner::{CommandRunner, Invocation},
+    utils,
+};
+
+/// A public key to append to a guest account's `~/.ssh/authorized_keys`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SshInject {
+    pub user: String,
+    pub key_file: PathBuf,
+}
+
+impl std::str::FromStr for SshInject {
+    type Err = VmError;
+
+    /// Parses `<user>:<public key file>`
+    fn from_str(s: &str) -> Result<Self> {
+        let (user, key_file) = s.split_once(':')
+            .filter(|(_, key_file)| !key_file.is_empty())
+            .ok_or_else(|| VmError::InvalidInput(format!("Invalid SSH key '{}'. Use <user>:<public key file>", s)))?;
+        utils::validate_guest_user(user)?;
+        Ok(Self {
+            user: user.to_string(),
+            key_file: PathBuf::from(key_file),
+        })
+    }
+}
+
+/// Changes libguestfs makes inside a disk image, applied in this order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Customization {
+    /// Packages to install with the guest's package manager
+    pub install: Vec<String>,
+    /// Shell commands run inside the image, as root
+    pub run_commands: Vec<String>,
+    pub ssh_keys: Vec<SshInject>,
+}
+
+impl Customization {
+    pub fn is_empty(&self) -> bool {
+        self.install.is_empty() && self.run_commands.is_empty() && self.ssh_keys.is_empty()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some(package) = self.install.iter().find(|package| {
+            package.is_empty()
+                || package.starts_with('-')
+                || !package.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.' | '_' | ':'))
+        }) {
+            return Err(VmError::InvalidInput(format!("Invalid package name '{}'", package)));
+        }
+        if self.run_commands.iter().any(|command| command.trim().is_empty()) {
+            return Err(VmError::InvalidInput("Commands to run can't be empty".to_string()));
+        }
+        if let Some(key) = self.ssh_keys.iter().find(|key| !key.key_file.is_file()) {
+            return Err(VmError::InvalidInput(format!("SSH key file {} not found", key.key_file.display())));
+        }
+        Ok(())
+    }
+
+    /// Operation arguments for `virt-customize`, which applies them in command-line order
+    pub fn virt_customize_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if !self.install.is_empty() {
+            args.extend(["--install".to_string(), self.install.join(",")]);
+        }
+        for command in &self.run_commands {
+            args.extend(["--run-command".to_string(), command.clone()]);
+        }
+        for key in &self.ssh_keys {
+            args.extend(["--ssh-inject".to_string(), format!("{}:file:{}", key.user, key.key_file.display())]);
+        }
+        args
+    }
+}
+
+/// Runs `virt-customize` on `image`, its progress going straight to the terminal;
+/// installing packages takes as long as the downloads do, so there is no timeout
+pub async fn customize(runner: &dyn CommandRunner, image: &Path, customization: &Customization) -> Result<()> {
+    let customize = Invocation::new("virt-customize")
+        .arg("-a")
+        .arg(image)
+        .args(customization.virt_customize_args());
+    let status = runner.status(&customize)
+        .await
+        .map_err(spawn_error)?;
+
+    if !status.success() {
+        return Err(VmError::CommandError(format!("virt-customize failed on {}", image.display())));
+    }
+    Ok(())
+}
+
+/// Error for a `virt-customize` that couldn't be started, with the package to install
+pub(crate) fn spawn_error(e: std::io::Error) -> VmError {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => VmError::CommandError(
+            "virt-customize not found; install libguestfs-tools (guestfs-tools on Fedora)".to_string()
+        ),
+        _ => VmError::CommandError(format!("Failed to execute virt-customize: {}", e)),
+    }
+}
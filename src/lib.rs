@@ -0,0 +1,64 @@
+//! Core VM management for vmtools.
+//!
+//! `vmtools_core` wraps libvirt (`virsh`), QEMU's monitor protocol and the
+//! vmtools configuration behind an async API. Query methods return plain data
+//! types so other frontends can render them however they like; operations
+//! still report their progress on stdout as they go. The library never reads
+//! stdin: questions go to a callback the frontend supplies. The `vmtools`
+//! binary is a thin CLI over this crate.
+//!
+//! ```no_run
+//! use vmtools_core::{config::Config, runner::SystemRunner, vm::{ListOptions, VmManager}};
+//!
+//! # async fn example() -> vmtools_core::error::Result<()> {
+//! let config = Config::load(&SystemRunner).await?;
+//! // Falls back to the session URI without asking, if it has to
+//! let manager = VmManager::new(&config, &|_, default| default).await?;
+//! for vm in manager.list(&ListOptions { all: true, ..Default::default() }).await? {
+//!     println!("{} {}", vm.name, vm.state.label());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod arch;
+pub mod backend;
+pub mod capabilities;
+pub mod cloud_init;
+pub mod config;
+pub mod diagnosis;
+pub mod domain;
+pub mod error;
+pub mod events;
+pub mod firewall;
+pub mod health;
+pub mod host;
+pub mod idle;
+pub mod image;
+pub mod libvirt;
+pub mod logging;
+pub mod manifest;
+pub mod mock;
+pub mod network;
+pub mod notify;
+pub mod optimize;
+pub mod osinfo;
+pub mod privilege;
+pub mod qemu;
+pub mod qemu_backend;
+pub mod recording;
+pub mod retention;
+pub mod runner;
+pub mod secret;
+pub mod setup;
+pub mod stats;
+pub mod storage;
+pub mod systemd;
+pub mod terminal;
+pub mod trash;
+pub mod utils;
+pub mod validation;
+pub mod vault;
+pub mod virsh;
+pub mod vm;
+pub mod webhook;
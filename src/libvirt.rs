@@ -1,109 +1,123 @@
+use async_trait::async_trait;
+use base64::Engine;
+use std::path::{Path, PathBuf};
+use std::process::Output;
 use std::str;
-use tokio::process::Command as AsyncCommand;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 
 use crate::{
+    backend::{Backend, ConsoleOptions},
+    capabilities::HostCapabilities,
+    diagnosis,
+    domain::{self, VmMetadata},
     error::{VmError, Result},
-    vm::{VmInfo, VmState, DiskInfo, NetworkInfo},
+    events::EventStream,
+    image::{self, Customization},
+    privilege::{self, AccessLevel, Privileges},
+    runner::Invocation,
+    secret::{self, SecretInfo},
+    stats::{self, DomainStats},
+    storage::{self, PoolInfo, VolumeInfo},
+    utils::{self, CopyProgress},
+    virsh,
+    vm::{NetworkInfo, VmInfo, VmState, SnapshotInfo},
 };
 
+/// Maximum number of domains queried concurrently by `list_domains`
+const LIST_CONCURRENCY: usize = 8;
+
+#[derive(Clone)]
 pub struct LibvirtClient {
-    uri: String,
+    privileges: Privileges,
     temp_dir: String,
+    timeout: Duration,
 }
 
 impl LibvirtClient {
-    pub async fn new(uri: &str, temp_dir: &str) -> Result<Self> {
+    pub async fn new(privileges: Privileges, temp_dir: &str, timeout_secs: u64) -> Result<Self> {
+        if privileges.is_unresponsive() {
+            return Err(VmError::Timeout(format!(
+                "libvirt at {} did not answer within libvirt.timeout; check that libvirtd is not hung",
+                privileges.uri()
+            )));
+        }
+        if privileges.access() == AccessLevel::ReadOnly {
+            tracing::warn!("Read-only access to libvirt; operations that modify VMs will be refused");
+        }
+
+        let client = Self {
+            privileges,
+            temp_dir: temp_dir.to_string(),
+            timeout: Duration::from_secs(timeout_secs),
+        };
+
         // Test connection
-        let output = AsyncCommand::new("virsh")
-            .args(&["-c", uri, "version"])
-            .output()
-            .await
-            .map_err(|e| VmError::LibvirtError(format!("Failed to execute virsh: {}", e)))?;
+        let output = client.run(client.privileges.virsh_read(&["version"])?, "connect to libvirt").await?;
 
         if !output.status.success() {
             let error = String::from_utf8_lossy(&output.stderr);
             return Err(VmError::LibvirtError(format!("Failed to connect to libvirt: {}", error)));
         }
 
-        Ok(Self {
-            uri: uri.to_string(),
-            temp_dir: temp_dir.to_string(),
-        })
+        Ok(client)
     }
 
-    pub async fn list_domains(&self, all: bool) -> Result<Vec<VmInfo>> {
-        let args = if all {
-            vec!["-c", &self.uri, "list", "--all"]
-        } else {
-            vec!["-c", &self.uri, "list"]
-        };
+    /// Runs a virsh command, killing it and returning `VmError::Timeout` if it
+    /// outlives the configured libvirt timeout (0 disables the limit). A command
+    /// refused for lack of permission fails with `VmError::PermissionDenied` and
+    /// how to get it, instead of virsh's own message.
+    async fn run(&self, invocation: Invocation, operation: &str) -> Result<Output> {
+        let output = self.privileges.runner().output(&invocation.timeout(self.timeout)).await
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::TimedOut => VmError::Timeout(format!(
+                    "{} did not finish within {}s (libvirt.timeout)", operation, self.timeout.as_secs()
+                )),
+                _ => VmError::LibvirtError(format!("Failed to {}: {}", operation, e)),
+            })?;
+        self.check_permission(&output, operation)?;
+        Ok(output)
+    }
+
+    fn check_permission(&self, output: &Output, operation: &str) -> Result<()> {
+        if output.status.success() {
+            return Ok(());
+        }
+        match self.privileges.explain_failure(operation, &String::from_utf8_lossy(&output.stderr)) {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
 
-        let output = AsyncCommand::new("virsh")
-            .args(&args)
-            .output()
+    /// Runs a block job with `--verbose` progress going straight to the terminal;
+    /// jobs take as long as the data takes to copy, so there is no timeout
+    async fn block_job(&self, name: &str, args: &[&str], job: &str) -> Result<()> {
+        let status = self.privileges.runner().status(&self.privileges.virsh_write(args)?)
             .await
-            .map_err(|e| VmError::LibvirtError(format!("Failed to list domains: {}", e)))?;
+            .map_err(|e| VmError::LibvirtError(format!("Failed to run block {}: {}", job, e)))?;
 
-        if !output.status.success() {
-            let error = String::from_utf8_lossy(&output.stderr);
-            return Err(VmError::LibvirtError(format!("Failed to list domains: {}", error)));
+        if !status.success() {
+            return Err(VmError::LibvirtError(format!("Block {} on VM '{}' failed", job, name)));
         }
 
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let mut vms = Vec::new();
-
-        for line in stdout.lines().skip(2) {
-            let line = line.trim();
-            if line.is_empty() || line.starts_with("---") {
-                continue;
-            }
+        Ok(())
+    }
 
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 3 {
-                let name = parts[1].to_string();
-                let state_str = parts[2];
-                
-                let state = match state_str {
-                    "running" => VmState::Running,
-                    "shut" => VmState::Stopped,
-                    "paused" => VmState::Paused,
-                    "in" => VmState::Stopped, // "in shutdown"
-                    _ => VmState::Unknown,
-                };
-
-                // Get detailed info for each VM
-                if let Ok(vm_info) = self.get_domain_info(&name).await {
-                    vms.push(vm_info);
-                } else {
-                    // Fallback with basic info
-                    vms.push(VmInfo {
-                        name,
-                        uuid: "unknown".to_string(),
-                        state,
-                        memory: 0,
-                        cpus: 0,
-                        uptime: None,
-                        cpu_usage: None,
-                        memory_usage: None,
-                        disk_usage: Vec::new(),
-                        network_info: Vec::new(),
-                        created_at: 0,
-                        last_started: None,
-                    });
-                }
-            }
+    /// Runs a virsh command and returns its stdout, or its stderr as the error
+    async fn checked(&self, invocation: Invocation, operation: &str) -> Result<String> {
+        let output = self.run(invocation, operation).await?;
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(VmError::LibvirtError(format!("Failed to {}: {}", operation, error.trim())));
         }
-
-        Ok(vms)
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
     }
 
-    pub async fn get_domain_info(&self, name: &str) -> Result<VmInfo> {
+    async fn fetch_domain_info(&self, name: &str, detailed: bool) -> Result<VmInfo> {
         // Get basic domain info
-        let dominfo_output = AsyncCommand::new("virsh")
-            .args(&["-c", &self.uri, "dominfo", name])
-            .output()
-            .await
-            .map_err(|e| VmError::LibvirtError(format!("Failed to get domain info: {}", e)))?;
+        let dominfo_output = self.run(self.privileges.virsh_read(&["dominfo", name])?, "get domain info").await?;
 
         if !dominfo_output.status.success() {
             let error = String::from_utf8_lossy(&dominfo_output.stderr);
@@ -113,13 +127,13 @@ impl LibvirtClient {
             return Err(VmError::LibvirtError(format!("Failed to get domain info: {}", error)));
         }
 
-        let dominfo = String::from_utf8_lossy(&dominfo_output.stdout);
+        let dominfo = virsh::parse_dominfo(&String::from_utf8_lossy(&dominfo_output.stdout));
         let mut vm_info = VmInfo {
             name: name.to_string(),
-            uuid: String::new(),
-            state: VmState::Unknown,
-            memory: 0,
-            cpus: 0,
+            uuid: dominfo.uuid,
+            state: dominfo.state,
+            memory: dominfo.memory / 1024, // Convert to MB
+            cpus: dominfo.cpus,
             uptime: None,
             cpu_usage: None,
             memory_usage: None,
@@ -127,39 +141,16 @@ impl LibvirtClient {
             network_info: Vec::new(),
             created_at: 0,
             last_started: None,
+            autostart: dominfo.autostart,
+            health: Vec::new(),
+            guest: None,
+            graphics: Vec::new(),
+            snapshot_count: None,
+            last_backup: None,
         };
 
-        // Parse dominfo output
-        for line in dominfo.lines() {
-            let parts: Vec<&str> = line.splitn(2, ':').collect();
-            if parts.len() == 2 {
-                let key = parts[0].trim();
-                let value = parts[1].trim();
-
-                match key {
-                    "UUID" => vm_info.uuid = value.to_string(),
-                    "State" => {
-                        vm_info.state = match value {
-                            "running" => VmState::Running,
-                            "shut off" => VmState::Stopped,
-                            "paused" => VmState::Paused,
-                            "suspended" => VmState::Suspended,
-                            _ => VmState::Unknown,
-                        };
-                    }
-                    "Max memory" => {
-                        if let Ok(memory_kb) = value.split_whitespace().next().unwrap_or("0").parse::<u64>() {
-                            vm_info.memory = memory_kb / 1024; // Convert to MB
-                        }
-                    }
-                    "CPU(s)" => {
-                        if let Ok(cpus) = value.parse::<u32>() {
-                            vm_info.cpus = cpus;
-                        }
-                    }
-                    _ => {}
-                }
-            }
+        if !detailed {
+            return Ok(vm_info);
         }
 
         // Get additional info if VM is running
@@ -174,21 +165,144 @@ impl LibvirtClient {
             vm_info.uptime = self.get_domain_uptime(name).await.ok();
         }
 
-        // Get disk info
-        vm_info.disk_usage = self.get_domain_disks(name).await.unwrap_or_default();
-
-        // Get network info
-        vm_info.network_info = self.get_domain_interfaces(name).await.unwrap_or_default();
+        // Disks and interfaces both come from the live definition
+        let xml = self.checked(self.privileges.virsh_read(&["dumpxml", name])?, "get domain XML").await.unwrap_or_default();
+        vm_info.disk_usage = virsh::parse_disks(&xml);
+        vm_info.network_info = virsh::parse_interfaces(&xml);
+        if vm_info.state == VmState::Running && !vm_info.network_info.is_empty() {
+            self.discover_addresses(name, &mut vm_info.network_info).await;
+        }
 
         Ok(vm_info)
     }
 
-    pub async fn get_domain_state(&self, name: &str) -> Result<VmState> {
-        let output = AsyncCommand::new("virsh")
-            .args(&["-c", &self.uri, "domstate", name])
-            .output()
-            .await
-            .map_err(|e| VmError::LibvirtError(format!("Failed to get domain state: {}", e)))?;
+    /// Looks up guest addresses in libvirt's DHCP leases (v4 and DHCPv6), then asks
+    /// the guest agent for interfaces still without one, e.g. on a host bridge
+    async fn discover_addresses(&self, name: &str, interfaces: &mut [NetworkInfo]) {
+        for source in ["lease", "agent"] {
+            if interfaces.iter().all(|net| net.ip_address.is_some() || net.ipv6_address.is_some()) {
+                break;
+            }
+            let Ok(inv) = self.privileges.virsh_read(&["domifaddr", name, "--source", source]) else {
+                break;
+            };
+            // No agent, or none answering, just leaves the addresses unknown
+            if let Ok(output) = self.checked(inv, "get interface addresses").await {
+                virsh::assign_addresses(interfaces, &output);
+            }
+        }
+    }
+
+    async fn get_domain_stats(&self, _name: &str) -> Result<(Option<f64>, Option<f64>)> {
+        // This is a simplified implementation - in a real scenario you'd parse domstats output
+        Ok((None, None))
+    }
+
+    /// How long the domain has run: libvirt writes its QEMU's pidfile once the
+    /// process is up, so that is the age of the file. Where it can't be read, as
+    /// for another user's system domains, it is the time since vmtools last started it.
+    async fn get_domain_uptime(&self, name: &str) -> Result<u64> {
+        let pid_file = self.qemu_run_dir().join(format!("{}.pid", name));
+        let age = tokio::fs::metadata(&pid_file).await.ok()
+            .and_then(|m| m.modified().ok())
+            .and_then(|t| t.elapsed().ok());
+        if let Some(age) = age {
+            return Ok(age.as_secs());
+        }
+
+        let xml = self.get_inactive_domain_xml(name).await?;
+        let started = VmMetadata::parse(&xml).and_then(|metadata| metadata.last_started)
+            .ok_or_else(|| VmError::OperationError(format!("No start time recorded for VM '{}'", name)))?;
+        Ok((chrono::Utc::now().timestamp() as u64).saturating_sub(started))
+    }
+
+    /// Where libvirt keeps the QEMU pidfiles of this connection's domains
+    fn qemu_run_dir(&self) -> PathBuf {
+        if self.privileges.uri().ends_with("/session") {
+            dirs::runtime_dir().unwrap_or_else(std::env::temp_dir).join("libvirt/qemu/run")
+        } else {
+            PathBuf::from("/run/libvirt/qemu")
+        }
+    }
+}
+
+#[async_trait]
+impl Backend for LibvirtClient {
+    /// Lists domains, fetching per-domain details concurrently.
+    ///
+    /// With `fast` set only `dominfo` is queried for each domain, skipping the
+    /// disk, interface and statistics lookups.
+    async fn list_domains(&self, all: bool, fast: bool) -> Result<Vec<VmInfo>> {
+        // One name per line: names may hold spaces, which the table can't tell
+        // apart from its column padding
+        let args: &[&str] = if all { &["list", "--all", "--name"] } else { &["list", "--name"] };
+
+        let output = self.run(self.privileges.virsh_read(args)?, "list domains").await?;
+
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(VmError::LibvirtError(format!("Failed to list domains: {}", error)));
+        }
+
+        let domains = virsh::parse_names(&String::from_utf8_lossy(&output.stdout));
+
+        // Each domain needs several virsh calls, so query them concurrently
+        // with a bounded number of in-flight domains
+        let semaphore = Arc::new(Semaphore::new(LIST_CONCURRENCY));
+        let mut tasks = JoinSet::new();
+
+        for (index, name) in domains.iter().enumerate() {
+            let client = self.clone();
+            let semaphore = semaphore.clone();
+            let name = name.clone();
+            tasks.spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                (index, client.fetch_domain_info(&name, !fast).await)
+            });
+        }
+
+        let mut details: Vec<Option<VmInfo>> = vec![None; domains.len()];
+        while let Some(joined) = tasks.join_next().await {
+            if let Ok((index, Ok(vm_info))) = joined {
+                details[index] = Some(vm_info);
+            }
+        }
+
+        let vms = domains.into_iter().zip(details)
+            .map(|(name, detail)| detail.unwrap_or_else(|| {
+                // Fallback with basic info; the state is only known from dominfo
+                VmInfo {
+                    name,
+                    uuid: "unknown".to_string(),
+                    state: VmState::Unknown,
+                    memory: 0,
+                    cpus: 0,
+                    uptime: None,
+                    cpu_usage: None,
+                    memory_usage: None,
+                    disk_usage: Vec::new(),
+                    network_info: Vec::new(),
+                    created_at: 0,
+                    last_started: None,
+                    autostart: false,
+                    health: Vec::new(),
+                    guest: None,
+                    graphics: Vec::new(),
+                    snapshot_count: None,
+                    last_backup: None,
+                }
+            }))
+            .collect();
+
+        Ok(vms)
+    }
+
+    async fn get_domain_info(&self, name: &str) -> Result<VmInfo> {
+        self.fetch_domain_info(name, true).await
+    }
+
+    async fn get_domain_state(&self, name: &str) -> Result<VmState> {
+        let output = self.run(self.privileges.virsh_read(&["domstate", name])?, "get domain state").await?;
 
         if !output.status.success() {
             let error = String::from_utf8_lossy(&output.stderr);
@@ -198,24 +312,11 @@ impl LibvirtClient {
             return Err(VmError::LibvirtError(format!("Failed to get domain state: {}", error)));
         }
 
-        let state_str = String::from_utf8_lossy(&output.stdout).trim().to_string();
-        let state = match state_str.as_str() {
-            "running" => VmState::Running,
-            "shut off" => VmState::Stopped,
-            "paused" => VmState::Paused,
-            "suspended" => VmState::Suspended,
-            _ => VmState::Unknown,
-        };
-
-        Ok(state)
+        Ok(virsh::parse_state(&String::from_utf8_lossy(&output.stdout)))
     }
 
-    pub async fn start_domain(&self, name: &str) -> Result<()> {
-        let output = AsyncCommand::new("virsh")
-            .args(&["-c", &self.uri, "start", name])
-            .output()
-            .await
-            .map_err(|e| VmError::LibvirtError(format!("Failed to start domain: {}", e)))?;
+    async fn start_domain(&self, name: &str) -> Result<()> {
+        let output = self.run(self.privileges.virsh_write(&["start", name])?, "start domain").await?;
 
         if !output.status.success() {
             let error = String::from_utf8_lossy(&output.stderr);
@@ -230,12 +331,8 @@ impl LibvirtClient {
         Ok(())
     }
 
-    pub async fn shutdown_domain(&self, name: &str) -> Result<()> {
-        let output = AsyncCommand::new("virsh")
-            .args(&["-c", &self.uri, "shutdown", name])
-            .output()
-            .await
-            .map_err(|e| VmError::LibvirtError(format!("Failed to shutdown domain: {}", e)))?;
+    async fn shutdown_domain(&self, name: &str) -> Result<()> {
+        let output = self.run(self.privileges.virsh_write(&["shutdown", name])?, "shutdown domain").await?;
 
         if !output.status.success() {
             let error = String::from_utf8_lossy(&output.stderr);
@@ -250,12 +347,40 @@ impl LibvirtClient {
         Ok(())
     }
 
-    pub async fn destroy_domain(&self, name: &str) -> Result<()> {
-        let output = AsyncCommand::new("virsh")
-            .args(&["-c", &self.uri, "destroy", name])
-            .output()
-            .await
-            .map_err(|e| VmError::LibvirtError(format!("Failed to destroy domain: {}", e)))?;
+    async fn pause_domain(&self, name: &str) -> Result<()> {
+        let output = self.run(self.privileges.virsh_write(&["suspend", name])?, "pause domain").await?;
+
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            if error.contains("not found") {
+                return Err(VmError::VmNotFound(name.to_string()));
+            } else if error.contains("not running") {
+                return Err(VmError::VmNotRunning(name.to_string()));
+            }
+            return Err(VmError::LibvirtError(format!("Failed to pause domain: {}", error)));
+        }
+
+        Ok(())
+    }
+
+    async fn resume_domain(&self, name: &str) -> Result<()> {
+        let output = self.run(self.privileges.virsh_write(&["resume", name])?, "resume domain").await?;
+
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            if error.contains("not found") {
+                return Err(VmError::VmNotFound(name.to_string()));
+            } else if error.contains("not running") {
+                return Err(VmError::VmNotRunning(name.to_string()));
+            }
+            return Err(VmError::LibvirtError(format!("Failed to resume domain: {}", error)));
+        }
+
+        Ok(())
+    }
+
+    async fn destroy_domain(&self, name: &str) -> Result<()> {
+        let output = self.run(self.privileges.virsh_write(&["destroy", name])?, "destroy domain").await?;
 
         if !output.status.success() {
             let error = String::from_utf8_lossy(&output.stderr);
@@ -268,17 +393,12 @@ impl LibvirtClient {
         Ok(())
     }
 
-    pub async fn define_domain(&self, xml: &str) -> Result<()> {
+    async fn define_domain(&self, xml: &str) -> Result<()> {
         // Write XML to temporary file using configurable temp directory
         let temp_file = format!("{}/vmtools_domain_{}.xml", self.temp_dir, uuid::Uuid::new_v4());
-        tokio::fs::write(&temp_file, xml).await
-            .map_err(|e| VmError::IoError(e))?;
+        utils::write_private_file(Path::new(&temp_file), xml).await?;
 
-        let output = AsyncCommand::new("virsh")
-            .args(&["-c", &self.uri, "define", &temp_file])
-            .output()
-            .await
-            .map_err(|e| VmError::LibvirtError(format!("Failed to define domain: {}", e)))?;
+        let output = self.run(self.privileges.virsh_write(&["define", &temp_file])?, "define domain").await?;
 
         // Clean up temp file
         let _ = tokio::fs::remove_file(&temp_file).await;
@@ -291,12 +411,8 @@ impl LibvirtClient {
         Ok(())
     }
 
-    pub async fn undefine_domain(&self, name: &str) -> Result<()> {
-        let output = AsyncCommand::new("virsh")
-            .args(&["-c", &self.uri, "undefine", name])
-            .output()
-            .await
-            .map_err(|e| VmError::LibvirtError(format!("Failed to undefine domain: {}", e)))?;
+    async fn undefine_domain(&self, name: &str) -> Result<()> {
+        let output = self.run(self.privileges.virsh_write(&["undefine", name, "--managed-save"])?, "undefine domain").await?;
 
         if !output.status.success() {
             let error = String::from_utf8_lossy(&output.stderr);
@@ -309,36 +425,53 @@ impl LibvirtClient {
         Ok(())
     }
 
-    pub async fn domain_exists(&self, name: &str) -> Result<bool> {
-        let output = AsyncCommand::new("virsh")
-            .args(&["-c", &self.uri, "dominfo", name])
-            .output()
-            .await
-            .map_err(|e| VmError::LibvirtError(format!("Failed to check domain existence: {}", e)))?;
+    async fn domain_exists(&self, name: &str) -> Result<bool> {
+        let output = self.run(self.privileges.virsh_read(&["dominfo", name])?, "check domain existence").await?;
 
         Ok(output.status.success())
     }
 
-    pub async fn connect_console(&self, name: &str) -> Result<()> {
-        let status = AsyncCommand::new("virsh")
-            .args(&["-c", &self.uri, "console", name])
-            .status()
-            .await
-            .map_err(|e| VmError::LibvirtError(format!("Failed to connect to console: {}", e)))?;
+    async fn block_commit(&self, name: &str, target: &str) -> Result<()> {
+        self.block_job(name, &["blockcommit", name, target, "--active", "--pivot", "--wait", "--verbose"], "commit").await
+    }
+
+    async fn block_pull(&self, name: &str, target: &str) -> Result<()> {
+        self.block_job(name, &["blockpull", name, target, "--wait", "--verbose"], "pull").await
+    }
+
+    async fn connect_console(&self, name: &str, options: ConsoleOptions<'_>) -> Result<()> {
+        // virsh takes the escape in caret notation, as a global option
+        let escape = format!("^{}", (options.escape ^ 0x40) as char);
+        let mut args = vec!["-e", &escape, "console", name];
+        if options.force {
+            args.push("--force");
+        }
+        let console = self.privileges.virsh_write(&args)?;
+        let (status, stderr) = match options.record {
+            Some(path) => self.privileges.runner().status_recorded(&console, path, &format!("{} console", name)).await
+                .map(|status| (status, Vec::new())),
+            None => self.privileges.runner().status_with_stderr(&console).await,
+        }
+        .map_err(|e| VmError::LibvirtError(format!("Failed to connect to console: {}", e)))?;
 
         if !status.success() {
+            if String::from_utf8_lossy(&stderr).contains("Active console session exists") {
+                return Err(VmError::InvalidVmState(format!(
+                    "Another session holds the console of '{}'; take it over with 'vmtools console {} --force'", name, name
+                )));
+            }
             return Err(VmError::LibvirtError("Failed to connect to console".to_string()));
         }
 
         Ok(())
     }
 
-    pub async fn get_domain_xml(&self, name: &str) -> Result<String> {
-        let output = AsyncCommand::new("sudo")
-            .args(&["virsh", "-c", &self.uri, "dumpxml", name])
-            .output()
-            .await
-            .map_err(|e| VmError::LibvirtError(format!("Failed to get domain XML: {}", e)))?;
+    fn subscribe_events(&self, domain: Option<&str>) -> Result<EventStream> {
+        EventStream::spawn(self.privileges.virsh_read(&[])?.to_command(), domain)
+    }
+
+    async fn get_domain_xml(&self, name: &str) -> Result<String> {
+        let output = self.run(self.privileges.virsh_read(&["dumpxml", name])?, "get domain XML").await?;
 
         if !output.status.success() {
             return Err(VmError::LibvirtError(format!(
@@ -351,126 +484,562 @@ impl LibvirtClient {
         Ok(String::from_utf8_lossy(&output.stdout).to_string())
     }
 
-    pub async fn list_networks(&self) -> Result<Vec<(String, bool, String, bool)>> {
-        let output = AsyncCommand::new("virsh")
-            .args(&["-c", &self.uri, "net-list", "--all"])
-            .output()
+    async fn get_inactive_domain_xml(&self, name: &str) -> Result<String> {
+        // Without --security-info libvirt leaves console passwords out, and a
+        // definition edited and defined again would lose them. A read-only
+        // connection may not ask for them, but can't define anything either.
+        let invocation = match self.privileges.access() {
+            AccessLevel::ReadOnly => self.privileges.virsh_read(&["dumpxml", "--inactive", name])?,
+            _ => self.privileges.virsh_read(&["dumpxml", "--inactive", "--security-info", name])?.sensitive(),
+        };
+        let output = self.run(invocation, "get domain XML").await?;
+
+        if !output.status.success() {
+            return Err(VmError::LibvirtError(format!(
+                "Failed to dump XML for domain '{}': {}",
+                name,
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    async fn agent_command(&self, name: &str, command: &serde_json::Value) -> Result<serde_json::Value> {
+        let command = command.to_string();
+        let output = self.run(
+            self.privileges.virsh_write(&["qemu-agent-command", name, &command, "--timeout", "10"])?,
+            "guest agent command",
+        ).await?;
+
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(VmError::LibvirtError(format!("Guest agent command failed: {}", error.trim())));
+        }
+
+        let response: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+        Ok(response.get("return").cloned().unwrap_or(serde_json::Value::Null))
+    }
+
+    async fn monitor_command(&self, name: &str, command: &serde_json::Value) -> Result<serde_json::Value> {
+        let command = command.to_string();
+        let output = self.run(
+            self.privileges.virsh_write(&["qemu-monitor-command", name, &command])?,
+            "monitor command",
+        ).await?;
+
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(VmError::LibvirtError(format!("Monitor command failed: {}", error.trim())));
+        }
+
+        let response: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+        Ok(response.get("return").cloned().unwrap_or(serde_json::Value::Null))
+    }
+
+    async fn set_memory_target(&self, name: &str, memory: u64) -> Result<()> {
+        let size = format!("{}M", memory);
+        let output = self.run(self.privileges.virsh_write(&["setmem", name, &size, "--live"])?, "set memory").await?;
+
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(VmError::LibvirtError(format!("Failed to set memory: {}", error.trim())));
+        }
+
+        Ok(())
+    }
+
+    async fn managed_save(&self, name: &str) -> Result<()> {
+        // Writing out guest RAM takes as long as the disk needs, so there is no timeout
+        let output = self.privileges.runner().output(&self.privileges.virsh_write(&["managedsave", name])?)
             .await
-            .map_err(|e| VmError::LibvirtError(format!("Failed to list networks: {}", e)))?;
+            .map_err(|e| VmError::LibvirtError(format!("Failed to save {}: {}", name, e)))?;
+        self.check_permission(&output, "save domain")?;
 
         if !output.status.success() {
             let error = String::from_utf8_lossy(&output.stderr);
-            return Err(VmError::LibvirtError(format!("Failed to list networks: {}", error)));
+            return Err(VmError::LibvirtError(format!("Failed to save {}: {}", name, error.trim())));
         }
+        Ok(())
+    }
 
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let mut networks = Vec::new();
+    async fn list_managed_saves(&self) -> Result<Vec<String>> {
+        let output = self.run(
+            self.privileges.virsh_read(&["list", "--all", "--with-managed-save", "--name"])?,
+            "list managed saves",
+        ).await?;
 
-        for line in stdout.lines().skip(2) {
-            let line = line.trim();
-            if line.is_empty() || line.starts_with("---") {
-                continue;
-            }
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(VmError::LibvirtError(format!("Failed to list managed saves: {}", error.trim())));
+        }
+        Ok(virsh::parse_names(&String::from_utf8_lossy(&output.stdout)))
+    }
 
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 4 {
-                let name = parts[0].to_string();
-                let active = parts[1] == "active";
-                let autostart = parts[2] == "yes";
-                let bridge = if parts.len() > 3 { parts[3].to_string() } else { "-".to_string() };
+    async fn update_device(&self, name: &str, device_xml: &str) -> Result<()> {
+        let temp_file = format!("{}/vmtools_device_{}.xml", self.temp_dir, uuid::Uuid::new_v4());
+        utils::write_private_file(Path::new(&temp_file), device_xml).await?;
 
-                networks.push((name, active, bridge, autostart));
-            }
+        let output = self.run(
+            self.privileges.virsh_write(&["update-device", name, &temp_file, "--live"])?,
+            "update device",
+        ).await;
+        let _ = tokio::fs::remove_file(&temp_file).await;
+        let output = output?;
+
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(VmError::LibvirtError(format!("Failed to update device: {}", error.trim())));
         }
 
-        Ok(networks)
+        Ok(())
     }
 
-    async fn get_domain_stats(&self, _name: &str) -> Result<(Option<f64>, Option<f64>)> {
-        // This is a simplified implementation - in a real scenario you'd parse domstats output
-        Ok((None, None))
+    async fn attach_device(&self, name: &str, device_xml: &str) -> Result<()> {
+        let temp_file = format!("{}/vmtools_device_{}.xml", self.temp_dir, uuid::Uuid::new_v4());
+        utils::write_private_file(Path::new(&temp_file), device_xml).await?;
+
+        let output = self.run(
+            self.privileges.virsh_write(&["attach-device", name, &temp_file, "--live"])?,
+            "attach device",
+        ).await;
+        let _ = tokio::fs::remove_file(&temp_file).await;
+        let output = output?;
+
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(VmError::LibvirtError(format!("Failed to attach device: {}", error.trim())));
+        }
+
+        Ok(())
     }
 
-    async fn get_domain_uptime(&self, _name: &str) -> Result<u64> {
-        // This would require parsing more detailed libvirt output
-        Ok(0)
+    async fn set_interface_bandwidth(&self, name: &str, mac: &str, inbound: u64, outbound: u64) -> Result<()> {
+        // An average of 0 removes the limit
+        let (inbound, outbound) = (inbound.to_string(), outbound.to_string());
+        let output = self.run(
+            self.privileges.virsh_write(&["domiftune", name, mac, "--inbound", &inbound, "--outbound", &outbound, "--live"])?,
+            "tune interface",
+        ).await?;
+
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(VmError::LibvirtError(format!("Failed to set bandwidth: {}", error.trim())));
+        }
+
+        Ok(())
     }
 
-    async fn get_domain_disks(&self, name: &str) -> Result<Vec<DiskInfo>> {
-        let output = AsyncCommand::new("virsh")
-            .args(&["-c", &self.uri, "domblklist", name, "--details"])
-            .output()
-            .await
-            .map_err(|e| VmError::LibvirtError(format!("Failed to get domain disks: {}", e)))?;
+    async fn set_cpu_tune(&self, name: &str, shares: u64, period: u64, quota: i64) -> Result<()> {
+        let shares = format!("cpu_shares={}", shares);
+        let period = format!("vcpu_period={}", period);
+        let quota = format!("vcpu_quota={}", quota);
+        let output = self.run(
+            self.privileges.virsh_write(&["schedinfo", name, "--live", "--set", &shares, "--set", &period, "--set", &quota])?,
+            "set scheduler parameters",
+        ).await?;
 
         if !output.status.success() {
-            return Ok(Vec::new());
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(VmError::LibvirtError(format!("Failed to set CPU limits: {}", error.trim())));
         }
 
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let mut disks = Vec::new();
+        Ok(())
+    }
 
-        for line in stdout.lines().skip(2) {
-            let line = line.trim();
-            if line.is_empty() || line.starts_with("---") {
-                continue;
+    async fn list_networks(&self) -> Result<Vec<(String, bool, String, bool)>> {
+        let names = self.checked(self.privileges.virsh_read(&["net-list", "--all", "--name"])?, "list networks").await?;
+        let mut networks = Vec::new();
+        for name in virsh::parse_names(&names) {
+            let info = self.checked(self.privileges.virsh_read(&["net-info", &name])?, "get network info").await?;
+            let active = virsh::parse_field(&info, "Active") == Some("yes");
+            let autostart = virsh::parse_field(&info, "Autostart") == Some("yes");
+            let bridge = virsh::parse_field(&info, "Bridge").unwrap_or("-").to_string();
+            networks.push((name, active, bridge, autostart));
+        }
+        Ok(networks)
+    }
+
+    async fn change_media(&self, name: &str, target: &str, media: Option<&str>) -> Result<()> {
+        let mut args = vec!["change-media", name, target];
+        match media {
+            Some(path) => args.extend(["--update", path]),
+            None => args.push("--eject"),
+        }
+        // --live fails on a stopped domain, so only add it when running
+        if self.get_domain_state(name).await? == VmState::Running {
+            args.push("--live");
+        }
+        args.extend(["--config", "--force"]);
+
+        let output = self.run(self.privileges.virsh_write(&args)?, "change media").await?;
+
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(VmError::LibvirtError(format!("Failed to change media: {}", error)));
+        }
+
+        Ok(())
+    }
+
+    async fn create_disk(&self, path: &Path, size_bytes: u64) -> Result<()> {
+        utils::create_qcow2_image(self.privileges.runner(), path, size_bytes).await
+    }
+
+    async fn clone_disk(&self, source: &Path, target: &Path, progress: CopyProgress<'_>) -> Result<()> {
+        utils::clone_qcow2_image(self.privileges.runner(), source, target, progress).await
+    }
+
+    async fn import_disk(&self, source: &Path, target: &Path, link: bool, progress: CopyProgress<'_>) -> Result<()> {
+        utils::import_image(self.privileges.runner(), source, target, link, progress).await
+    }
+
+    async fn create_snapshot(&self, name: &str, snapshot: &str, description: Option<&str>, memory: bool) -> Result<()> {
+        let mut args = vec!["snapshot-create-as", name, snapshot];
+        if let Some(description) = description {
+            args.extend(["--description", description]);
+        }
+        if memory {
+            // Ask for the RAM explicitly so libvirt fails rather than silently taking disks only
+            args.extend(["--memspec", "snapshot=internal", "--atomic"]);
+        }
+
+        let output = self.run(self.privileges.virsh_write(&args)?, "create snapshot").await?;
+
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            if error.contains("not found") {
+                return Err(VmError::VmNotFound(name.to_string()));
             }
+            return Err(VmError::LibvirtError(format!("Failed to create snapshot: {}", error)));
+        }
 
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 4 {
-                let device = parts[2].to_string();
-                let path = parts[3].to_string();
-
-                // Get disk size (simplified)
-                disks.push(DiskInfo {
-                    device,
-                    path: path.clone(),
-                    size: 0, // Would need to query actual size
-                    used: 0, // Would need to query actual usage
-                    format: "qcow2".to_string(), // Default assumption
-                });
+        Ok(())
+    }
+
+    async fn list_snapshots(&self, name: &str) -> Result<Vec<SnapshotInfo>> {
+        let output = self.run(self.privileges.virsh_read(&["snapshot-list", name])?, "list snapshots").await?;
+
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            if error.contains("not found") {
+                return Err(VmError::VmNotFound(name.to_string()));
             }
+            return Err(VmError::LibvirtError(format!("Failed to list snapshots: {}", error)));
+        }
+
+        // A domain without a current snapshot makes this fail, which just means none is current
+        let current = self.run(self.privileges.virsh_read(&["snapshot-current", name, "--name"])?, "get current snapshot").await?;
+        let current = if current.status.success() {
+            String::from_utf8_lossy(&current.stdout).trim().to_string()
+        } else {
+            String::new()
+        };
+
+        Ok(virsh::parse_snapshot_list(&String::from_utf8_lossy(&output.stdout), &current))
+    }
+
+    async fn revert_snapshot(&self, name: &str, snapshot: &str) -> Result<()> {
+        let output = self.run(self.privileges.virsh_write(&["snapshot-revert", name, snapshot])?, "revert snapshot").await?;
+
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(VmError::LibvirtError(format!("Failed to revert to snapshot '{}': {}", snapshot, error)));
         }
 
-        Ok(disks)
+        Ok(())
     }
 
-    async fn get_domain_interfaces(&self, name: &str) -> Result<Vec<NetworkInfo>> {
-        let output = AsyncCommand::new("virsh")
-            .args(&["-c", &self.uri, "domiflist", name])
-            .output()
-            .await
-            .map_err(|e| VmError::LibvirtError(format!("Failed to get domain interfaces: {}", e)))?;
+    async fn delete_snapshot(&self, name: &str, snapshot: &str) -> Result<()> {
+        let output = self.run(self.privileges.virsh_write(&["snapshot-delete", name, snapshot])?, "delete snapshot").await?;
 
         if !output.status.success() {
-            return Ok(Vec::new());
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(VmError::LibvirtError(format!("Failed to delete snapshot '{}': {}", snapshot, error)));
         }
 
+        Ok(())
+    }
+
+    async fn capabilities(&self) -> Result<HostCapabilities> {
+        let mut documents = Vec::new();
+        for command in ["capabilities", "domcapabilities"] {
+            let output = self.run(self.privileges.virsh_read(&[command])?, "query capabilities").await?;
+            if !output.status.success() {
+                let error = String::from_utf8_lossy(&output.stderr);
+                return Err(VmError::LibvirtError(format!("Failed to run {}: {}", command, error.trim())));
+            }
+            documents.push(String::from_utf8_lossy(&output.stdout).into_owned());
+        }
+        Ok(HostCapabilities::parse(&documents[0], &documents[1]))
+    }
+
+    async fn create_secret(&self, xml: &str, value: &[u8]) -> Result<String> {
+        let temp_file = format!("{}/vmtools_secret_{}.xml", self.temp_dir, uuid::Uuid::new_v4());
+        utils::write_private_file(Path::new(&temp_file), xml).await?;
+        let output = self.run(self.privileges.virsh_write(&["secret-define", &temp_file])?, "define secret").await?;
+        let _ = tokio::fs::remove_file(&temp_file).await;
+
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(VmError::LibvirtError(format!("Failed to define secret: {}", error.trim())));
+        }
+        // "Secret <uuid> created"
         let stdout = String::from_utf8_lossy(&output.stdout);
-        let mut interfaces = Vec::new();
+        let uuid = stdout.split_whitespace()
+            .find(|word| uuid::Uuid::parse_str(word).is_ok())
+            .ok_or_else(|| VmError::LibvirtError(format!("Unexpected secret-define output: {}", stdout.trim())))?
+            .to_string();
+
+        // The value goes through a private file so it never shows up in a process list
+        let value_file = format!("{}/vmtools_secret_{}.b64", self.temp_dir, uuid::Uuid::new_v4());
+        let encoded = base64::engine::general_purpose::STANDARD.encode(value);
+        utils::write_private_file(Path::new(&value_file), &encoded).await?;
+        let output = self.run(
+            self.privileges.virsh_write(&["secret-set-value", "--secret", &uuid, "--file", &value_file])?,
+            "set secret value",
+        ).await;
+        let _ = tokio::fs::remove_file(&value_file).await;
+        let output = output?;
+
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            // Don't leave a secret without a value behind
+            let _ = self.delete_secret(&uuid).await;
+            return Err(VmError::LibvirtError(format!("Failed to set secret value: {}", error.trim())));
+        }
+
+        Ok(uuid)
+    }
+
+    async fn domain_stats(&self) -> Result<Vec<DomainStats>> {
+        let output = self.run(self.privileges.virsh_read(&[
+            "domstats", "--list-running", "--cpu-total", "--balloon", "--vcpu", "--interface", "--block",
+        ])?, "get domain statistics").await?;
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(VmError::LibvirtError(format!("Failed to get domain statistics: {}", error.trim())));
+        }
+        Ok(stats::parse_domstats(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    async fn list_secrets(&self) -> Result<Vec<SecretInfo>> {
+        let output = self.run(self.privileges.virsh_read(&["secret-list"])?, "list secrets").await?;
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(VmError::LibvirtError(format!("Failed to list secrets: {}", error.trim())));
+        }
+        Ok(secret::parse_secret_list(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    async fn delete_secret(&self, uuid: &str) -> Result<()> {
+        let output = self.run(self.privileges.virsh_write(&["secret-undefine", uuid])?, "undefine secret").await?;
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(VmError::LibvirtError(format!("Failed to delete secret {}: {}", uuid, error.trim())));
+        }
+        Ok(())
+    }
 
-        for line in stdout.lines().skip(2) {
-            let line = line.trim();
-            if line.is_empty() || line.starts_with("---") {
-                continue;
+    async fn apply_firewall(&self, ruleset: &str) -> Result<()> {
+        let nft = self.privileges.root_command("nft").args(["-f", "-"]).stdin(ruleset);
+        let output = self.run(nft, "apply firewall rules").await?;
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            if privilege::is_denied(&error) {
+                return Err(VmError::PermissionDenied(
+                    "Changing firewall rules needs root; run the command with sudo".to_string()
+                ));
             }
+            return Err(VmError::OperationError(format!("nft rejected the firewall rules: {}", error.trim())));
+        }
+        Ok(())
+    }
+
+    async fn host_filter_rules(&self) -> Option<String> {
+        diagnosis::host_rules(&self.privileges, "filter").await
+    }
 
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 4 {
-                let interface = parts[0].to_string();
-                let network = parts[2].to_string();
-                let mac = parts[4].to_string();
-
-                interfaces.push(NetworkInfo {
-                    interface,
-                    network,
-                    mac_address: mac,
-                    ip_address: None, // Would need additional query
-                    bridge: "virbr0".to_string(), // Default assumption
-                });
+    async fn define_network(&self, xml: &str) -> Result<()> {
+        let name = domain::element_text(xml, "name")
+            .ok_or_else(|| VmError::InvalidInput("Network XML has no <name>".to_string()))?;
+        let temp_file = format!("{}/vmtools_network_{}.xml", self.temp_dir, uuid::Uuid::new_v4());
+        utils::write_private_file(Path::new(&temp_file), xml).await?;
+        let defined = self.checked(self.privileges.virsh_write(&["net-define", &temp_file])?, "define network").await;
+        let _ = tokio::fs::remove_file(&temp_file).await;
+        defined?;
+
+        let started = async {
+            self.checked(self.privileges.virsh_write(&["net-start", &name])?, "start network").await?;
+            self.checked(self.privileges.virsh_write(&["net-autostart", &name])?, "autostart network").await
+        }.await;
+        if let Err(e) = started {
+            // A subnet clash with a host interface shows up here; don't leave the network behind
+            let _ = self.run(self.privileges.virsh_write(&["net-undefine", &name])?, "undefine network").await;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    async fn define_pool(&self, xml: &str) -> Result<()> {
+        let name = domain::element_text(xml, "name")
+            .ok_or_else(|| VmError::InvalidInput("Pool XML has no <name>".to_string()))?;
+        let temp_file = format!("{}/vmtools_pool_{}.xml", self.temp_dir, uuid::Uuid::new_v4());
+        utils::write_private_file(Path::new(&temp_file), xml).await?;
+        let defined = self.checked(self.privileges.virsh_write(&["pool-define", &temp_file])?, "define pool").await;
+        let _ = tokio::fs::remove_file(&temp_file).await;
+        defined?;
+
+        // Only directory-like pools have something to build (the mount point)
+        let started = async {
+            if xml.contains("<pool type='netfs'>") || xml.contains("<pool type='dir'>") {
+                self.checked(self.privileges.virsh_write(&["pool-build", &name])?, "build pool").await?;
             }
+            self.checked(self.privileges.virsh_write(&["pool-start", &name])?, "start pool").await?;
+            self.checked(self.privileges.virsh_write(&["pool-autostart", &name])?, "autostart pool").await
+        }.await;
+        if let Err(e) = started {
+            // Don't leave a pool behind that can't be used
+            let _ = self.run(self.privileges.virsh_write(&["pool-undefine", &name])?, "undefine pool").await;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    async fn list_pools(&self) -> Result<Vec<PoolInfo>> {
+        let names = self.checked(self.privileges.virsh_read(&["pool-list", "--all", "--name"])?, "list pools").await?;
+        let mut pools = Vec::new();
+        for name in virsh::parse_names(&names) {
+            let info = self.checked(self.privileges.virsh_read(&["pool-info", &name, "--bytes"])?, "get pool info").await?;
+            let xml = self.checked(self.privileges.virsh_read(&["pool-dumpxml", &name])?, "get pool XML").await?;
+            pools.push(PoolInfo {
+                name,
+                kind: domain::attribute(&xml, "<pool type='").unwrap_or_default(),
+                active: virsh::parse_field(&info, "State") == Some("running"),
+                autostart: virsh::parse_field(&info, "Autostart") == Some("yes"),
+                capacity: virsh::parse_bytes(&info, "Capacity"),
+                allocation: virsh::parse_bytes(&info, "Allocation"),
+                available: virsh::parse_bytes(&info, "Available"),
+            });
+        }
+        Ok(pools)
+    }
+
+    async fn delete_pool(&self, name: &str) -> Result<()> {
+        // An inactive pool can't be destroyed; that's fine
+        self.run(self.privileges.virsh_write(&["pool-destroy", name])?, "stop pool").await?;
+        self.checked(self.privileges.virsh_write(&["pool-undefine", name])?, "undefine pool").await?;
+        Ok(())
+    }
+
+    async fn list_volumes(&self, pool: &str) -> Result<Vec<VolumeInfo>> {
+        let list = self.checked(self.privileges.virsh_read(&["vol-list", pool])?, "list volumes").await?;
+        let mut volumes = Vec::new();
+        for (name, path) in virsh::parse_vol_list(&list) {
+            let xml = self.checked(
+                self.privileges.virsh_read(&["vol-dumpxml", &name, "--pool", pool])?,
+                "get volume XML",
+            ).await?;
+            volumes.push(VolumeInfo {
+                name,
+                path,
+                capacity: domain::element_text(&xml, "capacity").and_then(|n| n.parse().ok()).unwrap_or(0),
+                format: domain::attribute(&xml, "<format type='").unwrap_or_else(|| "raw".to_string()),
+            });
+        }
+        Ok(volumes)
+    }
+
+    async fn volume_at(&self, path: &Path) -> Result<Option<(String, String)>> {
+        let path = path.to_string_lossy();
+        // Fails for files outside every pool
+        let pool = self.run(self.privileges.virsh_read(&["vol-pool", &path])?, "find volume pool").await?;
+        if !pool.status.success() {
+            return Ok(None);
+        }
+        let name = self.checked(self.privileges.virsh_read(&["vol-name", &path])?, "find volume name").await?;
+        Ok(Some((String::from_utf8_lossy(&pool.stdout).trim().to_string(), name.trim().to_string())))
+    }
+
+    async fn delete_volume(&self, pool: &str, name: &str) -> Result<()> {
+        self.checked(self.privileges.virsh_write(&["vol-delete", name, "--pool", pool])?, "delete volume").await?;
+        Ok(())
+    }
+
+    async fn create_volume(&self, pool: &str, name: &str, size_bytes: u64) -> Result<()> {
+        let size = size_bytes.to_string();
+        self.checked(
+            self.privileges.virsh_write(&["vol-create-as", pool, name, &size, "--format", "qcow2"])?,
+            "create volume",
+        ).await?;
+        Ok(())
+    }
+
+    async fn discover_iscsi_targets(&self, host: &str) -> Result<Vec<String>> {
+        let sources = self.checked(
+            self.privileges.virsh_read(&["find-storage-pool-sources-as", "iscsi", host])?,
+            "discover iSCSI targets",
+        ).await?;
+        Ok(storage::parse_iscsi_targets(&sources))
+    }
+
+    async fn set_guest_password(&self, name: &str, user: &str, password: &str) -> Result<()> {
+        // Through a private file so the password never shows up in a process list
+        let password_file = format!("{}/vmtools_password_{}", self.temp_dir, uuid::Uuid::new_v4());
+        utils::write_private_file(Path::new(&password_file), password).await?;
+
+        // Boots a libguestfs appliance, which easily outlasts libvirt.timeout
+        let customize = Invocation::new("virt-customize")
+            .args(["-c", self.privileges.uri(), "-d", name, "--no-network", "--password"])
+            .arg(format!("{}:file:{}", user, password_file));
+        let output = self.privileges.runner().output(&customize).await;
+        let _ = tokio::fs::remove_file(&password_file).await;
+
+        let output = output.map_err(image::spawn_error)?;
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(VmError::CommandError(format!("virt-customize failed: {}", error.trim())));
         }
+        Ok(())
+    }
+
+    async fn customize_image(&self, image: &Path, customization: &Customization) -> Result<()> {
+        image::customize(self.privileges.runner(), image, customization).await
+    }
 
-        Ok(interfaces)
+    async fn create_thin_volume(&self, vg: &str, thin_pool: &str, name: &str, size_bytes: u64) -> Result<PathBuf> {
+        storage::create_thin_volume(&self.privileges, vg, thin_pool, name, size_bytes).await
     }
-}
\ No newline at end of file
+
+    async fn create_zvol(&self, dataset: &str, name: &str, size_bytes: u64, volblocksize: &str, compression: &str) -> Result<PathBuf> {
+        storage::create_zvol(&self.privileges, dataset, name, size_bytes, volblocksize, compression).await
+    }
+    
+    async fn clone_zvol(&self, device: &Path, name: &str) -> Result<PathBuf> {
+        storage::clone_zvol(&self.privileges, device, name).await
+    }
+    
+    async fn snapshot_thin_volume(&self, device: &Path, snapshot: &str) -> Result<()> {
+        storage::snapshot_thin_volume(&self.privileges, device, snapshot).await
+    }
+
+    async fn list_thin_snapshots(&self, device: &Path) -> Result<Vec<(String, String)>> {
+        storage::list_thin_snapshots(&self.privileges, device).await
+    }
+
+    async fn revert_thin_volume(&self, device: &Path, snapshot: &str) -> Result<()> {
+        storage::revert_thin_volume(&self.privileges, device, snapshot).await
+    }
+
+    async fn delete_thin_snapshot(&self, device: &Path, snapshot: &str) -> Result<()> {
+        storage::delete_thin_snapshot(&self.privileges, device, snapshot).await
+    }
+
+    async fn remove_thin_volume(&self, device: &Path) -> Result<()> {
+        storage::remove_thin_volume(&self.privileges, device).await
+    }
+
+    fn privileges(&self) -> Option<&Privileges> {
+        Some(&self.privileges)
+    }
+}
@@ -0,0 +1,75 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+use crate::error::{VmError, Result};
+
+/// How log lines are written
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LogStyle {
+    /// One readable line per event
+    #[default]
+    Text,
+    /// One JSON object per event
+    Json,
+}
+
+impl std::str::FromStr for LogStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(LogStyle::Text),
+            "json" => Ok(LogStyle::Json),
+            _ => Err(format!("Invalid log format '{}'. Use text or json", s)),
+        }
+    }
+}
+
+/// Events from vmtools itself that a debug trace records: everything, down to
+/// each external command and its output
+const TRACE_FILTER: &str = "warn,vmtools=trace,vmtools_core=trace";
+
+/// Sends log events to stderr, warnings and errors unless `RUST_LOG` asks for
+/// more. With a `trace_dir`, every event of this run also goes to a new trace
+/// file there, whose path is returned so it can be attached to a bug report.
+pub fn init(style: LogStyle, trace_dir: Option<&Path>) -> Result<Option<PathBuf>> {
+    let console_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn"));
+    let console = match style {
+        LogStyle::Text => fmt::layer().with_writer(std::io::stderr).without_time().with_target(false).boxed(),
+        LogStyle::Json => fmt::layer().with_writer(std::io::stderr).json().boxed(),
+    }.with_filter(console_filter);
+
+    let (trace, path) = if let Some(dir) = trace_dir {
+        std::fs::create_dir_all(dir)?;
+        let name = format!("{}-{}.log", chrono::Local::now().format("%Y%m%d-%H%M%S"), std::process::id());
+        let path = dir.join(name);
+        let file = Mutex::new(std::fs::File::create(&path)?);
+        let layer = match style {
+            LogStyle::Text => fmt::layer().with_writer(file).with_ansi(false).boxed(),
+            LogStyle::Json => fmt::layer().with_writer(file).json().boxed(),
+        }.with_filter(EnvFilter::new(TRACE_FILTER));
+        (Some(layer), Some(path))
+    } else {
+        (None, None)
+    };
+
+    tracing_subscriber::registry()
+        .with(console)
+        .with(trace)
+        .try_init()
+        .map_err(|e| VmError::ConfigError(format!("Failed to set up logging: {}", e)))?;
+
+    if path.is_some() {
+        tracing::info!(version = env!("CARGO_PKG_VERSION"), args = ?std::env::args().collect::<Vec<_>>(), "vmtools started");
+    }
+    Ok(path)
+}
+
+/// Where `--debug` writes its traces, under the state directory; each file is
+/// named after the time and process
+pub fn trace_dir() -> Result<PathBuf> {
+    let state_dir = dirs::state_dir().or_else(dirs::cache_dir)
+        .ok_or_else(|| VmError::ConfigError("Cannot determine state directory".to_string()))?;
+    Ok(state_dir.join("vmtools").join("traces"))
+}
@@ -1,28 +1,71 @@
 use clap::Parser;
-use log::error;
+use tracing::error;
 use std::process;
-use tokio;
 
 mod cli;
-mod config;
-mod vm;
-mod libvirt;
-mod error;
-mod qemu;
-mod utils;
+mod render;
 
-use cli::Cli;
-use config::Config;
-use vm::VmManager;
-use error::VmError;
+use cli::{BenchAction, Cli, ConfigAction, CpuAction, DiskAction, FirewallAction, GroupAction, HostAction, ImageAction, MediaAction, NetworkAction, NicAction, PoolAction, SecretAction, SnapshotAction};
+use vmtools_core::config::Config;
+use vmtools_core::domain::KernelBoot;
+use vmtools_core::logging;
+use vmtools_core::manifest::Manifest;
+use vmtools_core::runner::SystemRunner;
+use vmtools_core::secret::SecretUsage;
+use vmtools_core::setup;
+use vmtools_core::stats::MetricsLog;
+use vmtools_core::utils::ImageBench;
+use vmtools_core::vm::{CreateOptions, DomainKind, ListOptions, VmManager};
+use vmtools_core::error::VmError;
+use vmtools_core::image::Customization;
 
 #[tokio::main]
 async fn main() {
-    env_logger::init();
-    
     let cli = Cli::parse();
     
-    let config = match Config::load() {
+    let trace_dir = match cli.debug.then(logging::trace_dir).transpose() {
+        Ok(dir) => dir,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    match logging::init(cli.log_format, trace_dir.as_deref()) {
+        Ok(Some(trace)) => eprintln!("🐞 Writing a debug trace to {}", trace.display()),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+    
+    // Checked before loading, which would stop at the first error
+    if let cli::Commands::Config { action: Some(ConfigAction::Validate { file }), .. } = &cli.command {
+        match Config::validate_file(file.as_deref()) {
+            Ok((path, issues)) => {
+                render::config_issues(&path, &issues);
+                process::exit(if issues.is_empty() { 0 } else { 1 });
+            }
+            Err(e) => {
+                error!("{}", e);
+                process::exit(1);
+            }
+        }
+    }
+    
+    let setup = match cli.command {
+        cli::Commands::Setup => setup::run().await,
+        _ => setup::offer_first_run().await,
+    };
+    if let Err(e) = setup {
+        error!("Setup failed: {}", e);
+        process::exit(1);
+    }
+    if matches!(cli.command, cli::Commands::Setup) {
+        return;
+    }
+    
+    let config = match Config::load(&SystemRunner).await {
         Ok(config) => config,
         Err(e) => {
             error!("Failed to load configuration: {}", e);
@@ -30,7 +73,7 @@ async fn main() {
         }
     };
     
-    let vm_manager = match VmManager::new(&config).await {
+    let vm_manager = match VmManager::new(&config, &confirm).await {
         Ok(manager) => manager,
         Err(e) => {
             error!("Failed to initialize VM manager: {}", e);
@@ -38,61 +81,436 @@ async fn main() {
         }
     };
     
+    let operation = cli.command.operation();
+    let started = std::time::Instant::now();
+    
     let result = match cli.command {
-        cli::Commands::List { all, running } => {
-            vm_manager.list_vms(all, running).await
+        cli::Commands::List { all, running, sort, state, filter, columns, fast } => {
+            let options = ListOptions {
+                all,
+                running_only: running,
+                state,
+                sort,
+                filters: filter,
+                columns,
+                fast,
+            };
+            vm_manager.list(&options).await
+                .map(|vms| render::vm_table(&vms, &options.columns))
+        }
+        cli::Commands::Start { all: true, tag, .. } => {
+            vm_manager.start_all(tag.as_deref()).await
+        }
+        cli::Commands::Start { group: Some(group), .. } => {
+            vm_manager.start_group(&group, std::time::Duration::from_secs(300)).await
+        }
+        cli::Commands::Start { name, boot_iso, console, .. } => {
+            let name = name.unwrap_or_default();
+            match (boot_iso, console) {
+                (boot_iso, true) => vm_manager.start_vm_with_console(&name, boot_iso.as_deref()).await,
+                (Some(iso), false) => vm_manager.start_vm_from_iso(&name, &iso).await,
+                (None, false) => vm_manager.start_vm(&name).await,
+            }
+        }
+        cli::Commands::StartGroup { tag, timeout } => {
+            vm_manager.start_group(&tag, std::time::Duration::from_secs(timeout)).await
+        }
+        cli::Commands::Stop { all: true, tag, force, .. } => {
+            vm_manager.stop_all(tag.as_deref(), force).await
+        }
+        cli::Commands::Stop { group: Some(group), force, .. } => {
+            vm_manager.stop_group(&group, force).await
+        }
+        cli::Commands::Stop { name, force, .. } => {
+            vm_manager.stop_vm(&name.unwrap_or_default(), force).await
+        }
+        cli::Commands::Status { all: true, .. } => {
+            vm_manager.host_summary().await
+                .map(|summary| render::host_summary(&summary))
+        }
+        cli::Commands::Status { group: Some(group), .. } => {
+            vm_manager.group_status(&group).await
+                .map(|vms| render::group_status(&group, &vms))
+        }
+        cli::Commands::Status { name, .. } => {
+            vm_manager.info(&name.unwrap_or_default()).await
+                .map(|info| render::vm_status(&info))
+        }
+        cli::Commands::Group { action } => match action {
+            GroupAction::Create { name, vms } => vm_manager.create_group(&name, &vms).await,
+            GroupAction::Add { name, vms } => vm_manager.add_to_group(&name, &vms).await,
+            GroupAction::Remove { name, vms } => vm_manager.remove_from_group(&name, &vms).await,
+            GroupAction::List => {
+                vm_manager.groups().await
+                    .map(|groups| render::groups(&groups))
+            }
+        },
+        cli::Commands::Depends { name, add, remove, clear } => {
+            if add.is_empty() && remove.is_empty() && !clear {
+                vm_manager.dependencies(&name).await
+                    .map(|dependencies| render::dependencies(&name, &dependencies))
+            } else {
+                vm_manager.set_dependencies(&name, &add, &remove, clear).await
+            }
         }
-        cli::Commands::Start { name } => {
-            vm_manager.start_vm(&name).await
+        cli::Commands::Health { name, add, remove, clear } => {
+            if add.is_empty() && remove.is_empty() && !clear {
+                vm_manager.check_health(&name).await
+                    .map(|results| render::health_report(&name, &results))
+            } else {
+                vm_manager.set_health_checks(&name, &add, &remove, clear).await
+            }
+        }
+        cli::Commands::Create(args) => {
+            let cli::CreateArgs {
+                name,
+                memory,
+                cpus,
+                disk_size,
+                iso_path,
+                os_variant,
+                start,
+                template,
+                kind,
+                rootfs,
+                arch,
+                emulated,
+                usb_redirect,
+                audio,
+                listen,
+                tls,
+                tls_port,
+                x509_dir,
+                disk_bus,
+                net_queues,
+                iothreads,
+                cpu_model,
+                disk,
+                kernel,
+                initrd,
+                cmdline,
+                like,
+            } = *args;
+            let created = match (kind, rootfs, like) {
+                (_, _, Some(like)) => vm_manager.create_like(&name, &like, iso_path.as_deref()).await,
+                (DomainKind::Container, Some(rootfs), None) => {
+                    vm_manager.create_container(&name, memory, cpus, &rootfs).await
+                }
+                _ => {
+                    let options = CreateOptions {
+                        memory,
+                        cpus,
+                        disk_size,
+                        iso_path,
+                        os_variant,
+                        template,
+                        arch,
+                        emulated,
+                        usb_redirect,
+                        audio,
+                        listen,
+                        tls,
+                        tls_port,
+                        x509_dir,
+                        disk_bus,
+                        net_queues,
+                        iothreads,
+                        networks: Vec::new(),
+                        base_image: None,
+                        link_base_image: false,
+                        cpu_model,
+                        disk,
+                        kernel_boot: kernel.map(|kernel| KernelBoot { kernel, initrd, cmdline }),
+                    };
+                    vm_manager.create_vm(&name, &options).await
+                }
+            };
+            match created {
+                Ok(()) if start => vm_manager.start_vm(&name).await,
+                created => created,
+            }
         }
-        cli::Commands::Stop { name, force } => {
-            vm_manager.stop_vm(&name, force).await
+        cli::Commands::Adopt { name, template, tags } => {
+            vm_manager.adopt_vm(&name, template.as_deref(), &tags).await
         }
-        cli::Commands::Status { name } => {
-            vm_manager.get_vm_status(&name).await
+        cli::Commands::ImportDisk { path, name, os_variant, memory, cpus, link } => {
+            vm_manager.import_disk(&name, &path, os_variant.as_deref(), memory, cpus, link).await
         }
-        cli::Commands::Create { 
-            name, 
-            memory, 
-            cpus, 
-            disk_size, 
-            iso_path,
-            template 
-        } => {
-            vm_manager.create_vm(&name, memory, cpus, disk_size, iso_path.as_deref(), template.as_deref()).await
+        cli::Commands::Delete { name, force, trash } => {
+            if force || confirm(&format!("Are you sure you want to delete VM '{}'?", name), false) {
+                vm_manager.delete_vm(&name, trash).await
+            } else {
+                println!("Operation cancelled");
+                Ok(())
+            }
         }
-        cli::Commands::Delete { name, force } => {
-            vm_manager.delete_vm(&name, force).await
+        cli::Commands::Undelete { name } => {
+            match name {
+                Some(name) => vm_manager.undelete_vm(&name).await,
+                None => vm_manager.trash_entries().await
+                    .map(|entries| render::trash_table(&entries, config.storage.trash_retention_days)),
+            }
         }
         cli::Commands::Clone { source, target } => {
             vm_manager.clone_vm(&source, &target).await
         }
-        cli::Commands::Monitor { name } => {
-            vm_manager.monitor_vm(&name).await
+        cli::Commands::VncPasswd { name, password_stdin, clear } => {
+            if clear {
+                vm_manager.clear_console_password(&name).await
+            } else if password_stdin {
+                let mut password = String::new();
+                match std::io::stdin().read_line(&mut password) {
+                    Ok(_) => vm_manager.rotate_console_password(&name, Some(password.trim_end_matches(['\r', '\n']))).await,
+                    Err(e) => Err(e.into()),
+                }
+            } else {
+                vm_manager.rotate_console_password(&name, None).await
+            }
         }
-        cli::Commands::Console { name } => {
-            vm_manager.connect_console(&name).await
+        cli::Commands::ResetPassword { name, user, password_stdin } => {
+            if password_stdin {
+                let mut password = String::new();
+                match std::io::stdin().read_line(&mut password) {
+                    Ok(_) => vm_manager.reset_guest_password(&name, &user, Some(password.trim_end_matches(['\r', '\n']))).await,
+                    Err(e) => Err(e.into()),
+                }
+            } else {
+                vm_manager.reset_guest_password(&name, &user, None).await
+            }
+        }
+        cli::Commands::Diff { name, template } => {
+            vm_manager.diff_template(&name, &template).await
+                .map(|differences| render::template_diff(&name, &template, &differences))
+        }
+        cli::Commands::ExportXml { name, inactive } => {
+            vm_manager.export_xml(&name, inactive).await
+                .map(|xml| print!("{}", xml))
+        }
+        cli::Commands::ImportXml { file, rename, regenerate_ids } => {
+            vm_manager.import_xml(&file, rename.as_deref(), regenerate_ids).await
+        }
+        cli::Commands::Set { name, memory, cpus } => {
+            vm_manager.set_resources(&name, memory, cpus).await
+        }
+        cli::Commands::Balloon { name, target, autodeflate, free_page_reporting } => {
+            vm_manager.adjust_balloon(&name, target, autodeflate, free_page_reporting).await
+        }
+        cli::Commands::Boot { name, order } => {
+            vm_manager.set_boot_order(&name, &order).await
+        }
+        cli::Commands::Nic { action } => match action {
+            NicAction::Limit { name, mac, inbound, outbound } => {
+                vm_manager.limit_nic(&name, &mac, inbound.as_deref(), outbound.as_deref()).await
+            }
+        },
+        cli::Commands::Bench { action } => match action {
+            BenchAction::Disk { name, disk, cache, aio, write, guest, requests, depth, block_size } => {
+                let bench = ImageBench { write, requests, depth, block_size };
+                vm_manager.bench_disk(&name, disk.as_deref(), &cache, &aio, &bench, guest).await
+                    .map(|results| render::bench_table(&results))
+            }
+        },
+        cli::Commands::Pool { action } => match action {
+            PoolAction::List => {
+                vm_manager.pools().await
+                    .map(|pools| render::pool_table(&pools))
+            }
+            PoolAction::Volumes { pool } => {
+                vm_manager.volumes(&pool).await
+                    .map(|volumes| render::volume_table(&pool, &volumes))
+            }
+            PoolAction::Discover { host } => {
+                vm_manager.discover_iscsi(&host).await
+                    .map(|targets| render::iscsi_targets(&host, &targets))
+            }
+            PoolAction::AddNfs { name, host, export } => {
+                vm_manager.add_nfs_pool(&name, &host, &export).await
+            }
+            PoolAction::AddIscsi { name, host, target, chap_user } => {
+                vm_manager.add_iscsi_pool(&name, &host, &target, chap_user.as_deref()).await
+            }
+            PoolAction::Remove { name } => {
+                vm_manager.remove_pool(&name).await
+            }
+        },
+        cli::Commands::Disk { action } => match action {
+            DiskAction::Attach { name, source, size } => vm_manager.attach_disk(&name, &source, size).await.map(|_| ()),
+            DiskAction::Commit { name, disk } => vm_manager.commit_disk(&name, disk.as_deref()).await,
+            DiskAction::Pull { name, disk } => vm_manager.pull_disk(&name, disk.as_deref()).await,
+        },
+        cli::Commands::Cpu { action } => match action {
+            CpuAction::Limit { name, shares, quota } => {
+                vm_manager.limit_cpu(&name, shares, quota.as_deref()).await
+            }
+        },
+        cli::Commands::Apply { manifest } => match Manifest::load(&manifest) {
+            Ok(manifest) => vm_manager.apply_manifest(&manifest).await,
+            Err(e) => Err(e),
+        },
+        cli::Commands::Plan { manifest } => match Manifest::load(&manifest) {
+            Ok(manifest) => vm_manager.plan_manifest(&manifest).await.map(|changes| render::manifest_plan(&changes)),
+            Err(e) => Err(e),
+        },
+        cli::Commands::Media { name, action } => match action {
+            MediaAction::Eject => vm_manager.change_media(&name, None).await,
+            MediaAction::Insert { iso } => vm_manager.change_media(&name, Some(&iso)).await,
+        },
+        cli::Commands::Snapshot { action } => match action {
+            SnapshotAction::Create { vm, name, description, memory } => {
+                vm_manager.create_snapshot(&vm, &name, description.as_deref(), memory).await
+            }
+            SnapshotAction::List { vm } => {
+                vm_manager.snapshots(&vm).await
+                    .map(|snapshots| render::snapshot_table(&vm, &snapshots))
+            }
+            SnapshotAction::Du { vm } => {
+                vm_manager.snapshot_usage(&vm).await
+                    .map(|usage| render::snapshot_usage_table(&vm, &usage))
+            }
+            SnapshotAction::Revert { vm, name } => {
+                vm_manager.revert_snapshot(&vm, &name).await
+            }
+            SnapshotAction::Delete { vm, name } => {
+                vm_manager.delete_snapshot(&vm, &name).await
+            }
+        },
+        cli::Commands::Capabilities => {
+            vm_manager.capabilities().await
+                .map(|capabilities| render::capabilities(&capabilities))
+        }
+        cli::Commands::Image { action } => match action {
+            ImageAction::Customize { image, install, run_command, ssh_inject } => {
+                let customization = Customization {
+                    install,
+                    run_commands: run_command,
+                    ssh_keys: ssh_inject,
+                };
+                vm_manager.customize_image(&image, &customization).await
+            }
+        },
+        cli::Commands::Secret { action } => match action {
+            SecretAction::Create { kind, usage, value_file, description } => {
+                let value = match value_file {
+                    Some(path) => std::fs::read(&path),
+                    None => {
+                        let mut value = Vec::new();
+                        std::io::Read::read_to_end(&mut std::io::stdin(), &mut value).map(|_| {
+                            while value.last().is_some_and(|b| *b == b'\n' || *b == b'\r') {
+                                value.pop();
+                            }
+                            value
+                        })
+                    }
+                };
+                match (value, SecretUsage::new(&kind, &usage)) {
+                    (Ok(value), Ok(usage)) => vm_manager.create_secret(&usage, &value, description.as_deref()).await.map(|_| ()),
+                    (Err(e), _) => Err(e.into()),
+                    (_, Err(e)) => Err(e),
+                }
+            }
+            SecretAction::List => {
+                vm_manager.secrets().await
+                    .map(|secrets| render::secret_table(&secrets))
+            }
+            SecretAction::Delete { id } => {
+                vm_manager.delete_secret(&id).await
+            }
+        },
+        cli::Commands::Du => {
+            vm_manager.disk_usage().await
+                .map(|report| render::disk_usage_table(&report))
+        }
+        cli::Commands::SuspendAll => {
+            vm_manager.suspend_all().await
+        }
+        cli::Commands::ResumeAll => {
+            vm_manager.resume_all().await
+        }
+        cli::Commands::InstallUnits { dir } => {
+            vm_manager.install_units(&dir).await.map(|_| ())
+        }
+        cli::Commands::Daemon => {
+            vm_manager.run_daemon().await
+        }
+        cli::Commands::Top { sort } => loop {
+            match vm_manager.vm_usage(std::time::Duration::from_secs(1), sort).await {
+                Ok(usage) => render::top_table(&usage),
+                // Like the daemon, a failed sample (a VM going away mid-read) skips a refresh
+                Err(e) => {
+                    tracing::warn!("Failed to sample VM statistics: {}", e);
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                }
+            }
+        },
+        cli::Commands::Monitor { name, interval, log, format } => {
+            match log.map(|path| MetricsLog::open(&path, format.unwrap_or_default())).transpose() {
+                Ok(log) => vm_manager.monitor_vm(&name, log, std::time::Duration::from_secs(interval)).await,
+                Err(e) => Err(e),
+            }
+        }
+        cli::Commands::Console { name, record, force } => {
+            vm_manager.connect_console(&name, record.as_deref(), force).await
+        }
+        cli::Commands::Qmp { name, command } => {
+            vm_manager.qmp(&name, &command).await
+                .map(|result| render::json(&result))
+        }
+        cli::Commands::Ga { name, command } => {
+            vm_manager.guest_agent(&name, &command).await
+                .map(|reply| render::agent_reply(&command, &reply))
         }
         cli::Commands::Networks => {
-            vm_manager.list_networks().await
+            vm_manager.networks().await
+                .map(|networks| render::network_table(&networks))
         }
-        cli::Commands::Config { show, set, get } => {
-            if show {
+        cli::Commands::Firewall { action } => match action {
+            FirewallAction::Allow(args) => vm_manager.allow_port(&args.name, args.forward()).await,
+            FirewallAction::Remove(args) => vm_manager.remove_port(&args.name, args.forward()).await,
+            FirewallAction::List => {
+                vm_manager.firewall_rules().await
+                    .map(|rules| render::firewall_table(&rules))
+            }
+            FirewallAction::Sync => {
+                vm_manager.sync_firewall().await
+                    .map(|rules| render::firewall_table(&rules))
+            }
+        },
+        cli::Commands::Network { action } => match action {
+            NetworkAction::Create { name, template, ipv6 } => {
+                vm_manager.create_network(&name, &template, ipv6.as_deref()).await
+            }
+        },
+        cli::Commands::Events { vm, json } => {
+            vm_manager.watch_events(vm.as_deref(), json).await
+        }
+        cli::Commands::Setup => Ok(()),
+        cli::Commands::StorageInit => vm_manager.init_storage(),
+        cli::Commands::Host { action: HostAction::Tune { apply } } => vm_manager.tune_host(apply),
+        cli::Commands::Config { show, set, get, encrypt, keyring, action } => {
+            if matches!(action, Some(ConfigAction::List)) {
+                config.entries().map(|entries| render::config_entries(&entries))
+            } else if show {
                 println!("{}", config);
                 Ok(())
             } else if let Some((key, value)) = set {
                 vm_manager.set_config(&key, &value).await
             } else if let Some(key) = get {
                 vm_manager.get_config(&key).await
+            } else if let Some(key) = encrypt {
+                vm_manager.encrypt_config(&key, keyring).await
             } else {
                 Err(VmError::InvalidInput("No config action specified".to_string()))
             }
         }
-        cli::Commands::FixNetwork { name, auto } => {
+        cli::Commands::FixNetwork { name, diagnose: true, .. } => {
+            vm_manager.diagnose_network(&name).await
+                .map(|steps| render::diagnosis(&name, &steps))
+        }
+        cli::Commands::FixNetwork { name, auto, .. } => {
             vm_manager.fix_network_issues(&name, auto).await
         }
-        cli::Commands::Optimize { name } => {
-            vm_manager.optimize_vm_config(&name).await
+        cli::Commands::Optimize { name, apply, yes, profile } => {
+            vm_manager.optimize_vm_config(&name, apply, profile, &|question, default| yes || confirm(question, default)).await
         }
         cli::Commands::FixClipboard { name } => {
             vm_manager.fix_clipboard_integration(&name).await
@@ -102,8 +520,32 @@ async fn main() {
         }
     };
     
+    if let Some((name, vm)) = operation {
+        vm_manager.notify_operation(name, vm.as_deref(), &result, started.elapsed()).await;
+    }
+    
     if let Err(e) = result {
         error!("Command failed: {}", e);
         process::exit(1);
     }
-}
\ No newline at end of file
+}
+
+/// Asks a yes/no question on the terminal. Without one, or when stdin is
+/// closed, the answer is `default`.
+fn confirm(question: &str, default: bool) -> bool {
+    use std::io::{IsTerminal, Write};
+    if !std::io::stdin().is_terminal() {
+        return default;
+    }
+    print!("{} {}: ", question, if default { "[Y/n]" } else { "[y/N]" });
+    let _ = std::io::stdout().flush();
+
+    let mut input = String::new();
+    match std::io::stdin().read_line(&mut input) {
+        Ok(0) | Err(_) => default,
+        Ok(_) => match input.trim().to_lowercase().as_str() {
+            "" => default,
+            answer => answer.starts_with('y'),
+        },
+    }
+}
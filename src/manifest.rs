@@ -0,0 +1,107 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    cloud_init::CloudInit,
+    domain::SpecDifference,
+    error::{VmError, Result},
+    utils,
+};
+
+/// A fleet of VMs described declaratively, as read by `vmtools apply`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(default)]
+    pub vms: Vec<VmManifest>,
+}
+
+/// The desired shape of one VM. Unset resources come from the template,
+/// or from `[defaults]` when there is none.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VmManifest {
+    pub name: String,
+    /// `absent` deletes the VM (into the trash) if it exists
+    #[serde(default)]
+    pub state: DesiredState,
+    #[serde(default)]
+    pub template: Option<String>,
+    /// Memory in MB
+    #[serde(default)]
+    pub memory: Option<u64>,
+    #[serde(default)]
+    pub cpus: Option<u32>,
+    /// Disk size in GB; only used when the VM is created
+    #[serde(default)]
+    pub disk_size: Option<u64>,
+    /// Networks to attach, the first being the primary interface;
+    /// empty picks the default network
+    #[serde(default)]
+    pub networks: Vec<String>,
+    /// Disk image copied as the system disk instead of a blank one,
+    /// typically a cloud image paired with `cloud_init`
+    #[serde(default)]
+    pub image: Option<PathBuf>,
+    #[serde(default)]
+    pub cloud_init: Option<CloudInit>,
+}
+
+/// Whether a manifest entry's VM should exist
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DesiredState {
+    #[default]
+    Present,
+    Absent,
+}
+
+/// One step needed to bring reality in line with a manifest
+#[derive(Debug, Clone, PartialEq)]
+pub enum ManifestChange {
+    /// The VM does not exist and will be created
+    Create { vm: String },
+    /// Resources that can be changed in place
+    Modify { vm: String, differences: Vec<SpecDifference> },
+    /// The VM is marked absent and will be moved to the trash
+    Delete { vm: String },
+    /// Differences apply leaves alone, and why
+    Drift { vm: String, differences: Vec<SpecDifference>, reason: String },
+}
+
+impl Manifest {
+    /// Reads a YAML manifest; relative paths in it are taken from the manifest's directory
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|e| VmError::ConfigError(format!(
+            "Cannot read manifest {}: {}", path.display(), e
+        )))?;
+        let mut manifest = Self::parse(&content)?;
+
+        let base = path.parent().unwrap_or(Path::new("."));
+        for vm in &mut manifest.vms {
+            if let Some(image) = &mut vm.image {
+                *image = base.join(&*image);
+            }
+            if let Some(cloud_init) = &mut vm.cloud_init {
+                for path in [&mut cloud_init.user_data, &mut cloud_init.network_config].into_iter().flatten() {
+                    *path = base.join(&*path);
+                }
+            }
+        }
+        Ok(manifest)
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let manifest: Self = serde_yaml::from_str(content)
+            .map_err(|e| VmError::ConfigError(format!("Invalid manifest: {}", e)))?;
+
+        let mut names = HashSet::new();
+        for vm in &manifest.vms {
+            utils::validate_vm_name(&vm.name)?;
+            if !names.insert(vm.name.as_str()) {
+                return Err(VmError::ConfigError(format!("VM '{}' appears twice in the manifest", vm.name)));
+            }
+        }
+        Ok(manifest)
+    }
+}
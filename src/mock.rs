@@ -0,0 +1,982 @@
+use async_trait::async_trait;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Output};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::{
+    backend::{Backend, ConsoleOptions},
+    capabilities::HostCapabilities,
+    domain::{self, DomainSpec},
+    error::{VmError, Result},
+    events::EventStream,
+    image::Customization,
+    recording::Recording,
+    runner::{CommandRunner, Invocation},
+    secret::{SecretInfo, SecretUsage},
+    stats::DomainStats,
+    storage::{PoolInfo, VolumeInfo},
+    utils::CopyProgress,
+    vm::{DiskInfo, NetworkInfo, SnapshotInfo, VmInfo, VmState},
+};
+
+/// A domain held by `MockBackend`
+#[derive(Debug, Clone)]
+struct MockDomain {
+    info: VmInfo,
+    xml: String,
+    snapshots: Vec<SnapshotInfo>,
+}
+
+#[derive(Default)]
+struct MockState {
+    domains: BTreeMap<String, MockDomain>,
+    networks: Vec<(String, bool, String, bool)>,
+    /// XML of the networks defined with `define_network`, by name
+    network_xmls: BTreeMap<String, String>,
+    /// The last ruleset passed to `apply_firewall`
+    firewall: Option<String>,
+    /// What `host_filter_rules` reports
+    filter_rules: Option<String>,
+    disk_sizes: HashMap<String, u64>,
+    capabilities: HostCapabilities,
+    secrets: Vec<(SecretInfo, Vec<u8>)>,
+    pools: BTreeMap<String, (PoolInfo, Vec<VolumeInfo>)>,
+    /// Thin LVs and zvols by device path, with their snapshots
+    thin_volumes: BTreeMap<String, Vec<(String, String)>>,
+    /// Images changed with `customize_image`, in call order
+    customizations: Vec<(PathBuf, Customization)>,
+    /// Guest account passwords set offline, by (VM, user)
+    guest_passwords: BTreeMap<(String, String), String>,
+    /// QMP replies by (VM, command), ahead of the built-in ones
+    monitor_replies: BTreeMap<(String, String), serde_json::Value>,
+    /// Guest agent replies by (VM, command), for VMs whose agent answers
+    agent_replies: BTreeMap<(String, String), serde_json::Value>,
+    /// Domains stopped with `managed_save`, until their next start
+    managed_saves: BTreeSet<String>,
+    /// Domains whose next console session ends with a restart
+    console_restarts: BTreeSet<String>,
+    /// Domains that are back up after their state was next read
+    restarting: BTreeSet<String>,
+    /// `domain_stats` calls so far, which drive the counters it reports
+    stats_samples: u64,
+    failures: HashMap<String, VmError>,
+    calls: Vec<String>,
+}
+
+/// In-memory backend with scriptable domain state for tests.
+///
+/// Domains are parsed from the XML passed to `define_domain`, state changes
+/// follow libvirt's rules (e.g. starting a running domain fails), and disk
+/// operations create real but empty files so trash and delete flows can be
+/// exercised in a temporary directory. Starts with an active `default` network.
+pub struct MockBackend {
+    state: Mutex<MockState>,
+}
+
+impl Default for MockBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        let state = MockState {
+            networks: vec![("default".to_string(), true, "virbr0".to_string(), true)],
+            ..Default::default()
+        };
+
+        Self {
+            state: Mutex::new(state),
+        }
+    }
+
+    /// Adds a network, replacing any existing one with the same name
+    pub fn with_network(self, name: &str, active: bool) -> Self {
+        {
+            let mut state = self.lock();
+            state.networks.retain(|(n, _, _, _)| n != name);
+            state.networks.push((name.to_string(), active, format!("br-{}", name), false));
+        }
+        self
+    }
+
+    /// Defines a bare domain without disks in the given state
+    pub fn add_domain(&self, name: &str, vm_state: VmState) {
+        let xml = format!(
+            "<domain type='kvm'>\n  <name>{}</name>\n  <uuid>{}</uuid>\n  <memory unit='MiB'>1024</memory>\n  <vcpu>1</vcpu>\n</domain>",
+            name, uuid::Uuid::new_v4()
+        );
+        let info = parse_domain_xml(&xml, &HashMap::new())
+            .expect("generated mock XML is valid");
+
+        self.lock().domains.insert(name.to_string(), MockDomain {
+            info: VmInfo { state: vm_state, ..info },
+            xml,
+            snapshots: Vec::new(),
+        });
+    }
+
+    /// Forces a domain into a state, as if it changed behind vmtools' back
+    pub fn set_state(&self, name: &str, vm_state: VmState) {
+        if let Some(domain) = self.lock().domains.get_mut(name) {
+            domain.info.state = vm_state;
+        }
+    }
+
+    /// Makes a domain report having run for `seconds`
+    pub fn set_uptime(&self, name: &str, seconds: u64) {
+        if let Some(domain) = self.lock().domains.get_mut(name) {
+            domain.info.uptime = Some(seconds);
+        }
+    }
+
+    pub fn state(&self, name: &str) -> Option<VmState> {
+        self.lock().domains.get(name).map(|d| d.info.state.clone())
+    }
+
+    pub fn domain(&self, name: &str) -> Option<VmInfo> {
+        self.lock().domains.get(name).map(|d| d.info.clone())
+    }
+
+    pub fn domain_names(&self) -> Vec<String> {
+        self.lock().domains.keys().cloned().collect()
+    }
+
+    /// Makes the next call of `operation` (a `Backend` method name) fail with `error`
+    pub fn fail_next(&self, operation: &str, error: VmError) {
+        self.lock().failures.insert(operation.to_string(), error);
+    }
+
+    /// Replaces what `capabilities` reports; empty by default
+    pub fn set_capabilities(&self, capabilities: HostCapabilities) {
+        self.lock().capabilities = capabilities;
+    }
+
+    /// The value stored in secret `uuid`
+    pub fn secret_value(&self, uuid: &str) -> Option<Vec<u8>> {
+        self.lock().secrets.iter()
+            .find(|(secret, _)| secret.uuid == uuid)
+            .map(|(_, value)| value.clone())
+    }
+
+    /// Customizations applied to images so far
+    pub fn customizations(&self) -> Vec<(PathBuf, Customization)> {
+        self.lock().customizations.clone()
+    }
+
+    /// The password last set for `user` inside VM `name`
+    pub fn guest_password(&self, name: &str, user: &str) -> Option<String> {
+        self.lock().guest_passwords.get(&(name.to_string(), user.to_string())).cloned()
+    }
+
+    /// Makes the next console session of `name` end with the domain restarting:
+    /// it reads as stopped once, then runs again
+    pub fn restart_during_console(&self, name: &str) {
+        self.lock().console_restarts.insert(name.to_string());
+    }
+
+    /// The XML network `name` was defined with, if it was defined through the backend
+    pub fn network_xml(&self, name: &str) -> Option<String> {
+        self.lock().network_xmls.get(name).cloned()
+    }
+
+    /// The last nftables ruleset applied, if any was
+    pub fn firewall_ruleset(&self) -> Option<String> {
+        self.lock().firewall.clone()
+    }
+
+    /// Makes the host's filter rules `rules`, e.g. what libvirt adds for a NAT network
+    pub fn set_filter_rules(&self, rules: &str) {
+        self.lock().filter_rules = Some(rules.to_string());
+    }
+
+    /// Gives the domain's first interface the IPv4 address `ip`, as a DHCP lease
+    /// would; `None` takes it away
+    pub fn set_ip_address(&self, name: &str, ip: Option<&str>) {
+        if let Some(domain) = self.lock().domains.get_mut(name) {
+            if domain.info.network_info.is_empty() {
+                domain.info.network_info.push(NetworkInfo {
+                    interface: "vnet0".to_string(),
+                    network: "default".to_string(),
+                    mac_address: "52:54:00:00:00:01".to_string(),
+                    ip_address: None,
+                    ipv6_address: None,
+                    bridge: "virbr0".to_string(),
+                });
+            }
+            domain.info.network_info[0].ip_address = ip.map(str::to_string);
+        }
+    }
+
+    /// Starts or stops network `name`
+    pub fn set_network_active(&self, name: &str, active: bool) {
+        if let Some(network) = self.lock().networks.iter_mut().find(|(network, _, _, _)| network == name) {
+            network.1 = active;
+        }
+    }
+
+    /// Registers the existing file at `path` as volume `name` of pool `pool`
+    pub fn add_volume(&self, pool: &str, name: &str, path: &Path) {
+        if let Some((_, volumes)) = self.lock().pools.get_mut(pool) {
+            volumes.push(VolumeInfo {
+                name: name.to_string(),
+                path: path.to_string_lossy().into_owned(),
+                capacity: std::fs::metadata(path).map_or(0, |metadata| metadata.len()),
+                format: "qcow2".to_string(),
+            });
+        }
+    }
+
+    /// Makes QEMU of VM `name` answer the QMP `command` with `reply` while it runs
+    pub fn set_monitor_reply(&self, name: &str, command: &str, reply: serde_json::Value) {
+        self.lock().monitor_replies.insert((name.to_string(), command.to_string()), reply);
+    }
+
+    /// Makes the guest agent of VM `name` answer `command` with `reply` while it
+    /// runs; the agent of other VMs, and other commands, don't answer
+    pub fn set_agent_reply(&self, name: &str, command: &str, reply: serde_json::Value) {
+        self.lock().agent_replies.insert((name.to_string(), command.to_string()), reply);
+    }
+
+    /// Every backend call so far, as "operation" or "operation:argument"
+    pub fn calls(&self) -> Vec<String> {
+        self.lock().calls.clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Records the call and returns the state, or the scripted failure
+    fn enter(&self, operation: &str, argument: &str) -> Result<MutexGuard<'_, MockState>> {
+        let mut state = self.lock();
+        state.calls.push(if argument.is_empty() {
+            operation.to_string()
+        } else {
+            format!("{}:{}", operation, argument)
+        });
+
+        match state.failures.remove(operation) {
+            Some(error) => Err(error),
+            None => Ok(state),
+        }
+    }
+}
+
+fn thin_volume<'a>(state: &'a mut MockState, device: &Path) -> Result<&'a mut Vec<(String, String)>> {
+    state.thin_volumes.get_mut(device.to_string_lossy().as_ref())
+        .ok_or_else(|| VmError::CommandError(format!("lvs failed: {} not found", device.display())))
+}
+
+fn domain_mut<'a>(state: &'a mut MockState, name: &str) -> Result<&'a mut MockDomain> {
+    state.domains.get_mut(name).ok_or_else(|| VmError::VmNotFound(name.to_string()))
+}
+
+/// Block jobs need an active domain with the disk
+fn block_job_disk(state: &mut MockState, name: &str, target: &str) -> Result<()> {
+    let domain = domain_mut(state, name)?;
+    if domain.info.state != VmState::Running {
+        return Err(VmError::LibvirtError("domain is not running".to_string()));
+    }
+    if !domain.xml.contains(&format!("<target dev='{}'", target)) {
+        return Err(VmError::LibvirtError(format!("disk '{}' not found in domain", target)));
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl Backend for MockBackend {
+    async fn list_domains(&self, all: bool, _fast: bool) -> Result<Vec<VmInfo>> {
+        let state = self.enter("list_domains", "")?;
+        Ok(state.domains.values()
+            .filter(|d| all || d.info.state == VmState::Running)
+            .map(|d| d.info.clone())
+            .collect())
+    }
+
+    async fn get_domain_info(&self, name: &str) -> Result<VmInfo> {
+        let mut state = self.enter("get_domain_info", name)?;
+        Ok(domain_mut(&mut state, name)?.info.clone())
+    }
+
+    async fn get_domain_state(&self, name: &str) -> Result<VmState> {
+        let mut state = self.enter("get_domain_state", name)?;
+        let restarted = state.restarting.remove(name);
+        let domain = domain_mut(&mut state, name)?;
+        let current = domain.info.state.clone();
+        if restarted {
+            domain.info.state = VmState::Running;
+        }
+        Ok(current)
+    }
+
+    async fn domain_exists(&self, name: &str) -> Result<bool> {
+        let state = self.enter("domain_exists", name)?;
+        Ok(state.domains.contains_key(name))
+    }
+
+    async fn get_domain_xml(&self, name: &str) -> Result<String> {
+        let mut state = self.enter("get_domain_xml", name)?;
+        let xml = domain_mut(&mut state, name)?.xml.clone();
+        // Like libvirt without --security-info, console passwords are left out
+        Ok(domain::set_graphics_password(&xml, None).unwrap_or(xml))
+    }
+
+    async fn get_inactive_domain_xml(&self, name: &str) -> Result<String> {
+        let mut state = self.enter("get_inactive_domain_xml", name)?;
+        Ok(domain_mut(&mut state, name)?.xml.clone())
+    }
+
+    async fn start_domain(&self, name: &str) -> Result<()> {
+        let mut state = self.enter("start_domain", name)?;
+        let domain = domain_mut(&mut state, name)?;
+        if domain.info.state == VmState::Running {
+            return Err(VmError::VmAlreadyRunning(name.to_string()));
+        }
+        domain.info.state = VmState::Running;
+        // Starting restores and discards a managed save
+        state.managed_saves.remove(name);
+        Ok(())
+    }
+
+    async fn shutdown_domain(&self, name: &str) -> Result<()> {
+        let mut state = self.enter("shutdown_domain", name)?;
+        let domain = domain_mut(&mut state, name)?;
+        if domain.info.state != VmState::Running {
+            return Err(VmError::VmNotRunning(name.to_string()));
+        }
+        domain.info.state = VmState::Stopped;
+        Ok(())
+    }
+
+    async fn pause_domain(&self, name: &str) -> Result<()> {
+        let mut state = self.enter("pause_domain", name)?;
+        let domain = domain_mut(&mut state, name)?;
+        if domain.info.state != VmState::Running {
+            return Err(VmError::VmNotRunning(name.to_string()));
+        }
+        domain.info.state = VmState::Paused;
+        Ok(())
+    }
+
+    async fn resume_domain(&self, name: &str) -> Result<()> {
+        let mut state = self.enter("resume_domain", name)?;
+        let domain = domain_mut(&mut state, name)?;
+        if domain.info.state != VmState::Paused {
+            return Err(VmError::VmNotRunning(name.to_string()));
+        }
+        domain.info.state = VmState::Running;
+        Ok(())
+    }
+
+    async fn destroy_domain(&self, name: &str) -> Result<()> {
+        let mut state = self.enter("destroy_domain", name)?;
+        let domain = domain_mut(&mut state, name)?;
+        if domain.info.state == VmState::Stopped {
+            return Err(VmError::VmNotRunning(name.to_string()));
+        }
+        domain.info.state = VmState::Stopped;
+        Ok(())
+    }
+
+    async fn define_domain(&self, xml: &str) -> Result<()> {
+        let mut state = self.enter("define_domain", "")?;
+        let mut info = parse_domain_xml(xml, &state.disk_sizes)?;
+        let name = info.name.clone();
+        if let Some(call) = state.calls.last_mut() {
+            call.push_str(&format!(":{}", name));
+        }
+
+        // Redefining keeps the runtime state and snapshots, like libvirt
+        let snapshots = match state.domains.get(&name) {
+            Some(existing) => {
+                info.state = existing.info.state.clone();
+                info.network_info = existing.info.network_info.clone();
+                existing.snapshots.clone()
+            }
+            None => Vec::new(),
+        };
+
+        state.domains.insert(name, MockDomain {
+            info,
+            xml: xml.to_string(),
+            snapshots,
+        });
+        Ok(())
+    }
+
+    async fn undefine_domain(&self, name: &str) -> Result<()> {
+        let mut state = self.enter("undefine_domain", name)?;
+        state.domains.remove(name).ok_or_else(|| VmError::VmNotFound(name.to_string()))?;
+        Ok(())
+    }
+
+    async fn change_media(&self, name: &str, _target: &str, media: Option<&str>) -> Result<()> {
+        let mut state = self.enter("change_media", name)?;
+        let domain = domain_mut(&mut state, name)?;
+        domain.xml = domain::set_cdrom_media(&domain.xml, media)?;
+        Ok(())
+    }
+
+    async fn set_memory_target(&self, name: &str, _memory: u64) -> Result<()> {
+        let mut state = self.enter("set_memory_target", name)?;
+        if domain_mut(&mut state, name)?.info.state != VmState::Running {
+            return Err(VmError::VmNotRunning(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Answers scripted replies, then `query-status` and `query-name` from the
+    /// domain's state; other commands fail as QEMU fails unknown ones
+    async fn monitor_command(&self, name: &str, command: &serde_json::Value) -> Result<serde_json::Value> {
+        let mut state = self.enter("monitor_command", name)?;
+        let domain = domain_mut(&mut state, name)?;
+        let status = match domain.info.state {
+            VmState::Running => "running",
+            VmState::Paused => "paused",
+            _ => return Err(VmError::VmNotRunning(name.to_string())),
+        };
+        let execute = command.get("execute").and_then(|e| e.as_str());
+        if let Some(reply) = state.monitor_replies.get(&(name.to_string(), execute.unwrap_or_default().to_string())) {
+            return Ok(reply.clone());
+        }
+        match execute {
+            Some("query-status") => Ok(serde_json::json!({ "status": status, "running": status == "running", "singlestep": false })),
+            Some("query-name") => Ok(serde_json::json!({ "name": name })),
+            Some(other) => Err(VmError::LibvirtError(format!("internal error: unable to execute QEMU command '{}': The command {} has not been found", other, other))),
+            None => Err(VmError::LibvirtError("internal error: cannot parse json: missing execute".to_string())),
+        }
+    }
+
+    async fn agent_command(&self, name: &str, command: &serde_json::Value) -> Result<serde_json::Value> {
+        let mut state = self.enter("agent_command", name)?;
+        if domain_mut(&mut state, name)?.info.state != VmState::Running {
+            return Err(VmError::VmNotRunning(name.to_string()));
+        }
+        let execute = command.get("execute").and_then(|e| e.as_str()).unwrap_or_default();
+        state.agent_replies.get(&(name.to_string(), execute.to_string())).cloned()
+            .ok_or_else(|| VmError::LibvirtError("Guest agent command failed: Guest agent is not responding".to_string()))
+    }
+
+    async fn managed_save(&self, name: &str) -> Result<()> {
+        let mut state = self.enter("managed_save", name)?;
+        let domain = domain_mut(&mut state, name)?;
+        if !matches!(domain.info.state, VmState::Running | VmState::Paused) {
+            return Err(VmError::VmNotRunning(name.to_string()));
+        }
+        domain.info.state = VmState::Stopped;
+        state.managed_saves.insert(name.to_string());
+        Ok(())
+    }
+
+    async fn list_managed_saves(&self) -> Result<Vec<String>> {
+        Ok(self.enter("list_managed_saves", "")?.managed_saves.iter().cloned().collect())
+    }
+
+    async fn update_device(&self, name: &str, _device_xml: &str) -> Result<()> {
+        let mut state = self.enter("update_device", name)?;
+        if domain_mut(&mut state, name)?.info.state != VmState::Running {
+            return Err(VmError::VmNotRunning(name.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn attach_device(&self, name: &str, _device_xml: &str) -> Result<()> {
+        let mut state = self.enter("attach_device", name)?;
+        if domain_mut(&mut state, name)?.info.state != VmState::Running {
+            return Err(VmError::VmNotRunning(name.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn set_interface_bandwidth(&self, name: &str, _mac: &str, _inbound: u64, _outbound: u64) -> Result<()> {
+        let mut state = self.enter("set_interface_bandwidth", name)?;
+        if domain_mut(&mut state, name)?.info.state != VmState::Running {
+            return Err(VmError::VmNotRunning(name.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn set_cpu_tune(&self, name: &str, _shares: u64, _period: u64, _quota: i64) -> Result<()> {
+        let mut state = self.enter("set_cpu_tune", name)?;
+        if domain_mut(&mut state, name)?.info.state != VmState::Running {
+            return Err(VmError::VmNotRunning(name.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn list_networks(&self) -> Result<Vec<(String, bool, String, bool)>> {
+        let state = self.enter("list_networks", "")?;
+        Ok(state.networks.clone())
+    }
+
+    async fn apply_firewall(&self, ruleset: &str) -> Result<()> {
+        let mut state = self.enter("apply_firewall", "")?;
+        state.firewall = Some(ruleset.to_string());
+        Ok(())
+    }
+
+    async fn host_filter_rules(&self) -> Option<String> {
+        self.lock().filter_rules.clone()
+    }
+
+    async fn define_network(&self, xml: &str) -> Result<()> {
+        let name = domain::element_text(xml, "name").unwrap_or_default();
+        let mut state = self.enter("define_network", &name)?;
+        if state.networks.iter().any(|(network, _, _, _)| *network == name) {
+            return Err(VmError::LibvirtError(format!("network '{}' already exists", name)));
+        }
+        let bridge = format!("virbr{}", state.networks.len());
+        state.networks.push((name.clone(), true, bridge, true));
+        state.network_xmls.insert(name, xml.to_string());
+        Ok(())
+    }
+
+    async fn create_disk(&self, path: &Path, size_bytes: u64) -> Result<()> {
+        let mut state = self.enter("create_disk", &path.to_string_lossy())?;
+        std::fs::write(path, b"")?;
+        state.disk_sizes.insert(path.to_string_lossy().to_string(), size_bytes);
+        Ok(())
+    }
+
+    async fn clone_disk(&self, source: &Path, target: &Path, progress: CopyProgress<'_>) -> Result<()> {
+        let mut state = self.enter("clone_disk", &target.to_string_lossy())?;
+        std::fs::copy(source, target)?;
+        let size = state.disk_sizes.get(source.to_string_lossy().as_ref()).copied().unwrap_or(0);
+        progress(size / 2, size);
+        progress(size, size);
+        state.disk_sizes.insert(target.to_string_lossy().to_string(), size);
+        Ok(())
+    }
+
+    async fn import_disk(&self, source: &Path, target: &Path, link: bool, progress: CopyProgress<'_>) -> Result<()> {
+        let mut state = self.enter(if link { "link_disk" } else { "import_disk" }, &target.to_string_lossy())?;
+        let size = std::fs::metadata(source)?.len();
+        if link {
+            std::fs::write(target, b"")?;
+        } else {
+            std::fs::copy(source, target)?;
+            progress(size, size);
+        }
+        state.disk_sizes.insert(target.to_string_lossy().to_string(), size);
+        Ok(())
+    }
+
+    async fn capabilities(&self) -> Result<HostCapabilities> {
+        Ok(self.enter("capabilities", "")?.capabilities.clone())
+    }
+
+    async fn create_secret(&self, xml: &str, value: &[u8]) -> Result<String> {
+        let kind = domain::attribute(xml, "<usage type='").unwrap_or_default();
+        let id = ["volume", "target", "name"].iter()
+            .find_map(|element| domain::element_text(xml, element))
+            .unwrap_or_default();
+        let usage = SecretUsage::new(&kind, &id)?;
+        let mut state = self.enter("create_secret", usage.id())?;
+        if state.secrets.iter().any(|(secret, _)| secret.usage == usage) {
+            return Err(VmError::LibvirtError(format!("a secret for {} {} already exists", kind, id)));
+        }
+
+        let uuid = uuid::Uuid::new_v4().to_string();
+        state.secrets.push((SecretInfo { uuid: uuid.clone(), usage }, value.to_vec()));
+        Ok(uuid)
+    }
+
+    /// Each call adds a fixed amount of work per running domain: half a second of
+    /// CPU time per vCPU, 1 MiB read and written on disk, 64 KiB each way on the network
+    async fn domain_stats(&self) -> Result<Vec<DomainStats>> {
+        let mut state = self.enter("domain_stats", "")?;
+        state.stats_samples += 1;
+        let samples = state.stats_samples;
+        Ok(state.domains.values()
+            .filter(|domain| domain.info.state == VmState::Running)
+            .map(|domain| DomainStats {
+                name: domain.info.name.clone(),
+                cpu_time: samples * 500_000_000 * domain.info.cpus as u64,
+                vcpus: domain.info.cpus,
+                memory: domain.info.memory * 1024,
+                memory_unused: Some(domain.info.memory * 512),
+                disk_read: samples << 20,
+                disk_written: samples << 20,
+                net_rx: samples << 16,
+                net_tx: samples << 16,
+            })
+            .collect())
+    }
+
+    async fn list_secrets(&self) -> Result<Vec<SecretInfo>> {
+        Ok(self.enter("list_secrets", "")?.secrets.iter().map(|(secret, _)| secret.clone()).collect())
+    }
+
+    async fn delete_secret(&self, uuid: &str) -> Result<()> {
+        let mut state = self.enter("delete_secret", uuid)?;
+        let before = state.secrets.len();
+        state.secrets.retain(|(secret, _)| secret.uuid != uuid);
+        if state.secrets.len() == before {
+            return Err(VmError::LibvirtError(format!("secret {} not found", uuid)));
+        }
+        Ok(())
+    }
+
+    async fn define_pool(&self, xml: &str) -> Result<()> {
+        let name = domain::element_text(xml, "name").unwrap_or_default();
+        let mut state = self.enter("define_pool", &name)?;
+        if state.pools.contains_key(&name) {
+            return Err(VmError::LibvirtError(format!("pool '{}' already exists", name)));
+        }
+
+        let pool = PoolInfo {
+            name: name.clone(),
+            kind: domain::attribute(xml, "<pool type='").unwrap_or_default(),
+            active: true,
+            autostart: true,
+            capacity: 0,
+            allocation: 0,
+            available: 0,
+        };
+        state.pools.insert(name, (pool, Vec::new()));
+        Ok(())
+    }
+
+    async fn list_pools(&self) -> Result<Vec<PoolInfo>> {
+        Ok(self.enter("list_pools", "")?.pools.values().map(|(pool, _)| pool.clone()).collect())
+    }
+
+    async fn delete_pool(&self, name: &str) -> Result<()> {
+        let mut state = self.enter("delete_pool", name)?;
+        state.pools.remove(name)
+            .map(|_| ())
+            .ok_or_else(|| VmError::LibvirtError(format!("pool '{}' not found", name)))
+    }
+
+    async fn list_volumes(&self, pool: &str) -> Result<Vec<VolumeInfo>> {
+        let state = self.enter("list_volumes", pool)?;
+        state.pools.get(pool)
+            .map(|(_, volumes)| volumes.clone())
+            .ok_or_else(|| VmError::LibvirtError(format!("pool '{}' not found", pool)))
+    }
+
+    async fn create_volume(&self, pool: &str, name: &str, size_bytes: u64) -> Result<()> {
+        let mut state = self.enter("create_volume", name)?;
+        let (_, volumes) = state.pools.get_mut(pool)
+            .ok_or_else(|| VmError::LibvirtError(format!("pool '{}' not found", pool)))?;
+        if volumes.iter().any(|volume| volume.name == name) {
+            return Err(VmError::LibvirtError(format!("volume '{}' already exists", name)));
+        }
+        volumes.push(VolumeInfo {
+            name: name.to_string(),
+            path: format!("/mnt/{}/{}", pool, name),
+            capacity: size_bytes,
+            format: "qcow2".to_string(),
+        });
+        Ok(())
+    }
+
+    async fn volume_at(&self, path: &Path) -> Result<Option<(String, String)>> {
+        let state = self.enter("volume_at", &path.to_string_lossy())?;
+        Ok(state.pools.iter()
+            .find_map(|(pool, (_, volumes))| volumes.iter()
+                .find(|volume| Path::new(&volume.path) == path)
+                .map(|volume| (pool.clone(), volume.name.clone()))))
+    }
+
+    async fn delete_volume(&self, pool: &str, name: &str) -> Result<()> {
+        let mut state = self.enter("delete_volume", name)?;
+        let (_, volumes) = state.pools.get_mut(pool)
+            .ok_or_else(|| VmError::LibvirtError(format!("pool '{}' not found", pool)))?;
+        let index = volumes.iter().position(|volume| volume.name == name)
+            .ok_or_else(|| VmError::LibvirtError(format!("volume '{}' not found", name)))?;
+        let volume = volumes.remove(index);
+        // Volumes of a directory pool are files like any other
+        std::fs::remove_file(&volume.path).ok();
+        Ok(())
+    }
+
+    async fn customize_image(&self, image: &Path, customization: &Customization) -> Result<()> {
+        let mut state = self.enter("customize_image", &image.to_string_lossy())?;
+        if !image.exists() {
+            return Err(VmError::CommandError(format!("virt-customize: {} not found", image.display())));
+        }
+        state.customizations.push((image.to_path_buf(), customization.clone()));
+        Ok(())
+    }
+
+    async fn set_guest_password(&self, name: &str, user: &str, password: &str) -> Result<()> {
+        let mut state = self.enter("set_guest_password", name)?;
+        if domain_mut(&mut state, name)?.info.state != VmState::Stopped {
+            return Err(VmError::CommandError(format!("virt-customize: domain {} is in use", name)));
+        }
+        state.guest_passwords.insert((name.to_string(), user.to_string()), password.to_string());
+        Ok(())
+    }
+
+    async fn create_thin_volume(&self, vg: &str, _thin_pool: &str, name: &str, _size_bytes: u64) -> Result<PathBuf> {
+        let device = format!("/dev/{}/{}", vg, name);
+        let mut state = self.enter("create_thin_volume", &device)?;
+        if state.thin_volumes.insert(device.clone(), Vec::new()).is_some() {
+            return Err(VmError::CommandError(format!("lvcreate failed: {} already exists", device)));
+        }
+        Ok(PathBuf::from(device))
+    }
+
+    async fn create_zvol(&self, dataset: &str, name: &str, _size_bytes: u64, _volblocksize: &str, _compression: &str) -> Result<PathBuf> {
+        let device = format!("/dev/zvol/{}/{}", dataset, name);
+        let mut state = self.enter("create_zvol", &device)?;
+        if state.thin_volumes.insert(device.clone(), Vec::new()).is_some() {
+            return Err(VmError::CommandError(format!("zfs failed: {} already exists", device)));
+        }
+        Ok(PathBuf::from(device))
+    }
+
+    async fn clone_zvol(&self, device: &Path, name: &str) -> Result<PathBuf> {
+        let mut state = self.enter("clone_zvol", name)?;
+        thin_volume(&mut state, device)?;
+        let clone = device.with_file_name(name);
+        if state.thin_volumes.insert(clone.to_string_lossy().into_owned(), Vec::new()).is_some() {
+            return Err(VmError::CommandError(format!("zfs failed: {} already exists", clone.display())));
+        }
+        Ok(clone)
+    }
+
+    async fn snapshot_thin_volume(&self, device: &Path, snapshot: &str) -> Result<()> {
+        let mut state = self.enter("snapshot_thin_volume", snapshot)?;
+        thin_volume(&mut state, device)?.push((snapshot.to_string(), "2024-01-01 00:00:00 +0000".to_string()));
+        Ok(())
+    }
+
+    async fn list_thin_snapshots(&self, device: &Path) -> Result<Vec<(String, String)>> {
+        let mut state = self.enter("list_thin_snapshots", &device.to_string_lossy())?;
+        Ok(thin_volume(&mut state, device)?.clone())
+    }
+
+    async fn revert_thin_volume(&self, device: &Path, snapshot: &str) -> Result<()> {
+        let mut state = self.enter("revert_thin_volume", snapshot)?;
+        if !thin_volume(&mut state, device)?.iter().any(|(name, _)| name == snapshot) {
+            return Err(VmError::CommandError(format!("lvcreate failed: no snapshot {}", snapshot)));
+        }
+        Ok(())
+    }
+
+    async fn delete_thin_snapshot(&self, device: &Path, snapshot: &str) -> Result<()> {
+        let mut state = self.enter("delete_thin_snapshot", snapshot)?;
+        thin_volume(&mut state, device)?.retain(|(name, _)| name != snapshot);
+        Ok(())
+    }
+
+    async fn remove_thin_volume(&self, device: &Path) -> Result<()> {
+        let mut state = self.enter("remove_thin_volume", &device.to_string_lossy())?;
+        state.thin_volumes.remove(device.to_string_lossy().as_ref())
+            .map(|_| ())
+            .ok_or_else(|| VmError::InvalidInput(format!("{} is not a thin LV or zvol; leaving it alone", device.display())))
+    }
+
+    async fn block_commit(&self, name: &str, target: &str) -> Result<()> {
+        let mut state = self.enter("block_commit", name)?;
+        block_job_disk(&mut state, name, target)
+    }
+
+    async fn block_pull(&self, name: &str, target: &str) -> Result<()> {
+        let mut state = self.enter("block_pull", name)?;
+        block_job_disk(&mut state, name, target)
+    }
+
+    async fn create_snapshot(&self, name: &str, snapshot: &str, description: Option<&str>, memory: bool) -> Result<()> {
+        let mut state = self.enter(if memory { "create_memory_snapshot" } else { "create_snapshot" }, name)?;
+        let domain = domain_mut(&mut state, name)?;
+        if domain.snapshots.iter().any(|s| s.name == snapshot) {
+            return Err(VmError::LibvirtError(format!("snapshot '{}' already exists", snapshot)));
+        }
+        if memory && domain.info.state != VmState::Running {
+            return Err(VmError::LibvirtError("memory snapshots need an active domain".to_string()));
+        }
+
+        for existing in &mut domain.snapshots {
+            existing.current = false;
+        }
+        domain.snapshots.push(SnapshotInfo {
+            name: snapshot.to_string(),
+            created: chrono::Utc::now().format("%Y-%m-%d %H:%M:%S %z").to_string(),
+            state: domain.info.state.clone(),
+            current: true,
+            description: description.map(|d| d.to_string()),
+        });
+        Ok(())
+    }
+
+    async fn list_snapshots(&self, name: &str) -> Result<Vec<SnapshotInfo>> {
+        let mut state = self.enter("list_snapshots", name)?;
+        Ok(domain_mut(&mut state, name)?.snapshots.clone())
+    }
+
+    async fn revert_snapshot(&self, name: &str, snapshot: &str) -> Result<()> {
+        let mut state = self.enter("revert_snapshot", name)?;
+        let domain = domain_mut(&mut state, name)?;
+        let target = domain.snapshots.iter()
+            .find(|s| s.name == snapshot)
+            .map(|s| s.state.clone())
+            .ok_or_else(|| VmError::LibvirtError(format!("snapshot '{}' not found", snapshot)))?;
+
+        for existing in &mut domain.snapshots {
+            existing.current = existing.name == snapshot;
+        }
+        domain.info.state = target;
+        Ok(())
+    }
+
+    async fn delete_snapshot(&self, name: &str, snapshot: &str) -> Result<()> {
+        let mut state = self.enter("delete_snapshot", name)?;
+        let domain = domain_mut(&mut state, name)?;
+        let before = domain.snapshots.len();
+        domain.snapshots.retain(|s| s.name != snapshot);
+        if domain.snapshots.len() == before {
+            return Err(VmError::LibvirtError(format!("snapshot '{}' not found", snapshot)));
+        }
+        Ok(())
+    }
+
+    async fn connect_console(&self, name: &str, options: ConsoleOptions<'_>) -> Result<()> {
+        let mut state = self.enter(if options.force { "force_console" } else { "connect_console" }, name)?;
+        if domain_mut(&mut state, name)?.info.state != VmState::Running {
+            return Err(VmError::VmNotRunning(name.to_string()));
+        }
+        if state.console_restarts.remove(name) {
+            domain_mut(&mut state, name)?.info.state = VmState::Stopped;
+            state.restarting.insert(name.to_string());
+        }
+        // A login prompt, split inside a multi-byte character
+        if let Some(path) = options.record {
+            let mut recording = Recording::create(path, 80, 24, &format!("{} console", name))?;
+            recording.output(b"Ubuntu 24.04 \xe2\x80")?;
+            recording.output(b"\x94 login: ")?;
+            recording.finish()?;
+        }
+        Ok(())
+    }
+
+    fn subscribe_events(&self, _domain: Option<&str>) -> Result<EventStream> {
+        Err(VmError::OperationError("Event streams are not supported by the mock backend".to_string()))
+    }
+}
+
+/// Command runner for tests: records every invocation and answers with scripted
+/// output, matched on the program and the start of its arguments. Unscripted
+/// commands succeed with no output.
+#[derive(Default)]
+pub struct MockRunner {
+    responses: Mutex<Vec<(Vec<String>, Output)>>,
+    /// Prefixes of commands that never finish
+    hangs: Mutex<Vec<Vec<String>>>,
+    invocations: Mutex<Vec<Invocation>>,
+}
+
+impl MockRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers commands starting with `prefix` (program, then arguments) with
+    /// `stdout`, `stderr` and exit `code`; later responses win over earlier ones
+    pub fn respond(&self, prefix: &[&str], code: i32, stdout: &str, stderr: &str) {
+        let output = Output {
+            status: ExitStatus::from_raw(code << 8),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: stderr.as_bytes().to_vec(),
+        };
+        let prefix = prefix.iter().map(|part| part.to_string()).collect();
+        self.responses.lock().unwrap_or_else(|e| e.into_inner()).push((prefix, output));
+    }
+
+    /// Makes commands starting with `prefix` never finish, like virsh against a
+    /// hung libvirtd; the invocation's timeout is not applied
+    pub fn hang(&self, prefix: &[&str]) {
+        let prefix = prefix.iter().map(|part| part.to_string()).collect();
+        self.hangs.lock().unwrap_or_else(|e| e.into_inner()).push(prefix);
+    }
+
+    /// Every invocation so far, in order, with what it was fed on stdin
+    pub fn invocations(&self) -> Vec<Invocation> {
+        self.invocations.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Every invocation so far as a command line, in order
+    pub fn commands(&self) -> Vec<String> {
+        self.invocations.lock().unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(Invocation::to_string)
+            .collect()
+    }
+
+    async fn answer(&self, invocation: &Invocation) -> Output {
+        self.invocations.lock().unwrap_or_else(|e| e.into_inner()).push(invocation.clone());
+        let line: Vec<&str> = std::iter::once(invocation.program.as_str())
+            .chain(invocation.args.iter().map(String::as_str))
+            .collect();
+        let matches = |prefix: &Vec<String>| line.starts_with(&prefix.iter().map(String::as_str).collect::<Vec<_>>());
+        if self.hangs.lock().unwrap_or_else(|e| e.into_inner()).iter().any(matches) {
+            return std::future::pending().await;
+        }
+        self.responses.lock().unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .rev()
+            .find(|(prefix, _)| matches(prefix))
+            .map(|(_, output)| output.clone())
+            .unwrap_or_else(|| Output { status: ExitStatus::from_raw(0), stdout: Vec::new(), stderr: Vec::new() })
+    }
+}
+
+#[async_trait]
+impl CommandRunner for MockRunner {
+    async fn output(&self, invocation: &Invocation) -> std::io::Result<Output> {
+        Ok(self.answer(invocation).await)
+    }
+
+    async fn status(&self, invocation: &Invocation) -> std::io::Result<ExitStatus> {
+        Ok(self.answer(invocation).await.status)
+    }
+
+    async fn status_with_stderr(&self, invocation: &Invocation) -> std::io::Result<(ExitStatus, Vec<u8>)> {
+        let output = self.answer(invocation).await;
+        Ok((output.status, output.stderr))
+    }
+
+    async fn status_recorded(&self, invocation: &Invocation, _path: &Path, _title: &str) -> std::io::Result<ExitStatus> {
+        Ok(self.answer(invocation).await.status)
+    }
+}
+
+/// Builds a stopped `VmInfo` from domain XML
+fn parse_domain_xml(xml: &str, disk_sizes: &HashMap<String, u64>) -> Result<VmInfo> {
+    let spec = DomainSpec::parse(xml)?;
+
+    Ok(VmInfo {
+        name: spec.name,
+        uuid: spec.uuid,
+        state: VmState::Stopped,
+        memory: spec.memory,
+        cpus: spec.cpus,
+        uptime: None,
+        cpu_usage: None,
+        memory_usage: None,
+        disk_usage: spec.disks.into_iter()
+            .map(|disk| DiskInfo {
+                device: disk.target,
+                size: disk_sizes.get(&disk.path).copied().unwrap_or(0),
+                path: disk.path,
+                used: 0,
+                format: disk.format,
+            })
+            .collect(),
+        network_info: Vec::new(),
+        created_at: 0,
+        last_started: None,
+        autostart: false,
+        health: Vec::new(),
+        guest: None,
+        graphics: Vec::new(),
+        snapshot_count: None,
+        last_backup: None,
+    })
+}
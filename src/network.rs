@@ -0,0 +1,208 @@
+use crate::config::{NetworkMode, NetworkTemplate};
+use crate::error::{Result, VmError};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// An IPv4 subnet as written in CIDR form, e.g. 192.168.150.0/24
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ipv4Subnet {
+    pub network: Ipv4Addr,
+    pub prefix: u8,
+}
+
+impl std::str::FromStr for Ipv4Subnet {
+    type Err = VmError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || VmError::InvalidInput(format!("Invalid subnet '{}': expected e.g. 192.168.150.0/24", s));
+        let (address, prefix) = s.split_once('/').ok_or_else(invalid)?;
+        let address: Ipv4Addr = address.parse().map_err(|_| invalid())?;
+        let prefix: u8 = prefix.parse().map_err(|_| invalid())?;
+        // Anything smaller than a /30 leaves no room for the host and a guest
+        if !(8..=30).contains(&prefix) {
+            return Err(VmError::InvalidInput(format!(
+                "Subnet '{}' must be between a /8 and a /30", s
+            )));
+        }
+        let subnet = Self { network: address, prefix };
+        if subnet.network != Ipv4Addr::from(u32::from(address) & subnet.mask()) {
+            return Err(VmError::InvalidInput(format!(
+                "'{}' is not a network address; did you mean {}/{}?",
+                s, Ipv4Addr::from(u32::from(address) & subnet.mask()), prefix
+            )));
+        }
+        Ok(subnet)
+    }
+}
+
+impl std::fmt::Display for Ipv4Subnet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+impl Ipv4Subnet {
+    /// The subnet `address` is in, for an address with a `prefix`-bit netmask
+    pub fn containing(address: Ipv4Addr, prefix: u8) -> Self {
+        let prefix = prefix.min(32);
+        let subnet = Self { network: address, prefix };
+        Self { network: Ipv4Addr::from(u32::from(address) & subnet.mask()), prefix }
+    }
+
+    fn mask(&self) -> u32 {
+        u32::MAX.checked_shl(32 - u32::from(self.prefix)).unwrap_or(0)
+    }
+
+    pub fn netmask(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.mask())
+    }
+
+    /// The host's own address on the network: the first one
+    pub fn gateway(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.network) + 1)
+    }
+
+    fn broadcast(&self) -> u32 {
+        u32::from(self.network) | !self.mask()
+    }
+
+    /// Whether `address` is a guest address: inside, and neither the network,
+    /// the gateway nor the broadcast address
+    pub fn is_guest_address(&self, address: Ipv4Addr) -> bool {
+        let address = u32::from(address);
+        address > u32::from(self.gateway()) && address < self.broadcast()
+    }
+
+    /// The upper half of the subnet, leaving the lower half for static addresses
+    pub fn default_dhcp_range(&self) -> (Ipv4Addr, Ipv4Addr) {
+        let size = !self.mask() + 1;
+        let start = (u32::from(self.network) + size / 2).max(u32::from(self.gateway()) + 1);
+        (Ipv4Addr::from(start), Ipv4Addr::from(self.broadcast() - 1))
+    }
+}
+
+/// An IPv6 /64 as written in CIDR form, e.g. fd00:150::/64. Stateless
+/// autoconfiguration only works on a /64, so no other prefix is accepted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ipv6Subnet {
+    pub network: Ipv6Addr,
+}
+
+impl std::str::FromStr for Ipv6Subnet {
+    type Err = VmError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || VmError::InvalidInput(format!("Invalid IPv6 prefix '{}': expected e.g. fd00:150::/64", s));
+        let (address, prefix) = s.split_once('/').ok_or_else(invalid)?;
+        let address: Ipv6Addr = address.parse().map_err(|_| invalid())?;
+        if prefix != "64" {
+            return Err(VmError::InvalidInput(format!("IPv6 prefix '{}' must be a /64", s)));
+        }
+        let network = Ipv6Addr::from(u128::from(address) & !u128::from(u64::MAX));
+        if network != address {
+            return Err(VmError::InvalidInput(format!(
+                "'{}' is not a network address; did you mean {}/64?", s, network
+            )));
+        }
+        if network.is_unicast_link_local() || network.is_multicast() {
+            return Err(VmError::InvalidInput(format!(
+                "'{}' can't be routed; use a unique-local (fd00::/8) or global prefix", s
+            )));
+        }
+        Ok(Self { network })
+    }
+}
+
+impl Ipv6Subnet {
+    /// The host's own address on the network: ::1 of the prefix
+    pub fn gateway(&self) -> Ipv6Addr {
+        Ipv6Addr::from(u128::from(self.network) + 1)
+    }
+
+    /// ::100 to ::1ff of the prefix, clear of the host and of addresses
+    /// guests give themselves
+    pub fn dhcp_range(&self) -> (Ipv6Addr, Ipv6Addr) {
+        let network = u128::from(self.network);
+        (Ipv6Addr::from(network + 0x100), Ipv6Addr::from(network + 0x1ff))
+    }
+}
+
+/// Checks a network name before it goes into network XML
+pub fn validate_network_name(name: &str) -> Result<()> {
+    // Bridge names derive from it on some setups, and Linux caps those at 15 characters
+    if name.is_empty() || name.len() > 15 || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(VmError::InvalidInput(format!(
+            "Invalid network name '{}': use up to 15 letters, digits, hyphens and underscores", name
+        )));
+    }
+    Ok(())
+}
+
+/// Network XML for `template`, dual-stack when given an IPv6 /64; libvirt picks
+/// the bridge name. Fails on a subnet, DHCP range or domain that doesn't make sense.
+pub fn network_xml(name: &str, template: &NetworkTemplate, ipv6: Option<&Ipv6Subnet>) -> Result<String> {
+    validate_network_name(name)?;
+    let subnet: Ipv4Subnet = template.subnet.parse()?;
+
+    let (start, end) = match (&template.dhcp_start, &template.dhcp_end) {
+        (None, None) => subnet.default_dhcp_range(),
+        (Some(start), Some(end)) => {
+            let parse = |address: &str| -> Result<Ipv4Addr> {
+                let parsed: Ipv4Addr = address.parse()
+                    .map_err(|_| VmError::InvalidInput(format!("Invalid DHCP address '{}'", address)))?;
+                if !subnet.is_guest_address(parsed) {
+                    return Err(VmError::InvalidInput(format!(
+                        "DHCP address {} is not a guest address in {}", parsed, template.subnet
+                    )));
+                }
+                Ok(parsed)
+            };
+            let (start, end) = (parse(start)?, parse(end)?);
+            if start > end {
+                return Err(VmError::InvalidInput(format!("DHCP range {}-{} runs backwards", start, end)));
+            }
+            (start, end)
+        }
+        _ => return Err(VmError::InvalidInput(
+            "Set both dhcp_start and dhcp_end, or neither".to_string()
+        )),
+    };
+
+    let forward = match (template.mode, ipv6) {
+        // Without this libvirt leaves IPv6 routed, which a unique-local prefix can't be
+        (NetworkMode::Nat, Some(_)) => "\n  <forward mode='nat'>\n    <nat ipv6='yes'/>\n  </forward>",
+        (NetworkMode::Nat, None) => "\n  <forward mode='nat'/>",
+        (NetworkMode::Isolated, _) => "",
+    };
+    // libvirt's dnsmasq sends router advertisements for every IPv6 address of a
+    // network; with a DHCPv6 range they tell guests to take their address from it
+    let ipv6 = ipv6.map(|subnet| {
+        let (start, end) = subnet.dhcp_range();
+        format!(r#"
+  <ip family='ipv6' address='{}' prefix='64'>
+    <dhcp>
+      <range start='{}' end='{}'/>
+    </dhcp>
+  </ip>"#, subnet.gateway(), start, end)
+    }).unwrap_or_default();
+    let domain = match &template.domain {
+        Some(domain) => {
+            if domain.is_empty() || !domain.split('.').all(|label| {
+                !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            }) {
+                return Err(VmError::InvalidInput(format!("Invalid domain name '{}'", domain)));
+            }
+            // Names under the lab domain are answered here and never forwarded upstream
+            format!("\n  <domain name='{}' localOnly='yes'/>", domain)
+        }
+        None => String::new(),
+    };
+
+    Ok(format!(r#"<network>
+  <name>{}</name>{}{}
+  <ip address='{}' netmask='{}'>
+    <dhcp>
+      <range start='{}' end='{}'/>
+    </dhcp>
+  </ip>{}
+</network>"#, name, forward, domain, subnet.gateway(), subnet.netmask(), start, end, ipv6))
+}
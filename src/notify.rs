@@ -0,0 +1,131 @@
+use std::time::Duration;
+
+use crate::{
+    config::NotificationConfig,
+    error::{VmError, Result},
+    runner::{CommandRunner, Invocation},
+};
+
+/// A message about a finished operation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notice {
+    pub title: String,
+    pub body: String,
+    pub success: bool,
+}
+
+impl Notice {
+    pub fn operation(operation: &str, vm: Option<&str>, result: &std::result::Result<(), String>, elapsed: Duration) -> Self {
+        let subject = match vm {
+            Some(vm) => format!("{} of '{}'", operation, vm),
+            None => operation.to_string(),
+        };
+        let took = crate::utils::format_duration(elapsed.as_secs());
+        match result {
+            Ok(()) => Self {
+                title: format!("vmtools: {} finished", subject),
+                body: format!("{} completed after {}.", subject, took),
+                success: true,
+            },
+            Err(message) => Self {
+                title: format!("vmtools: {} failed", subject),
+                body: format!("{} failed after {}: {}", subject, took, message),
+                success: false,
+            },
+        }
+    }
+
+    /// The notice as an email, headers included
+    pub fn email(&self, from: &str, to: &[String]) -> String {
+        format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+            from,
+            to.join(", "),
+            self.title.replace(['\r', '\n'], " "),
+            chrono::Utc::now().to_rfc2822(),
+            self.body,
+        )
+    }
+}
+
+/// Sends notices through the channels `NotificationConfig` turns on
+pub struct Notifier {
+    config: NotificationConfig,
+}
+
+impl Notifier {
+    pub fn new(config: &NotificationConfig) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.config.desktop || self.email_enabled()
+    }
+
+    fn email_enabled(&self) -> bool {
+        self.config.smtp_url.is_some() && !self.config.email_to.is_empty()
+    }
+
+    /// Whether finishing `operation` after `elapsed` deserves a notice
+    pub fn wants(&self, operation: &str, elapsed: Duration) -> bool {
+        self.is_enabled()
+            && elapsed.as_secs() >= self.config.min_seconds
+            && self.config.operations.iter().any(|o| o.eq_ignore_ascii_case(operation))
+    }
+
+    /// Delivers the notice on every channel through `runner`; failures are logged, never fatal
+    pub async fn send(&self, runner: &dyn CommandRunner, notice: &Notice) {
+        if self.config.desktop {
+            if let Err(e) = desktop(runner, notice).await {
+                tracing::warn!("Desktop notification failed: {}", e);
+            }
+        }
+        if let (Some(url), true) = (&self.config.smtp_url, self.email_enabled()) {
+            if let Err(e) = email(runner, url, &self.config, notice).await {
+                tracing::warn!("Email notification to {} failed: {}", self.config.email_to.join(", "), e);
+            }
+        }
+    }
+}
+
+/// `notify-send`, which talks to the session's notification daemon over D-Bus
+async fn desktop(runner: &dyn CommandRunner, notice: &Notice) -> Result<()> {
+    let notify = Invocation::new("notify-send")
+        .args(["--app-name", "vmtools", "--urgency", if notice.success { "normal" } else { "critical" }])
+        .arg(&notice.title)
+        .arg(&notice.body);
+    let output = runner.output(&notify)
+        .await
+        .map_err(|e| VmError::CommandError(format!("Failed to execute notify-send: {}", e)))?;
+
+    if !output.status.success() {
+        return Err(VmError::CommandError(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(())
+}
+
+/// Sends the notice with curl's SMTP support, the message on stdin
+async fn email(runner: &dyn CommandRunner, url: &str, config: &NotificationConfig, notice: &Notice) -> Result<()> {
+    let mut command = Invocation::new("curl")
+        .args(["-sS", "--netrc-optional", "--max-time", "30", "--url", url])
+        .args(["--mail-from", &config.email_from]);
+    for to in &config.email_to {
+        command = command.args(["--mail-rcpt", to]);
+    }
+    if url.starts_with("smtp://") {
+        // Upgrade with STARTTLS where the server offers it; smtps:// is TLS from the start
+        command = command.arg("--ssl");
+    }
+    let command = command
+        .args(["--upload-file", "-"])
+        .stdin(notice.email(&config.email_from, &config.email_to));
+    let output = runner.output(&command)
+        .await
+        .map_err(|e| VmError::NetworkError(format!("Failed to execute curl: {}", e)))?;
+    if !output.status.success() {
+        return Err(VmError::NetworkError(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(())
+}
@@ -0,0 +1,384 @@
+use std::fmt;
+
+use crate::{
+    capabilities::HostCapabilities,
+    domain,
+    error::Result,
+};
+
+/// Interface models emulating real NICs, each with a virtio-net equivalent
+const EMULATED_NICS: &[&str] = &["e1000", "e1000e", "rtl8139", "ne2k_pci", "pcnet"];
+
+/// Hyper-V enlightenments Windows uses when it finds them, as `<hyperv>` entries
+const HYPERV_ENLIGHTENMENTS: &[&str] = &[
+    "<relaxed state='on'/>",
+    "<vapic state='on'/>",
+    "<spinlocks state='on' retries='8191'/>",
+    "<vpindex state='on'/>",
+    "<runtime state='on'/>",
+    "<synic state='on'/>",
+    "<stimer state='on'/>",
+    "<reset state='on'/>",
+    "<frequencies state='on'/>",
+    "<tlbflush state='on'/>",
+    "<ipi state='on'/>",
+];
+
+/// Guest-specific tuning `optimize --profile` adds to the general suggestions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Profile {
+    /// Hyper-V enlightenments, no HPET and a local-time clock for Windows guests
+    Windows,
+    /// Dedicated host CPUs, huge pages and no balloon for latency-sensitive
+    /// guests: audio, gaming, network functions
+    Realtime,
+}
+
+impl std::str::FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "windows" => Ok(Profile::Windows),
+            "realtime" => Ok(Profile::Realtime),
+            _ => Err(format!("Invalid profile '{}'. Use windows or realtime", s)),
+        }
+    }
+}
+
+/// What the host has to spare, for the suggestions that depend on it
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct HostResources {
+    /// Online host CPUs
+    pub cpus: u32,
+    /// Free huge pages in KiB
+    pub free_hugepages: u64,
+}
+
+impl HostResources {
+    pub fn detect() -> Self {
+        let meminfo = std::fs::read_to_string("/proc/meminfo").unwrap_or_default();
+        let field = |name: &str| meminfo.lines()
+            .find_map(|line| line.strip_prefix(name))
+            .and_then(|rest| rest.split_whitespace().next())
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(0);
+        Self {
+            cpus: std::thread::available_parallelism().map_or(1, |cpus| cpus.get() as u32),
+            free_hugepages: field("HugePages_Free:") * field("Hugepagesize:"),
+        }
+    }
+}
+
+/// A change to a domain definition that `optimize` suggests
+#[derive(Debug, Clone, PartialEq)]
+pub enum Optimization {
+    /// virtio memory balloon, so the host can reclaim memory the guest doesn't use
+    Balloon,
+    /// virtio RNG fed by the host, so the guest never waits for entropy
+    Rng,
+    /// virtio-serial agent channel carrying clipboard and display resizing;
+    /// SPICE's own channel with SPICE graphics, QEMU's vdagent otherwise
+    AgentChannel { spice: bool },
+    /// One virtio-net queue pair per vCPU on the interface with MAC address `mac`
+    Multiqueue { mac: String, queues: u32 },
+    /// Pass the host CPU through instead of a model that hides some of its features
+    CpuPassthrough { from: String },
+    /// Move to the newest version of the machine type's family
+    MachineType { from: String, to: String },
+    /// Move the disk at `target` from an emulated SATA or IDE controller to virtio as `dev`
+    VirtioDisk { target: String, dev: String },
+    /// Replace an emulated NIC model with virtio-net
+    VirtioNic { mac: String, model: String },
+    /// Back guest memory with the host's huge pages
+    Hugepages,
+    /// Hyper-V enlightenments and the Hyper-V reference clock, so Windows
+    /// schedules, times and flushes as it would on Hyper-V
+    HypervEnlightenments,
+    /// Drop the emulated HPET, which Windows polls at high cost
+    DisableHpet,
+    /// Keep the guest clock in local time, as Windows expects of the hardware clock
+    LocaltimeClock { from: String },
+    /// Pin vCPU `i` to host CPU `vcpus[i]` and QEMU's emulator and I/O threads to
+    /// `housekeeping`, so nothing else runs where the guest does
+    CpuPinning { vcpus: Vec<u32>, housekeeping: u32, iothreads: u32 },
+    /// Remove the memory balloon, whose inflating and deflating stalls the guest
+    RemoveBalloon,
+}
+
+impl Optimization {
+    /// Returns `xml` with this change made
+    pub fn apply(&self, xml: &str) -> Result<String> {
+        match self {
+            Optimization::Balloon => match xml.find("<memballoon model='none'") {
+                Some(start) => {
+                    let end = start + xml[start..].find('>').unwrap_or(0) + 1;
+                    Ok(format!("{}<memballoon model='virtio'/>{}", &xml[..start], &xml[end..]))
+                }
+                None => domain::add_device(xml, "<memballoon model='virtio'/>"),
+            },
+            Optimization::Rng => domain::add_device(xml, "<rng model='virtio'>
+      <backend model='random'>/dev/urandom</backend>
+    </rng>"),
+            Optimization::AgentChannel { spice } => {
+                let mut xml = xml.to_string();
+                if !xml.contains("<controller type='virtio-serial'") {
+                    xml = domain::add_device(&xml, "<controller type='virtio-serial' index='0'/>")?;
+                }
+                let channel = if *spice {
+                    "<channel type='spicevmc'>
+      <target type='virtio' name='com.redhat.spice.0'/>
+    </channel>"
+                } else {
+                    "<channel type='qemu-vdagent'>
+      <source>
+        <clipboard copypaste='yes'/>
+        <mouse mode='client'/>
+      </source>
+      <target type='virtio' name='com.redhat.spice.0'/>
+    </channel>"
+                };
+                domain::add_device(&xml, channel)
+            }
+            Optimization::Multiqueue { mac, queues } => domain::set_interface_queues(xml, mac, *queues),
+            Optimization::CpuPassthrough { .. } => domain::set_cpu(xml, "<cpu mode='host-passthrough' check='none'/>"),
+            Optimization::MachineType { to, .. } => domain::set_machine(xml, to),
+            Optimization::VirtioDisk { target, dev } => domain::set_disk_virtio(xml, target, dev),
+            Optimization::VirtioNic { mac, .. } => domain::set_interface_model(xml, mac, "virtio"),
+            Optimization::Hugepages => domain::set_hugepages(xml),
+            Optimization::HypervEnlightenments => {
+                let hyperv = format!("<hyperv mode='custom'>\n      {}\n    </hyperv>", HYPERV_ENLIGHTENMENTS.join("\n      "));
+                let xml = domain::set_feature(xml, "hyperv", &hyperv)?;
+                domain::set_timer(&xml, "hypervclock", "<timer name='hypervclock' present='yes'/>")
+            }
+            Optimization::DisableHpet => domain::set_timer(xml, "hpet", "<timer name='hpet' present='no'/>"),
+            Optimization::LocaltimeClock { .. } => domain::set_clock_offset(xml, "localtime"),
+            Optimization::CpuPinning { vcpus, housekeeping, iothreads } => {
+                domain::set_cpu_pinning(xml, vcpus, &housekeeping.to_string(), *iothreads)
+            }
+            Optimization::RemoveBalloon => {
+                let start = xml.find("<memballoon ")
+                    .ok_or_else(|| crate::error::VmError::InvalidInput("VM has no memory balloon".to_string()))?;
+                let tag_end = start + xml[start..].find('>').unwrap_or(0) + 1;
+                let end = if xml[..tag_end].ends_with("/>") {
+                    tag_end
+                } else {
+                    tag_end + xml[tag_end..].find("</memballoon>").unwrap_or(0) + "</memballoon>".len()
+                };
+                Ok(format!("{}<memballoon model='none'/>{}", &xml[..start], &xml[end..]))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Optimization {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Optimization::Balloon => write!(f, "Add a virtio memory balloon so the host can reclaim unused guest memory"),
+            Optimization::Rng => write!(f, "Add a virtio RNG so the guest doesn't stall waiting for entropy"),
+            Optimization::AgentChannel { spice: true } => {
+                write!(f, "Add the SPICE agent channel for clipboard sharing and display resizing")
+            }
+            Optimization::AgentChannel { spice: false } => {
+                write!(f, "Add a qemu-vdagent channel for clipboard sharing and display resizing")
+            }
+            Optimization::Multiqueue { mac, queues } => {
+                write!(f, "Use {} virtio-net queues on {} so every vCPU handles network traffic", queues, mac)
+            }
+            Optimization::CpuPassthrough { from } => {
+                write!(f, "Pass the host CPU through instead of {} so the guest sees all its features", from)
+            }
+            Optimization::MachineType { from, to } => write!(f, "Upgrade the machine type from {} to {}", from, to),
+            Optimization::VirtioDisk { target, dev } => {
+                write!(f, "Move disk {} to virtio as {} (the guest needs virtio drivers)", target, dev)
+            }
+            Optimization::VirtioNic { mac, model } => {
+                write!(f, "Replace the emulated {} NIC {} with virtio-net (the guest needs virtio drivers)", model, mac)
+            }
+            Optimization::Hugepages => write!(f, "Back guest memory with huge pages to cut TLB misses"),
+            Optimization::HypervEnlightenments => {
+                write!(f, "Enable Hyper-V enlightenments and the Hyper-V clock so Windows runs as on Hyper-V")
+            }
+            Optimization::DisableHpet => write!(f, "Disable the emulated HPET, which Windows polls at a high CPU cost"),
+            Optimization::LocaltimeClock { from } => {
+                write!(f, "Set the clock offset from {} to localtime, as Windows keeps the hardware clock", from)
+            }
+            Optimization::CpuPinning { vcpus, housekeeping, .. } => {
+                let cpus: Vec<String> = vcpus.iter().map(u32::to_string).collect();
+                write!(f, "Pin vCPUs to host CPUs {} and the emulator threads to CPU {}", cpus.join(","), housekeeping)
+            }
+            Optimization::RemoveBalloon => write!(f, "Remove the memory balloon, which stalls the guest while it resizes"),
+        }
+    }
+}
+
+/// Performance devices the definition lacks: a virtio balloon and RNG, the agent
+/// channel when the guest has a display, and multiqueue on virtio interfaces of a
+/// guest with more than one vCPU
+pub fn performance_devices(xml: &str) -> Result<Vec<Optimization>> {
+    let spec = domain::DomainSpec::parse(xml)?;
+    let mut optimizations = Vec::new();
+
+    if spec.balloon.is_none() {
+        optimizations.push(Optimization::Balloon);
+    }
+    if !xml.contains("<rng model='virtio'") {
+        optimizations.push(Optimization::Rng);
+    }
+    if xml.contains("<graphics ") && !xml.contains("name='com.redhat.spice.0'") {
+        optimizations.push(Optimization::AgentChannel { spice: xml.contains("<graphics type='spice'") });
+    }
+    if spec.cpus > 1 {
+        for (mac, model, queues) in domain::interfaces(xml) {
+            if model == "virtio" && queues < spec.cpus {
+                optimizations.push(Optimization::Multiqueue { mac, queues: spec.cpus });
+            }
+        }
+    }
+    Ok(optimizations)
+}
+
+/// Settings worth changing given what the host offers: the CPU mode, an outdated
+/// machine type, emulated disk and NIC models, and huge pages when
+/// the host's free huge pages can hold the guest's memory
+pub fn configuration(xml: &str, capabilities: &HostCapabilities, host: &HostResources) -> Result<Vec<Optimization>> {
+    let spec = domain::DomainSpec::parse(xml)?;
+    let mut optimizations = Vec::new();
+
+    // Only a KVM guest can run on the host CPU
+    let kvm = xml.contains("<domain type='kvm'");
+    if kvm && capabilities.cpu_modes.iter().any(|mode| mode == "host-passthrough") {
+        let mode = domain::cpu_mode(xml).unwrap_or_else(|| "the default model".to_string());
+        if mode != "host-passthrough" {
+            optimizations.push(Optimization::CpuPassthrough { from: mode });
+        }
+    }
+
+    // A bare family name (q35, pc) already stands for the newest version
+    if spec.machine_type.contains('-') {
+        let family = family(&spec.machine_type);
+        let newest = capabilities.machines.iter()
+            .find(|machine| machine.name == family)
+            .and_then(|alias| alias.canonical.clone());
+        if let Some(newest) = newest.filter(|newest| *newest != spec.machine_type) {
+            optimizations.push(Optimization::MachineType { from: spec.machine_type.clone(), to: newest });
+        }
+    }
+
+    let mut taken: Vec<String> = spec.disks.iter().map(|disk| disk.target.clone()).collect();
+    for disk in spec.disks.iter().filter(|disk| matches!(disk.bus.as_str(), "sata" | "ide")) {
+        let dev = (b'a'..=b'z').map(|letter| format!("vd{}", letter as char))
+            .find(|dev| !taken.contains(dev))
+            .unwrap_or_default();
+        taken.push(dev.clone());
+        optimizations.push(Optimization::VirtioDisk { target: disk.target.clone(), dev });
+    }
+    for (mac, model, _) in domain::interfaces(xml) {
+        if EMULATED_NICS.contains(&model.as_str()) {
+            optimizations.push(Optimization::VirtioNic { mac, model });
+        }
+    }
+
+    if kvm && !hugepages(xml) && spec.memory > 0 && host.free_hugepages >= spec.memory * 1024 {
+        optimizations.push(Optimization::Hugepages);
+    }
+    Ok(optimizations)
+}
+
+/// Everything `optimize` suggests for the definition: performance devices, host
+/// dependent settings and what `profile` calls for, without changes the profile
+/// rules out (a realtime guest keeps no balloon)
+pub fn suggestions(
+    xml: &str,
+    capabilities: &HostCapabilities,
+    host: &HostResources,
+    profile: Option<Profile>,
+) -> Result<Vec<Optimization>> {
+    let mut optimizations = performance_devices(xml)?;
+    optimizations.extend(configuration(xml, capabilities, host)?);
+    if let Some(profile) = profile {
+        for optimization in self::profile(xml, profile, host)? {
+            if !optimizations.contains(&optimization) {
+                optimizations.push(optimization);
+            }
+        }
+    }
+    if profile == Some(Profile::Realtime) {
+        optimizations.retain(|optimization| *optimization != Optimization::Balloon);
+    }
+    Ok(optimizations)
+}
+
+/// The changes `profile` calls for that the definition doesn't have yet
+pub fn profile(xml: &str, profile: Profile, host: &HostResources) -> Result<Vec<Optimization>> {
+    let mut optimizations = Vec::new();
+    match profile {
+        Profile::Windows => {
+            let hyperv = domain::feature(xml, "hyperv").unwrap_or_default();
+            let hypervclock = domain::timer(xml, "hypervclock").is_some_and(|timer| timer.contains("present='yes'"));
+            if !hypervclock || HYPERV_ENLIGHTENMENTS.iter().any(|entry| !hyperv.contains(entry)) {
+                optimizations.push(Optimization::HypervEnlightenments);
+            }
+            if !domain::timer(xml, "hpet").is_some_and(|timer| timer.contains("present='no'")) {
+                optimizations.push(Optimization::DisableHpet);
+            }
+            let offset = domain::clock_offset(xml).unwrap_or_else(|| "utc".to_string());
+            if offset != "localtime" {
+                optimizations.push(Optimization::LocaltimeClock { from: offset });
+            }
+        }
+        Profile::Realtime => {
+            let spec = domain::DomainSpec::parse(xml)?;
+            let tune = domain::element_text(xml, "cputune").unwrap_or_default();
+            let pinned = tune.contains("<emulatorpin ")
+                && (0..spec.cpus).all(|vcpu| tune.contains(&format!("<vcpupin vcpu='{}'", vcpu)));
+            // CPU 0 stays with the host and QEMU's own threads
+            if !pinned && host.cpus > spec.cpus {
+                optimizations.push(Optimization::CpuPinning {
+                    vcpus: (1..=spec.cpus).collect(),
+                    housekeeping: 0,
+                    iothreads: spec.iothreads,
+                });
+            }
+            if !hugepages(xml) {
+                optimizations.push(Optimization::Hugepages);
+            }
+            if opening_tag_model(xml, "memballoon").is_some_and(|model| model != "none") {
+                optimizations.push(Optimization::RemoveBalloon);
+            }
+        }
+    }
+    Ok(optimizations)
+}
+
+/// The family alias a versioned machine type belongs to: `pc-q35-7.2` → `q35`,
+/// `pc-i440fx-8.0` → `pc`
+fn family(machine: &str) -> String {
+    let prefix = machine.rsplit_once('-').map_or(machine, |(prefix, _)| prefix);
+    match prefix.strip_prefix("pc-") {
+        Some("i440fx") => "pc".to_string(),
+        Some(family) => family.to_string(),
+        None => prefix.to_string(),
+    }
+}
+
+/// Whether guest memory is already backed by huge pages
+fn hugepages(xml: &str) -> bool {
+    domain::element_text(xml, "memoryBacking").is_some_and(|body| body.contains("<hugepages"))
+}
+
+/// The `model` of the first `<tag>`, e.g. the balloon's
+fn opening_tag_model(xml: &str, tag: &str) -> Option<String> {
+    let start = xml.find(&format!("<{} ", tag))?;
+    let end = start + xml[start..].find('>')?;
+    domain::attribute(&xml[start..end], "model='")
+}
+
+/// `before` and `after` as a unified diff of their lines
+pub fn diff(before: &str, after: &str) -> String {
+    similar::TextDiff::from_lines(before, after)
+        .unified_diff()
+        .context_radius(3)
+        .header("current", "optimized")
+        .to_string()
+}
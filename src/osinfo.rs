@@ -0,0 +1,168 @@
+use std::fmt;
+use std::path::Path;
+
+use crate::{
+    domain,
+    error::{VmError, Result},
+    optimize::{self, HostResources, Profile},
+    runner::{CommandRunner, Invocation},
+};
+
+/// Guests whose installers predate virtio and carry no drivers for it
+const LEGACY: &[&str] = &["msdos", "freedos", "netware", "generic"];
+
+/// Windows releases without a driver for the emulated e1000e
+const E1000_WINDOWS: &[&str] = &["win2k", "winxp", "win2k3", "winvista", "win7", "win2k8"];
+
+/// A guest OS by its osinfo short ID, as virt-install's `--os-variant` takes it
+#[derive(Debug, Clone, PartialEq)]
+pub struct OsVariant {
+    /// Such as `ubuntu24.04`, `win11` or `freebsd14.0`
+    pub short_id: String,
+    /// Such as "Ubuntu 24.04 LTS"; the short ID when nothing better is known
+    pub name: String,
+}
+
+/// How a guest gets its virtio drivers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VirtioSupport {
+    /// In the installer and kernel, as with Linux and the BSDs
+    Builtin,
+    /// From a separate driver disk (virtio-win) once installed
+    Separate,
+    /// Not at all
+    Unavailable,
+}
+
+/// The hardware an OS works best with, as virt-install derives it from osinfo
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OsDefaults {
+    pub virtio: VirtioSupport,
+    /// NIC model for guests without virtio drivers
+    pub nic_model: &'static str,
+    /// Wants the windows optimize profile: Hyper-V enlightenments, no HPET, local-time clock
+    pub windows: bool,
+}
+
+impl Default for OsDefaults {
+    /// A current Linux guest, which is what the generated definitions assume
+    fn default() -> Self {
+        Self { virtio: VirtioSupport::Builtin, nic_model: "virtio", windows: false }
+    }
+}
+
+impl OsVariant {
+    /// The variant the guest agent's `guest-get-osinfo` reply describes
+    pub fn from_agent(osinfo: &serde_json::Value) -> Option<Self> {
+        let text = |key: &str| osinfo.get(key).and_then(|v| v.as_str()).filter(|v| !v.is_empty());
+        let id = text("id")?;
+        let version = text("version-id").unwrap_or_default();
+        let short_id = match id {
+            // Servers go by release year: win2k19 for Windows Server 2019
+            "mswindows" if text("variant") == Some("server") => {
+                format!("win2k{}", version.strip_prefix("20").unwrap_or(version))
+            }
+            "mswindows" => format!("win{}", version),
+            _ => format!("{}{}", id, version),
+        };
+        Some(Self {
+            name: text("pretty-name").map_or_else(|| short_id.clone(), String::from),
+            short_id,
+        })
+    }
+
+    pub fn defaults(&self) -> OsDefaults {
+        let id = self.short_id.as_str();
+        if LEGACY.iter().any(|legacy| id.starts_with(legacy)) {
+            OsDefaults { virtio: VirtioSupport::Unavailable, nic_model: "e1000", windows: false }
+        } else if id.starts_with("win") {
+            let nic_model = if E1000_WINDOWS.contains(&id) { "e1000" } else { "e1000e" };
+            OsDefaults { virtio: VirtioSupport::Separate, nic_model, windows: true }
+        } else {
+            OsDefaults::default()
+        }
+    }
+}
+
+impl std::str::FromStr for OsVariant {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let short_id = s.trim().to_lowercase();
+        if short_id.is_empty() || !short_id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_')) {
+            return Err(format!(
+                "Invalid OS variant '{}'. Use an osinfo short ID such as ubuntu24.04 or win11 (osinfo-query os lists them)", s
+            ));
+        }
+        Ok(Self { name: short_id.clone(), short_id })
+    }
+}
+
+impl fmt::Display for OsVariant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.name == self.short_id {
+            write!(f, "{}", self.short_id)
+        } else {
+            write!(f, "{} ({})", self.name, self.short_id)
+        }
+    }
+}
+
+/// The OS an installer or live ISO carries, from libosinfo's `osinfo-detect`;
+/// `None` for media its database doesn't know
+pub async fn detect_media(runner: &dyn CommandRunner, iso: &Path) -> Result<Option<OsVariant>> {
+    let detect = Invocation::new("osinfo-detect").args(["--format=env", "--type=media"]).arg(iso);
+    let output = runner.output(&detect).await.map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => VmError::CommandError(
+            "osinfo-detect not found; install libosinfo-bin (Debian/Ubuntu) or libosinfo (Fedora)".to_string()
+        ),
+        _ => VmError::CommandError(format!("Failed to run osinfo-detect: {}", e)),
+    })?;
+    if !output.status.success() {
+        return Err(VmError::CommandError(format!(
+            "osinfo-detect failed: {}", String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let Some(os) = ["OSINFO_INSTALLER=", "OSINFO_LIVE="].iter()
+        .find_map(|key| stdout.lines().find_map(|line| line.trim().strip_prefix(key)))
+    else {
+        return Ok(None);
+    };
+
+    let query = Invocation::new("osinfo-query").args(["os", "--fields=short-id,name"]).arg(format!("id={}", os));
+    let output = runner.output(&query).await?;
+    let table = String::from_utf8_lossy(&output.stdout);
+    // A header, a rule, then "ubuntu24.04 | Ubuntu 24.04 LTS"
+    Ok(table.lines().nth(2)
+        .and_then(|row| row.split_once('|'))
+        .map(|(short_id, name)| OsVariant { short_id: short_id.trim().to_string(), name: name.trim().to_string() })
+        .filter(|variant| !variant.short_id.is_empty()))
+}
+
+/// Returns a freshly generated definition fitted to `defaults`: SATA disks and an
+/// emulated NIC for guests without virtio drivers, the windows profile's tuning
+/// for Windows
+pub fn fit(xml: &str, defaults: &OsDefaults) -> Result<String> {
+    let mut xml = xml.to_string();
+    if defaults.virtio != VirtioSupport::Builtin {
+        for disk in domain::DomainSpec::parse(&xml)?.disks.iter().filter(|disk| disk.bus == "virtio") {
+            // The installer CD-ROM already holds a SATA name
+            let dev = (b'a'..=b'z').map(|letter| format!("sd{}", letter as char))
+                .find(|dev| !xml.contains(&format!("<target dev='{}'", dev)))
+                .ok_or_else(|| VmError::InvalidInput("No free SATA disk names left".to_string()))?;
+            xml = domain::set_disk_bus(&xml, &disk.target, "sata", &dev)?;
+        }
+        for (mac, model, _) in domain::interfaces(&xml) {
+            if model == "virtio" {
+                xml = domain::set_interface_model(&xml, &mac, defaults.nic_model)?;
+            }
+        }
+    }
+    if defaults.windows {
+        for optimization in optimize::profile(&xml, Profile::Windows, &HostResources::default())? {
+            xml = optimization.apply(&xml)?;
+        }
+    }
+    Ok(xml)
+}
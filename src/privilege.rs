@@ -0,0 +1,244 @@
+use std::io::IsTerminal;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{
+    error::{VmError, Result},
+    runner::{CommandRunner, Invocation, SystemRunner},
+    storage::{self, SecurityModule},
+};
+
+/// Level of access the current user has to a libvirt URI
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessLevel {
+    ReadWrite,
+    ReadOnly,
+    Denied,
+}
+
+/// Decides how libvirt commands are run for the current user.
+///
+/// Read-only connections are used when that is all the user is granted, and
+/// write operations are escalated through polkit (`pkexec`) only when an
+/// interactive agent can prompt for it. Otherwise callers get an actionable
+/// `PermissionDenied` error instead of a silent `sudo` retry.
+#[derive(Clone)]
+pub struct Privileges {
+    uri: String,
+    access: AccessLevel,
+    is_root: bool,
+    polkit: bool,
+    /// libvirtd didn't answer the probe within the timeout
+    unresponsive: bool,
+    runner: Arc<dyn CommandRunner>,
+}
+
+impl Privileges {
+    /// Probes `uri`, giving up on each probe after `timeout` (zero waits forever)
+    pub async fn detect(uri: &str, timeout: Duration) -> Self {
+        Self::detect_with(uri, Arc::new(SystemRunner), timeout).await
+    }
+
+    /// Probes `uri` through `runner`, which then runs every command built here
+    pub async fn detect_with(uri: &str, runner: Arc<dyn CommandRunner>, timeout: Duration) -> Self {
+        let mut unresponsive = false;
+        let access = match probe(runner.as_ref(), uri, false, timeout).await {
+            Some(true) => AccessLevel::ReadWrite,
+            // A libvirtd that doesn't answer won't answer a read-only connection either
+            None => {
+                tracing::warn!("{} did not answer within {}s (libvirt.timeout)", uri, timeout.as_secs());
+                unresponsive = true;
+                AccessLevel::Denied
+            }
+            Some(false) if probe(runner.as_ref(), uri, true, timeout).await == Some(true) => AccessLevel::ReadOnly,
+            Some(false) => AccessLevel::Denied,
+        };
+
+        // pkexec needs an agent to authenticate against; only rely on it interactively
+        let polkit = Path::new("/usr/bin/pkexec").exists() && std::io::stdin().is_terminal();
+
+        Self {
+            uri: uri.to_string(),
+            access,
+            is_root: unsafe { libc::geteuid() } == 0,
+            polkit,
+            unresponsive,
+            runner,
+        }
+    }
+
+    /// What runs the commands these privileges build
+    pub fn runner(&self) -> &dyn CommandRunner {
+        self.runner.as_ref()
+    }
+
+    pub fn shared_runner(&self) -> Arc<dyn CommandRunner> {
+        self.runner.clone()
+    }
+
+    pub fn access(&self) -> AccessLevel {
+        self.access
+    }
+
+    pub fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.is_root
+    }
+
+    /// Whether libvirtd was there but didn't answer in time, as when it hangs
+    pub fn is_unresponsive(&self) -> bool {
+        self.unresponsive
+    }
+
+    /// A `virsh` command for queries that don't modify anything
+    pub fn virsh_read(&self, args: &[&str]) -> Result<Invocation> {
+        match self.access {
+            AccessLevel::ReadWrite => Ok(self.virsh_direct(args, false)),
+            AccessLevel::ReadOnly => Ok(self.virsh_direct(args, true)),
+            AccessLevel::Denied if self.polkit => Ok(self.virsh_pkexec(args)),
+            AccessLevel::Denied => Err(self.permission_error("query libvirt")),
+        }
+    }
+
+    /// A `virsh` command that changes domain, network or storage state
+    pub fn virsh_write(&self, args: &[&str]) -> Result<Invocation> {
+        match self.access {
+            AccessLevel::ReadWrite => Ok(self.virsh_direct(args, false)),
+            _ if self.polkit => Ok(self.virsh_pkexec(args)),
+            _ => Err(self.permission_error("modify libvirt state")),
+        }
+    }
+
+    fn virsh_direct(&self, args: &[&str], readonly: bool) -> Invocation {
+        let readonly: &[&str] = if readonly { &["-r"] } else { &[] };
+        Invocation::new("virsh").args(readonly).args(["-c", &self.uri]).args(args)
+    }
+
+    fn virsh_pkexec(&self, args: &[&str]) -> Invocation {
+        Invocation::new("pkexec").args(["virsh", "-c", &self.uri]).args(args)
+    }
+
+    /// A host command that needs root, such as `nft`; other users go through
+    /// polkit when it is there
+    pub fn root_command(&self, program: &str) -> Invocation {
+        if self.is_root || !self.polkit {
+            Invocation::new(program)
+        } else {
+            Invocation::new("pkexec").arg(program)
+        }
+    }
+
+    /// Builds an error telling the user exactly how to gain the missing access
+    pub fn permission_error(&self, action: &str) -> VmError {
+        if self.is_root {
+            return VmError::PermissionDenied(format!(
+                "Cannot {} on {} even as root; check that libvirtd is running", action, self.uri
+            ));
+        }
+
+        let group = libvirt_group();
+        let current = match self.access {
+            AccessLevel::ReadOnly => "you only have read-only access",
+            _ => "you have no access (or libvirtd is not running)",
+        };
+
+        VmError::PermissionDenied(format!(
+            "Cannot {} on {}: {}.\n  \
+             Add your user to the '{}' group and log in again:\n    sudo usermod -aG {} $USER\n  \
+             or use a per-user connection:\n    vmtools config --set libvirt.uri=qemu:///session",
+            action, self.uri, current, group, group
+        ))
+    }
+
+    /// The `PermissionDenied` error behind a failed virsh command, when `stderr`
+    /// says it was refused: by libvirtd (group membership, polkit, a read-only
+    /// connection), by /dev/kvm or by a file the guest could not open
+    pub fn explain_failure(&self, action: &str, stderr: &str) -> Option<VmError> {
+        let lower = stderr.to_lowercase();
+        let refused_by_libvirtd = lower.contains("read only access prevents")
+            || lower.contains("authentication unavailable")
+            || lower.contains("authentication failed")
+            || lower.contains("access denied by policy")
+            || (lower.contains("libvirt-sock") && is_denied(stderr));
+        if refused_by_libvirtd {
+            return Some(self.permission_error(action));
+        }
+        if is_kvm_denied(stderr) {
+            return Some(kvm_error());
+        }
+        storage::explain_denial(stderr, SecurityModule::detect())
+            .map(|fix| VmError::PermissionDenied(format!("Cannot {}: {}", action, fix)))
+    }
+}
+
+/// Whether a command's stderr reports EACCES or EPERM
+pub fn is_denied(stderr: &str) -> bool {
+    stderr.contains("Permission denied") || stderr.contains("Operation not permitted")
+}
+
+/// Whether `stderr` says /dev/kvm could not be opened, as virsh or qemu word it
+pub fn is_kvm_denied(stderr: &str) -> bool {
+    let lower = stderr.to_lowercase();
+    is_denied(stderr) && (lower.contains("/dev/kvm") || lower.contains("kvm kernel module") || lower.contains("initialize kvm"))
+}
+
+/// The `PermissionDenied` error behind a failed qemu-img run, when `stderr` says
+/// the current user may not open or create the image it names
+pub fn image_error(action: &str, stderr: &str) -> Option<VmError> {
+    if !is_denied(stderr) {
+        return None;
+    }
+    // qemu-img quotes the file: Could not create '/var/lib/libvirt/images/web.qcow2': Permission denied
+    let path = stderr.split('\'').skip(1).step_by(2).filter(|part| part.starts_with('/')).last()
+        .unwrap_or("the image");
+    Some(VmError::PermissionDenied(format!(
+        "Cannot {} {}: permission denied.\n  \
+         Take ownership of it:\n    sudo chown $USER {}\n  \
+         or keep images in a directory you own:\n    vmtools config --set storage.vm_images_path=$HOME/vms && vmtools storage-init",
+        action, path, path
+    )))
+}
+
+/// The error for a user who may not open /dev/kvm
+pub fn kvm_error() -> VmError {
+    VmError::PermissionDenied(
+        "Cannot open /dev/kvm.\n  \
+         Add your user to the 'kvm' group and log in again:\n    sudo usermod -aG kvm $USER\n  \
+         or create guests without acceleration: vmtools create --emulated".to_string()
+    )
+}
+
+/// Whether `virsh uri` succeeds on `uri`; `None` when it didn't finish within `timeout`
+async fn probe(runner: &dyn CommandRunner, uri: &str, readonly: bool, timeout: Duration) -> Option<bool> {
+    let readonly: &[&str] = if readonly { &["-r"] } else { &[] };
+    let invocation = Invocation::new("virsh").args(readonly).args(["-c", uri, "uri"]).timeout(timeout);
+    let run = runner.output(&invocation);
+    // Bounded here as well, so no runner can keep startup waiting on a hung libvirtd
+    let result = if timeout.is_zero() {
+        run.await
+    } else {
+        tokio::time::timeout(timeout, run).await.ok()?
+    };
+    match result {
+        Err(e) if e.kind() == std::io::ErrorKind::TimedOut => None,
+        result => Some(result.is_ok_and(|output| output.status.success())),
+    }
+}
+
+/// Name of the group granting libvirt access on this distribution
+pub fn libvirt_group() -> &'static str {
+    let groups = std::fs::read_to_string("/etc/group").unwrap_or_default();
+    let has_group = |name: &str| groups.lines().any(|line| line.split(':').next() == Some(name));
+
+    if has_group("libvirt") {
+        "libvirt"
+    } else if has_group("libvirtd") {
+        "libvirtd"
+    } else {
+        "libvirt"
+    }
+}
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use tokio::net::UnixStream;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
 use serde_json::{json, Value};
 
 use crate::error::{VmError, Result};
@@ -18,45 +19,73 @@ impl QemuMonitor {
         }
     }
 
+    /// Connects and completes the QMP capabilities handshake
     pub async fn connect(&self) -> Result<QemuConnection> {
         let stream = UnixStream::connect(&self.socket_path)
             .await
             .map_err(|e| VmError::QemuError(format!("Failed to connect to QEMU monitor: {}", e)))?;
 
-        Ok(QemuConnection { stream })
+        let (reader, writer) = stream.into_split();
+        let mut connection = QemuConnection {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        };
+
+        // QEMU greets with {"QMP": {...}} and accepts commands only after qmp_capabilities
+        connection.read_message().await?;
+        connection.execute_command("qmp_capabilities").await?;
+
+        Ok(connection)
     }
 }
 
 #[allow(dead_code)]
 pub struct QemuConnection {
-    stream: UnixStream,
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
 }
 
 #[allow(dead_code)]
 impl QemuConnection {
     pub async fn execute_command(&mut self, command: &str) -> Result<Value> {
-        // Send QMP command
+        self.execute(command, json!({})).await
+    }
+
+    /// Runs a QMP command and returns its response, skipping asynchronous events
+    pub async fn execute(&mut self, command: &str, arguments: Value) -> Result<Value> {
         let qmp_command = json!({
             "execute": command,
-            "arguments": {}
+            "arguments": arguments
         });
 
-        let command_str = format!("{}\n", qmp_command.to_string());
-        self.stream.write_all(command_str.as_bytes())
+        let command_str = format!("{}\n", qmp_command);
+        self.writer.write_all(command_str.as_bytes())
             .await
             .map_err(|e| VmError::QemuError(format!("Failed to send command: {}", e)))?;
 
-        // Read response
-        let mut buffer = vec![0; 4096];
-        let n = self.stream.read(&mut buffer)
-            .await
-            .map_err(|e| VmError::QemuError(format!("Failed to read response: {}", e)))?;
+        loop {
+            let response = self.read_message().await?;
+            if response.get("event").is_some() {
+                continue;
+            }
+
+            if let Some(error) = response.get("error") {
+                let desc = error.get("desc").and_then(|d| d.as_str()).unwrap_or("unknown error");
+                return Err(VmError::QemuError(format!("{} failed: {}", command, desc)));
+            }
+
+            return Ok(response);
+        }
+    }
 
-        let response = String::from_utf8_lossy(&buffer[..n]);
-        let json_response: Value = serde_json::from_str(&response)
-            .map_err(|e| VmError::QemuError(format!("Failed to parse response: {}", e)))?;
+    async fn read_message(&mut self) -> Result<Value> {
+        let line = self.lines.next_line()
+            .await
+            .map_err(|e| VmError::QemuError(format!("Failed to read response: {}", e)))?
+            .ok_or_else(|| VmError::QemuError("QEMU monitor closed the connection".to_string()))?;
 
-        Ok(json_response)
+        serde_json::from_str(&line)
+            .map_err(|e| VmError::QemuError(format!("Failed to parse response: {}", e)))
     }
 
     pub async fn get_vm_status(&mut self) -> Result<HashMap<String, Value>> {
@@ -88,34 +117,12 @@ impl QemuConnection {
     }
 
     pub async fn screenshot(&mut self, filename: &str) -> Result<()> {
-        let command = json!({
-            "execute": "screendump",
-            "arguments": {
-                "filename": filename
-            }
-        });
-
-        let command_str = format!("{}\n", command.to_string());
-        self.stream.write_all(command_str.as_bytes())
-            .await
-            .map_err(|e| VmError::QemuError(format!("Failed to take screenshot: {}", e)))?;
-
+        self.execute("screendump", json!({ "filename": filename })).await?;
         Ok(())
     }
 
     pub async fn send_key(&mut self, key: &str) -> Result<()> {
-        let command = json!({
-            "execute": "send-key",
-            "arguments": {
-                "keys": [key]
-            }
-        });
-
-        let command_str = format!("{}\n", command.to_string());
-        self.stream.write_all(command_str.as_bytes())
-            .await
-            .map_err(|e| VmError::QemuError(format!("Failed to send key: {}", e)))?;
-
+        self.execute("send-key", json!({ "keys": [{ "type": "qcode", "data": key }] })).await?;
         Ok(())
     }
 }
\ No newline at end of file
@@ -0,0 +1,193 @@
+use serde_json::json;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::ExitStatus;
+use std::time::Instant;
+
+use crate::{
+    error::{VmError, Result},
+    runner::Invocation,
+};
+
+/// A terminal session saved in asciinema's asciicast v2 format: a JSON header line,
+/// then one `[seconds, "o", text]` line per chunk of output
+pub struct Recording {
+    out: BufWriter<File>,
+    start: Instant,
+    /// Trailing bytes of an incomplete UTF-8 sequence, held for the next chunk
+    pending: Vec<u8>,
+}
+
+impl Recording {
+    pub fn create(path: &Path, width: u16, height: u16, title: &str) -> Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        let header = json!({
+            "version": 2,
+            "width": width,
+            "height": height,
+            "timestamp": chrono::Utc::now().timestamp(),
+            "title": title,
+            "env": { "TERM": std::env::var("TERM").unwrap_or_else(|_| "xterm".to_string()) },
+        });
+        writeln!(out, "{}", header)?;
+        Ok(Self { out, start: Instant::now(), pending: Vec::new() })
+    }
+
+    /// Appends terminal output received now
+    pub fn output(&mut self, data: &[u8]) -> Result<()> {
+        self.pending.extend_from_slice(data);
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            // An error without error_len is a sequence cut off at the end of the chunk
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => self.pending.len(),
+        };
+        if valid == 0 {
+            return Ok(());
+        }
+        let text = String::from_utf8_lossy(&self.pending[..valid]).into_owned();
+        self.pending.drain(..valid);
+        self.event(&text)
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        if !self.pending.is_empty() {
+            let text = String::from_utf8_lossy(&self.pending).into_owned();
+            self.event(&text)?;
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn event(&mut self, text: &str) -> Result<()> {
+        let event = json!([self.start.elapsed().as_secs_f64(), "o", text]);
+        writeln!(self.out, "{}", event)?;
+        Ok(())
+    }
+}
+
+/// Runs an interactive console command on a new pseudo-terminal, relaying it to this
+/// terminal while everything it prints is recorded to `path`. Keystrokes are not
+/// recorded, so passwords typed at a login prompt stay out of the file.
+/// Runners call this from `CommandRunner::status_recorded`.
+pub async fn record_console(console: &Invocation, path: &Path, title: &str) -> Result<ExitStatus> {
+    let (console, path, title) = (console.clone(), path.to_path_buf(), title.to_string());
+
+    tokio::task::spawn_blocking(move || relay(&console, &path, &title))
+        .await
+        .map_err(|e| VmError::CommandError(format!("Console recording failed: {}", e)))?
+}
+
+fn relay(console: &Invocation, path: &Path, title: &str) -> Result<ExitStatus> {
+    let (width, height) = terminal_size();
+    let (mut master, slave) = open_pty(width, height)?;
+    let mut recording = Recording::create(path, width, height, title)?;
+
+    let mut command = console.to_std_command();
+    command
+        .stdin(slave.try_clone()?)
+        .stdout(slave.try_clone()?)
+        .stderr(slave);
+    // The console program needs the pty as its controlling terminal
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut child = command.spawn()
+        .map_err(|e| VmError::CommandError(format!("Failed to execute {}: {}", console.program, e)))?;
+    // Our copies of the slave end must close for reads to end when the program exits
+    drop(command);
+
+    let _raw = RawMode::enable();
+    let mut input = master.try_clone()?;
+    // Blocks on stdin until the process exits; nothing to join
+    std::thread::spawn(move || {
+        let _ = std::io::copy(&mut std::io::stdin(), &mut input);
+    });
+
+    let mut stdout = std::io::stdout();
+    let mut buffer = [0u8; 4096];
+    loop {
+        // EIO once the console program exits and the pty closes
+        let read = match master.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(read) => read,
+        };
+        stdout.write_all(&buffer[..read])?;
+        stdout.flush()?;
+        recording.output(&buffer[..read])?;
+    }
+
+    recording.finish()?;
+    Ok(child.wait()?)
+}
+
+fn terminal_size() -> (u16, u16) {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDIN_FILENO, libc::TIOCGWINSZ, &mut size) } == 0 && size.ws_col > 0 {
+        (size.ws_col, size.ws_row)
+    } else {
+        (80, 24)
+    }
+}
+
+/// A new pseudo-terminal pair of the given size, as (master, slave)
+fn open_pty(width: u16, height: u16) -> Result<(File, File)> {
+    let error = |what: &str| VmError::CommandError(format!("Failed to {}: {}", what, std::io::Error::last_os_error()));
+    unsafe {
+        let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        if master < 0 {
+            return Err(error("open a pseudo-terminal"));
+        }
+        let master = File::from_raw_fd(master);
+        if libc::grantpt(master.as_raw_fd()) != 0 || libc::unlockpt(master.as_raw_fd()) != 0 {
+            return Err(error("unlock the pseudo-terminal"));
+        }
+        let mut name = [0 as libc::c_char; 128];
+        if libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len()) != 0 {
+            return Err(error("name the pseudo-terminal"));
+        }
+        let size = libc::winsize { ws_row: height, ws_col: width, ws_xpixel: 0, ws_ypixel: 0 };
+        libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size);
+
+        let slave_path = std::ffi::CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned();
+        let slave = std::fs::OpenOptions::new().read(true).write(true).open(slave_path)?;
+        Ok((master, slave))
+    }
+}
+
+/// Puts this terminal in raw mode so keystrokes such as Ctrl+] reach the console
+/// program unprocessed; restores the previous mode when dropped
+struct RawMode(Option<libc::termios>);
+
+impl RawMode {
+    fn enable() -> Self {
+        unsafe {
+            let mut saved: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut saved) != 0 {
+                return Self(None);
+            }
+            let mut raw = saved;
+            libc::cfmakeraw(&mut raw);
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw);
+            Self(Some(saved))
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if let Some(saved) = &self.0 {
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved);
+            }
+        }
+    }
+}
@@ -0,0 +1,687 @@
+use colored::*;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use vmtools_core::{
+    capabilities::HostCapabilities,
+    diagnosis::{self, DiagnosisStep, Verdict},
+    domain::SpecDifference,
+    firewall::FirewallRule,
+    health::{self, HealthResult},
+    manifest::ManifestChange,
+    secret::SecretInfo,
+    stats::VmUsage,
+    storage::{PoolInfo, VolumeInfo},
+    trash::TrashEntry,
+    utils,
+    validation::ConfigIssue,
+    vm::{BenchResult, GuestInfo, HostSummary, ListColumn, SnapshotInfo, SnapshotLayer, SnapshotUsage, VmDiskUsage, VmInfo},
+};
+
+/// Truncates `text` to `width` characters, marking the cut with an ellipsis
+fn truncate_cell(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        text.to_string()
+    } else {
+        let mut cut: String = text.chars().take(width.saturating_sub(1)).collect();
+        cut.push('…');
+        cut
+    }
+}
+
+/// Snapshot counts from here on are highlighted: long chains slow down disk I/O
+const SNAPSHOT_SPRAWL: usize = 10;
+
+/// Backups older than a week are highlighted as stale
+const BACKUP_STALE_SECS: u64 = 7 * 86400;
+
+pub fn vm_table(vms: &[VmInfo], columns: &[ListColumn]) {
+    if vms.is_empty() {
+        println!("{}", "No virtual machines found".yellow());
+        return;
+    }
+
+    let columns = if columns.is_empty() {
+        ListColumn::DEFAULT
+    } else {
+        columns
+    };
+
+    let rows: Vec<Vec<String>> = vms.iter()
+        .map(|vm| columns.iter()
+            .map(|column| truncate_cell(&column.value(vm), column.max_width()))
+            .collect())
+        .collect();
+
+    // Size each column to its widest cell so long names don't break alignment
+    let widths: Vec<usize> = columns.iter().enumerate()
+        .map(|(i, column)| rows.iter()
+            .map(|row| row[i].chars().count())
+            .chain(std::iter::once(column.header().len()))
+            .max()
+            .unwrap_or(0))
+        .collect();
+
+    let header: Vec<String> = columns.iter().zip(&widths)
+        .map(|(column, width)| format!("{:<width$}", column.header(), width = width).bold().to_string())
+        .collect();
+    println!("{}", header.join(" "));
+    println!("{}", "─".repeat(widths.iter().sum::<usize>() + widths.len().saturating_sub(1)));
+
+    for (vm, row) in vms.iter().zip(&rows) {
+        let cells: Vec<String> = columns.iter().zip(row).zip(&widths)
+            .map(|((column, cell), width)| {
+                let padded = format!("{:<width$}", cell, width = width);
+                if *column == ListColumn::State {
+                    vm.state.paint(&padded).to_string()
+                } else if *column == ListColumn::Health && !vm.health.is_empty() {
+                    if vm.health.iter().all(|result| result.passed) {
+                        padded.green().to_string()
+                    } else {
+                        padded.red().to_string()
+                    }
+                } else if *column == ListColumn::Snapshots && vm.snapshot_count.is_some_and(|count| count >= SNAPSHOT_SPRAWL) {
+                    padded.yellow().to_string()
+                } else if *column == ListColumn::LastBackup {
+                    match vm.last_backup {
+                        Some(time) if (chrono::Utc::now().timestamp() as u64).saturating_sub(time) < BACKUP_STALE_SECS => padded,
+                        Some(_) => padded.yellow().to_string(),
+                        None => padded.red().to_string(),
+                    }
+                } else {
+                    padded
+                }
+            })
+            .collect();
+        println!("{}", cells.join(" ").trim_end());
+    }
+}
+
+pub fn vm_status(vm_info: &VmInfo) {
+    println!("{}", format!("VM Status: {}", vm_info.name).bold());
+    println!("{}", "═".repeat(40));
+    println!("State: {}", vm_info.state);
+    println!("UUID: {}", vm_info.uuid);
+    println!("Memory: {}MB", vm_info.memory);
+    println!("CPUs: {}", vm_info.cpus);
+    if vm_info.created_at > 0 {
+        println!("Created: {}", utils::format_timestamp(vm_info.created_at));
+    }
+    if let Some(last_started) = vm_info.last_started {
+        println!("Last started: {}", utils::format_timestamp(last_started));
+    }
+
+    if let Some(uptime) = vm_info.uptime {
+        println!("Uptime: {}", utils::format_duration(uptime));
+    }
+
+    if !vm_info.health.is_empty() {
+        println!("Health: {}", health::summary(&vm_info.health));
+        for result in vm_info.health.iter().filter(|result| !result.passed) {
+            println!("  {} {}: {}", "✗".red(), result.check, result.error.as_deref().unwrap_or("failed"));
+        }
+    }
+
+    if let Some(cpu_usage) = vm_info.cpu_usage {
+        println!("CPU Usage: {:.1}%", cpu_usage);
+    }
+
+    if let Some(memory_usage) = vm_info.memory_usage {
+        println!("Memory Usage: {:.1}%", memory_usage);
+    }
+
+    if !vm_info.graphics.is_empty() {
+        println!("\nConsole:");
+        for console in &vm_info.graphics {
+            let address = match console.uri() {
+                Some(uri) => uri,
+                None => format!("{} (port picked at start)", console.listen.as_deref().unwrap_or("localhost")),
+            };
+            let tls = match console.tls_port {
+                Some(port) if console.port.is_some() => format!(", TLS port {}", port),
+                _ => String::new(),
+            };
+            let viewer = match console.connected {
+                Some(true) => format!(", {}", "viewer connected".green()),
+                Some(false) => ", no viewer connected".to_string(),
+                None => String::new(),
+            };
+            println!("  {}: {}{}{}", console.kind.to_uppercase(), address, tls, viewer);
+        }
+    }
+
+    if let Some(guest) = &vm_info.guest {
+        println!("\nGuest:");
+        guest_os(guest);
+        println!("  Agent version: {}", guest.agent_version);
+    }
+
+    if !vm_info.disk_usage.is_empty() {
+        println!("\nDisk Information:");
+        for disk in &vm_info.disk_usage {
+            println!("  {} ({}): {}/{} ({})",
+                     disk.device,
+                     disk.format,
+                     utils::format_bytes(disk.used),
+                     utils::format_bytes(disk.size),
+                     disk.path);
+        }
+    }
+
+    if !vm_info.network_info.is_empty() {
+        println!("\nNetwork Information:");
+        for net in &vm_info.network_info {
+            let addresses: Vec<&str> = net.ip_address.iter().chain(&net.ipv6_address).map(String::as_str).collect();
+            println!("  {}: {} ({})",
+                     net.interface,
+                     if addresses.is_empty() { "No IP".to_string() } else { addresses.join(", ") },
+                     net.mac_address);
+        }
+    }
+}
+
+pub fn config_entries(entries: &[(String, String)]) {
+    for (key, value) in entries {
+        println!("{} = {}", key.cyan(), value);
+    }
+}
+
+pub fn config_issues(path: &Path, issues: &[ConfigIssue]) {
+    if issues.is_empty() {
+        println!("{} {} is valid", "✓".green(), path.display());
+        return;
+    }
+    for issue in issues {
+        println!("{} {}", "✗".red(), issue);
+    }
+    println!("{}", format!("{} issue(s) in {}", issues.len(), path.display()).yellow());
+}
+
+pub fn group_status(group: &str, vms: &[VmInfo]) {
+    println!("{} {}", "Group:".bold(), group.cyan());
+    vm_table(vms, ListColumn::GROUP);
+}
+
+pub fn host_summary(summary: &HostSummary) {
+    println!("{}", "Host Summary".bold());
+    println!("{}", "═".repeat(40));
+    let states: Vec<String> = summary.states.iter()
+        .map(|(state, count)| format!("{} {}", count, state.paint(&state.label().to_lowercase())))
+        .collect();
+    if states.is_empty() {
+        println!("VMs: 0");
+    } else {
+        println!("VMs: {} ({})", summary.total(), states.join(", "));
+    }
+
+    let percent = |part: f64, whole: f64| if whole > 0.0 { format!(" ({:.0}%)", part * 100.0 / whole) } else { String::new() };
+    let host_memory = summary.host.as_ref().map_or(0, |host| host.total_memory);
+    let host_cpus = summary.host.as_ref().map_or(0, |host| host.cpu_count);
+    println!("Memory: {}MB allocated{}, {}MB used{}{}",
+             summary.memory_allocated, percent(summary.memory_allocated as f64, host_memory as f64),
+             summary.memory_used, percent(summary.memory_used as f64, host_memory as f64),
+             if host_memory > 0 { format!(" of {}MB", host_memory) } else { String::new() });
+    // cpu_percent counts each host CPU as 100%
+    println!("CPU: {} vCPUs allocated{}, {:.1}% used{}",
+             summary.vcpus_allocated,
+             if host_cpus > 0 { format!(" on {} host CPUs", host_cpus) } else { String::new() },
+             summary.cpu_percent,
+             percent(summary.cpu_percent, host_cpus as f64 * 100.0));
+
+    if !summary.pools.is_empty() {
+        println!("\n{}", "Storage pools:".bold());
+        for pool in &summary.pools {
+            if pool.active {
+                println!("  {:<16} {} of {} used{}", truncate_cell(&pool.name, 16),
+                         utils::format_bytes(pool.allocation), utils::format_bytes(pool.capacity),
+                         percent(pool.allocation as f64, pool.capacity as f64));
+            } else {
+                println!("  {:<16} {}", truncate_cell(&pool.name, 16), "inactive".yellow());
+            }
+        }
+    }
+
+    if summary.issues.is_empty() {
+        println!("\n{} No issues found", "✓".green());
+    } else {
+        println!("\n{}", "Issues:".bold());
+        for issue in &summary.issues {
+            println!("  {} {}", "⚠".yellow(), issue);
+        }
+    }
+}
+
+pub fn groups(groups: &BTreeMap<String, Vec<String>>) {
+    if groups.is_empty() {
+        println!("{}", "No groups; create one with: vmtools group create <name> <vm>...".yellow());
+        return;
+    }
+    for (group, members) in groups {
+        println!("{} ({}): {}", group.bold(), members.len(), members.join(", "));
+    }
+}
+
+pub fn dependencies(name: &str, dependencies: &[String]) {
+    if dependencies.is_empty() {
+        println!("{}", format!("VM '{}' has no dependencies", name).yellow());
+    } else {
+        println!("{} depends on: {}", name.bold(), dependencies.join(", "));
+    }
+}
+
+pub fn health_report(name: &str, results: &[HealthResult]) {
+    if results.is_empty() {
+        println!("{}", format!("VM '{}' has no health checks; add one with --add", name).yellow());
+        return;
+    }
+
+    for result in results {
+        match &result.error {
+            None => println!("  {} {}", "✓".green(), result.check),
+            Some(error) => println!("  {} {}: {}", "✗".red(), result.check, error),
+        }
+    }
+    println!("{}: {}", name.bold(), health::summary(results));
+}
+
+pub fn trash_table(entries: &[TrashEntry], retention_days: u64) {
+    if entries.is_empty() {
+        println!("{}", "Trash is empty".yellow());
+        return;
+    }
+
+    println!("{:<20} {:<20} {:<6}", "NAME".bold(), "DELETED".bold(), "DISKS".bold());
+    println!("{}", "─".repeat(50));
+    for entry in entries {
+        println!("{:<20} {:<20} {:<6}",
+                 entry.name,
+                 entry.deleted_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                 entry.disks.len());
+    }
+
+    if retention_days > 0 {
+        println!("\nEntries are purged after {} day(s)", retention_days);
+    }
+}
+
+pub fn bench_table(results: &[BenchResult]) {
+    println!("\n{:<18} {:<12} {:<10} {:>10} {:>12} {:>12}",
+             "TARGET".bold(), "CACHE".bold(), "AIO".bold(),
+             "IOPS".bold(), "THROUGHPUT".bold(), "LATENCY".bold());
+    println!("{}", "─".repeat(79));
+    for result in results {
+        println!("{:<18} {:<12} {:<10} {:>10.0} {:>10}/s {:>10.1}µs",
+                 result.target,
+                 result.cache.as_deref().unwrap_or("-"),
+                 result.aio.as_deref().unwrap_or("-"),
+                 result.iops,
+                 utils::format_bytes(result.throughput as u64),
+                 result.latency_us);
+    }
+}
+
+pub fn capabilities(capabilities: &HostCapabilities) {
+    println!("{}", "Host Capabilities".bold());
+    println!("{}", "═".repeat(40));
+    println!("Architecture: {}", capabilities.arch);
+    if let Some(model) = &capabilities.cpu_model {
+        println!("Host CPU: {} {}", capabilities.cpu_vendor.as_deref().unwrap_or(""), model);
+    }
+    println!("KVM: {}", if capabilities.kvm { "yes".green() } else { "no".red() });
+    if let Some(max) = capabilities.max_vcpus {
+        println!("Max vCPUs: {}", max);
+    }
+
+    // Aliases name the current default of each family; list those, then the count of versions
+    println!("\nMachine types:");
+    for alias in capabilities.machines.iter().filter(|machine| machine.canonical.is_some()) {
+        println!("  {:<12} → {}", alias.name, alias.canonical.as_deref().unwrap_or_default());
+    }
+    let versioned = capabilities.machines.iter().filter(|machine| machine.canonical.is_none()).count();
+    println!("  ({} versioned types in total)", versioned);
+
+    println!("\nCPU modes: {}", capabilities.cpu_modes.join(", "));
+    if let Some(model) = &capabilities.host_model {
+        println!("host-model expands to: {}", model);
+    }
+    if !capabilities.cpu_models.is_empty() {
+        println!("Usable named models: {}", capabilities.cpu_models.join(", "));
+    }
+
+    if !capabilities.firmware.is_empty() {
+        println!("\nUEFI firmware:");
+        for firmware in &capabilities.firmware {
+            println!("  {}", firmware);
+        }
+    }
+}
+
+pub fn secret_table(secrets: &[SecretInfo]) {
+    if secrets.is_empty() {
+        println!("{}", "No secrets defined".yellow());
+        return;
+    }
+
+    println!("{:<38} {:<8} {}", "UUID".bold(), "TYPE".bold(), "USAGE".bold());
+    println!("{}", "─".repeat(72));
+    for secret in secrets {
+        println!("{:<38} {:<8} {}", secret.uuid, secret.usage.kind(), secret.usage.id());
+    }
+}
+
+pub fn diagnosis(name: &str, steps: &[DiagnosisStep]) {
+    println!("{}", format!("Network path of VM '{}'", name).bold());
+    for (number, step) in steps.iter().enumerate() {
+        let mark = match step.verdict {
+            Verdict::Pass => "✓".green(),
+            Verdict::Fail => "✗".red(),
+            Verdict::Skip => "-".yellow(),
+        };
+        let detail = if step.detail.is_empty() { String::new() } else { format!(": {}", step.detail) };
+        println!("  {}. {} {}{}", number + 1, mark, step.check, detail.bright_black());
+        if let Some(hint) = &step.hint {
+            println!("       💡 {}", hint);
+        }
+    }
+
+    match diagnosis::first_failure(steps) {
+        Some(step) => println!("\n❌ Packets die at: {}", step.check.red()),
+        None if steps.iter().any(|step| step.verdict == Verdict::Skip) => {
+            println!("\n✅ No break found in the steps that could run")
+        }
+        None => println!("\n✅ Packets get through every hop"),
+    }
+}
+
+pub fn firewall_table(rules: &[FirewallRule]) {
+    if rules.is_empty() {
+        println!("{}", "No port forwards; add one with 'vmtools firewall allow <vm> --port <port>'".yellow());
+        return;
+    }
+
+    println!("{:<20} {:<10} {:<22}", "VM".bold(), "HOST PORT".bold(), "GUEST".bold());
+    println!("{}", "─".repeat(54));
+    for rule in rules {
+        let guest = match (rule.address, &rule.rejected_on) {
+            (Some(address), Some(bridge)) => format!("{}:{}, rejected by libvirt on {}", address, rule.forward.guest_port, bridge).red(),
+            (Some(address), None) => format!("{}:{}", address, rule.forward.guest_port).normal(),
+            (None, _) => format!("port {}, no address yet", rule.forward.guest_port).yellow(),
+        };
+        println!("{:<20} {:<10} {}",
+                 truncate_cell(&rule.vm, 20),
+                 format!("{}/{}", rule.forward.host_port, rule.forward.protocol),
+                 guest);
+    }
+}
+
+pub fn pool_table(pools: &[PoolInfo]) {
+    if pools.is_empty() {
+        println!("{}", "No storage pools defined".yellow());
+        return;
+    }
+
+    println!("{:<16} {:<8} {:<10} {:<10} {:<10} {:<10}",
+             "NAME".bold(), "TYPE".bold(), "STATE".bold(),
+             "CAPACITY".bold(), "USED".bold(), "FREE".bold());
+    println!("{}", "─".repeat(68));
+    for pool in pools {
+        let state = if pool.active { "running".green() } else { "inactive".yellow() };
+        println!("{:<16} {:<8} {:<10} {:<10} {:<10} {:<10}",
+                 truncate_cell(&pool.name, 16), pool.kind, state,
+                 utils::format_bytes(pool.capacity),
+                 utils::format_bytes(pool.allocation),
+                 utils::format_bytes(pool.available));
+    }
+}
+
+pub fn volume_table(pool: &str, volumes: &[VolumeInfo]) {
+    if volumes.is_empty() {
+        println!("{}", format!("Pool '{}' has no volumes", pool).yellow());
+        return;
+    }
+
+    println!("{:<24} {:<7} {:<10} {}", "VOLUME".bold(), "FORMAT".bold(), "SIZE".bold(), "PATH".bold());
+    println!("{}", "─".repeat(80));
+    for volume in volumes {
+        println!("{:<24} {:<7} {:<10} {}",
+                 truncate_cell(&volume.name, 24), volume.format,
+                 utils::format_bytes(volume.capacity), volume.path);
+    }
+}
+
+pub fn iscsi_targets(host: &str, targets: &[String]) {
+    if targets.is_empty() {
+        println!("{}", format!("{} offers no iSCSI targets", host).yellow());
+        return;
+    }
+
+    println!("{}", format!("iSCSI targets on {}:", host).bold());
+    for target in targets {
+        println!("  {}", target);
+    }
+}
+
+pub fn disk_usage_table(report: &[VmDiskUsage]) {
+    if report.is_empty() {
+        println!("{}", "No virtual machines found".yellow());
+        return;
+    }
+
+    println!("{:<20} {:<6} {:<10} {:<10} {:<10} {:<10}",
+             "NAME".bold(), "DISKS".bold(), "VIRTUAL".bold(),
+             "ACTUAL".bold(), "BACKING".bold(), "SNAPSHOTS".bold());
+    println!("{}", "─".repeat(72));
+
+    for usage in report {
+        let snapshots = if usage.snapshots > 0 {
+            format!("{} ({})", usage.snapshots, utils::format_bytes(usage.snapshot_state))
+        } else {
+            "-".to_string()
+        };
+
+        println!("{:<20} {:<6} {:<10} {:<10} {:<10} {}",
+                 usage.name,
+                 usage.disks,
+                 utils::format_bytes(usage.virtual_size),
+                 utils::format_bytes(usage.actual_size),
+                 utils::format_bytes(usage.backing_size),
+                 snapshots);
+    }
+
+    println!("{}", "─".repeat(72));
+    println!("{:<20} {:<6} {:<10} {:<10} {:<10}",
+             "TOTAL".bold(),
+             report.iter().map(|u| u.disks).sum::<usize>(),
+             utils::format_bytes(report.iter().map(|u| u.virtual_size).sum()),
+             utils::format_bytes(report.iter().map(|u| u.actual_size).sum()),
+             utils::format_bytes(report.iter().map(|u| u.backing_size).sum()));
+    println!("\nBACKING counts shared base images once per VM that uses them");
+}
+
+pub fn snapshot_usage_table(vm: &str, usage: &[SnapshotUsage]) {
+    if usage.is_empty() {
+        println!("{}", format!("VM '{}' has no images qemu-img can inspect", vm).yellow());
+        return;
+    }
+
+    println!("{:<32} {:<9} {:<17} {:>10}", "NAME".bold(), "LAYER".bold(), "CREATED".bold(), "SIZE".bold());
+    println!("{}", "─".repeat(72));
+    for line in usage {
+        // Image files by name; the directory is the VM's image directory in all but odd setups
+        let name = match line.layer {
+            SnapshotLayer::Internal => line.name.clone(),
+            _ => std::path::Path::new(&line.name).file_name()
+                .map(|file| file.to_string_lossy().into_owned())
+                .unwrap_or_else(|| line.name.clone()),
+        };
+        println!("{:<32} {:<9} {:<17} {:>10}",
+                 truncate_cell(&name, 32),
+                 line.layer.to_string(),
+                 line.created.map(utils::format_timestamp).unwrap_or_else(|| "-".to_string()),
+                 utils::format_bytes(line.size));
+    }
+    println!("{}", "─".repeat(72));
+    println!("{:<60} {:>10}", "TOTAL".bold(), utils::format_bytes(usage.iter().map(|line| line.size).sum()));
+    if usage.iter().any(|line| line.layer == SnapshotLayer::Internal) {
+        println!("
+Internal snapshots show their saved memory; their disk data shares the image file");
+    }
+}
+
+/// One screen of `vmtools top`, redrawn in place
+pub fn top_table(usage: &[VmUsage]) {
+    print!("\x1B[2J\x1B[1;1H"); // Clear screen
+    println!("{}", format!("vmtools top | {} | {} running (Ctrl+C to exit)",
+                           chrono::Local::now().format("%Y-%m-%d %H:%M:%S"), usage.len()).bold());
+    println!("{:<20} {:>7} {:>5} {:>19} {:>11} {:>11} {:>11} {:>11}",
+             "NAME".bold(), "CPU%".bold(), "VCPU".bold(), "MEMORY".bold(),
+             "DISK READ".bold(), "DISK WRITE".bold(), "NET RX".bold(), "NET TX".bold());
+    println!("{}", "─".repeat(103));
+
+    let rate = |bytes: u64| format!("{}/s", utils::format_bytes(bytes));
+    for vm in usage {
+        let memory = format!("{}/{}",
+                             utils::format_bytes(vm.memory_used * 1024),
+                             utils::format_bytes(vm.memory * 1024));
+        println!("{:<20} {:>7.1} {:>5} {:>19} {:>11} {:>11} {:>11} {:>11}",
+                 truncate_cell(&vm.name, 20),
+                 vm.cpu_percent,
+                 vm.vcpus,
+                 memory,
+                 rate(vm.disk_read_rate),
+                 rate(vm.disk_write_rate),
+                 rate(vm.net_rx_rate),
+                 rate(vm.net_tx_rate));
+    }
+}
+
+pub fn snapshot_table(vm: &str, snapshots: &[SnapshotInfo]) {
+    if snapshots.is_empty() {
+        println!("{}", format!("VM '{}' has no snapshots", vm).yellow());
+        return;
+    }
+
+    println!("{:<24} {:<28} {:<10} {}", "NAME".bold(), "CREATED".bold(), "STATE".bold(), "DESCRIPTION".bold());
+    println!("{}", "─".repeat(72));
+    for snapshot in snapshots {
+        let name = if snapshot.current {
+            format!("{} *", snapshot.name)
+        } else {
+            snapshot.name.clone()
+        };
+        println!("{:<24} {:<28} {} {}",
+                 name,
+                 snapshot.created,
+                 snapshot.state.paint(&format!("{:<10}", snapshot.state.label())),
+                 snapshot.description.as_deref().unwrap_or(""));
+    }
+    println!("\n* current snapshot");
+}
+
+pub fn template_diff(vm: &str, template: &str, differences: &[SpecDifference]) {
+    if differences.is_empty() {
+        println!("{}", format!("✓ VM '{}' matches template '{}'", vm, template).green());
+        return;
+    }
+
+    println!("{:<24} {:<28} {}", "SETTING".bold(), format!("TEMPLATE ({})", template).bold(), "VM".bold());
+    println!("{}", "─".repeat(72));
+    for difference in differences {
+        println!("{:<24} {:<28} {}",
+                 difference.field,
+                 truncate_cell(&difference.expected, 28),
+                 difference.actual.yellow());
+    }
+    println!("
+{} setting(s) drifted from template '{}'", differences.len(), template);
+}
+
+/// Terraform-style summary of the changes `vmtools apply` would make
+pub fn manifest_plan(changes: &[ManifestChange]) {
+    if changes.is_empty() {
+        println!("{}", "✓ No changes; all VMs match the manifest".green());
+        return;
+    }
+
+    let differences = |differences: &[SpecDifference]| {
+        for difference in differences {
+            println!("      {}: {} → {}", difference.field, difference.actual, difference.expected);
+        }
+    };
+
+    let (mut create, mut modify, mut delete, mut drift) = (0, 0, 0, 0);
+    for change in changes {
+        match change {
+            ManifestChange::Create { vm } => {
+                println!("  {} {}", "+".green().bold(), vm.green());
+                create += 1;
+            }
+            ManifestChange::Modify { vm, differences: changed } => {
+                println!("  {} {}", "~".yellow().bold(), vm.yellow());
+                differences(changed);
+                modify += 1;
+            }
+            ManifestChange::Delete { vm } => {
+                println!("  {} {}", "-".red().bold(), vm.red());
+                delete += 1;
+            }
+            ManifestChange::Drift { vm, differences: drifted, reason } => {
+                println!("  {} {} ({})", "!".magenta().bold(), vm.magenta(), reason);
+                differences(drifted);
+                drift += 1;
+            }
+        }
+    }
+
+    println!("\nPlan: {} to create, {} to modify, {} to delete; {} drifted outside apply's reach",
+             create, modify, delete, drift);
+}
+
+pub fn network_table(networks: &[(String, bool, String, bool)]) {
+    println!("{:<20} {:<12} {:<15} {:<10}",
+             "NAME".bold(), "STATE".bold(), "BRIDGE".bold(), "AUTOSTART".bold());
+    println!("{}", "─".repeat(60));
+
+    for (name, active, bridge, autostart) in networks {
+        let state = if *active { "ACTIVE".green() } else { "INACTIVE".red() };
+        let autostart_str = if *autostart { "Yes".green() } else { "No".red() };
+
+        println!("{:<20} {:<12} {:<15} {:<10}",
+                 name, state, bridge, autostart_str);
+    }
+}
+
+/// A JSON result as QMP or the guest agent returned it, indented
+pub fn json(value: &serde_json::Value) {
+    println!("{}", serde_json::to_string_pretty(value).unwrap_or_else(|_| value.to_string()));
+}
+
+fn guest_os(guest: &GuestInfo) {
+    if let Some(os_name) = &guest.os_name {
+        println!("  OS: {}", os_name);
+    }
+    if let Some(kernel) = &guest.kernel {
+        println!("  Kernel: {}", kernel);
+    }
+}
+
+/// A guest agent reply; `guest-info` and `guest-get-osinfo` are summarized, others printed as JSON
+pub fn agent_reply(command: &str, reply: &serde_json::Value) {
+    match command.trim() {
+        "guest-info" => {
+            println!("Agent version: {}", reply.get("version").and_then(|v| v.as_str()).unwrap_or("unknown"));
+            let commands: Vec<&str> = reply.get("supported_commands").and_then(|c| c.as_array())
+                .map(|commands| commands.iter()
+                    .filter(|command| command.get("enabled").and_then(|e| e.as_bool()) != Some(false))
+                    .filter_map(|command| command.get("name").and_then(|n| n.as_str()))
+                    .collect())
+                .unwrap_or_default();
+            println!("Enabled commands ({}): {}", commands.len(), commands.join(", "));
+        }
+        "guest-get-osinfo" => {
+            println!("Guest:");
+            guest_os(&GuestInfo::from_replies(&serde_json::Value::Null, Some(reply)));
+        }
+        _ => json(reply),
+    }
+}
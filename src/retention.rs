@@ -0,0 +1,94 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+use crate::config::SnapshotPolicy;
+
+/// Prefix of the snapshots a retention policy manages; others are never touched
+pub const AUTO_PREFIX: &str = "auto-";
+
+/// How often a policy takes a snapshot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl Period {
+    pub const ALL: [Period; 4] = [Period::Hourly, Period::Daily, Period::Weekly, Period::Monthly];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Period::Hourly => "hourly",
+            Period::Daily => "daily",
+            Period::Weekly => "weekly",
+            Period::Monthly => "monthly",
+        }
+    }
+
+    /// The calendar hour, day, ISO week or month `time` falls in (UTC); one snapshot is taken per bucket
+    fn bucket(&self, time: DateTime<Utc>) -> String {
+        let format = match self {
+            Period::Hourly => "%Y%m%d%H",
+            Period::Daily => "%Y%m%d",
+            Period::Weekly => "%G%V",
+            Period::Monthly => "%Y%m",
+        };
+        time.format(format).to_string()
+    }
+
+    fn keep(&self, policy: &SnapshotPolicy) -> u32 {
+        match self {
+            Period::Hourly => policy.hourly,
+            Period::Daily => policy.daily,
+            Period::Weekly => policy.weekly,
+            Period::Monthly => policy.monthly,
+        }
+    }
+}
+
+/// Name of the `period` snapshot taken at `time`, e.g. `auto-daily-20261016T0300Z`
+pub fn snapshot_name(period: Period, time: DateTime<Utc>) -> String {
+    format!("{}{}-{}", AUTO_PREFIX, period.label(), time.format("%Y%m%dT%H%MZ"))
+}
+
+/// The period and time of a snapshot named by `snapshot_name`
+pub fn parse_snapshot_name(name: &str) -> Option<(Period, DateTime<Utc>)> {
+    let (label, stamp) = name.strip_prefix(AUTO_PREFIX)?.split_once('-')?;
+    let period = Period::ALL.into_iter().find(|period| period.label() == label)?;
+    let time = NaiveDateTime::parse_from_str(stamp, "%Y%m%dT%H%MZ").ok()?.and_utc();
+    Some((period, time))
+}
+
+/// What enforcing a policy on one VM takes
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RetentionPlan {
+    pub create: Vec<String>,
+    pub delete: Vec<String>,
+}
+
+/// Compares a VM's snapshots with its policy at `now`: a snapshot for each period
+/// that has none in its current bucket yet, and deletion of all but the newest
+/// `keep` of each period once that one exists
+pub fn plan(policy: &SnapshotPolicy, existing: &[String], now: DateTime<Utc>) -> RetentionPlan {
+    let mut plan = RetentionPlan::default();
+    for period in Period::ALL {
+        let keep = period.keep(policy) as usize;
+        let mut taken: Vec<(DateTime<Utc>, &String)> = existing.iter()
+            .filter_map(|name| match parse_snapshot_name(name) {
+                Some((p, time)) if p == period => Some((time, name)),
+                _ => None,
+            })
+            .collect();
+        taken.sort_by_key(|(time, _)| std::cmp::Reverse(*time));
+
+        let mut kept = taken.len();
+        if keep > 0 && !taken.iter().any(|(time, _)| period.bucket(*time) == period.bucket(now)) {
+            plan.create.push(snapshot_name(period, now));
+            kept += 1;
+        }
+        let surplus = kept.saturating_sub(keep);
+        plan.delete.extend(taken.iter().rev().take(surplus).map(|(_, name)| (*name).clone()));
+    }
+    plan
+}
@@ -0,0 +1,289 @@
+use async_trait::async_trait;
+use std::fmt;
+use std::path::Path;
+use std::process::{ExitStatus, Output, Stdio};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+
+use crate::recording;
+
+/// One run of an external program: what to start, what to feed it and how long it may take
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Invocation {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Written to the program's stdin, which is otherwise empty
+    pub stdin: Option<Vec<u8>>,
+    /// Killed once it runs this long; `None` waits for as long as it takes
+    pub timeout: Option<Duration>,
+    /// Its output holds secrets, so traces leave it out
+    pub sensitive: bool,
+}
+
+impl Invocation {
+    pub fn new(program: &str) -> Self {
+        Self {
+            program: program.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn arg(mut self, arg: impl AsRef<std::ffi::OsStr>) -> Self {
+        self.args.push(arg.as_ref().to_string_lossy().into_owned());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<std::ffi::OsStr>,
+    {
+        self.args.extend(args.into_iter().map(|arg| arg.as_ref().to_string_lossy().into_owned()));
+        self
+    }
+
+    pub fn stdin(mut self, input: impl Into<Vec<u8>>) -> Self {
+        self.stdin = Some(input.into());
+        self
+    }
+
+    /// Keeps what the program prints out of the debug trace, for decrypted
+    /// secrets and XML carrying console passwords
+    pub fn sensitive(mut self) -> Self {
+        self.sensitive = true;
+        self
+    }
+
+    /// A zero timeout means none, as `libvirt.timeout` has it
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = (!timeout.is_zero()).then_some(timeout);
+        self
+    }
+
+    /// The process to start, for runs a runner can't own: long-lived streams
+    pub fn to_command(&self) -> Command {
+        let mut command = Command::from(self.to_std_command());
+        command.kill_on_drop(true);
+        command
+    }
+
+    /// Like `to_command`, for processes started from blocking code
+    pub fn to_std_command(&self) -> std::process::Command {
+        let mut command = std::process::Command::new(&self.program);
+        // Output gets parsed, so keep it in English whatever the user's locale
+        command.args(&self.args).env("LC_ALL", "C");
+        command
+    }
+}
+
+impl fmt::Display for Invocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.program)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        Ok(())
+    }
+}
+
+/// Called with each chunk of stdout a streaming run reads
+pub type OnStdout<'a> = dyn Fn(&[u8]) + Send + Sync + 'a;
+
+/// Starts external programs. virsh, qemu-img and the other tools vmtools drives
+/// go through one, so a test can swap in `mock::MockRunner` and a debug trace
+/// sees each command.
+#[async_trait]
+pub trait CommandRunner: Send + Sync {
+    /// Runs to completion with stdout and stderr captured. A run that outlives
+    /// the invocation's timeout fails with `ErrorKind::TimedOut`.
+    async fn output(&self, invocation: &Invocation) -> std::io::Result<Output>;
+
+    /// Like `output`, handing stdout to `on_stdout` as it arrives, for progress
+    async fn output_streaming(
+        &self,
+        invocation: &Invocation,
+        on_stdout: &OnStdout<'_>,
+    ) -> std::io::Result<Output> {
+        let output = self.output(invocation).await?;
+        on_stdout(&output.stdout);
+        Ok(output)
+    }
+
+    /// Runs with the terminal attached, for consoles and programs drawing their own progress
+    async fn status(&self, invocation: &Invocation) -> std::io::Result<ExitStatus>;
+
+    /// Like `status`, also returning what the program wrote to stderr, which still
+    /// reaches the terminal; for sessions whose failures need telling apart
+    async fn status_with_stderr(&self, invocation: &Invocation) -> std::io::Result<(ExitStatus, Vec<u8>)> {
+        Ok((self.status(invocation).await?, Vec::new()))
+    }
+
+    /// Like `status`, on a pseudo-terminal whose output is also recorded to
+    /// `path` as an asciicast titled `title`
+    async fn status_recorded(&self, invocation: &Invocation, path: &Path, title: &str) -> std::io::Result<ExitStatus>;
+}
+
+/// Runs programs on this host, logging each one: its command line before it
+/// starts, then how it ended, how long it took and what it printed
+pub struct SystemRunner;
+
+impl SystemRunner {
+    async fn capture(
+        invocation: &Invocation,
+        on_stdout: Option<&OnStdout<'_>>,
+    ) -> std::io::Result<Output> {
+        let mut child = invocation.to_command()
+            .stdin(if invocation.stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let input = child.stdin.take();
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let mut stderr = child.stderr.take().expect("stderr is piped");
+
+        let feed = async {
+            if let (Some(mut pipe), Some(bytes)) = (input, &invocation.stdin) {
+                // Closing the pipe afterwards tells the program its input ended
+                let _ = pipe.write_all(bytes).await;
+            }
+        };
+        let read = async {
+            let mut captured = Vec::new();
+            let mut buffer = [0u8; 4096];
+            while let Ok(count) = stdout.read(&mut buffer).await {
+                if count == 0 {
+                    break;
+                }
+                if let Some(on_stdout) = on_stdout {
+                    on_stdout(&buffer[..count]);
+                }
+                captured.extend_from_slice(&buffer[..count]);
+            }
+            captured
+        };
+        let mut errors = Vec::new();
+        let (_, captured, _) = tokio::join!(feed, read, stderr.read_to_end(&mut errors));
+
+        Ok(Output {
+            status: child.wait().await?,
+            stdout: captured,
+            stderr: errors,
+        })
+    }
+
+    async fn limited<T>(invocation: &Invocation, run: impl std::future::Future<Output = std::io::Result<T>>) -> std::io::Result<T> {
+        let Some(timeout) = invocation.timeout else {
+            return run.await;
+        };
+        // Dropping the run on timeout kills the child (kill_on_drop)
+        tokio::time::timeout(timeout, run).await.unwrap_or_else(|_| Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            format!("{} did not finish within {}s", invocation.program, timeout.as_secs()),
+        )))
+    }
+
+    fn log(invocation: &Invocation, started: Instant, result: &std::io::Result<Output>) {
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        match result {
+            Ok(output) if invocation.sensitive => tracing::trace!(
+                command = %invocation,
+                status = %output.status,
+                elapsed_ms,
+                stdout = %format!("<{} bytes withheld>", output.stdout.len()),
+                stderr = %String::from_utf8_lossy(&output.stderr).trim_end(),
+                "finished",
+            ),
+            Ok(output) => tracing::trace!(
+                command = %invocation,
+                status = %output.status,
+                elapsed_ms,
+                stdout = %String::from_utf8_lossy(&output.stdout).trim_end(),
+                stderr = %String::from_utf8_lossy(&output.stderr).trim_end(),
+                "finished",
+            ),
+            Err(e) => tracing::debug!(command = %invocation, elapsed_ms, error = %e, "failed to run"),
+        }
+    }
+}
+
+#[async_trait]
+impl CommandRunner for SystemRunner {
+    async fn output(&self, invocation: &Invocation) -> std::io::Result<Output> {
+        tracing::debug!(command = %invocation, "running");
+        let started = Instant::now();
+        let result = Self::limited(invocation, Self::capture(invocation, None)).await;
+        Self::log(invocation, started, &result);
+        result
+    }
+
+    async fn output_streaming(
+        &self,
+        invocation: &Invocation,
+        on_stdout: &OnStdout<'_>,
+    ) -> std::io::Result<Output> {
+        tracing::debug!(command = %invocation, "running");
+        let started = Instant::now();
+        let result = Self::limited(invocation, Self::capture(invocation, Some(on_stdout))).await;
+        Self::log(invocation, started, &result);
+        result
+    }
+
+    async fn status(&self, invocation: &Invocation) -> std::io::Result<ExitStatus> {
+        tracing::debug!(command = %invocation, "running attached to the terminal");
+        let started = Instant::now();
+        let result = Self::limited(invocation, invocation.to_command().status()).await;
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        match &result {
+            Ok(status) => tracing::trace!(command = %invocation, status = %status, elapsed_ms, "finished"),
+            Err(e) => tracing::debug!(command = %invocation, elapsed_ms, error = %e, "failed to run"),
+        }
+        result
+    }
+
+    async fn status_with_stderr(&self, invocation: &Invocation) -> std::io::Result<(ExitStatus, Vec<u8>)> {
+        tracing::debug!(command = %invocation, "running attached to the terminal");
+        let started = Instant::now();
+        let run = async {
+            let mut child = invocation.to_command().stderr(Stdio::piped()).spawn()?;
+            let mut stderr = child.stderr.take().expect("stderr is piped");
+            let mut captured = Vec::new();
+            let mut buffer = [0u8; 1024];
+            let mut terminal = tokio::io::stderr();
+            while let Ok(count) = stderr.read(&mut buffer).await {
+                if count == 0 {
+                    break;
+                }
+                let _ = terminal.write_all(&buffer[..count]).await;
+                captured.extend_from_slice(&buffer[..count]);
+            }
+            Ok((child.wait().await?, captured))
+        };
+        let result = Self::limited(invocation, run).await;
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        match &result {
+            Ok((status, stderr)) => tracing::trace!(
+                command = %invocation,
+                status = %status,
+                elapsed_ms,
+                stderr = %String::from_utf8_lossy(stderr).trim_end(),
+                "finished",
+            ),
+            Err(e) => tracing::debug!(command = %invocation, elapsed_ms, error = %e, "failed to run"),
+        }
+        result
+    }
+    async fn status_recorded(&self, invocation: &Invocation, path: &Path, title: &str) -> std::io::Result<ExitStatus> {
+        tracing::debug!(command = %invocation, recording = %path.display(), "running attached to the terminal");
+        let started = Instant::now();
+        let result = Self::limited(invocation, async {
+            recording::record_console(invocation, path, title).await.map_err(|e| std::io::Error::other(e.to_string()))
+        }).await;
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        match &result {
+            Ok(status) => tracing::trace!(command = %invocation, status = %status, elapsed_ms, "finished"),
+            Err(e) => tracing::debug!(command = %invocation, elapsed_ms, error = %e, "failed to run"),
+        }
+        result
+    }
+}
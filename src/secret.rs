@@ -0,0 +1,99 @@
+use serde::Serialize;
+
+use crate::error::{VmError, Result};
+
+/// What a libvirt secret unlocks, which is also how disks refer to it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum SecretUsage {
+    /// Passphrase of a LUKS-encrypted volume, by volume path
+    Volume(String),
+    /// cephx key, by usage name (e.g. `client.libvirt secret`)
+    Ceph(String),
+    /// CHAP password, by iSCSI target
+    Iscsi(String),
+    /// Key of a TLS client or server certificate
+    Tls(String),
+    /// Persistent state of an emulated TPM
+    Vtpm(String),
+}
+
+impl SecretUsage {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SecretUsage::Volume(_) => "volume",
+            SecretUsage::Ceph(_) => "ceph",
+            SecretUsage::Iscsi(_) => "iscsi",
+            SecretUsage::Tls(_) => "tls",
+            SecretUsage::Vtpm(_) => "vtpm",
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        match self {
+            SecretUsage::Volume(id)
+            | SecretUsage::Ceph(id)
+            | SecretUsage::Iscsi(id)
+            | SecretUsage::Tls(id)
+            | SecretUsage::Vtpm(id) => id,
+        }
+    }
+
+    pub fn new(kind: &str, id: &str) -> Result<Self> {
+        if id.is_empty() || id.len() > 256 || id.chars().any(|c| c.is_control() || matches!(c, '<' | '>' | '&' | '\'' | '"')) {
+            return Err(VmError::InvalidInput(format!("Invalid secret usage '{}'", id)));
+        }
+        let id = id.to_string();
+        match kind {
+            "volume" => Ok(SecretUsage::Volume(id)),
+            "ceph" => Ok(SecretUsage::Ceph(id)),
+            "iscsi" => Ok(SecretUsage::Iscsi(id)),
+            "tls" => Ok(SecretUsage::Tls(id)),
+            "vtpm" => Ok(SecretUsage::Vtpm(id)),
+            _ => Err(VmError::InvalidInput(format!(
+                "Unknown secret type '{}' (expected volume, ceph, iscsi, tls or vtpm)", kind
+            ))),
+        }
+    }
+
+    /// Definition of a private, persistent secret for this usage
+    pub fn secret_xml(&self, description: Option<&str>) -> String {
+        let element = match self {
+            SecretUsage::Volume(_) => "volume",
+            SecretUsage::Iscsi(_) => "target",
+            _ => "name",
+        };
+        let description = description
+            .map(|text| format!("\n  <description>{}</description>", text.replace('&', "&amp;").replace('<', "&lt;")))
+            .unwrap_or_default();
+        format!(r#"<secret ephemeral='no' private='yes'>{}
+  <usage type='{}'>
+    <{}>{}</{}>
+  </usage>
+</secret>"#, description, self.kind(), element, self.id(), element)
+    }
+}
+
+/// A secret libvirt holds; the value itself never leaves libvirt
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SecretInfo {
+    pub uuid: String,
+    pub usage: SecretUsage,
+}
+
+/// Parses `virsh secret-list`; secrets without a known usage are skipped
+pub fn parse_secret_list(output: &str) -> Vec<SecretInfo> {
+    output.lines()
+        .skip_while(|line| !line.trim_start().starts_with("---"))
+        .skip(1)
+        .filter_map(|line| {
+            // Columns are space-padded; the usage id itself may contain spaces
+            let (uuid, usage) = line.trim().split_once(char::is_whitespace)?;
+            let (kind, id) = usage.trim_start().split_once(char::is_whitespace)?;
+            let id = id.trim();
+            Some(SecretInfo {
+                uuid: uuid.to_string(),
+                usage: SecretUsage::new(kind, id).ok()?,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,214 @@
+use colored::*;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::{
+    config::Config,
+    domain,
+    error::{VmError, Result},
+    privilege::{AccessLevel, Privileges},
+    runner::SystemRunner,
+};
+
+/// What setup found behind one libvirt URI
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostProbe {
+    pub uri: String,
+    pub access: AccessLevel,
+    /// Active storage pools, with the directory they keep volumes in when they have one
+    pub pools: Vec<(String, Option<PathBuf>)>,
+    /// Active networks
+    pub networks: Vec<String>,
+}
+
+impl HostProbe {
+    pub async fn probe(uri: &str, timeout: Duration) -> Self {
+        let privileges = Privileges::detect(uri, timeout).await;
+        let mut probe = Self {
+            uri: uri.to_string(),
+            access: privileges.access(),
+            pools: Vec::new(),
+            networks: Vec::new(),
+        };
+        if probe.access == AccessLevel::Denied {
+            return probe;
+        }
+
+        for pool in virsh_names(&privileges, &["pool-list", "--name"]).await {
+            let xml = virsh_output(&privileges, &["pool-dumpxml", &pool]).await.unwrap_or_default();
+            probe.pools.push((pool, pool_directory(&xml)));
+        }
+        probe.networks = virsh_names(&privileges, &["net-list", "--name"]).await;
+        probe
+    }
+
+    /// The pool setup offers: the configured one when the host has it, else the first directory pool
+    pub fn suggested_pool(&self, config: &Config) -> Option<&str> {
+        self.pools.iter()
+            .find(|(name, _)| *name == config.storage.default_pool)
+            .or_else(|| self.pools.iter().find(|(_, path)| path.is_some()))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// The network setup offers: the configured one when it is active, else the first active one
+    pub fn suggested_network(&self, config: &Config) -> Option<&str> {
+        self.networks.iter()
+            .find(|name| **name == config.network.default_network)
+            .or_else(|| self.networks.first())
+            .map(String::as_str)
+    }
+}
+
+/// The target directory of a pool that keeps its volumes as files
+pub fn pool_directory(xml: &str) -> Option<PathBuf> {
+    let kind = xml.split_once("<pool type=")?.1.trim_start_matches(['\'', '"']).split(['\'', '"']).next()?;
+    if !matches!(kind, "dir" | "fs" | "netfs") {
+        return None;
+    }
+    let target = domain::element_text(xml, "target")?;
+    domain::element_text(&target, "path").map(PathBuf::from)
+}
+
+/// Points `config` at the URI probed and the pool and network picked from it;
+/// a per-user session URI also moves storage to the user's data directory
+pub fn apply(config: &mut Config, probe: &HostProbe, pool: Option<&str>, network: Option<&str>) {
+    if probe.uri != config.libvirt.uri {
+        if probe.uri.ends_with(":///session") {
+            config.use_session_uri(&probe.uri);
+        } else {
+            config.libvirt.uri = probe.uri.clone();
+        }
+    }
+    if let Some((name, path)) = pool.and_then(|pool| probe.pools.iter().find(|(name, _)| name == pool)) {
+        config.storage.default_pool = name.clone();
+        if let Some(path) = path {
+            config.storage.vm_images_path = path.clone();
+        }
+    }
+    if let Some(network) = network {
+        config.network.default_network = network.to_string();
+    }
+}
+
+/// On the very first run, offers setup instead of writing defaults that may not
+/// fit this host; without a terminal to ask on, the defaults are written as before
+pub async fn offer_first_run() -> Result<()> {
+    let path = Config::config_path()?;
+    if path.exists() || !std::io::stdin().is_terminal() {
+        return Ok(());
+    }
+    println!("No configuration found at {}.", path.display());
+    if ask("Set vmtools up for this host now? [Y/n]", "y").to_lowercase().starts_with('n') {
+        return Ok(());
+    }
+    run().await
+}
+
+/// Probes the host (KVM, reachable libvirt URIs, their pools and networks), asks
+/// which to use and writes the config file
+pub async fn run() -> Result<()> {
+    if !std::io::stdin().is_terminal() {
+        return Err(VmError::InvalidInput("Setup is interactive; run it from a terminal".to_string()));
+    }
+    let path = Config::config_path()?;
+    let mut config = if path.exists() { Config::load(&SystemRunner).await? } else { Config::default() };
+    println!("🔧 {}", "vmtools setup".bold());
+
+    if config.system.kvm_device.exists() {
+        println!("✓ KVM available at {}", config.system.kvm_device.display());
+    } else {
+        println!("{} {} is missing: enable virtualization in the firmware and load the kvm module, \
+                  or create guests with --emulated", "⚠️ ".yellow(), config.system.kvm_device.display());
+    }
+
+    let mut uris = vec![config.libvirt.uri.clone()];
+    if config.libvirt.uri.ends_with(":///system") {
+        uris.push(config.libvirt.uri.replace(":///system", ":///session"));
+    }
+    let mut probes = Vec::new();
+    for uri in uris {
+        let probe = HostProbe::probe(&uri, Duration::from_secs(config.libvirt.timeout)).await;
+        match probe.access {
+            AccessLevel::ReadWrite => println!("✓ {}: reachable, {} pool(s), {} active network(s)",
+                                               uri, probe.pools.len(), probe.networks.len()),
+            AccessLevel::ReadOnly => println!("{} {}: read-only access", "⚠️ ".yellow(), uri),
+            AccessLevel::Denied => println!("✗ {}: not reachable", uri),
+        }
+        probes.push(probe);
+    }
+
+    let Some(suggested) = probes.iter().find(|probe| probe.access == AccessLevel::ReadWrite) else {
+        println!("{} No libvirt URI is usable; install and start libvirtd, then run: vmtools setup",
+                 "⚠️ ".yellow());
+        config.save()?;
+        println!("Wrote defaults to {}", path.display());
+        return Ok(());
+    };
+    let uri = choose("Libvirt URI", &probes.iter().map(|probe| probe.uri.clone()).collect::<Vec<_>>(), &suggested.uri);
+    let probe = probes.iter().find(|probe| probe.uri == uri).unwrap_or(suggested);
+
+    let pool = match probe.suggested_pool(&config) {
+        Some(suggested) => {
+            for (name, path) in &probe.pools {
+                let path = path.as_deref().map(Path::display).map(|path| path.to_string());
+                println!("  pool {} {}", name.cyan(), path.unwrap_or_default());
+            }
+            let names: Vec<String> = probe.pools.iter().map(|(name, _)| name.clone()).collect();
+            Some(choose("Storage pool", &names, suggested))
+        }
+        None => {
+            println!("No storage pools on {}; disks go to {}", uri, config.storage.vm_images_path.display());
+            None
+        }
+    };
+    let network = match probe.suggested_network(&config) {
+        Some(suggested) => Some(choose("Default network", &probe.networks, suggested)),
+        None => {
+            println!("{} No active networks; start one with: virsh -c {} net-start default", "⚠️ ".yellow(), uri);
+            None
+        }
+    };
+
+    apply(&mut config, probe, pool.as_deref(), network.as_deref());
+    config.save()?;
+    println!("✅ Wrote {}", path.display());
+    println!("   Libvirt URI: {}", config.libvirt.uri);
+    println!("   VM images: {}", config.storage.vm_images_path.display());
+    println!("   Network: {}", config.network.default_network);
+    Ok(())
+}
+
+/// Asks until the answer is one of `options`; an empty answer takes `default`
+fn choose(question: &str, options: &[String], default: &str) -> String {
+    loop {
+        let answer = ask(&format!("{} ({}) [{}]", question, options.join(", "), default), default);
+        if options.contains(&answer) {
+            return answer;
+        }
+        println!("Choose one of: {}", options.join(", "));
+    }
+}
+
+fn ask(question: &str, default: &str) -> String {
+    print!("{}: ", question);
+    let _ = std::io::stdout().flush();
+    let mut input = String::new();
+    let _ = std::io::stdin().read_line(&mut input);
+    match input.trim() {
+        "" => default.to_string(),
+        answer => answer.to_string(),
+    }
+}
+
+/// Names virsh prints one per line
+async fn virsh_names(privileges: &Privileges, args: &[&str]) -> Vec<String> {
+    virsh_output(privileges, args).await
+        .map(|output| output.lines().map(str::trim).filter(|line| !line.is_empty()).map(String::from).collect())
+        .unwrap_or_default()
+}
+
+async fn virsh_output(privileges: &Privileges, args: &[&str]) -> Option<String> {
+    let output = privileges.runner().output(&privileges.virsh_read(args).ok()?).await.ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
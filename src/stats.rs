@@ -0,0 +1,211 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::error::{VmError, Result};
+
+/// Cumulative counters of a running domain, from `virsh domstats`
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DomainStats {
+    pub name: String,
+    /// CPU time used by the whole domain, in nanoseconds
+    pub cpu_time: u64,
+    pub vcpus: u32,
+    /// Memory the guest currently has, in KiB
+    pub memory: u64,
+    /// Memory the guest reports as unused, in KiB; needs the balloon driver
+    pub memory_unused: Option<u64>,
+    /// Totals over all disks, in bytes
+    pub disk_read: u64,
+    pub disk_written: u64,
+    /// Totals over all interfaces, in bytes
+    pub net_rx: u64,
+    pub net_tx: u64,
+}
+
+/// Parses `virsh domstats` output: a `Domain: 'name'` line per domain followed
+/// by indented `key=value` lines
+pub fn parse_domstats(output: &str) -> Vec<DomainStats> {
+    let mut domains: Vec<DomainStats> = Vec::new();
+    for line in output.lines() {
+        let line = line.trim();
+        if let Some(name) = line.strip_prefix("Domain: ") {
+            domains.push(DomainStats {
+                name: name.trim_matches(['\'', '"']).to_string(),
+                ..Default::default()
+            });
+            continue;
+        }
+        let (Some(stats), Some((key, value))) = (domains.last_mut(), line.split_once('=')) else {
+            continue;
+        };
+        let Ok(value) = value.parse::<u64>() else {
+            continue;
+        };
+
+        match key {
+            "cpu.time" => stats.cpu_time = value,
+            "vcpu.current" => stats.vcpus = value as u32,
+            "balloon.current" => stats.memory = value,
+            "balloon.unused" => stats.memory_unused = Some(value),
+            _ => {
+                // Per-device counters: block.<n>.rd.bytes, net.<n>.rx.bytes, ...
+                let Some((kind, rest)) = key.split_once('.') else { continue };
+                let Some((_, counter)) = rest.split_once('.') else { continue };
+                match (kind, counter) {
+                    ("block", "rd.bytes") => stats.disk_read += value,
+                    ("block", "wr.bytes") => stats.disk_written += value,
+                    ("net", "rx.bytes") => stats.net_rx += value,
+                    ("net", "tx.bytes") => stats.net_tx += value,
+                    _ => {}
+                }
+            }
+        }
+    }
+    domains
+}
+
+/// What a running VM used between two samples of its counters
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VmUsage {
+    pub name: String,
+    /// Like top: 100% is one host CPU fully busy
+    pub cpu_percent: f64,
+    pub vcpus: u32,
+    /// In KiB; all of the guest's memory when it doesn't report what is unused
+    pub memory_used: u64,
+    pub memory: u64,
+    /// Rates in bytes per second
+    pub disk_read_rate: u64,
+    pub disk_write_rate: u64,
+    pub net_rx_rate: u64,
+    pub net_tx_rate: u64,
+}
+
+impl VmUsage {
+    pub fn between(before: &DomainStats, after: &DomainStats, elapsed: Duration) -> Self {
+        let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+        let rate = |before: u64, after: u64| (after.saturating_sub(before) as f64 / seconds) as u64;
+        Self {
+            name: after.name.clone(),
+            cpu_percent: after.cpu_time.saturating_sub(before.cpu_time) as f64 / 1e9 / seconds * 100.0,
+            vcpus: after.vcpus,
+            memory_used: after.memory.saturating_sub(after.memory_unused.unwrap_or(0)),
+            memory: after.memory,
+            disk_read_rate: rate(before.disk_read, after.disk_read),
+            disk_write_rate: rate(before.disk_written, after.disk_written),
+            net_rx_rate: rate(before.net_rx, after.net_rx),
+            net_tx_rate: rate(before.net_tx, after.net_tx),
+        }
+    }
+
+    /// Usage of every VM sampled in both `before` and `after`; VMs started in
+    /// between have no earlier sample to compare against
+    pub fn compare(before: &[DomainStats], after: &[DomainStats], elapsed: Duration) -> Vec<Self> {
+        after.iter()
+            .filter_map(|stats| {
+                let earlier = before.iter().find(|b| b.name == stats.name)?;
+                Some(Self::between(earlier, stats, elapsed))
+            })
+            .collect()
+    }
+
+    /// Disk reads and writes together
+    pub fn disk_rate(&self) -> u64 {
+        self.disk_read_rate + self.disk_write_rate
+    }
+
+    pub fn net_rate(&self) -> u64 {
+        self.net_rx_rate + self.net_tx_rate
+    }
+}
+
+/// What `vmtools monitor` saw of a VM at one moment; rates and CPU need an earlier
+/// sample and live statistics, so the first line of a log has none
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MonitorSample {
+    pub timestamp: DateTime<Utc>,
+    pub name: String,
+    pub state: String,
+    pub cpu_percent: Option<f64>,
+    /// In KiB
+    pub memory_used: Option<u64>,
+    pub memory: u64,
+    /// Rates in bytes per second
+    pub disk_read_rate: Option<u64>,
+    pub disk_write_rate: Option<u64>,
+    pub net_rx_rate: Option<u64>,
+    pub net_tx_rate: Option<u64>,
+    /// In seconds
+    pub uptime: Option<u64>,
+}
+
+/// File format of a monitor log
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LogFormat {
+    /// A header line, then one comma-separated row per sample
+    #[default]
+    Csv,
+    /// One JSON object per line
+    Jsonl,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(LogFormat::Csv),
+            "jsonl" | "json" => Ok(LogFormat::Jsonl),
+            _ => Err(format!("Invalid log format '{}'. Use csv or jsonl", s)),
+        }
+    }
+}
+
+const CSV_HEADER: &str = "timestamp,vm,state,cpu_percent,memory_used_kib,memory_kib,\
+disk_read_bytes_per_sec,disk_write_bytes_per_sec,net_rx_bytes_per_sec,net_tx_bytes_per_sec,uptime_secs";
+
+/// Monitor samples appended to a file, so a session can be compared with a later one
+pub struct MetricsLog {
+    file: File,
+    format: LogFormat,
+}
+
+impl MetricsLog {
+    /// Opens `path` for appending; a new or empty CSV file gets the header first
+    pub fn open(path: &Path, format: LogFormat) -> Result<Self> {
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)
+            .map_err(|e| VmError::IoError(std::io::Error::new(e.kind(), format!("Cannot open {}: {}", path.display(), e))))?;
+        if format == LogFormat::Csv && file.metadata()?.len() == 0 {
+            writeln!(file, "{}", CSV_HEADER)?;
+        }
+        Ok(Self { file, format })
+    }
+
+    /// Writes one sample straight through, so an interrupted monitor loses nothing
+    pub fn append(&mut self, sample: &MonitorSample) -> Result<()> {
+        let line = match self.format {
+            LogFormat::Jsonl => serde_json::to_string(sample)?,
+            LogFormat::Csv => {
+                let field = |value: Option<u64>| value.map(|v| v.to_string()).unwrap_or_default();
+                format!("{},{},{},{},{},{},{},{},{},{},{}",
+                        sample.timestamp.to_rfc3339(),
+                        sample.name,
+                        sample.state,
+                        sample.cpu_percent.map(|cpu| format!("{:.1}", cpu)).unwrap_or_default(),
+                        field(sample.memory_used),
+                        sample.memory,
+                        field(sample.disk_read_rate),
+                        field(sample.disk_write_rate),
+                        field(sample.net_rx_rate),
+                        field(sample.net_tx_rate),
+                        field(sample.uptime))
+            }
+        };
+        writeln!(self.file, "{}", line)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,604 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    domain::attributes,
+    error::{VmError, Result},
+    privilege::Privileges,
+    runner::{CommandRunner, Invocation},
+};
+
+/// Port Ceph monitors listen on unless a host says otherwise
+const CEPH_MONITOR_PORT: u16 = 6789;
+
+/// Prefix of the ZFS snapshots `zfs clone` needs as origins; hidden from snapshot lists
+const CLONE_SNAPSHOT_PREFIX: &str = "vmtools-clone-";
+
+/// Where a VM disk's data lives
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiskSource {
+    /// Image file on the host, normally qcow2 under `storage.vm_images_path`
+    File(PathBuf),
+    /// Image in a Ceph pool, read by QEMU's librbd driver
+    Rbd(Box<RbdImage>),
+    /// Thin LV to allocate in `<vg>/<thin_pool>`; becomes a `Block` disk once created
+    Lvm {
+        vg: String,
+        thin_pool: String,
+    },
+    /// Sparse zvol to create under the ZFS dataset; becomes a `Block` disk once created
+    Zfs {
+        dataset: String,
+    },
+    /// Block device on the host, e.g. a thin LV at /dev/<vg>/<lv> or a zvol
+    Block(PathBuf),
+    /// Volume of a libvirt storage pool, such as a file on an NFS export or an iSCSI LUN
+    Volume {
+        pool: String,
+        volume: String,
+        /// Image format; raw for LUNs, qcow2 for volumes vmtools creates
+        format: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RbdImage {
+    pub pool: String,
+    pub image: String,
+    /// Monitors as `host` or `host:port`; empty lets librbd read /etc/ceph/ceph.conf
+    pub monitors: Vec<String>,
+    /// cephx user and the UUID of the libvirt secret holding its key
+    pub auth: Option<(String, String)>,
+}
+
+impl DiskSource {
+    /// `type` attribute of the `<disk>` element
+    pub fn disk_type(&self) -> &'static str {
+        match self {
+            DiskSource::File(_) => "file",
+            DiskSource::Rbd(_) => "network",
+            DiskSource::Volume { .. } => "volume",
+            DiskSource::Lvm { .. } | DiskSource::Zfs { .. } | DiskSource::Block(_) => "block",
+        }
+    }
+
+    /// Image format for the `<driver>` element; RBD images are raw block devices
+    pub fn format(&self) -> &str {
+        match self {
+            DiskSource::File(_) => "qcow2",
+            DiskSource::Rbd(_) => "raw",
+            DiskSource::Volume { format, .. } => format,
+            DiskSource::Lvm { .. } | DiskSource::Zfs { .. } | DiskSource::Block(_) => "raw",
+        }
+    }
+
+    /// Extra `<driver>` attributes; block devices skip the host page cache
+    pub fn driver_tuning(&self) -> &'static str {
+        match self {
+            DiskSource::Lvm { .. } | DiskSource::Zfs { .. } | DiskSource::Block(_) => " cache='none' io='native'",
+            _ => "",
+        }
+    }
+
+    /// The `<auth>` and `<source>` elements, indented for a `<disk>` inside `<devices>`
+    pub fn source_xml(&self) -> String {
+        match self {
+            DiskSource::File(path) => format!("<source file='{}'/>", path.display()),
+            DiskSource::Volume { pool, volume, .. } => format!("<source pool='{}' volume='{}'/>", pool, volume),
+            DiskSource::Block(device) => format!("<source dev='{}'/>", device.display()),
+            DiskSource::Lvm { vg, .. } => format!("<source dev='/dev/{}'/>", vg),
+            DiskSource::Zfs { dataset } => format!("<source dev='/dev/zvol/{}'/>", dataset),
+            DiskSource::Rbd(rbd) => {
+                let auth = rbd.auth.as_ref()
+                    .map(|(user, uuid)| format!(
+                        "<auth username='{}'>\n        <secret type='ceph' uuid='{}'/>\n      </auth>\n      ",
+                        user, uuid
+                    ))
+                    .unwrap_or_default();
+                let hosts: String = rbd.monitors.iter()
+                    .map(|monitor| {
+                        let (host, port) = match monitor.rsplit_once(':') {
+                            Some((host, port)) if port.parse::<u16>().is_ok() => (host, port.to_string()),
+                            _ => (monitor.as_str(), CEPH_MONITOR_PORT.to_string()),
+                        };
+                        format!("\n        <host name='{}' port='{}'/>", host, port)
+                    })
+                    .collect();
+                if hosts.is_empty() {
+                    format!("{}<source protocol='rbd' name='{}/{}'/>", auth, rbd.pool, rbd.image)
+                } else {
+                    format!("{}<source protocol='rbd' name='{}/{}'>{}\n      </source>", auth, rbd.pool, rbd.image, hosts)
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for DiskSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DiskSource::File(path) => write!(f, "{}", path.display()),
+            DiskSource::Rbd(rbd) => write!(f, "rbd:{}/{}", rbd.pool, rbd.image),
+            DiskSource::Volume { pool, volume, .. } => write!(f, "pool:{}/{}", pool, volume),
+            DiskSource::Lvm { vg, thin_pool } => write!(f, "lvm:{}/{}", vg, thin_pool),
+            DiskSource::Zfs { dataset } => write!(f, "zfs:{}", dataset),
+            DiskSource::Block(device) => write!(f, "{}", device.display()),
+        }
+    }
+}
+
+impl std::str::FromStr for DiskSource {
+    type Err = VmError;
+
+    /// Parses `rbd:<pool>/<image>`, `pool:<pool>/<volume>`, `lvm:<vg>/<thin-pool>` or
+    /// `zfs:<dataset>`; monitors, credentials, volume formats and new LVs/zvols are filled
+    /// in when the disk is used
+    fn from_str(s: &str) -> Result<Self> {
+        // iSCSI LUNs are named like unit:0:0:1
+        let valid = |part: &str| !part.is_empty()
+            && part.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+            && !part.starts_with('.');
+        let split = |prefix: &str| s.strip_prefix(prefix)
+            .and_then(|rest| rest.split_once('/'))
+            .filter(|(pool, name)| valid(pool) && valid(name));
+
+        if let Some((pool, image)) = split("rbd:") {
+            Ok(DiskSource::Rbd(Box::new(RbdImage {
+                pool: pool.to_string(),
+                image: image.to_string(),
+                monitors: Vec::new(),
+                auth: None,
+            })))
+        } else if let Some((vg, thin_pool)) = split("lvm:").filter(|(vg, pool)| !vg.contains(':') && !pool.contains(':')) {
+            Ok(DiskSource::Lvm {
+                vg: vg.to_string(),
+                thin_pool: thin_pool.to_string(),
+            })
+        } else if let Some(dataset) = s.strip_prefix("zfs:")
+            .filter(|dataset| dataset.split('/').all(|part| valid(part) && !part.contains(':')))
+        {
+            Ok(DiskSource::Zfs { dataset: dataset.to_string() })
+        } else if let Some((pool, volume)) = split("pool:") {
+            Ok(DiskSource::Volume {
+                pool: pool.to_string(),
+                volume: volume.to_string(),
+                format: "raw".to_string(),
+            })
+        } else {
+            Err(VmError::InvalidInput(format!(
+                "Invalid disk '{}'. Use rbd:<pool>/<image>, pool:<pool>/<volume>, lvm:<vg>/<thin-pool> or zfs:<dataset>", s
+            )))
+        }
+    }
+}
+
+/// A libvirt storage pool
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PoolInfo {
+    pub name: String,
+    /// libvirt pool type: dir, netfs, iscsi, logical, ...
+    pub kind: String,
+    pub active: bool,
+    pub autostart: bool,
+    /// Sizes in bytes; zero while the pool is inactive
+    pub capacity: u64,
+    pub allocation: u64,
+    pub available: u64,
+}
+
+impl PoolInfo {
+    /// Whether vmtools can create new volumes in the pool (LUNs are made on the appliance)
+    pub fn can_create_volumes(&self) -> bool {
+        matches!(self.kind.as_str(), "dir" | "fs" | "netfs" | "logical")
+    }
+}
+
+/// A volume of a storage pool
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VolumeInfo {
+    pub name: String,
+    pub path: String,
+    pub capacity: u64,
+    /// Image format (qcow2, raw); LUNs are raw
+    pub format: String,
+}
+
+/// Pool mounting `export` from the NFS server `host` at `mount_point`
+pub fn nfs_pool_xml(name: &str, host: &str, export: &str, mount_point: &Path) -> String {
+    format!(r#"<pool type='netfs'>
+  <name>{}</name>
+  <source>
+    <host name='{}'/>
+    <dir path='{}'/>
+    <format type='nfs'/>
+  </source>
+  <target>
+    <path>{}</path>
+  </target>
+</pool>"#, name, host, export, mount_point.display())
+}
+
+/// Pool logging in to the iSCSI `target` (an IQN) on `host`; with `chap_user`, the
+/// CHAP password comes from the iscsi secret whose usage is the target
+pub fn iscsi_pool_xml(name: &str, host: &str, target: &str, chap_user: Option<&str>) -> String {
+    let auth = chap_user
+        .map(|user| format!("\n    <auth type='chap' username='{}'>\n      <secret usage='{}'/>\n    </auth>", user, target))
+        .unwrap_or_default();
+    format!(r#"<pool type='iscsi'>
+  <name>{}</name>
+  <source>
+    <host name='{}'/>
+    <device path='{}'/>{}
+  </source>
+  <target>
+    <path>/dev/disk/by-path</path>
+  </target>
+</pool>"#, name, host, target, auth)
+}
+
+/// Target IQNs from `virsh find-storage-pool-sources-as iscsi <host>`
+pub fn parse_iscsi_targets(sources: &str) -> Vec<String> {
+    attributes(sources, "<device path='")
+}
+
+pub fn validate_pool_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > 64 || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(VmError::InvalidInput(format!(
+            "Invalid pool name '{}': use letters, digits, hyphens and underscores", name
+        )));
+    }
+    Ok(())
+}
+
+/// Checks a host name, NFS export or IQN before it goes into pool XML
+pub fn validate_pool_field(what: &str, value: &str) -> Result<()> {
+    if value.is_empty() || value.chars().any(|c| c.is_whitespace() || c.is_control() || matches!(c, '<' | '>' | '&' | '\'' | '"')) {
+        return Err(VmError::InvalidInput(format!("Invalid {}: '{}'", what, value)));
+    }
+    Ok(())
+}
+
+/// Volume group and LV name of a `/dev/<vg>/<lv>` device path
+pub fn lv_name(device: &Path) -> Result<(String, String)> {
+    let mut parts = device.strip_prefix("/dev").ok()
+        .map(|rest| rest.iter().map(|part| part.to_string_lossy().into_owned()).collect::<Vec<_>>())
+        .unwrap_or_default()
+        .into_iter();
+    match (parts.next(), parts.next(), parts.next()) {
+        // /dev/mapper/* are device-mapper names and /dev/zvol/* zvols, not <vg>/<lv>
+        (Some(vg), Some(lv), None) if vg != "mapper" && vg != "zvol" => Ok((vg, lv)),
+        _ => Err(VmError::InvalidInput(format!("{} is not an LVM volume (/dev/<vg>/<lv>)", device.display()))),
+    }
+}
+
+/// ZFS dataset of a `/dev/zvol/<pool>/.../<zvol>` device path
+pub fn zvol_name(device: &Path) -> Result<String> {
+    device.strip_prefix("/dev/zvol").ok()
+        .filter(|dataset| dataset.iter().count() >= 2)
+        .map(|dataset| dataset.to_string_lossy().into_owned())
+        .ok_or_else(|| VmError::InvalidInput(format!("{} is not a zvol (/dev/zvol/<dataset>)", device.display())))
+}
+
+/// Name of the thin snapshot `snapshot` of LV `lv`; LVM allows dots, VM and snapshot names don't
+fn snapshot_lv(lv: &str, snapshot: &str) -> String {
+    format!("{}.{}", lv, snapshot)
+}
+
+async fn run(runner: &dyn CommandRunner, command: &Invocation, program: &str) -> Result<String> {
+    let output = runner.output(command)
+        .await
+        .map_err(|e| VmError::CommandError(format!("Failed to execute {}: {}", program, e)))?;
+
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        return Err(VmError::CommandError(format!("{} failed: {}", program, error.trim())));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Runs an LVM or ZFS tool as root, through polkit for other users where it is available
+async fn run_as_root(privileges: &Privileges, program: &str, args: &[&str]) -> Result<String> {
+    run(privileges.runner(), &privileges.root_command(program).args(args), program).await
+}
+
+/// Creates a thin LV of `size_bytes` in `vg/thin_pool` and returns its device path
+pub async fn create_thin_volume(privileges: &Privileges, vg: &str, thin_pool: &str, name: &str, size_bytes: u64) -> Result<PathBuf> {
+    let size = format!("{}b", size_bytes);
+    let pool = format!("{}/{}", vg, thin_pool);
+    run_as_root(privileges, "lvcreate", &["--yes", "-V", &size, "-T", &pool, "-n", name]).await?;
+    Ok(PathBuf::from(format!("/dev/{}/{}", vg, name)))
+}
+
+/// Creates a sparse zvol of `size_bytes` under `dataset` and returns its device path
+pub async fn create_zvol(privileges: &Privileges, dataset: &str, name: &str, size_bytes: u64, volblocksize: &str, compression: &str) -> Result<PathBuf> {
+    let zvol = format!("{}/{}", dataset, name);
+    run_as_root(privileges, "zfs", &[
+        "create", "-s", "-V", &size_bytes.to_string(),
+        "-o", &format!("volblocksize={}", volblocksize),
+        "-o", &format!("compression={}", compression),
+        &zvol,
+    ]).await?;
+    Ok(PathBuf::from(format!("/dev/zvol/{}", zvol)))
+}
+
+/// Clones the zvol `device` as `name` next to it. The clone shares all blocks with a
+/// snapshot of the source taken for it, which is destroyed along with the clone.
+pub async fn clone_zvol(privileges: &Privileges, device: &Path, name: &str) -> Result<PathBuf> {
+    let dataset = zvol_name(device)?;
+    let parent = dataset.rsplit_once('/').map_or(dataset.as_str(), |(parent, _)| parent);
+    let origin = format!("{}@{}{}", dataset, CLONE_SNAPSHOT_PREFIX, name);
+    let clone = format!("{}/{}", parent, name);
+
+    run_as_root(privileges, "zfs", &["snapshot", &origin]).await?;
+    if let Err(e) = run_as_root(privileges, "zfs", &["clone", &origin, &clone]).await {
+        let _ = run_as_root(privileges, "zfs", &["destroy", &origin]).await;
+        return Err(e);
+    }
+    Ok(PathBuf::from(format!("/dev/zvol/{}", clone)))
+}
+
+/// Whether `device` is a thin LV or a zvol, i.e. something vmtools may snapshot and remove
+pub async fn is_thin_volume(privileges: &Privileges, device: &Path) -> Result<bool> {
+    if let Ok(dataset) = zvol_name(device) {
+        let kind = run_as_root(privileges, "zfs", &["get", "-H", "-o", "value", "type", &dataset]).await?;
+        return Ok(kind.trim() == "volume");
+    }
+    let (vg, lv) = lv_name(device)?;
+    let attr = run_as_root(privileges, "lvs", &["--noheadings", "-o", "lv_attr", &format!("{}/{}", vg, lv)]).await?;
+    Ok(attr.trim_start().starts_with('V'))
+}
+
+/// Takes a thin snapshot of `device`; it shares blocks with the origin and needs no size
+pub async fn snapshot_thin_volume(privileges: &Privileges, device: &Path, snapshot: &str) -> Result<()> {
+    if let Ok(dataset) = zvol_name(device) {
+        run_as_root(privileges, "zfs", &["snapshot", &format!("{}@{}", dataset, snapshot)]).await?;
+        return Ok(());
+    }
+    let (vg, lv) = lv_name(device)?;
+    run_as_root(privileges, "lvcreate", &["-s", "-n", &snapshot_lv(&lv, snapshot), &format!("{}/{}", vg, lv)]).await?;
+    Ok(())
+}
+
+/// Snapshots of `device` as (name, creation time)
+pub async fn list_thin_snapshots(privileges: &Privileges, device: &Path) -> Result<Vec<(String, String)>> {
+    if let Ok(dataset) = zvol_name(device) {
+        let output = run_as_root(privileges, "zfs", &["list", "-H", "-r", "-t", "snapshot", "-s", "creation", "-o", "name,creation", &dataset]).await?;
+        return Ok(parse_zfs_snapshots(&output, &dataset));
+    }
+    let (vg, lv) = lv_name(device)?;
+    let output = run_as_root(privileges, "lvs", &["--noheadings", "--separator", "|", "-o", "lv_name,lv_time", &vg]).await?;
+    Ok(parse_thin_snapshots(&output, &lv))
+}
+
+/// Snapshots of `lv` in `lvs -o lv_name,lv_time --separator '|'` output
+pub fn parse_thin_snapshots(lvs: &str, lv: &str) -> Vec<(String, String)> {
+    let prefix = format!("{}.", lv);
+    lvs.lines()
+        .filter_map(|line| line.trim().split_once('|'))
+        .filter_map(|(name, time)| {
+            name.strip_prefix(&prefix).map(|snapshot| (snapshot.to_string(), time.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Snapshots of `dataset` in `zfs list -H -o name,creation` output, without clone origins
+pub fn parse_zfs_snapshots(list: &str, dataset: &str) -> Vec<(String, String)> {
+    let prefix = format!("{}@", dataset);
+    list.lines()
+        .filter_map(|line| line.split_once('\t'))
+        .filter_map(|(name, time)| {
+            name.strip_prefix(&prefix)
+                .filter(|snapshot| !snapshot.starts_with(CLONE_SNAPSHOT_PREFIX))
+                .map(|snapshot| (snapshot.to_string(), time.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Replaces `device` with a writable copy of `snapshot`, keeping the snapshot. The old
+/// origin is only removed once the copy exists. ZFS can only roll back to the latest
+/// snapshot; newer ones have to be deleted first.
+pub async fn revert_thin_volume(privileges: &Privileges, device: &Path, snapshot: &str) -> Result<()> {
+    if let Ok(dataset) = zvol_name(device) {
+        run_as_root(privileges, "zfs", &["rollback", &format!("{}@{}", dataset, snapshot)]).await?;
+        return Ok(());
+    }
+    let (vg, lv) = lv_name(device)?;
+    let retired = format!("{}.vmtools-revert", lv);
+    run_as_root(privileges, "lvrename", &[&vg, &lv, &retired]).await?;
+
+    let source = format!("{}/{}", vg, snapshot_lv(&lv, snapshot));
+    if let Err(e) = run_as_root(privileges, "lvcreate", &["-s", "-kn", "-n", &lv, &source]).await {
+        let _ = run_as_root(privileges, "lvrename", &[&vg, &retired, &lv]).await;
+        return Err(e);
+    }
+    run_as_root(privileges, "lvremove", &["--yes", &format!("{}/{}", vg, retired)]).await?;
+    Ok(())
+}
+
+pub async fn delete_thin_snapshot(privileges: &Privileges, device: &Path, snapshot: &str) -> Result<()> {
+    if let Ok(dataset) = zvol_name(device) {
+        run_as_root(privileges, "zfs", &["destroy", &format!("{}@{}", dataset, snapshot)]).await?;
+        return Ok(());
+    }
+    let (vg, lv) = lv_name(device)?;
+    run_as_root(privileges, "lvremove", &["--yes", &format!("{}/{}", vg, snapshot_lv(&lv, snapshot))]).await?;
+    Ok(())
+}
+
+/// Removes the thin LV or zvol `device` and its snapshots; refuses anything else
+pub async fn remove_thin_volume(privileges: &Privileges, device: &Path) -> Result<()> {
+    if !is_thin_volume(privileges, device).await? {
+        return Err(VmError::InvalidInput(format!("{} is not a thin LV or zvol; leaving it alone", device.display())));
+    }
+    if let Ok(dataset) = zvol_name(device) {
+        // A clone's origin snapshot was only taken for it
+        let origin = run_as_root(privileges, "zfs", &["get", "-H", "-o", "value", "origin", &dataset]).await?;
+        run_as_root(privileges, "zfs", &["destroy", "-r", &dataset]).await?;
+        let origin = origin.trim();
+        if origin.split_once('@').is_some_and(|(_, snapshot)| snapshot.starts_with(CLONE_SNAPSHOT_PREFIX)) {
+            run_as_root(privileges, "zfs", &["destroy", origin]).await?;
+        }
+        return Ok(());
+    }
+    for (snapshot, _) in list_thin_snapshots(privileges, device).await? {
+        delete_thin_snapshot(privileges, device, &snapshot).await?;
+    }
+    let (vg, lv) = lv_name(device)?;
+    run_as_root(privileges, "lvremove", &["--yes", &format!("{}/{}", vg, lv)]).await?;
+    Ok(())
+}
+
+/// SELinux types guests may use as disk images or read-only media
+const GUEST_IMAGE_TYPES: &[&str] = &["virt_image_t", "svirt_image_t", "virt_content_t", "nfs_t"];
+
+/// Whether the host runs SELinux, enforcing or not
+pub fn selinux_enabled() -> bool {
+    Path::new("/sys/fs/selinux/enforce").exists()
+}
+
+/// The SELinux type of `path`, e.g. `virt_image_t`
+pub fn selinux_type(path: &Path) -> Option<String> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut buffer = [0u8; 256];
+    let size = unsafe {
+        libc::getxattr(path.as_ptr(), c"security.selinux".as_ptr(), buffer.as_mut_ptr().cast(), buffer.len())
+    };
+    if size <= 0 {
+        return None;
+    }
+    let context = String::from_utf8_lossy(&buffer[..size as usize]).trim_end_matches('\0').to_string();
+    context.split(':').nth(2).map(String::from)
+}
+
+/// Whether guests may open images labelled with SELinux type `label`
+pub fn is_guest_image_type(label: &str) -> bool {
+    GUEST_IMAGE_TYPES.contains(&label)
+}
+
+/// Makes a storage directory ready for disk images before anything is written to
+/// it: created when missing and checked for write access. Returns a warning when
+/// SELinux labels it so that guests would be refused their disks.
+pub fn init_directory(path: &Path, key: &str) -> Result<Option<String>> {
+    if path.exists() && !path.is_dir() {
+        return Err(VmError::InvalidInput(format!(
+            "{} ({}) is not a directory; point it elsewhere with: vmtools config --set {}=<dir>",
+            path.display(), key, key
+        )));
+    }
+    std::fs::create_dir_all(path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::PermissionDenied => VmError::PermissionDenied(format!(
+            "Cannot create {} ({}). Create it once with: sudo install -d -o $USER {}, \
+             or use a directory you own: vmtools config --set {}=<dir>",
+            path.display(), key, path.display(), key
+        )),
+        _ => VmError::IoError(std::io::Error::new(e.kind(), format!("Cannot create {}: {}", path.display(), e))),
+    })?;
+
+    let probe = path.join(format!(".vmtools-write-test-{}", uuid::Uuid::new_v4()));
+    match std::fs::File::create(&probe) {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied || e.raw_os_error() == Some(libc::EROFS) => {
+            return Err(VmError::PermissionDenied(format!(
+                "{} ({}) is not writable. Give yourself access with: sudo chown $USER {}, \
+                 or use a directory you own: vmtools config --set {}=<dir>",
+                path.display(), key, path.display(), key
+            )));
+        }
+        Err(e) => return Err(VmError::IoError(e)),
+    }
+
+    if !selinux_enabled() {
+        return Ok(None);
+    }
+    Ok(selinux_type(path).filter(|label| !is_guest_image_type(label)).map(|label| format!(
+        "{} is labelled {}, which guests may be denied. Label it for VM images with: \
+         sudo semanage fcontext -a -t virt_image_t '{}(/.*)?' && sudo restorecon -Rv {}",
+        path.display(), label, path.display(), path.display()
+    )))
+}
+
+/// The mandatory access control that confines guests on this host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityModule {
+    SELinux,
+    AppArmor,
+    None,
+}
+
+impl SecurityModule {
+    pub fn detect() -> Self {
+        if selinux_enabled() {
+            SecurityModule::SELinux
+        } else if std::fs::read_to_string("/sys/module/apparmor/parameters/enabled").is_ok_and(|enabled| enabled.trim() == "Y") {
+            SecurityModule::AppArmor
+        } else {
+            SecurityModule::None
+        }
+    }
+}
+
+/// Labels an image file guests are about to use: `virt_image_t` for a disk the
+/// guest writes, `virt_content_t` for one it only reads (a shared backing file).
+/// Returns the label applied, or nothing when SELinux is off or the file already
+/// carries a guest type. AppArmor needs no step here: libvirt adds each disk of a
+/// domain to that domain's profile when it starts.
+pub async fn label_image(runner: &dyn CommandRunner, path: &Path, read_only: bool) -> Result<Option<&'static str>> {
+    if !selinux_enabled() || selinux_type(path).is_some_and(|label| is_guest_image_type(&label)) {
+        return Ok(None);
+    }
+    let label = if read_only { "virt_content_t" } else { "virt_image_t" };
+    let path_arg = path.to_string_lossy();
+    run(runner, &Invocation::new("chcon").args(["-t", label, &path_arg]), "chcon").await.map_err(|e| VmError::PermissionDenied(format!(
+        "Cannot label {} for guests ({}). Label it with: sudo semanage fcontext -a -t {} '{}' && sudo restorecon -v {}",
+        path.display(), e, label, path.display(), path.display()
+    )))?;
+    Ok(Some(label))
+}
+
+/// How to fix a guest being refused one of its files, when `message` (a failed
+/// start) says that is what happened
+pub fn explain_denial(message: &str, module: SecurityModule) -> Option<String> {
+    let denied = crate::privilege::is_denied(message)
+        || message.contains("security context")
+        || message.to_lowercase().contains("apparmor");
+    if !denied {
+        return None;
+    }
+    let path = ["Could not open '", "storage file '", " on '"].iter()
+        .find_map(|prefix| message.split_once(prefix))
+        .and_then(|(_, rest)| rest.split_once('\''))
+        .map(|(path, _)| path.to_string());
+    let file = path.clone().unwrap_or_else(|| "the disk image".to_string());
+
+    if message.contains("(as uid:") {
+        return Some(format!(
+            "qemu's user cannot reach {}. Make the file readable to it and every directory above it searchable, \
+             e.g. sudo setfacl -m u:qemu:x <each parent directory>, or keep images in the storage directory",
+            file
+        ));
+    }
+    let fix = match module {
+        SecurityModule::SELinux => match &path {
+            Some(path) => format!(
+                "SELinux keeps guests from {}. Label it with: sudo chcon -t virt_image_t {} \
+                 (to keep the label across relabels: sudo semanage fcontext -a -t virt_image_t '{}' && sudo restorecon -v {})",
+                path, path, path, path
+            ),
+            None => "SELinux keeps the guest from one of its files. Find it with: sudo ausearch -m avc -ts recent, \
+                     then label it with: sudo chcon -t virt_image_t <file>".to_string(),
+        },
+        SecurityModule::AppArmor => format!(
+            "AppArmor keeps guests from {}. Allow it by adding a line `\"{}\" rwk,` to \
+             /etc/apparmor.d/local/abstractions/libvirt-qemu, then retry (see: sudo journalctl -k | grep apparmor)",
+            file, path.as_deref().unwrap_or("/path/to/image")
+        ),
+        SecurityModule::None => return path.map(|path| format!(
+            "qemu cannot open {}; give its user access to the file (for a qemu:///session VM that is you: \
+             sudo chown $USER {})", path, path
+        )),
+    };
+    Some(fix)
+}
@@ -0,0 +1,46 @@
+use std::path::Path;
+
+/// Unit that restores saved VMs at boot and saves running ones at shutdown
+pub const GUESTS_UNIT: &str = "vmtools-guests.service";
+
+/// The `vmtools-guests.service` unit running `vmtools` from `exe`. It is "active"
+/// from boot to shutdown: starting it resumes VMs, stopping it saves them. Ordered
+/// after libvirtd, so at shutdown it stops, and the VMs get saved, before libvirtd.
+pub fn guests_unit(exe: &Path) -> String {
+    format!(r#"[Unit]
+Description=Save running VMs at host shutdown and resume them at boot (vmtools)
+Wants=libvirtd.service
+After=network-online.target libvirtd.service virtqemud.service
+
+[Service]
+Type=oneshot
+RemainAfterExit=yes
+ExecStart={exe} resume-all
+ExecStop={exe} suspend-all
+# Saving writes every VM's RAM to disk
+TimeoutStopSec=900
+
+[Install]
+WantedBy=multi-user.target
+"#, exe = exe.display())
+}
+
+/// Unit running `vmtools daemon`
+pub const DAEMON_UNIT: &str = "vmtools-daemon.service";
+
+/// The `vmtools-daemon.service` unit running `vmtools daemon` from `exe`
+pub fn daemon_unit(exe: &Path) -> String {
+    format!(r#"[Unit]
+Description=vmtools background policies (idle VM suspend, snapshot retention)
+Wants=libvirtd.service
+After=libvirtd.service virtqemud.service
+
+[Service]
+ExecStart={exe} daemon
+Restart=on-failure
+RestartSec=30
+
+[Install]
+WantedBy=multi-user.target
+"#, exe = exe.display())
+}
@@ -0,0 +1,48 @@
+use std::io::IsTerminal;
+
+/// Single keystrokes from this terminal, read without waiting for Enter or echoing
+/// them. Ctrl+C arrives as a key too, so whoever reads the keys decides how to
+/// stop and the terminal is restored, when this is dropped, on every way out.
+pub struct KeyInput(Option<libc::termios>);
+
+impl KeyInput {
+    /// Switches stdin to key-at-a-time input; when it isn't a terminal no keys are read
+    pub fn enable() -> Self {
+        if !std::io::stdin().is_terminal() {
+            return Self(None);
+        }
+        unsafe {
+            let mut saved: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut saved) != 0 {
+                return Self(None);
+            }
+            let mut keys = saved;
+            keys.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+            // Reads return at once, with or without a key
+            keys.c_cc[libc::VMIN] = 0;
+            keys.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &keys) != 0 {
+                return Self(None);
+            }
+            Self(Some(saved))
+        }
+    }
+
+    /// The next key pressed, if any; never blocks
+    pub fn key(&self) -> Option<u8> {
+        self.0.as_ref()?;
+        let mut key = 0u8;
+        let read = unsafe { libc::read(libc::STDIN_FILENO, (&mut key as *mut u8).cast(), 1) };
+        (read == 1).then_some(key)
+    }
+}
+
+impl Drop for KeyInput {
+    fn drop(&mut self) {
+        if let Some(saved) = &self.0 {
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved);
+            }
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::{
+    backend::Backend,
+    error::{VmError, Result},
+};
+
+const MANIFEST_FILE: &str = "manifest.json";
+const DOMAIN_XML_FILE: &str = "domain.xml";
+
+/// A disk moved into the trash, remembering where it came from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashedDisk {
+    pub original_path: PathBuf,
+    pub file_name: String,
+}
+
+/// A storage pool volume left in place, such as an iSCSI LUN
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrashedVolume {
+    pub pool: String,
+    pub volume: String,
+}
+
+/// A deleted VM held in `backup_path/trash/<name>-<timestamp>`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashEntry {
+    pub name: String,
+    pub deleted_at: DateTime<Utc>,
+    pub disks: Vec<TrashedDisk>,
+    /// Thin LVs and zvols that stay where they are for the restore
+    #[serde(default)]
+    pub thin_volumes: Vec<PathBuf>,
+    /// Pool volumes that stay where they are for the restore
+    #[serde(default)]
+    pub pool_volumes: Vec<TrashedVolume>,
+    #[serde(skip)]
+    pub dir: PathBuf,
+}
+
+pub struct Trash {
+    root: PathBuf,
+    retention_days: u64,
+}
+
+impl Trash {
+    pub fn new(backup_path: &Path, retention_days: u64) -> Self {
+        Self {
+            root: backup_path.join("trash"),
+            retention_days,
+        }
+    }
+
+    /// Moves the domain XML and disk images of a VM into a new trash entry, which
+    /// also records the devices and pool volumes left in place, so a purge removes them
+    pub async fn store(
+        &self,
+        name: &str,
+        domain_xml: &str,
+        disk_paths: &[String],
+        thin_volumes: Vec<PathBuf>,
+        pool_volumes: Vec<TrashedVolume>,
+    ) -> Result<TrashEntry> {
+        let deleted_at = Utc::now();
+        let dir = self.root.join(format!("{}-{}", name, deleted_at.format("%Y%m%d-%H%M%S")));
+        tokio::fs::create_dir_all(&dir).await?;
+
+        tokio::fs::write(dir.join(DOMAIN_XML_FILE), domain_xml).await?;
+
+        let mut entry = TrashEntry {
+            name: name.to_string(),
+            deleted_at,
+            disks: Vec::new(),
+            thin_volumes,
+            pool_volumes,
+            dir: dir.clone(),
+        };
+        // Keep the manifest current after every move so an interrupted delete stays restorable
+        write_manifest(&entry).await?;
+
+        for (i, disk_path) in disk_paths.iter().enumerate() {
+            let original_path = PathBuf::from(disk_path);
+            let base_name = original_path.file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| format!("disk{}", i));
+            // Prefix with the index so disks sharing a file name don't clash
+            let file_name = format!("{}-{}", i, base_name);
+
+            move_file(&original_path, &dir.join(&file_name)).await?;
+            entry.disks.push(TrashedDisk { original_path, file_name });
+            write_manifest(&entry).await?;
+        }
+
+        Ok(entry)
+    }
+
+    /// Lists all trash entries, newest first
+    pub async fn entries(&self) -> Result<Vec<TrashEntry>> {
+        let mut entries = Vec::new();
+
+        let mut dir = match tokio::fs::read_dir(&self.root).await {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(entries),
+            Err(e) => return Err(e.into()),
+        };
+
+        while let Some(item) = dir.next_entry().await? {
+            let path = item.path();
+            let Ok(content) = tokio::fs::read_to_string(path.join(MANIFEST_FILE)).await else {
+                continue;
+            };
+            if let Ok(mut entry) = serde_json::from_str::<TrashEntry>(&content) {
+                entry.dir = path;
+                entries.push(entry);
+            }
+        }
+
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.deleted_at));
+        Ok(entries)
+    }
+
+    /// Most recently deleted entry for a VM name
+    pub async fn latest(&self, name: &str) -> Result<TrashEntry> {
+        self.entries().await?
+            .into_iter()
+            .find(|entry| entry.name == name)
+            .ok_or_else(|| VmError::VmNotFound(format!("{} (no trash entry)", name)))
+    }
+
+    /// Moves disks back to their original locations and returns the saved domain XML
+    pub async fn restore(&self, entry: &TrashEntry) -> Result<String> {
+        for disk in &entry.disks {
+            if tokio::fs::try_exists(&disk.original_path).await.unwrap_or(false) {
+                return Err(VmError::OperationError(format!(
+                    "Cannot restore disk: {} already exists", disk.original_path.display()
+                )));
+            }
+        }
+
+        let xml = tokio::fs::read_to_string(entry.dir.join(DOMAIN_XML_FILE)).await?;
+
+        for disk in &entry.disks {
+            if let Some(parent) = disk.original_path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            move_file(&entry.dir.join(&disk.file_name), &disk.original_path).await?;
+        }
+
+        Ok(xml)
+    }
+
+    pub async fn remove(&self, entry: &TrashEntry) -> Result<()> {
+        tokio::fs::remove_dir_all(&entry.dir).await?;
+        Ok(())
+    }
+
+    /// Permanently removes entries older than the retention period (0 keeps entries
+    /// forever), with the volumes they left in place through `backend`. A volume that
+    /// can't be removed is reported, but doesn't keep its entry around.
+    pub async fn purge_expired(&self, backend: &dyn Backend) -> Result<Vec<TrashEntry>> {
+        if self.retention_days == 0 {
+            return Ok(Vec::new());
+        }
+
+        let cutoff = Utc::now() - Duration::days(self.retention_days as i64);
+        let mut purged = Vec::new();
+
+        for entry in self.entries().await? {
+            if entry.deleted_at < cutoff {
+                for device in &entry.thin_volumes {
+                    if let Err(e) = backend.remove_thin_volume(device).await {
+                        tracing::warn!("Failed to remove volume {} of '{}': {}", device.display(), entry.name, e);
+                    }
+                }
+                for TrashedVolume { pool, volume } in &entry.pool_volumes {
+                    if let Err(e) = backend.delete_volume(pool, volume).await {
+                        tracing::warn!("Failed to delete volume {}/{} of '{}': {}; remove it on the storage side", pool, volume, entry.name, e);
+                    }
+                }
+                self.remove(&entry).await?;
+                purged.push(entry);
+            }
+        }
+
+        Ok(purged)
+    }
+}
+
+async fn write_manifest(entry: &TrashEntry) -> Result<()> {
+    let manifest = serde_json::to_string_pretty(entry)?;
+    tokio::fs::write(entry.dir.join(MANIFEST_FILE), manifest).await?;
+    Ok(())
+}
+
+/// Renames a file, falling back to copy + remove across filesystems
+async fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if tokio::fs::rename(from, to).await.is_ok() {
+        return Ok(());
+    }
+
+    tokio::fs::copy(from, to).await
+        .map_err(|e| VmError::OperationError(format!("Failed to move {}: {}", from.display(), e)))?;
+    tokio::fs::remove_file(from).await?;
+    Ok(())
+}
@@ -1,10 +1,12 @@
 use std::path::{Path, PathBuf};
-use tokio::process::Command;
 use rand::Rng;
 
 use crate::{
     error::{VmError, Result},
     config::Config,
+    diagnosis,
+    privilege::{self, Privileges},
+    runner::{CommandRunner, Invocation},
 };
 
 /// Validates and sanitizes a file path to prevent path traversal attacks (CWE-22)
@@ -75,7 +77,7 @@ async fn read_validated_system_file(file_path: &Path, expected_prefix: &str) ->
         _ => return Err(VmError::SecurityError("Unauthorized file access".to_string()))
     };
     
-    content.map_err(|e| VmError::IoError(e))
+    content.map_err(VmError::IoError)
 }
 
 pub fn format_bytes(bytes: u64) -> String {
@@ -95,6 +97,14 @@ pub fn format_bytes(bytes: u64) -> String {
     }
 }
 
+/// Formats a Unix timestamp in local time, or "-" for 0 (unknown)
+pub fn format_timestamp(timestamp: u64) -> String {
+    chrono::DateTime::from_timestamp(timestamp as i64, 0)
+        .filter(|_| timestamp > 0)
+        .map(|time| time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
 pub fn format_duration(seconds: u64) -> String {
     let days = seconds / 86400;
     let hours = (seconds % 86400) / 3600;
@@ -122,103 +132,280 @@ pub fn generate_mac_address() -> String {
     )
 }
 
-pub async fn create_qcow2_image<P: AsRef<Path>>(path: P, size_bytes: u64) -> Result<()> {
+/// Random alphanumeric password, e.g. for console authentication
+pub fn generate_password(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+/// Writes a file only the current user can read, for XML that may carry secrets
+pub async fn write_private_file(path: &Path, content: &str) -> Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .await?;
+    file.write_all(content.as_bytes()).await?;
+    Ok(())
+}
+
+pub async fn create_qcow2_image<P: AsRef<Path>>(runner: &dyn CommandRunner, path: P, size_bytes: u64) -> Result<()> {
     let size_str = format!("{}G", size_bytes / (1024 * 1024 * 1024));
     
-    let output = Command::new("qemu-img")
-        .args(&[
+    let output = runner.output(&Invocation::new("qemu-img")
+        .args([
             "create",
             "-f", "qcow2",
             path.as_ref().to_str().unwrap(),
             &size_str
-        ])
-        .output()
+        ]))
         .await
-        .map_err(|e| VmError::IoError(e))?;
+        .map_err(VmError::IoError)?;
 
     if !output.status.success() {
         let error = String::from_utf8_lossy(&output.stderr);
-        return Err(VmError::IoError(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("Failed to create qcow2 image: {}", error)
-        )));
+        return Err(qemu_img_error("create", "Failed to create qcow2 image", &error));
     }
 
     Ok(())
 }
 
-pub async fn clone_qcow2_image<P: AsRef<Path>>(source: P, target: P) -> Result<()> {
-    let output = Command::new("qemu-img")
-        .args(&[
-            "convert",
-            "-f", "qcow2",
-            "-O", "qcow2",
-            source.as_ref().to_str().unwrap(),
-            target.as_ref().to_str().unwrap()
-        ])
-        .output()
-        .await
-        .map_err(|e| VmError::IoError(e))?;
+/// The error for a failed qemu-img run: how to get access when it was refused
+/// the image, else `message` with qemu-img's stderr
+fn qemu_img_error(action: &str, message: &str, stderr: &str) -> VmError {
+    privilege::image_error(action, stderr).unwrap_or_else(|| {
+        VmError::IoError(std::io::Error::other(format!("{}: {}", message, stderr.trim())))
+    })
+}
 
+/// Receives (bytes copied, total bytes) as a disk copy advances
+pub type CopyProgress<'a> = &'a (dyn Fn(u64, u64) + Send + Sync);
+
+/// Full copy of a qcow2 image. On Btrfs, XFS and other reflink-capable filesystems
+/// the copy shares extents with the source and is nearly instant; elsewhere, or
+/// when the image has a backing file to flatten, `qemu-img convert` copies the data.
+pub async fn clone_qcow2_image<P: AsRef<Path>>(runner: &dyn CommandRunner, source: P, target: P, progress: CopyProgress<'_>) -> Result<()> {
+    let info = get_image_info(runner, &source).await.ok();
+    let total = info.as_ref().map_or(0, |info| info.virtual_size);
+    let standalone = info.is_some_and(|info| info.backing_file.is_none());
+    if standalone && !target.as_ref().exists() && reflink_copy(runner, source.as_ref(), target.as_ref()).await {
+        progress(total, total);
+        return Ok(());
+    }
+
+    let convert = Invocation::new("qemu-img")
+        .args(["convert", "-p", "-f", "qcow2", "-O", "qcow2"])
+        .arg(source.as_ref())
+        .arg(target.as_ref());
+    convert_with_progress(runner, &convert, total, progress).await
+        .map_err(|error| qemu_img_error("write", "Failed to clone qcow2 image", &error))
+}
+
+/// Runs a `qemu-img convert -p`, turning the percentages it prints into bytes of
+/// `total`, the virtual size the percentage counts. Errs with qemu-img's stderr.
+async fn convert_with_progress(
+    runner: &dyn CommandRunner,
+    convert: &Invocation,
+    total: u64,
+    progress: CopyProgress<'_>,
+) -> std::result::Result<(), String> {
+    // Each update rewrites the line: `\r    (42.19/100%)`
+    let line = std::sync::Mutex::new(Vec::new());
+    let report = |chunk: &[u8]| {
+        let mut line = line.lock().unwrap_or_else(|e| e.into_inner());
+        for &byte in chunk {
+            if byte != b'\r' && byte != b'\n' {
+                line.push(byte);
+                continue;
+            }
+            if let Some(percent) = parse_convert_progress(&String::from_utf8_lossy(&line)) {
+                progress((total as f64 * percent / 100.0) as u64, total);
+            }
+            line.clear();
+        }
+    };
+
+    let output = runner.output_streaming(convert, &report).await.map_err(|e| e.to_string())?;
     if !output.status.success() {
-        let error = String::from_utf8_lossy(&output.stderr);
-        return Err(VmError::IoError(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("Failed to clone qcow2 image: {}", error)
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    progress(total, total);
+    Ok(())
+}
+
+/// Percentage from one `qemu-img -p` progress line such as `    (42.19/100%)`
+pub fn parse_convert_progress(line: &str) -> Option<f64> {
+    line.trim()
+        .strip_prefix('(')?
+        .strip_suffix("/100%)")?
+        .parse()
+        .ok()
+        .filter(|percent: &f64| (0.0..=100.0).contains(percent))
+}
+
+/// `cp --reflink=always`; false when the filesystem can't share extents between
+/// the two paths (no FICLONE support, or different filesystems)
+async fn reflink_copy(runner: &dyn CommandRunner, source: &Path, target: &Path) -> bool {
+    let copied = runner.output(&Invocation::new("cp").arg("--reflink=always").arg(source).arg(target))
+        .await
+        .is_ok_and(|output| output.status.success());
+    if !copied {
+        // cp leaves an empty file behind when the clone ioctl is refused
+        let _ = tokio::fs::remove_file(target).await;
+    }
+    copied
+}
+
+/// Turns an existing image of any format qemu-img reads (qcow2, raw, vmdk, ...)
+/// into a new qcow2 image at `target`: a full copy, or with `link` a thin
+/// overlay that keeps reading unchanged blocks from `source`
+pub async fn import_image(runner: &dyn CommandRunner, source: &Path, target: &Path, link: bool, progress: CopyProgress<'_>) -> Result<()> {
+    let info = get_image_info(runner, source).await?;
+    let format = info.format;
+    let source = std::fs::canonicalize(source)?;
+
+    let result = if link {
+        let create = Invocation::new("qemu-img")
+            .args(["create", "-f", "qcow2", "-b"])
+            .arg(&source)
+            .args(["-F", &format])
+            .arg(target);
+        let output = runner.output(&create).await.map_err(VmError::IoError)?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(String::from_utf8_lossy(&output.stderr).into_owned())
+        }
+    } else {
+        let convert = Invocation::new("qemu-img")
+            .args(["convert", "-p", "-f", &format, "-O", "qcow2"])
+            .arg(&source)
+            .arg(target);
+        convert_with_progress(runner, &convert, info.virtual_size, progress).await
+    };
+
+    result.map_err(|error| qemu_img_error("import", &format!("Failed to import {} image", format), &error))
+}
+
+/// Copies the data of an image's backing chain into the image itself and drops
+/// the backing file reference, with progress; the image must not be in use
+pub async fn flatten_image(runner: &dyn CommandRunner, path: &Path) -> Result<()> {
+    let status = runner.status(&Invocation::new("qemu-img").args(["rebase", "-p", "-b", ""]).arg(path))
+        .await
+        .map_err(VmError::IoError)?;
+
+    if !status.success() {
+        return Err(VmError::IoError(std::io::Error::other(
+            format!("Failed to flatten {}", path.display())
         )));
     }
 
     Ok(())
 }
 
+/// One `qemu-img bench` run: `requests` sequential requests of `block_size`
+/// bytes, `depth` in flight at a time
+#[derive(Debug, Clone)]
+pub struct ImageBench {
+    pub write: bool,
+    pub requests: u64,
+    pub depth: u32,
+    pub block_size: u64,
+}
+
+/// Runs `qemu-img bench` against an image and returns how long the requests took,
+/// in seconds. `cache` is the host cache mode, as in libvirt's `<driver cache=...>`,
+/// and `aio` is `threads`, `native` or `io_uring`, as in `<driver io=...>`. Reads
+/// share the image with a VM that has it open.
+pub async fn bench_image(
+    runner: &dyn CommandRunner,
+    path: &Path,
+    format: &str,
+    cache: &str,
+    aio: &str,
+    bench: &ImageBench,
+) -> Result<f64> {
+    let command = Invocation::new("qemu-img")
+        .args(["bench", "-f", format, "-t", cache, "-i", aio])
+        .args(["-c", &bench.requests.to_string()])
+        .args(["-d", &bench.depth.to_string()])
+        .args(["-s", &bench.block_size.to_string()])
+        .arg(if bench.write { "-w" } else { "-U" })
+        .arg(path);
+    let output = runner.output(&command).await.map_err(VmError::IoError)?;
+
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        return Err(qemu_img_error("benchmark", "qemu-img bench failed", &error));
+    }
+
+    // "Run completed in 3.456 seconds."
+    String::from_utf8_lossy(&output.stdout).lines()
+        .find_map(|line| line.strip_prefix("Run completed in ")?.strip_suffix(" seconds.")?.parse().ok())
+        .ok_or_else(|| VmError::IoError(std::io::Error::other("Unexpected qemu-img bench output")))
+}
+
 #[allow(dead_code)]
-pub async fn get_image_info<P: AsRef<Path>>(path: P) -> Result<ImageInfo> {
-    let output = Command::new("qemu-img")
-        .args(&["info", "--output=json", path.as_ref().to_str().unwrap()])
-        .output()
+pub async fn get_image_info<P: AsRef<Path>>(runner: &dyn CommandRunner, path: P) -> Result<ImageInfo> {
+    // -U (force-share) lets us inspect images that a running VM holds locked
+    let output = runner.output(&Invocation::new("qemu-img")
+        .args(["info", "-U", "--output=json", path.as_ref().to_str().unwrap()]))
         .await
-        .map_err(|e| VmError::IoError(e))?;
+        .map_err(VmError::IoError)?;
 
     if !output.status.success() {
         let error = String::from_utf8_lossy(&output.stderr);
-        return Err(VmError::IoError(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("Failed to get image info: {}", error)
-        )));
+        return Err(qemu_img_error("read", "Failed to get image info", &error));
     }
 
     let json_str = String::from_utf8_lossy(&output.stdout);
     let info: serde_json::Value = serde_json::from_str(&json_str)
-        .map_err(|e| VmError::SerdeError(e))?;
+        .map_err(VmError::SerdeError)?;
 
-    Ok(ImageInfo {
-        format: info["format"].as_str().unwrap_or("unknown").to_string(),
-        virtual_size: info["virtual-size"].as_u64().unwrap_or(0),
-        actual_size: info["actual-size"].as_u64().unwrap_or(0),
-        filename: info["filename"].as_str().unwrap_or("").to_string(),
-    })
+    Ok(ImageInfo::from_json(&info))
+}
+
+/// Returns the image followed by each of its backing files, top overlay first
+pub async fn get_backing_chain<P: AsRef<Path>>(runner: &dyn CommandRunner, path: P) -> Result<Vec<ImageInfo>> {
+    let output = runner.output(&Invocation::new("qemu-img")
+        .args(["info", "-U", "--backing-chain", "--output=json", path.as_ref().to_str().unwrap()]))
+        .await
+        .map_err(VmError::IoError)?;
+
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        return Err(qemu_img_error("read", "Failed to get backing chain", &error));
+    }
+
+    let json_str = String::from_utf8_lossy(&output.stdout);
+    let chain: Vec<serde_json::Value> = serde_json::from_str(&json_str)
+        .map_err(VmError::SerdeError)?;
+
+    Ok(chain.iter().map(ImageInfo::from_json).collect())
 }
 
 #[allow(dead_code)]
-pub async fn resize_image<P: AsRef<Path>>(path: P, new_size: u64) -> Result<()> {
+pub async fn resize_image<P: AsRef<Path>>(runner: &dyn CommandRunner, path: P, new_size: u64) -> Result<()> {
     let size_str = format!("{}G", new_size / (1024 * 1024 * 1024));
     
-    let output = Command::new("qemu-img")
-        .args(&[
+    let output = runner.output(&Invocation::new("qemu-img")
+        .args([
             "resize",
             path.as_ref().to_str().unwrap(),
             &size_str
-        ])
-        .output()
+        ]))
         .await
-        .map_err(|e| VmError::IoError(e))?;
+        .map_err(VmError::IoError)?;
 
     if !output.status.success() {
         let error = String::from_utf8_lossy(&output.stderr);
-        return Err(VmError::IoError(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("Failed to resize image: {}", error)
-        )));
+        return Err(qemu_img_error("resize", "Failed to resize image", &error));
     }
 
     Ok(())
@@ -231,9 +418,58 @@ pub struct ImageInfo {
     pub virtual_size: u64,
     pub actual_size: u64,
     pub filename: String,
+    pub backing_file: Option<String>,
+    /// Internal (qcow2) snapshots, oldest first
+    pub snapshots: Vec<ImageSnapshot>,
+}
+
+/// An internal snapshot stored inside a qcow2 image
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageSnapshot {
+    pub name: String,
+    /// Bytes of saved VM state; 0 for disk-only snapshots
+    pub vm_state_size: u64,
+    /// Creation time in Unix seconds
+    pub date: u64,
+}
+
+impl ImageInfo {
+    fn from_json(info: &serde_json::Value) -> Self {
+        let snapshots = info["snapshots"].as_array().map(|snapshots| {
+            snapshots.iter()
+                .map(|snapshot| ImageSnapshot {
+                    name: snapshot["name"].as_str().unwrap_or("").to_string(),
+                    vm_state_size: snapshot["vm-state-size"].as_u64().unwrap_or(0),
+                    date: snapshot["date-sec"].as_u64().unwrap_or(0),
+                })
+                .collect()
+        });
+        
+        ImageInfo {
+            format: info["format"].as_str().unwrap_or("unknown").to_string(),
+            virtual_size: info["virtual-size"].as_u64().unwrap_or(0),
+            actual_size: info["actual-size"].as_u64().unwrap_or(0),
+            filename: info["filename"].as_str().unwrap_or("").to_string(),
+            backing_file: info["full-backing-filename"].as_str()
+                .or_else(|| info["backing-filename"].as_str())
+                .map(|s| s.to_string()),
+            snapshots: snapshots.unwrap_or_default(),
+        }
+    }
 }
 
 #[allow(dead_code)]
+/// Validates a Linux account name inside a guest
+pub fn validate_guest_user(user: &str) -> Result<()> {
+    let valid = user.len() <= 32
+        && user.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+        && user.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '-' | '.'));
+    if !valid {
+        return Err(VmError::InvalidInput(format!("Invalid user name '{}'", user)));
+    }
+    Ok(())
+}
+
 pub fn validate_vm_name(name: &str) -> Result<()> {
     // Check for empty or whitespace-only names
     if name.trim().is_empty() {
@@ -269,6 +505,19 @@ pub fn validate_vm_name(name: &str) -> Result<()> {
 }
 
 #[allow(dead_code)]
+/// Checks a tag or similar short label: letters, digits, `.`, `_`, `-` and `:`
+pub fn validate_label(kind: &str, label: &str) -> Result<()> {
+    if label.is_empty() || label.len() > 64 {
+        return Err(VmError::InvalidInput(format!("{} must be 1 to 64 characters long", kind)));
+    }
+    if !label.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | ':')) {
+        return Err(VmError::InvalidInput(format!(
+            "{} '{}' may only contain letters, digits, '.', '_', '-' and ':'", kind, label
+        )));
+    }
+    Ok(())
+}
+
 pub fn validate_memory(memory_mb: u64) -> Result<()> {
     if memory_mb < 128 {
         return Err(VmError::InvalidInput("Memory must be at least 128MB".to_string()));
@@ -294,6 +543,31 @@ pub fn validate_cpus(cpus: u32) -> Result<()> {
     Ok(())
 }
 
+/// Parses a rate such as `100mbit`, `2gbit` or `512kb` (per second) into KiB/s,
+/// libvirt's bandwidth unit; a bare number is taken as KiB/s and 0 as unlimited
+pub fn parse_bandwidth(rate: &str) -> Result<u64> {
+    let rate = rate.trim().to_ascii_lowercase();
+    let invalid = || VmError::InvalidInput(format!(
+        "Invalid rate '{}'; use e.g. 100mbit, 1gbit, 512kbit, 10mb or 0 for unlimited", rate
+    ));
+
+    let split = rate.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rate.len());
+    let value: f64 = rate[..split].parse().map_err(|_| invalid())?;
+    let bytes_per_second = match &rate[split..] {
+        "" | "k" | "kb" | "kib" => value * 1024.0,
+        "m" | "mb" | "mib" => value * 1024.0 * 1024.0,
+        "g" | "gb" | "gib" => value * 1024.0 * 1024.0 * 1024.0,
+        "kbit" => value * 1_000.0 / 8.0,
+        "mbit" => value * 1_000_000.0 / 8.0,
+        "gbit" => value * 1_000_000_000.0 / 8.0,
+        _ => return Err(invalid()),
+    };
+
+    let kib = (bytes_per_second / 1024.0).round() as u64;
+    // Don't let a tiny but non-zero limit round down to "unlimited"
+    Ok(if kib == 0 && value > 0.0 { 1 } else { kib })
+}
+
 #[allow(dead_code)]
 pub fn validate_disk_size(size_gb: u64) -> Result<()> {
     if size_gb == 0 {
@@ -308,10 +582,8 @@ pub fn validate_disk_size(size_gb: u64) -> Result<()> {
 }
 
 #[allow(dead_code)]
-pub async fn check_libvirt_running() -> Result<()> {
-    let output = Command::new("systemctl")
-        .args(&["is-active", "libvirtd"])
-        .output()
+pub async fn check_libvirt_running(runner: &dyn CommandRunner) -> Result<()> {
+    let output = runner.output(&Invocation::new("systemctl").args(["is-active", "libvirtd"]))
         .await
         .map_err(|e| VmError::LibvirtError(format!("Failed to check libvirtd status: {}", e)))?;
 
@@ -328,12 +600,11 @@ pub async fn check_libvirt_running() -> Result<()> {
 }
 
 #[allow(dead_code)]
-pub async fn check_kvm_support(config: &Config) -> Result<()> {
+pub async fn check_kvm_support(runner: &dyn CommandRunner, config: &Config) -> Result<()> {
     // Check if KVM module is loaded
-    let output = Command::new("lsmod")
-        .output()
+    let output = runner.output(&Invocation::new("lsmod"))
         .await
-        .map_err(|e| VmError::IoError(e))?;
+        .map_err(VmError::IoError)?;
 
     let lsmod_output = String::from_utf8_lossy(&output.stdout);
     if !lsmod_output.contains("kvm") {
@@ -349,7 +620,6 @@ pub async fn check_kvm_support(config: &Config) -> Result<()> {
     Ok(())
 }
 
-#[allow(dead_code)]
 pub async fn get_host_info(config: &Config) -> Result<HostInfo> {
     // SECURITY: Use secure file reader to prevent CWE-22 path traversal
     let cpuinfo = read_validated_system_file(&config.system.proc_cpuinfo, "/proc/").await?;
@@ -382,7 +652,6 @@ pub async fn get_host_info(config: &Config) -> Result<HostInfo> {
 }
 
 #[derive(Debug, Clone)]
-#[allow(dead_code)]
 pub struct HostInfo {
     pub cpu_count: u32,
     pub total_memory: u64, // in MB
@@ -414,6 +683,7 @@ pub enum NetworkIssueType {
     InvalidNetworkReference,
     ConflictingConfiguration,
     MissingBridge,
+    MissingNatRules,
 }
 
 impl std::fmt::Display for NetworkIssueType {
@@ -424,22 +694,23 @@ impl std::fmt::Display for NetworkIssueType {
             NetworkIssueType::InvalidNetworkReference => write!(f, "Invalid Network Reference"),
             NetworkIssueType::ConflictingConfiguration => write!(f, "Conflicting Configuration"),
             NetworkIssueType::MissingBridge => write!(f, "Missing Bridge"),
+            NetworkIssueType::MissingNatRules => write!(f, "Missing NAT Rules"),
         }
     }
 }
 
 /// Detects network mismatches in VM configuration
-pub async fn detect_network_mismatches(vm_name: &str) -> Result<Vec<NetworkMismatch>> {
+pub async fn detect_network_mismatches(vm_name: &str, privileges: &Privileges) -> Result<Vec<NetworkMismatch>> {
     let mut mismatches = Vec::new();
     
     // Get VM's current network configuration
-    let vm_interfaces = get_vm_network_interfaces(vm_name).await?;
+    let vm_interfaces = get_vm_network_interfaces(vm_name, privileges).await?;
     
     // Get available libvirt networks
-    let available_networks = get_available_networks().await?;
+    let available_networks = get_available_networks(privileges).await?;
     
     // Check for duplicate MAC addresses across all VMs
-    let all_mac_addresses = get_all_vm_mac_addresses().await?;
+    let all_mac_addresses = get_all_vm_mac_addresses(privileges).await?;
     
     for interface in &vm_interfaces {
         // Check for duplicate MAC addresses
@@ -498,18 +769,74 @@ pub async fn detect_network_mismatches(vm_name: &str) -> Result<Vec<NetworkMisma
     }
     
     // NEW: Check for missing bridges and conflicting configurations
-    let bridge_conflicts = detect_bridge_and_config_issues(&vm_interfaces, &available_networks).await?;
+    let bridge_conflicts = detect_bridge_and_config_issues(&vm_interfaces, &available_networks, privileges).await?;
     mismatches.extend(bridge_conflicts);
     
+    mismatches.extend(detect_missing_nat_rules(&vm_interfaces, privileges).await);
+    
     Ok(mismatches)
 }
 
+/// Finds the active NAT networks of the VM whose MASQUERADE rule is gone. A
+/// firewalld reload or restart flushes libvirt's rules while the bridge stays
+/// up, so guests still get leases but nothing gets out. Reading the rules takes
+/// root; without it nothing is reported.
+async fn detect_missing_nat_rules(vm_interfaces: &[NetworkInterface], privileges: &Privileges) -> Vec<NetworkMismatch> {
+    let mut mismatches = Vec::new();
+    let mut rules = None;
+    let mut checked = Vec::new();
+    
+    for interface in vm_interfaces.iter().filter(|interface| interface.is_active) {
+        if checked.contains(&interface.network) {
+            continue;
+        }
+        checked.push(interface.network.clone());
+        
+        let Some((_, subnet)) = nat_subnet(&interface.network, privileges).await else {
+            continue;
+        };
+        if rules.is_none() {
+            rules = diagnosis::nat_rules(privileges).await;
+        }
+        let Some(rules) = &rules else {
+            tracing::debug!("Cannot read the NAT rules without root; not checking them");
+            return mismatches;
+        };
+        
+        if !diagnosis::masquerades(rules, &subnet) {
+            mismatches.push(NetworkMismatch {
+                interface_name: interface.bridge.clone(),
+                issue_type: NetworkIssueType::MissingNatRules,
+                current_config: Some(interface.clone()),
+                suggested_config: interface.clone(),
+            });
+        }
+    }
+    
+    mismatches
+}
+
+/// The XML and subnet of `network` when it is a NAT network with an IPv4 address
+async fn nat_subnet(network: &str, privileges: &Privileges) -> Option<(String, crate::network::Ipv4Subnet)> {
+    let output = privileges.runner().output(&privileges.virsh_read(&["net-dumpxml", network]).ok()?).await.ok()?;
+    let xml = String::from_utf8_lossy(&output.stdout).into_owned();
+    if !output.status.success() || diagnosis::forward_mode(&xml).as_deref() != Some("nat") {
+        return None;
+    }
+    let (_, subnet) = diagnosis::network_ipv4(&xml)?;
+    Some((xml, subnet))
+}
+
 /// Detects bridge and configuration issues for network interfaces
-async fn detect_bridge_and_config_issues(vm_interfaces: &[NetworkInterface], available_networks: &[NetworkInterface]) -> Result<Vec<NetworkMismatch>> {
+async fn detect_bridge_and_config_issues(
+    vm_interfaces: &[NetworkInterface],
+    available_networks: &[NetworkInterface],
+    privileges: &Privileges,
+) -> Result<Vec<NetworkMismatch>> {
     let mut mismatches = Vec::new();
     
     // Get system bridge information
-    let system_bridges = get_system_bridges().await?;
+    let system_bridges = get_system_bridges(privileges).await?;
     
     for interface in vm_interfaces {
         // Check for missing bridges
@@ -582,37 +909,23 @@ async fn detect_bridge_and_config_issues(vm_interfaces: &[NetworkInterface], ava
 }
 
 /// Gets network interfaces for a specific VM
-async fn get_vm_network_interfaces(vm_name: &str) -> Result<Vec<NetworkInterface>> {
-    // Try with regular virsh first, then with sudo if needed
-    let mut cmd = Command::new("virsh");
-    cmd.args(&["domiflist", vm_name]);
-    
-    let output = cmd.output().await
+async fn get_vm_network_interfaces(vm_name: &str, privileges: &Privileges) -> Result<Vec<NetworkInterface>> {
+    let output = privileges.runner().output(&privileges.virsh_read(&["domiflist", vm_name])?)
+        .await
         .map_err(|e| VmError::CommandError(format!("Failed to get VM network interfaces: {}", e)))?;
     
-    // If regular virsh fails, try with sudo
     if !output.status.success() {
-        let mut sudo_cmd = Command::new("sudo");
-        sudo_cmd.args(&["virsh", "domiflist", vm_name]);
-        
-        let sudo_output = sudo_cmd.output().await
-            .map_err(|e| VmError::CommandError(format!("Failed to get VM network interfaces with sudo: {}", e)))?;
-        
-        if !sudo_output.status.success() {
-            return Err(VmError::CommandError(format!(
-                "Failed to get VM network interfaces: {}", 
-                String::from_utf8_lossy(&sudo_output.stderr)
-            )));
-        }
-        
-        return parse_domiflist_output(&String::from_utf8_lossy(&sudo_output.stdout)).await;
+        return Err(VmError::CommandError(format!(
+            "Failed to get VM network interfaces: {}", 
+            String::from_utf8_lossy(&output.stderr)
+        )));
     }
     
-    parse_domiflist_output(&String::from_utf8_lossy(&output.stdout)).await
+    parse_domiflist_output(&String::from_utf8_lossy(&output.stdout), privileges).await
 }
 
 /// Helper function to parse domiflist output
-async fn parse_domiflist_output(output_str: &str) -> Result<Vec<NetworkInterface>> {
+async fn parse_domiflist_output(output_str: &str, privileges: &Privileges) -> Result<Vec<NetworkInterface>> {
     let mut interfaces = Vec::new();
     
     for line in output_str.lines().skip(2) { // Skip header lines
@@ -625,10 +938,10 @@ async fn parse_domiflist_output(output_str: &str) -> Result<Vec<NetworkInterface
             let mac = parts[4].to_string();      // MAC address
             
             // Get bridge name for this network
-            let bridge = get_network_bridge(&network).await.unwrap_or_else(|| "virbr0".to_string());
+            let bridge = get_network_bridge(&network, privileges).await.unwrap_or_else(|| "virbr0".to_string());
             
             // Check if network is active
-            let is_active = is_network_active(&network).await.unwrap_or(false);
+            let is_active = is_network_active(&network, privileges).await.unwrap_or(false);
             
             interfaces.push(NetworkInterface {
                 mac_address: mac,
@@ -643,11 +956,8 @@ async fn parse_domiflist_output(output_str: &str) -> Result<Vec<NetworkInterface
 }
 
 /// Gets all available libvirt networks
-async fn get_available_networks() -> Result<Vec<NetworkInterface>> {
-    // Always use sudo for network operations
-    let output = Command::new("sudo")
-        .args(&["virsh", "net-list", "--all"])
-        .output()
+async fn get_available_networks(privileges: &Privileges) -> Result<Vec<NetworkInterface>> {
+    let output = privileges.runner().output(&privileges.virsh_read(&["net-list", "--all"])?)
         .await
         .map_err(|e| VmError::CommandError(format!("Failed to list networks: {}", e)))?;
     
@@ -666,7 +976,7 @@ async fn get_available_networks() -> Result<Vec<NetworkInterface>> {
         if parts.len() >= 3 {
             let network_name = parts[0].to_string();
             let is_active = parts[1] == "active";
-            let bridge = get_network_bridge(&network_name).await.unwrap_or_else(|| "virbr0".to_string());
+            let bridge = get_network_bridge(&network_name, privileges).await.unwrap_or_else(|| "virbr0".to_string());
             
             networks.push(NetworkInterface {
                 mac_address: String::new(), // Not applicable for network definitions
@@ -681,10 +991,8 @@ async fn get_available_networks() -> Result<Vec<NetworkInterface>> {
 }
 
 /// Gets all MAC addresses used by VMs
-async fn get_all_vm_mac_addresses() -> Result<Vec<String>> {
-    let output = Command::new("virsh")
-        .args(&["list", "--all", "--name"])
-        .output()
+async fn get_all_vm_mac_addresses(privileges: &Privileges) -> Result<Vec<String>> {
+    let output = privileges.runner().output(&privileges.virsh_read(&["list", "--all", "--name"])?)
         .await
         .map_err(|e| VmError::CommandError(format!("Failed to list VMs: {}", e)))?;
     
@@ -704,7 +1012,7 @@ async fn get_all_vm_mac_addresses() -> Result<Vec<String>> {
     let mut all_macs = Vec::new();
     
     for vm_name in vm_names {
-        if let Ok(interfaces) = get_vm_network_interfaces(vm_name).await {
+        if let Ok(interfaces) = get_vm_network_interfaces(vm_name, privileges).await {
             for interface in interfaces {
                 all_macs.push(interface.mac_address);
             }
@@ -715,11 +1023,8 @@ async fn get_all_vm_mac_addresses() -> Result<Vec<String>> {
 }
 
 /// Checks if a network is currently active
-async fn is_network_active(network_name: &str) -> Result<bool> {
-    // Always use sudo for network operations to get accurate state
-    let output = Command::new("sudo")
-        .args(&["virsh", "net-info", network_name])
-        .output()
+async fn is_network_active(network_name: &str, privileges: &Privileges) -> Result<bool> {
+    let output = privileges.runner().output(&privileges.virsh_read(&["net-info", network_name])?)
         .await
         .map_err(|e| VmError::CommandError(format!("Failed to get network info: {}", e)))?;
     
@@ -738,11 +1043,8 @@ async fn is_network_active(network_name: &str) -> Result<bool> {
 }
 
 /// Gets the bridge name for a network
-async fn get_network_bridge(network_name: &str) -> Option<String> {
-    // Always use sudo for network operations
-    let output = Command::new("sudo")
-        .args(&["virsh", "net-info", network_name])
-        .output()
+async fn get_network_bridge(network_name: &str, privileges: &Privileges) -> Option<String> {
+    let output = privileges.runner().output(&privileges.virsh_read(&["net-info", network_name]).ok()?)
         .await
         .ok()?;
     
@@ -761,13 +1063,11 @@ async fn get_network_bridge(network_name: &str) -> Option<String> {
 }
 
 /// Gets all bridge interfaces available on the system
-async fn get_system_bridges() -> Result<Vec<String>> {
+async fn get_system_bridges(privileges: &Privileges) -> Result<Vec<String>> {
     let mut bridges = Vec::new();
     
     // Method 1: Check using ip link for bridge interfaces
-    let output = Command::new("ip")
-        .args(&["link", "show", "type", "bridge"])
-        .output()
+    let output = privileges.runner().output(&Invocation::new("ip").args(["link", "show", "type", "bridge"]))
         .await
         .map_err(|e| VmError::CommandError(format!("Failed to get bridge interfaces: {}", e)))?;
     
@@ -776,7 +1076,7 @@ async fn get_system_bridges() -> Result<Vec<String>> {
         for line in output_str.lines() {
             // Parse lines like: "3: virbr0: <BROADCAST,MULTICAST,UP,LOWER_UP> mtu 1500"
             if let Some(bridge_part) = line.split(':').nth(1) {
-                let bridge_name = bridge_part.trim().split_whitespace().next();
+                let bridge_name = bridge_part.split_whitespace().next();
                 if let Some(name) = bridge_name {
                     if name.starts_with("virbr") || name.starts_with("br-") {
                         bridges.push(name.to_string());
@@ -788,16 +1088,14 @@ async fn get_system_bridges() -> Result<Vec<String>> {
     
     // Method 2: Fallback to checking /sys/class/net for bridge interfaces
     if bridges.is_empty() {
-        let sys_output = Command::new("find")
-            .args(&["/sys/class/net", "-name", "virbr*", "-o", "-name", "br-*"])
-            .output()
-            .await;
+        let find = Invocation::new("find").args(["/sys/class/net", "-name", "virbr*", "-o", "-name", "br-*"]);
+        let sys_output = privileges.runner().output(&find).await;
         
         if let Ok(sys_output) = sys_output {
             if sys_output.status.success() {
                 let output_str = String::from_utf8_lossy(&sys_output.stdout);
                 for line in output_str.lines() {
-                    if let Some(bridge_name) = line.split('/').last() {
+                    if let Some(bridge_name) = line.split('/').next_back() {
                         bridges.push(bridge_name.to_string());
                     }
                 }
@@ -807,7 +1105,7 @@ async fn get_system_bridges() -> Result<Vec<String>> {
     
     // Method 3: Check libvirt networks for their bridges as ultimate fallback
     if bridges.is_empty() {
-        let networks = get_available_networks().await?;
+        let networks = get_available_networks(privileges).await?;
         for network in networks {
             if !bridges.contains(&network.bridge) {
                 bridges.push(network.bridge);
@@ -819,28 +1117,32 @@ async fn get_system_bridges() -> Result<Vec<String>> {
 }
 
 /// Automatically fixes network mismatches
-pub async fn auto_fix_network_mismatches(vm_name: &str, mismatches: &[NetworkMismatch]) -> Result<Vec<String>> {
+pub async fn auto_fix_network_mismatches(
+    vm_name: &str,
+    mismatches: &[NetworkMismatch],
+    privileges: &Privileges,
+) -> Result<Vec<String>> {
     let mut fixes_applied = Vec::new();
     
     for mismatch in mismatches {
         match mismatch.issue_type {
             NetworkIssueType::DuplicateMacAddress => {
-                if let Err(e) = update_vm_mac_address(vm_name, &mismatch.suggested_config.mac_address).await {
-                    eprintln!("Failed to update MAC address: {}", e);
+                if let Err(e) = update_vm_mac_address(vm_name, &mismatch.suggested_config.mac_address, privileges).await {
+                    tracing::warn!("Failed to update MAC address: {}", e);
                 } else {
                     fixes_applied.push(format!("Updated MAC address to {}", mismatch.suggested_config.mac_address));
                 }
             },
             NetworkIssueType::InactiveNetwork => {
-                if let Err(e) = start_network(&mismatch.suggested_config.network).await {
-                    eprintln!("Failed to start network {}: {}", mismatch.suggested_config.network, e);
+                if let Err(e) = start_network(&mismatch.suggested_config.network, privileges).await {
+                    tracing::warn!("Failed to start network {}: {}", mismatch.suggested_config.network, e);
                 } else {
                     fixes_applied.push(format!("Started network {}", mismatch.suggested_config.network));
                 }
             },
             NetworkIssueType::InvalidNetworkReference => {
                 if let Err(e) = update_vm_network(vm_name, &mismatch.current_config.as_ref().unwrap().network, &mismatch.suggested_config.network).await {
-                    eprintln!("Failed to update network reference: {}", e);
+                    tracing::warn!("Failed to update network reference: {}", e);
                 } else {
                     fixes_applied.push(format!("Updated network from {} to {}", 
                         mismatch.current_config.as_ref().unwrap().network, 
@@ -849,18 +1151,24 @@ pub async fn auto_fix_network_mismatches(vm_name: &str, mismatches: &[NetworkMis
             },
             NetworkIssueType::MissingBridge => {
                 // Create the missing bridge or update VM config to use existing bridge
-                if let Err(e) = update_vm_bridge(vm_name, &mismatch.current_config.as_ref().unwrap().bridge, &mismatch.suggested_config.bridge).await {
-                    eprintln!("Failed to update bridge reference: {}", e);
+                if let Err(e) = update_vm_bridge(vm_name, &mismatch.current_config.as_ref().unwrap().bridge, &mismatch.suggested_config.bridge, privileges).await {
+                    tracing::warn!("Failed to update bridge reference: {}", e);
                 } else {
                     fixes_applied.push(format!("Updated bridge from {} to {}", 
                         mismatch.current_config.as_ref().unwrap().bridge, 
                         mismatch.suggested_config.bridge));
                 }
             },
+            NetworkIssueType::MissingNatRules => {
+                match restore_nat_rules(&mismatch.suggested_config.network, &mismatch.suggested_config.bridge, privileges).await {
+                    Ok(fix) => fixes_applied.push(fix),
+                    Err(e) => tracing::warn!("Failed to restore the NAT rules of {}: {}", mismatch.suggested_config.network, e),
+                }
+            },
             NetworkIssueType::ConflictingConfiguration => {
                 // Resolve configuration conflicts by standardizing to suggested config
                 if let Err(e) = resolve_config_conflict(vm_name, mismatch).await {
-                    eprintln!("Failed to resolve configuration conflict: {}", e);
+                    tracing::warn!("Failed to resolve configuration conflict: {}", e);
                 } else {
                     fixes_applied.push(format!("Resolved configuration conflict for {}", mismatch.interface_name));
                 }
@@ -872,17 +1180,16 @@ pub async fn auto_fix_network_mismatches(vm_name: &str, mismatches: &[NetworkMis
 }
 
 /// Updates MAC address for a VM interface
-async fn update_vm_mac_address(vm_name: &str, new_mac: &str) -> Result<()> {
+async fn update_vm_mac_address(vm_name: &str, new_mac: &str, privileges: &Privileges) -> Result<()> {
     // This requires editing the VM XML configuration
     // For now, we'll use a simple sed-based approach, but in production
     // you'd want to use proper XML parsing
     
-    let output = Command::new("bash")
-        .args(&["-c", &format!(
-            "virsh dumpxml {} | sed 's/mac address=.*/mac address=\"{}\"\\/>/g' | virsh define /dev/stdin",
+    let output = privileges.runner().output(&Invocation::new("bash")
+        .args(["-c", &format!(
+            "virsh dumpxml --inactive --security-info {} | sed 's/mac address=.*/mac address=\"{}\"\\/>/g' | virsh define /dev/stdin",
             vm_name, new_mac
-        )])
-        .output()
+        )]))
         .await
         .map_err(|e| VmError::CommandError(format!("Failed to update MAC address: {}", e)))?;
     
@@ -897,10 +1204,8 @@ async fn update_vm_mac_address(vm_name: &str, new_mac: &str) -> Result<()> {
 }
 
 /// Starts a libvirt network
-async fn start_network(network_name: &str) -> Result<()> {
-    let output = Command::new("virsh")
-        .args(&["net-start", network_name])
-        .output()
+async fn start_network(network_name: &str, privileges: &Privileges) -> Result<()> {
+    let output = privileges.runner().output(&privileges.virsh_write(&["net-start", network_name])?)
         .await
         .map_err(|e| VmError::CommandError(format!("Failed to start network: {}", e)))?;
     
@@ -918,6 +1223,142 @@ async fn start_network(network_name: &str) -> Result<()> {
     Ok(())
 }
 
+/// Brings back the NAT rules of `network` that a firewall reload dropped.
+/// Reloading libvirt's network daemon makes it add the rules of every active
+/// network again without touching running guests; when that doesn't bring them
+/// back, the masquerade and forward rules libvirt would add are inserted by hand
+/// with the tool libvirt uses, leaving out those still there.
+async fn restore_nat_rules(network: &str, bridge: &str, privileges: &Privileges) -> Result<String> {
+    let (_, subnet) = nat_subnet(network, privileges).await
+        .ok_or_else(|| VmError::OperationError(format!("Network {} is not a NAT network", network)))?;
+    let restored = |rules: Option<String>| rules.is_some_and(|rules| diagnosis::masquerades(&rules, &subnet));
+    
+    // The modular daemons split the network driver out of libvirtd
+    for daemon in ["virtnetworkd", "libvirtd"] {
+        let active = privileges.runner().output(&Invocation::new("systemctl").args(["is-active", "--quiet", daemon])).await
+            .is_ok_and(|output| output.status.success());
+        if !active {
+            continue;
+        }
+        let reload = privileges.root_command("systemctl").args(["reload", daemon]);
+        let reloaded = privileges.runner().output(&reload).await.is_ok_and(|output| output.status.success());
+        if reloaded && restored(diagnosis::nat_rules(privileges).await) {
+            return Ok(format!("Reloaded {}, which restored the NAT rules of network {}", daemon, network));
+        }
+        break;
+    }
+    
+    let subnet = subnet.to_string();
+    match nat_tool(privileges).await {
+        NatTool::Iptables => insert_iptables_nat(bridge, &subnet, privileges).await?,
+        NatTool::Nftables => {
+            let nft = privileges.root_command("nft").args(["-f", "-"]).stdin(nft_nat_ruleset(bridge, &subnet));
+            let output = privileges.runner().output(&nft).await
+                .map_err(|e| VmError::CommandError(format!("Failed to run nft: {}", e)))?;
+            if !output.status.success() {
+                return Err(VmError::CommandError(format!(
+                    "nft rejected the NAT rules: {}", String::from_utf8_lossy(&output.stderr).trim()
+                )));
+            }
+        }
+    }
+    Ok(format!(
+        "Reinserted the NAT rules of network {} ({}); restart libvirtd once convenient so it owns them again",
+        network, subnet
+    ))
+}
+
+/// Which firewall tool NAT rules are restored with
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NatTool {
+    Iptables,
+    Nftables,
+}
+
+/// libvirt's nftables backend keeps its rules in tables of its own; otherwise
+/// iptables, when the host has it, is what libvirt uses
+async fn nat_tool(privileges: &Privileges) -> NatTool {
+    let tables = privileges.runner().output(&privileges.root_command("nft").args(["list", "tables"])).await;
+    if tables.is_ok_and(|output| output.status.success() && String::from_utf8_lossy(&output.stdout).contains("libvirt")) {
+        return NatTool::Nftables;
+    }
+    let iptables = privileges.runner().output(&Invocation::new("iptables").arg("--version")).await;
+    if iptables.is_ok_and(|output| output.status.success()) { NatTool::Iptables } else { NatTool::Nftables }
+}
+
+/// Adds the masquerade and forward rules libvirt would add for `subnet` on
+/// `bridge` that are missing, in one `iptables-restore` run; rules that did
+/// go in are taken out again when it fails
+async fn insert_iptables_nat(bridge: &str, subnet: &str, privileges: &Privileges) -> Result<()> {
+    let rules: [(&str, &str, Vec<&str>); 3] = [
+        ("nat", "POSTROUTING", vec!["-s", subnet, "!", "-d", subnet, "-j", "MASQUERADE"]),
+        ("filter", "FORWARD", vec!["-i", bridge, "-s", subnet, "-j", "ACCEPT"]),
+        ("filter", "FORWARD", vec!["-o", bridge, "-d", subnet, "-m", "conntrack", "--ctstate", "RELATED,ESTABLISHED", "-j", "ACCEPT"]),
+    ];
+    let iptables = |table: &str, action: &str, chain: &str, rule: &[&str]| {
+        privileges.root_command("iptables").args(["-t", table, action, chain]).args(rule)
+    };
+
+    let mut missing = Vec::new();
+    for (table, chain, rule) in &rules {
+        let check = privileges.runner().output(&iptables(table, "-C", chain, rule)).await
+            .map_err(|e| VmError::CommandError(format!("Failed to run iptables: {}", e)))?;
+        if !check.status.success() {
+            missing.push((*table, *chain, rule));
+        }
+    }
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let mut script = String::new();
+    for table in ["nat", "filter"] {
+        let lines: Vec<String> = missing.iter()
+            .filter(|(rule_table, _, _)| *rule_table == table)
+            .map(|(_, chain, rule)| {
+                // Masquerading goes last, as libvirt has it; accepts go ahead of any reject
+                let action = if *chain == "POSTROUTING" { "-A" } else { "-I" };
+                format!("{} {} {}", action, chain, rule.join(" "))
+            })
+            .collect();
+        if !lines.is_empty() {
+            script.push_str(&format!("*{}\n{}\nCOMMIT\n", table, lines.join("\n")));
+        }
+    }
+
+    let restore = privileges.root_command("iptables-restore").arg("--noflush").stdin(script);
+    let output = privileges.runner().output(&restore).await
+        .map_err(|e| VmError::CommandError(format!("Failed to run iptables-restore: {}", e)))?;
+    if !output.status.success() {
+        // Each table commits on its own, so one may have gone in; the rules were missing before
+        for (table, chain, rule) in &missing {
+            let check = privileges.runner().output(&iptables(table, "-C", chain, rule)).await;
+            if check.is_ok_and(|check| check.status.success()) {
+                let _ = privileges.runner().output(&iptables(table, "-D", chain, rule)).await;
+            }
+        }
+        return Err(VmError::CommandError(format!(
+            "Failed to insert NAT rules: {}", String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// The `nft -f` script replacing the vmtools table of `bridge` with the
+/// masquerade and forward rules libvirt would add, in a single transaction
+fn nft_nat_ruleset(bridge: &str, subnet: &str) -> String {
+    let table = format!("vmtools_nat_{}", bridge.replace(|c: char| !c.is_ascii_alphanumeric(), "_"));
+    // Declaring the table first makes the delete succeed on the first run too
+    format!(
+        "table ip {table}\ndelete table ip {table}\ntable ip {table} {{\n\
+         \tchain postrouting {{\n\t\ttype nat hook postrouting priority srcnat; policy accept;\n\
+         \t\tip saddr {subnet} ip daddr != {subnet} masquerade\n\t}}\n\
+         \tchain forward {{\n\t\ttype filter hook forward priority filter - 1; policy accept;\n\
+         \t\tiifname \"{bridge}\" ip saddr {subnet} accept\n\
+         \t\toifname \"{bridge}\" ip daddr {subnet} ct state related,established accept\n\t}}\n}}\n"
+    )
+}
+
 /// Updates VM network configuration
 async fn update_vm_network(_vm_name: &str, _old_network: &str, _new_network: &str) -> Result<()> {
     // This would require complex XML manipulation
@@ -928,33 +1369,19 @@ async fn update_vm_network(_vm_name: &str, _old_network: &str, _new_network: &st
 }
 
 /// Updates VM bridge configuration
-async fn update_vm_bridge(vm_name: &str, old_bridge: &str, new_bridge: &str) -> Result<()> {
-    // Try with regular virsh first, then with sudo if needed
-    let mut cmd = Command::new("virsh");
-    cmd.args(&["dumpxml", vm_name]);
-    
-    let output = cmd.output().await
+async fn update_vm_bridge(vm_name: &str, old_bridge: &str, new_bridge: &str, privileges: &Privileges) -> Result<()> {
+    let output = privileges.runner().output(&privileges.virsh_read(&["dumpxml", vm_name])?)
+        .await
         .map_err(|e| VmError::CommandError(format!("Failed to get VM XML: {}", e)))?;
     
-    let mut xml_content = if output.status.success() {
-        String::from_utf8_lossy(&output.stdout).to_string()
-    } else {
-        // Try with sudo
-        let sudo_output = Command::new("sudo")
-            .args(&["virsh", "dumpxml", vm_name])
-            .output()
-            .await
-            .map_err(|e| VmError::CommandError(format!("Failed to get VM XML with sudo: {}", e)))?;
-        
-        if !sudo_output.status.success() {
-            return Err(VmError::CommandError(format!(
-                "Failed to get VM XML: {}", 
-                String::from_utf8_lossy(&sudo_output.stderr)
-            )));
-        }
-        
-        String::from_utf8_lossy(&sudo_output.stdout).to_string()
-    };
+    if !output.status.success() {
+        return Err(VmError::CommandError(format!(
+            "Failed to get VM XML: {}", 
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+    
+    let mut xml_content = String::from_utf8_lossy(&output.stdout).to_string();
     
     // Simple bridge name replacement
     #[allow(unused_assignments)]
@@ -997,4 +1424,42 @@ async fn resolve_config_conflict(vm_name: &str, mismatch: &NetworkMismatch) -> R
     }
     
     Ok(())
-}
\ No newline at end of file
+}
+/// Host setup problems that would stop a TLS-only SPICE/VNC console from working.
+/// `kind` is "spice" or "vnc"; `x509_dir` overrides libvirt's default certificate directory.
+pub fn graphics_tls_problems(kind: &str, x509_dir: Option<&Path>) -> Vec<String> {
+    let mut problems = Vec::new();
+    let default_dir = PathBuf::from(format!("/etc/pki/libvirt-{}", kind));
+    let dir = x509_dir.unwrap_or(&default_dir);
+
+    for file in ["ca-cert.pem", "server-cert.pem", "server-key.pem"] {
+        if !dir.join(file).exists() {
+            problems.push(format!("{} is missing", dir.join(file).display()));
+        }
+    }
+
+    // TLS and the certificate directory are host-wide settings in qemu.conf
+    let Ok(qemu_conf) = std::fs::read_to_string("/etc/libvirt/qemu.conf") else {
+        problems.push(format!("Cannot read /etc/libvirt/qemu.conf to check that {}_tls = 1 is set", kind));
+        return problems;
+    };
+    let setting = |key: &str| qemu_conf.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.starts_with('#'))
+        .find_map(|line| {
+            let (name, value) = line.split_once('=')?;
+            (name.trim() == key).then(|| value.trim().trim_matches('"').to_string())
+        });
+
+    if setting(&format!("{}_tls", kind)).as_deref() != Some("1") {
+        problems.push(format!("Set {}_tls = 1 in /etc/libvirt/qemu.conf and restart libvirtd", kind));
+    }
+    if x509_dir.is_some() {
+        let key = format!("{}_tls_x509_cert_dir", kind);
+        if setting(&key).map(PathBuf::from).as_deref() != Some(dir) {
+            problems.push(format!("Set {} = \"{}\" in /etc/libvirt/qemu.conf", key, dir.display()));
+        }
+    }
+
+    problems
+}
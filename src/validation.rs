@@ -0,0 +1,236 @@
+use std::fmt;
+use std::path::Path;
+
+use crate::config::Config;
+
+/// A problem found in a config file, pointed at the key and line it concerns
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigIssue {
+    /// Dotted key, e.g. `defaults.memory`; empty for syntax errors
+    pub key: String,
+    pub line: Option<usize>,
+    /// The offending line as written
+    pub source: Option<String>,
+    pub message: String,
+    pub suggestion: Option<String>,
+}
+
+impl ConfigIssue {
+    /// An issue with `key`, found on its line in `content` when it is written there
+    pub fn new(content: &str, key: &str, message: impl Into<String>) -> Self {
+        // A missing key points at the table it belongs in
+        let line = std::iter::successors(Some(key), |key| key.rsplit_once('.').map(|(parent, _)| parent))
+            .find_map(|key| locate(content, key));
+        Self {
+            key: key.to_string(),
+            line,
+            source: line.and_then(|line| source_line(content, line)),
+            message: message.into(),
+            suggestion: None,
+        }
+    }
+
+    pub fn with_suggestion(mut self, suggestion: impl Into<String>) -> Self {
+        self.suggestion = Some(suggestion.into());
+        self
+    }
+
+    /// The issue behind a failed parse: a syntax error carries its position, a
+    /// type mismatch or missing field the key it concerns
+    pub fn from_toml(content: &str, error: &toml::de::Error) -> Self {
+        if let Some(span) = error.span() {
+            let line = content[..span.start.min(content.len())].matches('\n').count() + 1;
+            return Self {
+                key: String::new(),
+                line: Some(line),
+                source: source_line(content, line),
+                message: error.message().trim().replace('\n', "; "),
+                suggestion: None,
+            };
+        }
+
+        // Errors from deserializing a value end with "in `key`" instead of a position
+        let text = error.to_string();
+        let (message, key) = match text.rsplit_once("\nin `") {
+            Some((message, key)) => (message.trim().to_string(), key.trim_end().trim_end_matches('`').to_string()),
+            None => (text.trim().to_string(), String::new()),
+        };
+        let key = match message.strip_prefix("missing field `").and_then(|rest| rest.split_once('`')) {
+            Some((field, _)) if !key.is_empty() => format!("{}.{}", key, field),
+            _ => key,
+        };
+        let suggestion = suggest(&message, &key);
+        let issue = Self::new(content, &key, message);
+        match suggestion {
+            Some(suggestion) => issue.with_suggestion(suggestion),
+            None => issue,
+        }
+    }
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.line, self.key.is_empty()) {
+            (Some(line), false) => write!(f, "line {}, `{}`: {}", line, self.key, self.message)?,
+            (Some(line), true) => write!(f, "line {}: {}", line, self.message)?,
+            (None, false) => write!(f, "`{}`: {}", self.key, self.message)?,
+            (None, true) => write!(f, "{}", self.message)?,
+        }
+        if let Some(source) = &self.source {
+            write!(f, "\n    {}", source)?;
+        }
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "\n    hint: {}", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+fn source_line(content: &str, line: usize) -> Option<String> {
+    content.lines().nth(line.checked_sub(1)?).map(|text| text.trim().to_string())
+}
+
+/// The line `key` is set on, or the header of the table it names
+fn locate(content: &str, key: &str) -> Option<usize> {
+    if key.is_empty() {
+        return None;
+    }
+    let mut table = String::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if let Some(header) = line.strip_prefix('[') {
+            table = header.trim_start_matches('[').split(']').next().unwrap_or_default()
+                .split('.').map(|part| part.trim().trim_matches('"')).collect::<Vec<_>>().join(".");
+            if table == key {
+                return Some(index + 1);
+            }
+            continue;
+        }
+        let Some((name, _)) = line.split_once('=') else { continue };
+        let name = name.trim().trim_matches('"');
+        let path = if table.is_empty() { name.to_string() } else { format!("{}.{}", table, name) };
+        if path == key {
+            return Some(index + 1);
+        }
+    }
+    None
+}
+
+/// How to fix a value serde rejected
+fn suggest(message: &str, key: &str) -> Option<String> {
+    let field = key.rsplit('.').next().unwrap_or(key);
+    if message.starts_with("missing field") {
+        return Some(format!("add `{} = ...` to this table", field));
+    }
+    if let Some((_, expected)) = message.split_once(", expected one of ") {
+        return Some(format!("use one of {}", expected));
+    }
+    let expected = message.rsplit_once(", expected ").map(|(_, expected)| expected)?;
+    let hint = match expected {
+        "u8" | "u16" | "u32" | "u64" | "i32" | "i64" => "write a whole number without quotes, e.g. 2048",
+        "f32" | "f64" => "write a number without quotes",
+        "a boolean" => "use true or false without quotes",
+        "a string" => "put the value in double quotes",
+        "a sequence" => "use a list, e.g. [\"a\", \"b\"]",
+        _ => return None,
+    };
+    Some(hint.to_string())
+}
+
+/// Keys in `raw` (the file as written) that no setting reads, with the closest
+/// known key as a suggestion. `known` are serialized configs whose keys count as valid.
+pub fn unknown_keys(content: &str, raw: &toml::Table, known: &[&toml::Table]) -> Vec<ConfigIssue> {
+    let mut issues = Vec::new();
+    collect_unknown(content, "", raw, known, &mut issues);
+    issues
+}
+
+fn collect_unknown(content: &str, prefix: &str, raw: &toml::Table, known: &[&toml::Table], issues: &mut Vec<ConfigIssue>) {
+    for (name, value) in raw {
+        let key = if prefix.is_empty() { name.clone() } else { format!("{}.{}", prefix, name) };
+        let matches: Vec<&toml::Value> = known.iter().filter_map(|table| table.get(name)).collect();
+        if matches.is_empty() {
+            let candidates = known.iter().flat_map(|table| table.keys()).map(String::as_str);
+            let issue = ConfigIssue::new(content, &key, "unknown key, ignored");
+            issues.push(match closest(name, candidates) {
+                Some(candidate) => issue.with_suggestion(format!("did you mean `{}`?", candidate)),
+                None => issue,
+            });
+            continue;
+        }
+        if let toml::Value::Table(table) = value {
+            let nested: Vec<&toml::Table> = matches.iter().filter_map(|value| value.as_table()).collect();
+            if !nested.is_empty() {
+                collect_unknown(content, &key, table, &nested, issues);
+            }
+        }
+    }
+}
+
+/// The candidate within a small edit distance of `name`
+pub fn closest<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let limit = (name.len() / 3).max(1);
+    candidates
+        .map(|candidate| (distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            current.push((previous[j] + cost).min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Paths that cannot be what their setting needs: relative directories, files
+/// where a directory belongs, and files that a template or the console refers to
+/// but that don't exist
+pub fn path_issues(content: &str, config: &Config) -> Vec<ConfigIssue> {
+    let mut issues = Vec::new();
+    let directories = [
+        ("storage.vm_images_path", &config.storage.vm_images_path),
+        ("storage.iso_path", &config.storage.iso_path),
+        ("storage.backup_path", &config.storage.backup_path),
+        ("system.temp_dir", &config.system.temp_dir),
+        ("backend.qemu_state_dir", &config.backend.qemu_state_dir),
+    ];
+    for (key, path) in directories {
+        if path.is_relative() {
+            issues.push(ConfigIssue::new(content, key, format!("{} is a relative path", path.display()))
+                .with_suggestion("use an absolute path"));
+        } else if path.exists() && !path.is_dir() {
+            issues.push(ConfigIssue::new(content, key, format!("{} is not a directory", path.display())));
+        }
+    }
+    if let Some(dir) = &config.graphics.x509_dir {
+        if !dir.is_dir() {
+            issues.push(ConfigIssue::new(content, "graphics.x509_dir", format!("{} does not exist", dir.display()))
+                .with_suggestion("point it at the directory holding ca-cert.pem, server-cert.pem and server-key.pem"));
+        }
+    }
+
+    let mut templates: Vec<_> = config.templates.iter().collect();
+    templates.sort_by_key(|(name, _)| name.as_str());
+    for (name, template) in templates {
+        let Ok(Some(cloud_init)) = template.cloud_init() else { continue };
+        let files = [("user_data_path", cloud_init.user_data), ("network_config", cloud_init.network_config)];
+        for (field, path) in files {
+            if let Some(path) = path.filter(|path| !Path::new(path).is_file()) {
+                issues.push(ConfigIssue::new(
+                    content, &format!("templates.{}.{}", name, field), format!("{} does not exist", path.display()),
+                ).with_suggestion("relative paths are taken from ~/.config/vmtools"));
+            }
+        }
+    }
+    issues
+}
@@ -0,0 +1,98 @@
+use base64::Engine;
+use std::path::PathBuf;
+
+use crate::{
+    error::{VmError, Result},
+    runner::{CommandRunner, Invocation},
+};
+
+/// Prefix of a value encrypted to the local age identity, base64 after it
+pub const AGE_PREFIX: &str = "age:";
+/// Prefix of a value kept in the desktop keyring, the entry name after it
+pub const KEYRING_PREFIX: &str = "keyring:";
+
+/// Whether a config value is a reference to an encrypted secret rather than the value
+pub fn is_sealed(value: &str) -> bool {
+    value.starts_with(AGE_PREFIX) || value.starts_with(KEYRING_PREFIX)
+}
+
+/// The age identity config values are encrypted to; created on first use
+pub fn identity_path() -> Result<PathBuf> {
+    let config_dir = dirs::config_dir()
+        .ok_or_else(|| VmError::ConfigError("Cannot determine config directory".to_string()))?;
+    Ok(config_dir.join("vmtools").join("age.key"))
+}
+
+/// Encrypts `plain` to the local age identity
+pub async fn seal_age(runner: &dyn CommandRunner, plain: &str) -> Result<String> {
+    let identity = identity_path()?;
+    if !identity.exists() {
+        if let Some(parent) = identity.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // age-keygen creates the file readable by its owner only
+        run(runner, Invocation::new("age-keygen").arg("-o").arg(&identity)).await?;
+    }
+    let recipient = run(runner, Invocation::new("age-keygen").arg("-y").arg(&identity)).await?;
+    let recipient = String::from_utf8_lossy(&recipient).trim().to_string();
+
+    let ciphertext = run(runner, Invocation::new("age").args(["--encrypt", "--recipient", &recipient]).stdin(plain)).await?;
+    Ok(format!("{}{}", AGE_PREFIX, base64::engine::general_purpose::STANDARD.encode(ciphertext)))
+}
+
+/// Stores `plain` in the keyring through the Secret Service as entry `name`
+pub async fn seal_keyring(runner: &dyn CommandRunner, name: &str, plain: &str) -> Result<String> {
+    validate_entry(name)?;
+    run(
+        runner,
+        Invocation::new("secret-tool")
+            .args(["store", "--label", &format!("vmtools {}", name), "service", "vmtools", "key", name])
+            .stdin(plain),
+    ).await?;
+    Ok(format!("{}{}", KEYRING_PREFIX, name))
+}
+
+/// The value a config entry stands for; values that aren't sealed come back unchanged
+pub async fn unseal(runner: &dyn CommandRunner, value: &str) -> Result<String> {
+    let plain = if let Some(encoded) = value.strip_prefix(AGE_PREFIX) {
+        let ciphertext = base64::engine::general_purpose::STANDARD.decode(encoded.trim())
+            .map_err(|e| VmError::ConfigError(format!("Corrupt age value: {}", e)))?;
+        let identity = identity_path()?;
+        run(runner, Invocation::new("age").arg("--decrypt").arg("--identity").arg(&identity).stdin(ciphertext)).await?
+    } else if let Some(name) = value.strip_prefix(KEYRING_PREFIX) {
+        validate_entry(name)?;
+        let plain = run(runner, Invocation::new("secret-tool").args(["lookup", "service", "vmtools", "key", name])).await?;
+        if plain.is_empty() {
+            return Err(VmError::ConfigError(format!("No keyring entry '{}'", name)));
+        }
+        plain
+    } else {
+        return Ok(value.to_string());
+    };
+    String::from_utf8(plain).map_err(|_| VmError::ConfigError("Decrypted value is not UTF-8".to_string()))
+}
+
+fn validate_entry(name: &str) -> Result<()> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')) {
+        return Err(VmError::InvalidInput(format!("Invalid keyring entry name '{}'", name)));
+    }
+    Ok(())
+}
+
+/// Runs a secret helper and returns its stdout, which never reaches the debug trace
+async fn run(runner: &dyn CommandRunner, invocation: Invocation) -> Result<Vec<u8>> {
+    let invocation = invocation.sensitive();
+    let program = invocation.program.as_str();
+    let output = runner.output(&invocation).await.map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => VmError::ConfigError(format!(
+            "{} not found; install {}", program, if program == "secret-tool" { "libsecret-tools" } else { "age" }
+        )),
+        _ => VmError::ConfigError(format!("Failed to execute {}: {}", program, e)),
+    })?;
+    if !output.status.success() {
+        return Err(VmError::ConfigError(format!(
+            "{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
@@ -0,0 +1,175 @@
+//! Parsers for what `virsh` prints.
+//!
+//! Every command vmtools spawns runs with `LC_ALL=C` (see `Invocation::to_command`),
+//! so labels and states arrive untranslated. Where virsh has a machine-readable
+//! form (`--name`, the XML dumps) that is used instead of its tables.
+
+use std::net::IpAddr;
+
+use crate::{
+    domain,
+    vm::{DiskInfo, NetworkInfo, SnapshotInfo, VmState},
+};
+
+/// What `virsh dominfo` reports of a domain
+#[derive(Debug, Clone, PartialEq)]
+pub struct DomInfo {
+    pub uuid: String,
+    pub state: VmState,
+    /// Max memory in KiB
+    pub memory: u64,
+    pub cpus: u32,
+    pub autostart: bool,
+}
+
+/// A domain state as `domstate`, `dominfo` and `list` print it
+pub fn parse_state(state: &str) -> VmState {
+    match state.trim() {
+        "running" | "idle" | "blocked" => VmState::Running,
+        // A guest shutting down is treated as gone
+        "shut off" | "shutoff" | "in shutdown" | "crashed" => VmState::Stopped,
+        "paused" => VmState::Paused,
+        "pmsuspended" | "suspended" => VmState::Suspended,
+        _ => VmState::Unknown,
+    }
+}
+
+/// One name per line, as the `--name` forms of the list commands print them.
+/// Names are taken whole, spaces included; virsh ends the list with a blank line.
+pub fn parse_names(output: &str) -> Vec<String> {
+    output.lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|name| !name.trim().is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// The value of `key` in `Key:   value` output (`dominfo`, `net-info`, `pool-info`)
+pub fn parse_field<'a>(output: &'a str, key: &str) -> Option<&'a str> {
+    output.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        (name.trim() == key).then(|| value.trim())
+    })
+}
+
+/// The number of bytes in a field of `pool-info --bytes` or `vol-info --bytes`
+pub fn parse_bytes(output: &str, key: &str) -> u64 {
+    parse_field(output, key)
+        .and_then(|value| value.split_whitespace().next())
+        .and_then(|bytes| bytes.parse().ok())
+        .unwrap_or(0)
+}
+
+/// The body rows of a virsh table, after the header and the rule of dashes
+pub fn table_rows(output: &str) -> impl Iterator<Item = &str> {
+    output.lines()
+        .skip_while(|line| !line.trim_start().starts_with("---"))
+        .skip(1)
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+}
+
+pub fn parse_dominfo(output: &str) -> DomInfo {
+    DomInfo {
+        uuid: parse_field(output, "UUID").unwrap_or_default().to_string(),
+        state: parse_field(output, "State").map_or(VmState::Unknown, parse_state),
+        memory: parse_bytes(output, "Max memory"),
+        cpus: parse_field(output, "CPU(s)").and_then(|cpus| cpus.parse().ok()).unwrap_or(0),
+        autostart: parse_field(output, "Autostart") == Some("enable"),
+    }
+}
+
+/// The image files of a domain's disks, from its XML. CD-ROM media isn't VM
+/// storage, and network disks (RBD), pool volumes and block devices are no files
+/// vmtools may copy, move or remove, so they are left out.
+pub fn parse_disks(xml: &str) -> Vec<DiskInfo> {
+    domain::DomainSpec::parse(xml)
+        .map(|spec| spec.disks)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|disk| DiskInfo {
+            device: disk.target,
+            path: disk.path,
+            size: 0,
+            used: 0,
+            format: disk.format,
+        })
+        .collect()
+}
+
+/// A domain's network interfaces, from its XML; the host-side device (`vnet0`)
+/// only shows in the live XML of a running domain
+pub fn parse_interfaces(xml: &str) -> Vec<NetworkInfo> {
+    xml.split("<interface ").skip(1)
+        .filter_map(|block| {
+            let block = &block[..block.find("</interface>").unwrap_or(block.len())];
+            let source = block.find("<source ").map_or("", |pos| &block[pos..]);
+            let bridge = domain::attribute(source, "bridge='");
+            Some(NetworkInfo {
+                interface: domain::attribute(block, "<target dev='").unwrap_or_else(|| "-".to_string()),
+                network: domain::attribute(source, "network='")
+                    .or_else(|| bridge.clone())
+                    .unwrap_or_else(|| "-".to_string()),
+                mac_address: domain::attribute(block, "<mac address='")?,
+                ip_address: None,
+                ipv6_address: None,
+                bridge: bridge.unwrap_or_else(|| "-".to_string()),
+            })
+        })
+        .collect()
+}
+
+/// Fills in the guest addresses `domifaddr` reports, matching rows to `interfaces`
+/// by MAC. Only the first IPv4 and the first IPv6 address of an interface are
+/// kept; link-local IPv6 addresses are skipped, every interface has one.
+pub fn assign_addresses(interfaces: &mut [NetworkInfo], output: &str) {
+    let mut mac = String::new();
+    for row in table_rows(output) {
+        // Further addresses of an interface show "-" for its name and MAC
+        let (row_mac, address) = match row.split_whitespace().collect::<Vec<_>>()[..] {
+            [_, row_mac, _, address] => (row_mac, address),
+            _ => continue,
+        };
+        if row_mac != "-" {
+            mac = row_mac.to_string();
+        }
+        let Some(net) = interfaces.iter_mut().find(|net| net.mac_address.eq_ignore_ascii_case(&mac)) else {
+            continue;
+        };
+        let address = address.split('/').next().unwrap_or(address);
+        match address.parse::<IpAddr>() {
+            Ok(IpAddr::V4(_)) if net.ip_address.is_none() => net.ip_address = Some(address.to_string()),
+            Ok(IpAddr::V6(v6)) if net.ipv6_address.is_none() && !v6.is_unicast_link_local() => {
+                net.ipv6_address = Some(address.to_string());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// `snapshot-list` rows: name, creation time (date, time, zone) and state.
+/// Snapshot names never hold spaces, vmtools refuses them.
+pub fn parse_snapshot_list(output: &str, current: &str) -> Vec<SnapshotInfo> {
+    table_rows(output)
+        .filter_map(|row| {
+            let parts: Vec<&str> = row.split_whitespace().collect();
+            let (state, rest) = parts.split_last()?;
+            let (name, created) = rest.split_first()?;
+            (!created.is_empty()).then(|| SnapshotInfo {
+                name: name.to_string(),
+                created: created.join(" "),
+                state: parse_state(state),
+                current: *name == current,
+                description: None,
+            })
+        })
+        .collect()
+}
+
+/// `vol-list` rows as (name, path); paths may hold spaces, names don't
+pub fn parse_vol_list(output: &str) -> Vec<(String, String)> {
+    table_rows(output)
+        .filter_map(|row| row.split_once(char::is_whitespace))
+        .map(|(name, path)| (name.to_string(), path.trim().to_string()))
+        .collect()
+}
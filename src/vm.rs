@@ -1,15 +1,79 @@
 use serde::{Deserialize, Serialize};
+use base64::Engine;
 use colored::*;
 use tokio::time::{sleep, Duration};
 use indicatif::{ProgressBar, ProgressStyle};
+use std::sync::Arc;
 
 use crate::{
-    config::{Config, VmTemplate},
+    arch::{self, ArchProfile, Firmware},
+    capabilities::HostCapabilities,
+    config::{AudioBackend, BackendKind, Config, ConsoleConfig, CpuModel, DesktopConfig, GraphicsConfig, NetworkMode, VmTemplate},
+    diagnosis::{self, DiagnosisStep},
+    domain::{self, Bandwidth, CpuTune, DomainSpec, KernelBoot, SpecDifference, VmMetadata},
+    manifest::{DesiredState, Manifest, ManifestChange, VmManifest},
+    network::{self, Ipv6Subnet},
+    notify::{Notice, Notifier},
+    optimize::{self, HostResources, Optimization, Profile},
+    osinfo::{self, OsVariant, VirtioSupport},
     error::{VmError, Result},
+    events::{EventKind, EventStream},
+    firewall::{self, FirewallRule, PortForward},
+    health::{self, HealthCheck, HealthResult},
+    host,
+    idle::IdleTracker,
+    image::Customization,
+    backend::{Backend, ConsoleOptions},
     libvirt::LibvirtClient,
+    privilege::{AccessLevel, Privileges},
+    qemu_backend::QemuBackend,
+    retention,
+    runner::{CommandRunner, SystemRunner},
+    secret::{SecretInfo, SecretUsage},
+    stats::{DomainStats, MetricsLog, MonitorSample, VmUsage},
+    storage::{self, DiskSource, PoolInfo, RbdImage, SecurityModule, VolumeInfo},
+    systemd,
+    terminal,
+    trash::{Trash, TrashEntry, TrashedVolume},
+    vault,
     utils,
+    webhook::{WebhookDispatcher, WebhookPayload},
 };
 
+/// Per-VM device choices for generated domain definitions
+struct DeviceOptions<'a> {
+    iso: Option<&'a str>,
+    network: &'a str,
+    desktop: DesktopConfig,
+    graphics: GraphicsConfig,
+    disk_bus: DiskBus,
+    /// virtio-net queue pairs
+    net_queues: u32,
+    cpu_model: Option<CpuModel>,
+}
+
+/// VNC authentication only uses the first 8 characters of a password
+const VNC_PASSWORD_LENGTH: usize = 8;
+const SPICE_PASSWORD_LENGTH: usize = 16;
+
+/// Devices libvirt accepts in `<boot dev='...'/>`
+const BOOT_DEVICES: &[&str] = &["hd", "cdrom", "network", "fd"];
+
+/// How often `vmtools daemon` samples VM usage and checks snapshot policies
+const DAEMON_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Name the network diagnosis has the guest look up
+const DIAGNOSIS_HOST: &str = "example.com";
+
+/// How long `status --all` samples the running VMs' CPU time
+const SUMMARY_SAMPLE: Duration = Duration::from_secs(1);
+
+/// Pools this full show up as an issue in `status --all`
+const POOL_FULL_PERCENT: u64 = 90;
+
+/// How long a console session waits for its VM to come back after it went down
+const CONSOLE_RECONNECT_WAIT: Duration = Duration::from_secs(60);
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum VmState {
     Running,
@@ -19,16 +83,164 @@ pub enum VmState {
     Unknown,
 }
 
+impl VmState {
+    pub fn label(&self) -> &'static str {
+        match self {
+            VmState::Running => "RUNNING",
+            VmState::Stopped => "STOPPED",
+            VmState::Paused => "PAUSED",
+            VmState::Suspended => "SUSPENDED",
+            VmState::Unknown => "UNKNOWN",
+        }
+    }
+
+    /// Colors arbitrary text (e.g. a padded table cell) with this state's color
+    pub fn paint(&self, text: &str) -> ColoredString {
+        match self {
+            VmState::Running => text.green(),
+            VmState::Stopped => text.red(),
+            VmState::Paused => text.yellow(),
+            VmState::Suspended => text.blue(),
+            VmState::Unknown => text.bright_black(),
+        }
+    }
+}
+
 impl std::fmt::Display for VmState {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let state_str = match self {
-            VmState::Running => "RUNNING".green(),
-            VmState::Stopped => "STOPPED".red(),
-            VmState::Paused => "PAUSED".yellow(),
-            VmState::Suspended => "SUSPENDED".blue(),
-            VmState::Unknown => "UNKNOWN".bright_black(),
-        };
-        write!(f, "{}", state_str)
+        write!(f, "{}", self.paint(self.label()))
+    }
+}
+
+impl std::str::FromStr for VmState {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "running" => Ok(VmState::Running),
+            "stopped" | "shutoff" | "shut-off" => Ok(VmState::Stopped),
+            "paused" => Ok(VmState::Paused),
+            "suspended" => Ok(VmState::Suspended),
+            _ => Err(format!("Invalid state '{}'. Use running, stopped, paused or suspended", s)),
+        }
+    }
+}
+
+/// Settings for `vmtools create`
+#[derive(Debug, Clone)]
+pub struct CreateOptions {
+    /// Memory in MB (ignored when a template is used)
+    pub memory: u64,
+    pub cpus: u32,
+    /// Disk size in GB
+    pub disk_size: u64,
+    pub iso_path: Option<String>,
+    /// Guest OS; `None` detects it from `iso_path` when possible
+    pub os_variant: Option<OsVariant>,
+    pub template: Option<String>,
+    /// Guest architecture, overriding the template's
+    pub arch: Option<String>,
+    /// Run under TCG emulation instead of KVM
+    pub emulated: bool,
+    /// USB redirection channels; `None` uses the `[desktop]` config
+    pub usb_redirect: Option<u32>,
+    /// Audio backend; `None` uses the `[desktop]` config
+    pub audio: Option<AudioBackend>,
+    /// Console listen address; `None` uses the `[graphics]` config
+    pub listen: Option<String>,
+    /// Require TLS for the console (also implied by `tls_port`)
+    pub tls: bool,
+    pub tls_port: Option<u16>,
+    pub x509_dir: Option<std::path::PathBuf>,
+    pub disk_bus: DiskBus,
+    /// virtio-net queue pairs; `None` gives one per vCPU
+    pub net_queues: Option<u32>,
+    /// Disk I/O threads; `None` keeps the template's
+    pub iothreads: Option<u32>,
+    /// Networks to attach, the first being the primary; empty picks the default network
+    pub networks: Vec<String>,
+    /// Image (qcow2, raw, ...) imported as the system disk instead of creating a blank one
+    pub base_image: Option<std::path::PathBuf>,
+    /// Back the system disk by `base_image` instead of copying it
+    pub link_base_image: bool,
+    /// `None` uses `defaults.cpu_model`, or the architecture's model when emulated
+    pub cpu_model: Option<CpuModel>,
+    /// Existing storage to run from (e.g. an RBD image) instead of a new qcow2 file
+    pub disk: Option<DiskSource>,
+    /// Boot this kernel directly instead of the disk's bootloader
+    pub kernel_boot: Option<KernelBoot>,
+}
+
+impl Default for CreateOptions {
+    fn default() -> Self {
+        Self {
+            memory: 2048,
+            cpus: 2,
+            disk_size: 20,
+            iso_path: None,
+            os_variant: None,
+            template: None,
+            arch: None,
+            emulated: false,
+            usb_redirect: None,
+            audio: None,
+            listen: None,
+            tls: false,
+            tls_port: None,
+            x509_dir: None,
+            disk_bus: DiskBus::Virtio,
+            net_queues: None,
+            iothreads: None,
+            networks: Vec::new(),
+            base_image: None,
+            link_base_image: false,
+            cpu_model: None,
+            disk: None,
+            kernel_boot: None,
+        }
+    }
+}
+
+/// What `vmtools create` builds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DomainKind {
+    /// A full virtual machine with qcow2 disks
+    Vm,
+    /// An LXC container running from a root filesystem directory
+    Container,
+}
+
+impl std::str::FromStr for DomainKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "vm" => Ok(DomainKind::Vm),
+            "container" => Ok(DomainKind::Container),
+            _ => Err(format!("Invalid type '{}'. Use vm or container", s)),
+        }
+    }
+}
+
+/// Bus the system disk of a new VM is attached to
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DiskBus {
+    /// One virtio-blk PCI device per disk
+    #[default]
+    Virtio,
+    /// Disks behind a multiqueue virtio-scsi controller; scales to many disks and hotplug
+    VirtioScsi,
+}
+
+impl std::str::FromStr for DiskBus {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "virtio" | "virtio-blk" => Ok(DiskBus::Virtio),
+            "virtio-scsi" | "scsi" => Ok(DiskBus::VirtioScsi),
+            _ => Err(format!("Invalid disk bus '{}'. Use virtio or virtio-scsi", s)),
+        }
     }
 }
 
@@ -46,6 +258,96 @@ pub struct VmInfo {
     pub network_info: Vec<NetworkInfo>,
     pub created_at: u64,
     pub last_started: Option<u64>,
+    #[serde(default)]
+    pub autostart: bool,
+    /// Results of the VM's health checks; only filled in when asked for
+    #[serde(default)]
+    pub health: Vec<HealthResult>,
+    /// What the guest agent reports, for a running VM whose agent answered `status`
+    #[serde(default)]
+    pub guest: Option<GuestInfo>,
+    /// SPICE and VNC consoles; only filled in by `status`
+    #[serde(default)]
+    pub graphics: Vec<GraphicsInfo>,
+    /// Number of snapshots; only filled in when asked for
+    #[serde(default)]
+    pub snapshot_count: Option<usize>,
+    /// When the newest file in the VM's backup directory was written; only filled in when asked for
+    #[serde(default)]
+    pub last_backup: Option<u64>,
+}
+
+/// A SPICE or VNC console and how to reach it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GraphicsInfo {
+    /// `spice` or `vnc`
+    pub kind: String,
+    pub listen: Option<String>,
+    /// Picked when the VM starts with autoport, so `None` while it is stopped
+    pub port: Option<u16>,
+    pub tls_port: Option<u16>,
+    /// Whether a viewer is attached; `None` when QEMU could not be asked
+    pub connected: Option<bool>,
+}
+
+impl GraphicsInfo {
+    /// From a `<graphics>` element of the live definition
+    pub fn parse(element: &str) -> Option<Self> {
+        let open = &element[..element.find('>')?];
+        let port = |prefix: &str| domain::attribute(open, prefix).and_then(|port| port.parse().ok());
+        Some(Self {
+            kind: domain::attribute(open, "type='")?,
+            listen: domain::attribute(open, " listen='").or_else(|| domain::attribute(element, " address='")),
+            port: port(" port='"),
+            tls_port: port(" tlsPort='"),
+            connected: None,
+        })
+    }
+
+    /// Where a viewer connects, e.g. `spice://127.0.0.1:5900`
+    pub fn uri(&self) -> Option<String> {
+        let port = self.port.or(self.tls_port)?;
+        Some(format!("{}://{}:{}", self.kind, self.listen.as_deref().unwrap_or("localhost"), port))
+    }
+}
+
+/// The guest OS as its QEMU guest agent describes it
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GuestInfo {
+    pub agent_version: String,
+    /// Such as "Ubuntu 24.04 LTS"
+    pub os_name: Option<String>,
+    /// Kernel release and machine, such as "6.8.0-31-generic (x86_64)"
+    pub kernel: Option<String>,
+}
+
+impl GuestInfo {
+    /// From the replies to `guest-info` and `guest-get-osinfo`, which agents
+    /// older than 2.10 don't have
+    pub fn from_replies(info: &serde_json::Value, osinfo: Option<&serde_json::Value>) -> Self {
+        let text = |value: &serde_json::Value, key: &str| {
+            value.get(key).and_then(|v| v.as_str()).filter(|v| !v.is_empty()).map(String::from)
+        };
+        let os_name = osinfo.and_then(|os| text(os, "pretty-name").or_else(|| {
+            let name = text(os, "name")?;
+            Some(match text(os, "version") {
+                Some(version) => format!("{} {}", name, version),
+                None => name,
+            })
+        }));
+        let kernel = osinfo.and_then(|os| {
+            let release = text(os, "kernel-release")?;
+            Some(match text(os, "machine") {
+                Some(machine) => format!("{} ({})", release, machine),
+                None => release,
+            })
+        });
+        Self {
+            agent_version: text(info, "version").unwrap_or_else(|| "unknown".to_string()),
+            os_name,
+            kernel,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -62,477 +364,4449 @@ pub struct NetworkInfo {
     pub interface: String,
     pub network: String,
     pub mac_address: String,
+    /// IPv4 address of a running guest, from its DHCP lease or guest agent
     pub ip_address: Option<String>,
+    /// Global or unique-local IPv6 address, found the same way
+    #[serde(default)]
+    pub ipv6_address: Option<String>,
     pub bridge: String,
 }
 
+/// A point-in-time snapshot of a VM
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotInfo {
+    pub name: String,
+    /// Creation time as reported by the backend
+    pub created: String,
+    /// VM state captured in the snapshot
+    pub state: VmState,
+    /// Whether this is the snapshot the VM currently runs from
+    pub current: bool,
+    pub description: Option<String>,
+}
+
+/// Sort key for `vmtools list`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListSort {
+    Name,
+    Memory,
+    Cpus,
+    Uptime,
+    Created,
+    LastStarted,
+}
+
+impl std::str::FromStr for ListSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "name" => Ok(ListSort::Name),
+            "memory" | "mem" => Ok(ListSort::Memory),
+            "cpus" | "cpu" => Ok(ListSort::Cpus),
+            "uptime" => Ok(ListSort::Uptime),
+            "created" => Ok(ListSort::Created),
+            "started" | "last-started" => Ok(ListSort::LastStarted),
+            _ => Err(format!("Invalid sort key '{}'. Use name, memory, cpus, uptime, created or started", s)),
+        }
+    }
+}
+
+/// Sort key for `vmtools top`; each sorts busiest first
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum TopSort {
+    #[default]
+    Cpu,
+    Memory,
+    Disk,
+    Net,
+}
+
+impl std::str::FromStr for TopSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "cpu" => Ok(TopSort::Cpu),
+            "memory" | "mem" => Ok(TopSort::Memory),
+            "disk" | "io" => Ok(TopSort::Disk),
+            "net" | "network" => Ok(TopSort::Net),
+            _ => Err(format!("Invalid sort key '{}'. Use cpu, memory, disk or net", s)),
+        }
+    }
+}
+
+/// What a key does in `vmtools monitor`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MonitorKey {
+    /// `q`, Esc or Ctrl+C
+    Quit,
+    /// `p`: pauses a running VM, resumes a paused one
+    TogglePause,
+    /// `s`: takes a disk snapshot named after the time
+    Snapshot,
+}
+
+impl MonitorKey {
+    pub fn from_key(key: u8) -> Option<Self> {
+        match key {
+            b'q' | b'Q' | 0x1b | 0x03 => Some(MonitorKey::Quit),
+            b'p' | b'P' => Some(MonitorKey::TogglePause),
+            b's' | b'S' => Some(MonitorKey::Snapshot),
+            _ => None,
+        }
+    }
+}
+
+/// Name filter for `vmtools list`, written as `name~substring` or `name=exact`
+#[derive(Debug, Clone)]
+pub struct ListFilter {
+    pub pattern: String,
+    pub exact: bool,
+}
+
+impl ListFilter {
+    pub fn matches(&self, vm: &VmInfo) -> bool {
+        if self.exact {
+            vm.name == self.pattern
+        } else {
+            vm.name.to_lowercase().contains(&self.pattern.to_lowercase())
+        }
+    }
+}
+
+impl std::str::FromStr for ListFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (field, pattern, exact) = if let Some((field, pattern)) = s.split_once('~') {
+            (field, pattern, false)
+        } else if let Some((field, pattern)) = s.split_once('=') {
+            (field, pattern, true)
+        } else {
+            return Err("Invalid filter. Use name~substring or name=exact".to_string());
+        };
+
+        if field.trim() != "name" {
+            return Err(format!("Unsupported filter field '{}'. Only 'name' can be filtered", field));
+        }
+
+        Ok(ListFilter {
+            pattern: pattern.trim().to_string(),
+            exact,
+        })
+    }
+}
+
+/// Column that can be shown in `vmtools list` output
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListColumn {
+    Name,
+    State,
+    Memory,
+    Cpus,
+    Uptime,
+    Ip,
+    Autostart,
+    Uuid,
+    Created,
+    LastStarted,
+    Health,
+    Snapshots,
+    LastBackup,
+}
+
+impl ListColumn {
+    pub const DEFAULT: &'static [ListColumn] = &[
+        ListColumn::Name,
+        ListColumn::State,
+        ListColumn::Memory,
+        ListColumn::Cpus,
+        ListColumn::Uptime,
+        ListColumn::Ip,
+    ];
+
+    /// `status --group`: the defaults and each member's health
+    pub const GROUP: &'static [ListColumn] = &[
+        ListColumn::Name,
+        ListColumn::State,
+        ListColumn::Memory,
+        ListColumn::Cpus,
+        ListColumn::Uptime,
+        ListColumn::Ip,
+        ListColumn::Health,
+    ];
+
+    pub fn header(&self) -> &'static str {
+        match self {
+            ListColumn::Name => "NAME",
+            ListColumn::State => "STATE",
+            ListColumn::Memory => "MEMORY",
+            ListColumn::Cpus => "CPUS",
+            ListColumn::Uptime => "UPTIME",
+            ListColumn::Ip => "IP ADDRESS",
+            ListColumn::Autostart => "AUTOSTART",
+            ListColumn::Uuid => "UUID",
+            ListColumn::Created => "CREATED",
+            ListColumn::LastStarted => "LAST STARTED",
+            ListColumn::Health => "HEALTH",
+            ListColumn::Snapshots => "SNAPSHOTS",
+            ListColumn::LastBackup => "LAST BACKUP",
+        }
+    }
+
+    /// Upper bound for the column width; longer values are truncated with an ellipsis
+    pub fn max_width(&self) -> usize {
+        match self {
+            ListColumn::Name => 40,
+            _ => 36,
+        }
+    }
+
+    pub fn value(&self, vm: &VmInfo) -> String {
+        match self {
+            ListColumn::Name => vm.name.clone(),
+            ListColumn::State => vm.state.label().to_string(),
+            ListColumn::Memory => format!("{}MB", vm.memory),
+            ListColumn::Cpus => vm.cpus.to_string(),
+            ListColumn::Uptime => vm.uptime
+                .map(utils::format_duration)
+                .unwrap_or_else(|| "-".to_string()),
+            ListColumn::Ip => vm.network_info.iter()
+                .find_map(|net| net.ip_address.clone())
+                .unwrap_or_else(|| "-".to_string()),
+            ListColumn::Autostart => if vm.autostart { "yes" } else { "no" }.to_string(),
+            ListColumn::Uuid => vm.uuid.clone(),
+            ListColumn::Created => utils::format_timestamp(vm.created_at),
+            ListColumn::LastStarted => utils::format_timestamp(vm.last_started.unwrap_or(0)),
+            ListColumn::Health => health::summary(&vm.health),
+            ListColumn::Snapshots => vm.snapshot_count
+                .map(|count| count.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            ListColumn::LastBackup => match vm.last_backup {
+                Some(time) => format!("{} ago", utils::format_duration((chrono::Utc::now().timestamp() as u64).saturating_sub(time))),
+                None => "never".to_string(),
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for ListColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "name" => Ok(ListColumn::Name),
+            "state" => Ok(ListColumn::State),
+            "memory" | "mem" => Ok(ListColumn::Memory),
+            "cpus" | "cpu" => Ok(ListColumn::Cpus),
+            "uptime" => Ok(ListColumn::Uptime),
+            "ip" => Ok(ListColumn::Ip),
+            "autostart" => Ok(ListColumn::Autostart),
+            "uuid" => Ok(ListColumn::Uuid),
+            "created" => Ok(ListColumn::Created),
+            "started" | "last-started" => Ok(ListColumn::LastStarted),
+            "health" => Ok(ListColumn::Health),
+            "snapshots" | "snaps" => Ok(ListColumn::Snapshots),
+            "backup" | "last-backup" => Ok(ListColumn::LastBackup),
+            _ => Err(format!(
+                "Invalid column '{}'. Use name, state, memory, cpus, uptime, ip, autostart, uuid, created, started, \
+                 health, snapshots or backup", s
+            )),
+        }
+    }
+}
+
+/// Options controlling which VMs `vmtools list` shows and in what order
+#[derive(Debug, Clone, Default)]
+pub struct ListOptions {
+    pub all: bool,
+    pub running_only: bool,
+    pub state: Option<VmState>,
+    pub sort: Option<ListSort>,
+    pub filters: Vec<ListFilter>,
+    pub columns: Vec<ListColumn>,
+    pub fast: bool,
+}
+
+impl ListOptions {
+    /// Inactive domains are needed whenever the user asks for a non-running state
+    fn needs_inactive(&self) -> bool {
+        self.all || matches!(&self.state, Some(state) if *state != VmState::Running)
+    }
+    
+    /// Creation and start times live in each domain's metadata, which costs a lookup per VM
+    fn needs_metadata(&self) -> bool {
+        matches!(self.sort, Some(ListSort::Created | ListSort::LastStarted))
+            || self.columns.iter().any(|column| matches!(column, ListColumn::Created | ListColumn::LastStarted))
+    }
+
+    fn needs_health(&self) -> bool {
+        self.columns.contains(&ListColumn::Health)
+    }
+
+    fn needs_snapshots(&self) -> bool {
+        self.columns.contains(&ListColumn::Snapshots)
+    }
+
+    fn needs_backups(&self) -> bool {
+        self.columns.contains(&ListColumn::LastBackup)
+    }
+
+    fn apply(&self, vms: &mut Vec<VmInfo>) {
+        vms.retain(|vm| {
+            if self.running_only && vm.state != VmState::Running {
+                return false;
+            }
+            if let Some(state) = &self.state {
+                if vm.state != *state {
+                    return false;
+                }
+            }
+            self.filters.iter().all(|filter| filter.matches(vm))
+        });
+
+        match self.sort {
+            Some(ListSort::Name) => vms.sort_by(|a, b| a.name.cmp(&b.name)),
+            Some(ListSort::Memory) => vms.sort_by_key(|vm| std::cmp::Reverse(vm.memory)),
+            Some(ListSort::Cpus) => vms.sort_by_key(|vm| std::cmp::Reverse(vm.cpus)),
+            Some(ListSort::Uptime) => vms.sort_by_key(|vm| std::cmp::Reverse(vm.uptime.unwrap_or(0))),
+            // Newest first, like the other keys
+            Some(ListSort::Created) => vms.sort_by_key(|vm| std::cmp::Reverse(vm.created_at)),
+            Some(ListSort::LastStarted) => vms.sort_by_key(|vm| std::cmp::Reverse(vm.last_started.unwrap_or(0))),
+            None => {}
+        }
+    }
+}
+
+/// Disk usage of one VM's images, summed over all of its disks
+#[derive(Debug, Clone, Serialize)]
+pub struct VmDiskUsage {
+    pub name: String,
+    pub disks: usize,
+    pub virtual_size: u64,
+    pub actual_size: u64,
+    /// Actual size of backing files below the top overlays
+    pub backing_size: u64,
+    /// Internal snapshots across the whole chain
+    pub snapshots: usize,
+    /// Saved VM state held by those snapshots
+    pub snapshot_state: u64,
+}
+
+/// An image file behind one of a VM's disks
+struct DiskImage {
+    disk: domain::DomainDisk,
+    /// Pool and name of the volume it is, when a storage pool holds it
+    volume: Option<(String, String)>,
+}
+
+/// `vmtools status --all`: the host's VMs, what they take of it and what needs attention
+#[derive(Debug, Clone)]
+pub struct HostSummary {
+    /// How many VMs are in each state, most common first
+    pub states: Vec<(VmState, usize)>,
+    /// Memory in MB and vCPUs given to running VMs
+    pub memory_allocated: u64,
+    pub vcpus_allocated: u32,
+    /// Memory in MB the running guests actually use; all of it for guests without a balloon
+    pub memory_used: u64,
+    /// Like top: 100% is one host CPU fully busy
+    pub cpu_percent: f64,
+    /// `None` when `/proc` could not be read
+    pub host: Option<utils::HostInfo>,
+    pub pools: Vec<PoolInfo>,
+    pub issues: Vec<String>,
+}
+
+impl HostSummary {
+    pub fn total(&self) -> usize {
+        self.states.iter().map(|(_, count)| count).sum()
+    }
+}
+
+/// What a line of `vmtools snapshot du` is
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum SnapshotLayer {
+    /// Snapshot stored inside a qcow2 image
+    Internal,
+    /// Image file below the active one, frozen when an external snapshot or clone was made
+    Overlay,
+    /// Bottom of the chain, often a template shared with other VMs
+    Base,
+    /// The image the VM writes to
+    Active,
+}
+
+impl std::fmt::Display for SnapshotLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotLayer::Internal => write!(f, "internal"),
+            SnapshotLayer::Overlay => write!(f, "overlay"),
+            SnapshotLayer::Base => write!(f, "base"),
+            SnapshotLayer::Active => write!(f, "active"),
+        }
+    }
+}
+
+/// Host disk space of one snapshot or image file of a VM, summed over its disks
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SnapshotUsage {
+    /// Snapshot name, or the image file for the other layers
+    pub name: String,
+    pub layer: SnapshotLayer,
+    /// Bytes; for internal snapshots only the saved memory state, as qemu-img
+    /// doesn't attribute the disk clusters they share with the image
+    pub size: u64,
+    /// Creation time of internal snapshots, in Unix seconds
+    pub created: Option<u64>,
+}
+
+impl SnapshotUsage {
+    /// Lines for the backing chains of a VM's disks (each top overlay first, as
+    /// `qemu-img info --backing-chain` lists them): oldest layers first, then the
+    /// internal snapshots in creation order
+    pub fn from_chains(chains: &[Vec<utils::ImageInfo>]) -> Vec<Self> {
+        let mut files = Vec::new();
+        let mut internal: Vec<Self> = Vec::new();
+        for chain in chains {
+            for (depth, image) in chain.iter().enumerate().rev() {
+                let layer = match depth {
+                    0 => SnapshotLayer::Active,
+                    _ if depth == chain.len() - 1 => SnapshotLayer::Base,
+                    _ => SnapshotLayer::Overlay,
+                };
+                files.push(Self { name: image.filename.clone(), layer, size: image.actual_size, created: None });
+
+                // libvirt takes an internal snapshot of every qcow2 disk under the same name
+                for snapshot in &image.snapshots {
+                    match internal.iter_mut().find(|usage| usage.name == snapshot.name) {
+                        Some(usage) => usage.size += snapshot.vm_state_size,
+                        None => internal.push(Self {
+                            name: snapshot.name.clone(),
+                            layer: SnapshotLayer::Internal,
+                            size: snapshot.vm_state_size,
+                            created: Some(snapshot.date),
+                        }),
+                    }
+                }
+            }
+        }
+        files.sort_by_key(|usage| usage.layer == SnapshotLayer::Active);
+        internal.sort_by_key(|usage| usage.created);
+        files.extend(internal);
+        files
+    }
+}
+
+/// One line of `vmtools bench disk` output
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchResult {
+    /// What was measured, e.g. `vda read` or `guest randwrite`
+    pub target: String,
+    /// Host cache and AIO modes; `None` for in-guest runs
+    pub cache: Option<String>,
+    pub aio: Option<String>,
+    pub iops: f64,
+    /// Bytes per second
+    pub throughput: f64,
+    /// Mean completion latency in microseconds
+    pub latency_us: f64,
+}
+
+/// High-level VM operations on top of libvirt.
+///
+/// Questions for the user, asked by operations that need an answer halfway
+/// through, with the answer to assume when nobody can be asked
+pub type Confirm<'a> = &'a (dyn Fn(&str, bool) -> bool + Send + Sync);
+
+/// Query methods (`list`, `info`, `networks`, `disk_usage`, ...) return data;
+/// operation methods such as `create_vm` or `clone_vm` report progress on the
+/// terminal as they run. Nothing here reads stdin: questions go to a `Confirm`
+/// the frontend supplies.
 pub struct VmManager {
     config: Config,
-    libvirt: LibvirtClient,
+    /// The configuration as loaded; `config` may be adjusted for this run, e.g.
+    /// to the session URI, and only this one is ever saved back
+    loaded_config: Config,
+    backend: Arc<dyn Backend>,
+    webhooks: WebhookDispatcher,
+    notifier: Notifier,
+    /// Runs qemu-img for the image work done around the backend
+    runner: Arc<dyn CommandRunner>,
 }
 
 impl VmManager {
-    pub async fn new(config: &Config) -> Result<Self> {
+    /// Connects to the configured backend; `confirm` is asked before falling back
+    /// to the per-user session URI
+    pub async fn new(config: &Config, confirm: Confirm<'_>) -> Result<Self> {
+        if config.backend.kind == BackendKind::Qemu {
+            let backend = QemuBackend::new(&config.backend.qemu_state_dir)?;
+            return Ok(Self::with_backend(config, Arc::new(backend)));
+        }
+        
+        let mut runtime = config.clone();
+        let privileges = Self::select_connection(&mut runtime, confirm).await;
+        
+        let runner = privileges.shared_runner();
         let libvirt = LibvirtClient::new(
-            &config.libvirt.uri, 
-            config.system.temp_dir.to_str().unwrap_or("/tmp")
+            privileges,
+            runtime.system.temp_dir.to_str().unwrap_or("/tmp"),
+            runtime.libvirt.timeout,
         ).await?;
         
         Ok(Self {
-            config: config.clone(),
-            libvirt,
+            loaded_config: config.clone(),
+            ..Self::with_backend(&runtime, Arc::new(libvirt)).with_runner(runner)
         })
     }
     
-    pub async fn list_vms(&self, all: bool, running_only: bool) -> Result<()> {
-        let vms = self.libvirt.list_domains(all).await?;
-        
-        if vms.is_empty() {
-            println!("{}", "No virtual machines found".yellow());
-            return Ok(());
-        }
-        
-        println!("{:<20} {:<12} {:<8} {:<6} {:<8} {:<12}", 
-                 "NAME".bold(), "STATE".bold(), "MEMORY".bold(), 
-                 "CPUS".bold(), "UPTIME".bold(), "IP ADDRESS".bold());
-        println!("{}", "─".repeat(80));
-        
-        for vm in vms {
-            if running_only && vm.state != VmState::Running {
-                continue;
-            }
-            
-            let uptime_str = match vm.uptime {
-                Some(uptime) => utils::format_duration(uptime),
-                None => "-".to_string(),
-            };
-            
-            let ip_str = vm.network_info.first()
-                .and_then(|net| net.ip_address.as_ref())
-                .map(|ip| ip.as_str())
-                .unwrap_or("-");
-            
-            println!("{:<20} {:<12} {:<8} {:<6} {:<8} {:<12}",
-                     vm.name,
-                     vm.state,
-                     format!("{}MB", vm.memory),
-                     vm.cpus,
-                     uptime_str,
-                     ip_str);
+    /// Builds a manager on an already connected backend, e.g. `mock::MockBackend` in tests
+    pub fn with_backend(config: &Config, backend: Arc<dyn Backend>) -> Self {
+        Self {
+            webhooks: WebhookDispatcher::new(&config.webhooks),
+            notifier: Notifier::new(&config.notifications),
+            config: config.clone(),
+            loaded_config: config.clone(),
+            backend,
+            runner: Arc::new(SystemRunner),
         }
-        
-        Ok(())
+    }
+
+    /// Runs qemu-img and osinfo-detect through `runner`, e.g. `mock::MockRunner` in tests
+    pub fn with_runner(mut self, runner: Arc<dyn CommandRunner>) -> Self {
+        self.runner = runner;
+        self
     }
     
-    pub async fn start_vm(&self, name: &str) -> Result<()> {
-        println!("Starting VM '{}'...", name.green());
+    /// Uses the configured URI when accessible, otherwise offers the per-user
+    /// session URI so unprivileged users without libvirt group membership can
+    /// still manage their own VMs
+    async fn select_connection(config: &mut Config, confirm: Confirm<'_>) -> Privileges {
+        let timeout = Duration::from_secs(config.libvirt.timeout);
+        let privileges = Privileges::detect(&config.libvirt.uri, timeout).await;
         
-        // Validate VM name to prevent path traversal attacks (CWE-22)
-        utils::validate_vm_name(name)?;
+        if privileges.access() != AccessLevel::Denied
+            || privileges.is_root()
+            || !config.libvirt.session_fallback
+            || !config.libvirt.uri.ends_with(":///system")
+        {
+            return privileges;
+        }
         
-        let pb = ProgressBar::new_spinner();
-        pb.set_style(ProgressStyle::default_spinner()
-            .template("{spinner:.green} {msg}")
-            .unwrap());
-        pb.set_message("Starting virtual machine...");
+        let session_uri = config.libvirt.uri.replace(":///system", ":///session");
+        let session = Privileges::detect(&session_uri, timeout).await;
+        if session.access() != AccessLevel::ReadWrite {
+            return privileges;
+        }
         
-        self.libvirt.start_domain(name).await?;
+        if !confirm(&format!("No access to {}. Use your per-user {} instead?", config.libvirt.uri, session_uri), true) {
+            return privileges;
+        }
         
-        // Wait for VM to fully start
-        for _ in 0..30 {
-            pb.tick();
-            sleep(Duration::from_secs(1)).await;
-            
-            let state = self.libvirt.get_domain_state(name).await?;
-            if state == VmState::Running {
-                pb.finish_with_message(format!("✓ VM '{}' started successfully", name));
-                return Ok(());
-            }
+        config.use_session_uri(&session_uri);
+        if let Err(e) = std::fs::create_dir_all(&config.storage.vm_images_path) {
+            tracing::warn!("Failed to create {}: {}", config.storage.vm_images_path.display(), e);
         }
         
-        pb.finish_with_message(format!("⚠ VM '{}' may still be starting", name));
-        Ok(())
+        println!("{} Using {} with images in {}", 
+                 "Session:".cyan(), session_uri, config.storage.vm_images_path.display());
+        session
     }
     
-    pub async fn stop_vm(&self, name: &str, force: bool) -> Result<()> {
-        let action = if force { "Force stopping" } else { "Stopping" };
-        println!("{} VM '{}'...", action, name.red());
-        
-        // Validate VM name to prevent path traversal attacks (CWE-22)
-        utils::validate_vm_name(name)?;
-        
-        if force {
-            self.libvirt.destroy_domain(name).await?;
-        } else {
-            self.libvirt.shutdown_domain(name).await?;
+    /// Reports a finished operation to the configured webhooks, and when it took
+    /// `elapsed` long enough to matter, as a desktop or email notice
+    pub async fn notify_operation(&self, operation: &str, vm: Option<&str>, result: &Result<()>, elapsed: Duration) {
+        let outcome = result.as_ref().map(|_| ()).map_err(|e| e.to_string());
+        if self.notifier.wants(operation, elapsed) {
+            self.notifier.send(self.runner.as_ref(), &Notice::operation(operation, vm, &outcome, elapsed)).await;
         }
-        
-        println!("✓ VM '{}' stopped successfully", name);
-        Ok(())
+        self.webhooks.dispatch(self.runner.as_ref(), &WebhookPayload::operation(operation, vm, outcome)).await;
     }
     
-    pub async fn get_vm_status(&self, name: &str) -> Result<()> {
-        // Validate VM name to prevent path traversal attacks (CWE-22)
-        utils::validate_vm_name(name)?;
-        
-        let vm_info = self.libvirt.get_domain_info(name).await?;
-        
-        println!("{}", format!("VM Status: {}", name).bold());
-        println!("{}", "═".repeat(40));
-        println!("State: {}", vm_info.state);
-        println!("UUID: {}", vm_info.uuid);
-        println!("Memory: {}MB", vm_info.memory);
-        println!("CPUs: {}", vm_info.cpus);
-        
-        if let Some(uptime) = vm_info.uptime {
-            println!("Uptime: {}", utils::format_duration(uptime));
-        }
-        
-        if let Some(cpu_usage) = vm_info.cpu_usage {
-            println!("CPU Usage: {:.1}%", cpu_usage);
+    /// Lists VMs matching `options`, filtered and sorted
+    pub async fn list(&self, options: &ListOptions) -> Result<Vec<VmInfo>> {
+        let mut vms = self.backend.list_domains(options.needs_inactive(), options.fast).await?;
+        if options.needs_metadata() {
+            for vm in &mut vms {
+                self.load_metadata(vm).await;
+            }
         }
-        
-        if let Some(memory_usage) = vm_info.memory_usage {
-            println!("Memory Usage: {:.1}%", memory_usage);
+        options.apply(&mut vms);
+        if options.needs_health() {
+            self.load_health(&mut vms).await;
         }
-        
-        if !vm_info.disk_usage.is_empty() {
-            println!("\nDisk Information:");
-            for disk in &vm_info.disk_usage {
-                println!("  {} ({}): {}/{} ({})", 
-                         disk.device, 
-                         disk.format,
-                         utils::format_bytes(disk.used),
-                         utils::format_bytes(disk.size),
-                         disk.path);
+        for vm in &mut vms {
+            if options.needs_snapshots() {
+                vm.snapshot_count = self.snapshots(&vm.name).await.ok().map(|snapshots| snapshots.len());
             }
-        }
-        
-        if !vm_info.network_info.is_empty() {
-            println!("\nNetwork Information:");
-            for net in &vm_info.network_info {
-                println!("  {}: {} ({})", 
-                         net.interface,
-                         net.ip_address.as_deref().unwrap_or("No IP"),
-                         net.mac_address);
+            if options.needs_backups() {
+                vm.last_backup = self.last_backup(&vm.name);
             }
         }
-        
-        Ok(())
+        Ok(vms)
+    }
+
+    /// When a VM was last backed up: the newest modification time among the files
+    /// in `storage.backup_path/<name>`, where backups of it are kept
+    fn last_backup(&self, name: &str) -> Option<u64> {
+        std::fs::read_dir(self.config.storage.backup_path.join(name)).ok()?
+            .flatten()
+            .filter_map(|entry| entry.metadata().ok()?.modified().ok())
+            .filter_map(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|age| age.as_secs())
+            .max()
     }
     
-    pub async fn create_vm(
-        &self,
-        name: &str,
-        memory: u64,
-        cpus: u32,
-        disk_size: u64,
-        iso_path: Option<&str>,
-        template_name: Option<&str>,
-    ) -> Result<()> {
-        println!("Creating VM '{}'...", name.green());
-        
-        // Validate VM name to prevent path traversal attacks (CWE-22)
-        utils::validate_vm_name(name)?;
-        
-        // Check if VM already exists
-        if self.libvirt.domain_exists(name).await? {
-            return Err(VmError::VmAlreadyExists(name.to_string()));
+    /// Fills in the creation and last start times vmtools keeps in the domain's metadata
+    async fn load_metadata(&self, vm: &mut VmInfo) {
+        if let Ok(xml) = self.backend.get_inactive_domain_xml(&vm.name).await {
+            if let Some(metadata) = VmMetadata::parse(&xml) {
+                vm.created_at = metadata.created_at.unwrap_or(0);
+                vm.last_started = metadata.last_started;
+            }
+        }
+    }
+    
+    /// Runs the configured health checks of the running VMs in `vms`, all at once
+    async fn load_health(&self, vms: &mut [VmInfo]) {
+        let mut tasks = tokio::task::JoinSet::new();
+        for (index, vm) in vms.iter().enumerate() {
+            if vm.state != VmState::Running {
+                continue;
+            }
+            let checks = match self.health_checks(&vm.name).await {
+                Ok(checks) if !checks.is_empty() => checks,
+                _ => continue,
+            };
+            let mut ip = vm.network_info.iter().find_map(|net| net.ip_address.clone());
+            if ip.is_none() && checks.iter().any(HealthCheck::needs_ip) {
+                // `list --fast` skips the address lookup
+                ip = self.backend.get_domain_info(&vm.name).await.ok()
+                    .and_then(|info| info.network_info.into_iter().find_map(|net| net.ip_address));
+            }
+
+            let backend = self.backend.clone();
+            let runner = self.runner.clone();
+            let name = vm.name.clone();
+            tasks.spawn(async move {
+                let mut results = Vec::new();
+                for check in checks {
+                    results.push(check.run(backend.as_ref(), runner.as_ref(), &name, ip.as_deref()).await);
+                }
+                (index, results)
+            });
+        }
+
+        while let Some(joined) = tasks.join_next().await {
+            if let Ok((index, results)) = joined {
+                vms[index].health = results;
+            }
+        }
+    }
+
+    /// The health checks configured for a VM
+    pub async fn health_checks(&self, name: &str) -> Result<Vec<HealthCheck>> {
+        let xml = self.backend.get_inactive_domain_xml(name).await?;
+        let metadata = VmMetadata::parse(&xml).unwrap_or_default();
+        metadata.health_checks.iter()
+            .map(|spec| spec.parse().map_err(VmError::InvalidInput))
+            .collect()
+    }
+
+    /// Runs a VM's health checks now; fails when the VM is not running
+    pub async fn check_health(&self, name: &str) -> Result<Vec<HealthResult>> {
+        utils::validate_vm_name(name)?;
+        let mut info = self.backend.get_domain_info(name).await?;
+        if info.state != VmState::Running {
+            return Err(VmError::InvalidInput(format!("VM '{}' is not running", name)));
+        }
+        self.load_health(std::slice::from_mut(&mut info)).await;
+        Ok(info.health)
+    }
+
+    /// Adds and removes health checks, kept in the VM's metadata; `clear` drops
+    /// the existing ones first
+    pub async fn set_health_checks(
+        &self,
+        name: &str,
+        add: &[HealthCheck],
+        remove: &[HealthCheck],
+        clear: bool,
+    ) -> Result<()> {
+        utils::validate_vm_name(name)?;
+
+        let xml = self.backend.get_inactive_domain_xml(name).await?;
+        let mut metadata = VmMetadata::parse(&xml).unwrap_or_default();
+        if clear {
+            metadata.health_checks.clear();
+        }
+        for check in remove {
+            let spec = check.to_string();
+            if !metadata.health_checks.contains(&spec) {
+                return Err(VmError::InvalidInput(format!("VM '{}' has no health check '{}'", name, spec)));
+            }
+            metadata.health_checks.retain(|existing| *existing != spec);
+        }
+        for check in add {
+            let spec = check.to_string();
+            if !metadata.health_checks.contains(&spec) {
+                metadata.health_checks.push(spec);
+            }
+        }
+        self.backend.define_domain(&domain::set_metadata(&xml, &metadata)?).await?;
+
+        if metadata.health_checks.is_empty() {
+            println!("✅ VM '{}' has no health checks", name);
+        } else {
+            println!("✅ Health checks for VM '{}': {}", name, metadata.health_checks.join(", ").cyan());
+        }
+        Ok(())
+    }
+
+    /// The VMs `name` waits for when started with `start_group`
+    pub async fn dependencies(&self, name: &str) -> Result<Vec<String>> {
+        utils::validate_vm_name(name)?;
+        let xml = self.backend.get_inactive_domain_xml(name).await?;
+        Ok(VmMetadata::parse(&xml).map(|metadata| metadata.depends_on).unwrap_or_default())
+    }
+
+    /// Adds and removes startup dependencies, kept in the VM's metadata; `clear`
+    /// drops the existing ones first
+    pub async fn set_dependencies(&self, name: &str, add: &[String], remove: &[String], clear: bool) -> Result<()> {
+        utils::validate_vm_name(name)?;
+
+        let xml = self.backend.get_inactive_domain_xml(name).await?;
+        let mut metadata = VmMetadata::parse(&xml).unwrap_or_default();
+        if clear {
+            metadata.depends_on.clear();
+        }
+        for dependency in remove {
+            if !metadata.depends_on.contains(dependency) {
+                return Err(VmError::InvalidInput(format!("VM '{}' does not depend on '{}'", name, dependency)));
+            }
+            metadata.depends_on.retain(|existing| existing != dependency);
+        }
+        for dependency in add {
+            utils::validate_vm_name(dependency)?;
+            if dependency == name {
+                return Err(VmError::InvalidInput(format!("VM '{}' cannot depend on itself", name)));
+            }
+            if !self.backend.domain_exists(dependency).await? {
+                return Err(VmError::VmNotFound(dependency.clone()));
+            }
+            if self.depends_on_transitively(dependency, name).await? {
+                return Err(VmError::InvalidInput(format!(
+                    "'{}' already depends on '{}'; the dependency would form a cycle", dependency, name
+                )));
+            }
+            if !metadata.depends_on.contains(dependency) {
+                metadata.depends_on.push(dependency.clone());
+            }
+        }
+        self.backend.define_domain(&domain::set_metadata(&xml, &metadata)?).await?;
+
+        if metadata.depends_on.is_empty() {
+            println!("✅ VM '{}' has no dependencies", name);
+        } else {
+            println!("✅ VM '{}' depends on: {}", name, metadata.depends_on.join(", ").cyan());
+        }
+        Ok(())
+    }
+
+    /// Whether `name` depends on `target`, directly or through other VMs
+    async fn depends_on_transitively(&self, name: &str, target: &str) -> Result<bool> {
+        let mut pending = vec![name.to_string()];
+        let mut seen = std::collections::HashSet::new();
+        while let Some(current) = pending.pop() {
+            if current == target {
+                return Ok(true);
+            }
+            if !seen.insert(current.clone()) {
+                continue;
+            }
+            // Dependencies may have been deleted since they were recorded
+            if let Ok(xml) = self.backend.get_inactive_domain_xml(&current).await {
+                pending.extend(VmMetadata::parse(&xml).map(|metadata| metadata.depends_on).unwrap_or_default());
+            }
+        }
+        Ok(false)
+    }
+
+    /// Every group and its members. A group is a tag kept in the domain metadata,
+    /// so it exists for as long as a VM carries it.
+    pub async fn groups(&self) -> Result<std::collections::BTreeMap<String, Vec<String>>> {
+        let mut groups: std::collections::BTreeMap<String, Vec<String>> = std::collections::BTreeMap::new();
+        for vm in self.backend.list_domains(true, true).await? {
+            let xml = self.backend.get_inactive_domain_xml(&vm.name).await?;
+            for tag in VmMetadata::parse(&xml).map(|metadata| metadata.tags).unwrap_or_default() {
+                groups.entry(tag).or_default().push(vm.name.clone());
+            }
+        }
+        for members in groups.values_mut() {
+            members.sort();
+        }
+        Ok(groups)
+    }
+
+    /// The members of `group`, which must have at least one
+    pub async fn group_members(&self, group: &str) -> Result<Vec<String>> {
+        utils::validate_label("Group", group)?;
+        self.groups().await?.remove(group)
+            .ok_or_else(|| VmError::InvalidInput(format!("No group '{}'", group)))
+    }
+
+    /// Creates `group` with `vms` as its first members
+    pub async fn create_group(&self, group: &str, vms: &[String]) -> Result<()> {
+        utils::validate_label("Group", group)?;
+        if vms.is_empty() {
+            return Err(VmError::InvalidInput("A group needs at least one VM".to_string()));
+        }
+        if self.groups().await?.contains_key(group) {
+            return Err(VmError::InvalidInput(format!("Group '{}' already exists", group)));
+        }
+        for name in vms {
+            self.set_tag(name, group, true).await?;
+        }
+        println!("✅ Group '{}' created: {}", group.cyan(), vms.join(", "));
+        Ok(())
+    }
+
+    /// Adds `vms` to an existing group
+    pub async fn add_to_group(&self, group: &str, vms: &[String]) -> Result<()> {
+        let members = self.group_members(group).await?;
+        for name in vms.iter().filter(|name| !members.contains(name)) {
+            self.set_tag(name, group, true).await?;
+        }
+        println!("✅ Added to group '{}': {}", group.cyan(), vms.join(", "));
+        Ok(())
+    }
+
+    /// Removes `vms` from `group`; the group is gone once its last member leaves
+    pub async fn remove_from_group(&self, group: &str, vms: &[String]) -> Result<()> {
+        let members = self.group_members(group).await?;
+        if let Some(name) = vms.iter().find(|name| !members.contains(name)) {
+            return Err(VmError::InvalidInput(format!("VM '{}' is not in group '{}'", name, group)));
+        }
+        for name in vms {
+            self.set_tag(name, group, false).await?;
+        }
+        println!("✅ Removed from group '{}': {}", group.cyan(), vms.join(", "));
+        if members.iter().all(|member| vms.contains(member)) {
+            println!("Group '{}' has no members left and no longer exists", group);
+        }
+        Ok(())
+    }
+
+    /// Adds `tag` to a VM's metadata, or with `add` false removes it
+    async fn set_tag(&self, name: &str, tag: &str, add: bool) -> Result<()> {
+        utils::validate_vm_name(name)?;
+        let xml = self.backend.get_inactive_domain_xml(name).await?;
+        let mut metadata = VmMetadata::parse(&xml).unwrap_or_default();
+        if add {
+            if !metadata.tags.iter().any(|t| t == tag) {
+                metadata.tags.push(tag.to_string());
+            }
+        } else {
+            metadata.tags.retain(|t| t != tag);
+        }
+        self.backend.define_domain(&domain::set_metadata(&xml, &metadata)?).await
+    }
+
+    /// Shuts down the running members of `group` all at once
+    pub async fn stop_group(&self, group: &str, force: bool) -> Result<()> {
+        self.group_members(group).await?;
+        self.stop_all(Some(group), force).await
+    }
+
+    /// The members of `group` with their state, address and health
+    pub async fn group_status(&self, group: &str) -> Result<Vec<VmInfo>> {
+        let members = self.group_members(group).await?;
+        let options = ListOptions {
+            all: true,
+            columns: ListColumn::GROUP.to_vec(),
+            ..Default::default()
+        };
+        let mut vms = self.list(&options).await?;
+        vms.retain(|vm| members.contains(&vm.name));
+        Ok(vms)
+    }
+
+    /// Starts the VMs tagged `tag` so that each one's dependencies are up and
+    /// pass their health checks first; `timeout` bounds the wait for each dependency
+    pub async fn start_group(&self, tag: &str, timeout: Duration) -> Result<()> {
+        utils::validate_label("Tag", tag)?;
+
+        let mut group = std::collections::BTreeMap::new();
+        for vm in self.backend.list_domains(true, true).await? {
+            let xml = self.backend.get_inactive_domain_xml(&vm.name).await?;
+            if let Some(metadata) = VmMetadata::parse(&xml).filter(|metadata| metadata.tags.iter().any(|t| t == tag)) {
+                group.insert(vm.name, metadata.depends_on);
+            }
+        }
+        if group.is_empty() {
+            return Err(VmError::InvalidInput(format!("No VM is tagged '{}'", tag)));
+        }
+
+        let order = Self::startup_order(&group)?;
+        println!("🚀 Starting group '{}': {}", tag.cyan(), order.join(" → "));
+
+        // Dependencies outside the group are not started, only waited for
+        let outside: std::collections::BTreeSet<&String> = group.values().flatten()
+            .filter(|dependency| !group.contains_key(*dependency))
+            .collect();
+        for dependency in outside {
+            if self.backend.get_domain_state(dependency).await? != VmState::Running {
+                return Err(VmError::InvalidInput(format!(
+                    "'{}' is needed by group '{}' but is not running and not tagged '{}'", dependency, tag, tag
+                )));
+            }
+            self.wait_healthy(dependency, timeout).await?;
+        }
+
+        for name in &order {
+            if self.backend.get_domain_state(name).await? == VmState::Running {
+                println!("✓ VM '{}' is already running", name);
+            } else {
+                self.start_vm(name).await?;
+            }
+            if group.values().any(|dependencies| dependencies.contains(name)) {
+                self.wait_healthy(name, timeout).await?;
+            }
+        }
+
+        println!("✅ Group '{}' started ({} VMs)", tag, order.len());
+        Ok(())
+    }
+
+    /// Orders a group so every VM comes after its dependencies inside the group,
+    /// alphabetically among VMs that are ready at the same time
+    fn startup_order(group: &std::collections::BTreeMap<String, Vec<String>>) -> Result<Vec<String>> {
+        let mut order: Vec<String> = Vec::with_capacity(group.len());
+        while order.len() < group.len() {
+            let ready: Vec<String> = group.iter()
+                .filter(|(name, _)| !order.contains(name))
+                .filter(|(_, dependencies)| dependencies.iter()
+                    .all(|dependency| !group.contains_key(dependency) || order.contains(dependency)))
+                .map(|(name, _)| name.clone())
+                .collect();
+            if ready.is_empty() {
+                let stuck: Vec<&str> = group.keys()
+                    .filter(|name| !order.contains(name))
+                    .map(String::as_str)
+                    .collect();
+                return Err(VmError::InvalidInput(format!(
+                    "Dependency cycle between {}", stuck.join(", ")
+                )));
+            }
+            order.extend(ready);
+        }
+        Ok(order)
+    }
+
+    /// Saves the memory of every running or paused VM and stops it, as before a host
+    /// shutdown; `resume_all`, or starting a VM, continues it where it was. All VMs
+    /// are saved at once; one failing doesn't stop the others.
+    pub async fn suspend_all(&self) -> Result<()> {
+        let active: Vec<String> = self.backend.list_domains(false, true).await?.into_iter()
+            .filter(|vm| matches!(vm.state, VmState::Running | VmState::Paused))
+            .map(|vm| vm.name)
+            .collect();
+        if active.is_empty() {
+            println!("✓ No running VMs to save");
+            return Ok(());
+        }
+        println!("💾 Saving {} VM(s): {}", active.len(), active.join(", "));
+
+        let saved = self.for_each_vm(&active, "save", "saved", |backend, name| async move {
+            backend.managed_save(&name).await
+        }).await;
+        Self::all_succeeded(&saved, &active, "saved")?;
+        println!("✅ Saved {} VM(s); resume them with: vmtools resume-all", active.len());
+        Ok(())
+    }
+
+    /// Starts every stopped VM, or those tagged `tag`, all at once
+    pub async fn start_all(&self, tag: Option<&str>) -> Result<()> {
+        let stopped = self.vms_in_state(tag, |state| *state == VmState::Stopped).await?;
+        if stopped.is_empty() {
+            println!("✓ No stopped VMs to start");
+            return Ok(());
+        }
+        println!("🚀 Starting {} VM(s): {}", stopped.len(), stopped.join(", "));
+
+        let started = self.for_each_vm(&stopped, "start", "started", |backend, name| async move {
+            backend.start_domain(&name).await.map_err(|e| Self::start_error(&name, e))
+        }).await;
+        for name in &started {
+            self.record_start(name).await;
+        }
+        Self::all_succeeded(&started, &stopped, "started")?;
+        println!("✅ Started {} VM(s)", started.len());
+        Ok(())
+    }
+
+    /// Shuts down every running or paused VM, or those tagged `tag`, all at once;
+    /// `force` pulls the power instead
+    pub async fn stop_all(&self, tag: Option<&str>, force: bool) -> Result<()> {
+        let active = self.vms_in_state(tag, |state| matches!(state, VmState::Running | VmState::Paused)).await?;
+        if active.is_empty() {
+            println!("✓ No running VMs to stop");
+            return Ok(());
+        }
+        let action = if force { "Force stopping" } else { "Stopping" };
+        println!("{} {} VM(s): {}", action, active.len(), active.join(", "));
+
+        let stopped = self.for_each_vm(&active, "stop", "stopped", move |backend, name| async move {
+            if force {
+                backend.destroy_domain(&name).await
+            } else {
+                backend.shutdown_domain(&name).await
+            }
+        }).await;
+        Self::all_succeeded(&stopped, &active, "stopped")?;
+        println!("✅ Stopped {} VM(s)", stopped.len());
+        Ok(())
+    }
+
+    /// Names of the VMs in a state `wanted` accepts, limited to those tagged `tag`
+    async fn vms_in_state(&self, tag: Option<&str>, wanted: impl Fn(&VmState) -> bool) -> Result<Vec<String>> {
+        if let Some(tag) = tag {
+            utils::validate_label("Tag", tag)?;
+        }
+        let mut names = Vec::new();
+        for vm in self.backend.list_domains(true, true).await? {
+            if !wanted(&vm.state) {
+                continue;
+            }
+            if let Some(tag) = tag {
+                let xml = self.backend.get_inactive_domain_xml(&vm.name).await?;
+                if !VmMetadata::parse(&xml).is_some_and(|metadata| metadata.tags.iter().any(|t| t == tag)) {
+                    continue;
+                }
+            }
+            names.push(vm.name);
+        }
+        Ok(names)
+    }
+
+    /// Runs `action` for every VM in `names` at once, printing a line per VM as it
+    /// finishes (`verb` and `done` name the action, e.g. "save" and "saved").
+    /// Returns the VMs it succeeded for.
+    async fn for_each_vm<F, Fut>(&self, names: &[String], verb: &str, done: &str, action: F) -> Vec<String>
+    where
+        F: Fn(Arc<dyn Backend>, String) -> Fut,
+        Fut: std::future::Future<Output = Result<()>> + Send + 'static,
+    {
+        let mut tasks = tokio::task::JoinSet::new();
+        for name in names {
+            let task = action(self.backend.clone(), name.clone());
+            let name = name.clone();
+            tasks.spawn(async move { (name, task.await) });
+        }
+
+        let mut succeeded = Vec::new();
+        while let Some(joined) = tasks.join_next().await {
+            match joined {
+                Ok((name, Ok(()))) => {
+                    println!("✓ VM '{}' {}", name, done);
+                    succeeded.push(name);
+                }
+                Ok((name, Err(e))) => eprintln!("{} Failed to {} VM '{}': {}", "✗".red(), verb, name, e),
+                Err(e) => eprintln!("{} {}", "✗".red(), e),
+            }
+        }
+        succeeded.sort();
+        succeeded
+    }
+
+    /// The error for a `for_each_vm` run that didn't succeed for every VM
+    fn all_succeeded(succeeded: &[String], names: &[String], done: &str) -> Result<()> {
+        if succeeded.len() < names.len() {
+            return Err(VmError::CommandError(format!(
+                "{} of {} VMs could not be {}", names.len() - succeeded.len(), names.len(), done
+            )));
+        }
+        Ok(())
+    }
+
+    /// Starts every VM that has a saved state, dependencies first, so each continues
+    /// where `suspend_all` left it
+    pub async fn resume_all(&self) -> Result<()> {
+        let saved = self.backend.list_managed_saves().await?;
+        if saved.is_empty() {
+            println!("✓ No saved VMs to resume");
+            return Ok(());
+        }
+
+        let mut group = std::collections::BTreeMap::new();
+        for name in saved {
+            let xml = self.backend.get_inactive_domain_xml(&name).await?;
+            group.insert(name, VmMetadata::parse(&xml).map(|metadata| metadata.depends_on).unwrap_or_default());
+        }
+        let order = Self::startup_order(&group)?;
+        println!("▶️  Resuming {} VM(s): {}", order.len(), order.join(" → "));
+
+        let mut failed = 0;
+        for name in &order {
+            match self.backend.start_domain(name).await.map_err(|e| Self::start_error(name, e)) {
+                Ok(()) => println!("✓ VM '{}' resumed", name),
+                Err(e) => {
+                    failed += 1;
+                    eprintln!("{} Failed to resume VM '{}': {}", "✗".red(), name, e);
+                }
+            }
+        }
+
+        if failed > 0 {
+            return Err(VmError::CommandError(format!("{} of {} VMs could not be resumed", failed, order.len())));
+        }
+        println!("✅ Resumed {} VM(s)", order.len());
+        Ok(())
+    }
+
+    /// Writes vmtools' systemd units into `dir`, pointing at the running vmtools
+    /// binary: one runs `resume-all` at boot and `suspend-all` at shutdown, the
+    /// other runs the daemon
+    pub async fn install_units(&self, dir: &std::path::Path) -> Result<Vec<std::path::PathBuf>> {
+        let exe = std::env::current_exe()?;
+        let mut installed = Vec::new();
+        for (unit, content) in [
+            (systemd::GUESTS_UNIT, systemd::guests_unit(&exe)),
+            (systemd::DAEMON_UNIT, systemd::daemon_unit(&exe)),
+        ] {
+            let path = dir.join(unit);
+            tokio::fs::write(&path, content).await
+                .map_err(|e| VmError::IoError(std::io::Error::new(e.kind(), format!("Cannot write {}: {}", path.display(), e))))?;
+            println!("✓ Installed {}", path.display());
+            installed.push(path);
+        }
+
+        println!("💡 Enable them with: systemctl daemon-reload && systemctl enable --now {} {}",
+                 systemd::GUESTS_UNIT, systemd::DAEMON_UNIT);
+        Ok(installed)
+    }
+
+    /// Runs vmtools' background policies until interrupted: VMs below the `[idle]`
+    /// thresholds for long enough get a managed save, which frees their RAM until
+    /// they are started again, VMs with a snapshot policy get their automatic
+    /// snapshots taken and pruned, and port forwards follow their guests' leases.
+    pub async fn run_daemon(&self) -> Result<()> {
+        let policy = &self.config.idle;
+        let snapshot_policies = &self.config.snapshot_policies;
+        let forwards = self.firewall_rules().await?;
+        if !policy.enabled && snapshot_policies.is_empty() && forwards.is_empty() && !self.webhooks.is_enabled() {
+            return Err(VmError::InvalidInput(
+                "Nothing to do; enable the idle policy with 'vmtools config --set idle.enabled=true', \
+                 add [snapshot_policies.<vm>] or webhook URLs to the config, or forward a port with 'vmtools firewall allow'".to_string()
+            ));
+        }
+        if policy.enabled {
+            println!("🕒 Suspending VMs below {:.1}% CPU and {}/s network for {} minutes{}",
+                     policy.cpu_threshold,
+                     utils::format_bytes(policy.net_threshold),
+                     policy.minutes,
+                     if policy.allowlist.is_empty() { String::new() } else { format!(", except {}", policy.allowlist.join(", ")) });
+        }
+        if !snapshot_policies.is_empty() {
+            let mut names: Vec<&str> = snapshot_policies.keys().map(String::as_str).collect();
+            names.sort();
+            println!("📸 Keeping automatic snapshots of {}", names.join(", "));
+        }
+        if !forwards.is_empty() {
+            println!("🔥 Keeping {} port forward(s) pointed at their guests' addresses", forwards.len());
+        }
+        if self.webhooks.is_enabled() {
+            println!("🔔 Sending lifecycle events to the configured webhooks");
+        }
+
+        let mut tracker = IdleTracker::default();
+        let mut previous = if policy.enabled {
+            Some((self.backend.domain_stats().await?, std::time::Instant::now()))
+        } else {
+            None
+        };
+        let mut firewall = None;
+        let mut events = None;
+        loop {
+            // Failures are reported per VM as they happen
+            self.apply_snapshot_policies(chrono::Utc::now()).await.ok();
+            if let Err(e) = self.sync_firewall_if_changed(&mut firewall).await {
+                tracing::warn!("Failed to update the firewall rules: {}", e);
+            }
+            if self.webhooks.is_enabled() && events.is_none() {
+                match self.backend.subscribe_events(None) {
+                    Ok(stream) => events = Some(stream),
+                    Err(e) => tracing::warn!("Failed to subscribe to lifecycle events: {}", e),
+                }
+            }
+            self.relay_events(&mut events, tokio::time::Instant::now() + DAEMON_INTERVAL).await;
+
+            let Some((before, sampled)) = previous.take() else {
+                continue;
+            };
+            let stats = match self.backend.domain_stats().await {
+                Ok(stats) => stats,
+                Err(e) => {
+                    tracing::warn!("Failed to sample VM statistics: {}", e);
+                    previous = Some((before, sampled));
+                    continue;
+                }
+            };
+            let now = std::time::Instant::now();
+            let usage = VmUsage::compare(&before, &stats, now - sampled);
+            previous = Some((stats, now));
+
+            for name in tracker.observe(&usage, policy, now) {
+                println!("💤 VM '{}' has been idle for {} minutes, saving it...", name, policy.minutes);
+                match self.backend.managed_save(&name).await {
+                    Ok(()) => println!("✓ VM '{}' suspended; starting it resumes where it was", name),
+                    Err(e) => eprintln!("{} Failed to suspend VM '{}': {}", "✗".red(), name, e),
+                }
+                tracker.reset(&name);
+            }
+        }
+    }
+
+    /// Waits until `deadline`, sending the lifecycle events that arrive meanwhile
+    /// to the webhooks. A subscription that ends is dropped for the daemon to renew.
+    async fn relay_events(&self, events: &mut Option<EventStream>, deadline: tokio::time::Instant) {
+        while let Some(stream) = events.as_mut() {
+            tokio::select! {
+                event = stream.next() => match event {
+                    Ok(Some(event)) => self.webhooks.dispatch(self.runner.as_ref(), &WebhookPayload::from_event(&event)).await,
+                    Ok(None) | Err(_) => {
+                        tracing::warn!("The lifecycle event subscription ended; renewing it");
+                        *events = None;
+                    }
+                },
+                _ = tokio::time::sleep_until(deadline) => return,
+            }
+        }
+        tokio::time::sleep_until(deadline).await;
+    }
+
+    /// Takes the automatic snapshots due at `now` under `[snapshot_policies]` and
+    /// deletes those the policies no longer keep. A VM whose snapshot fails keeps
+    /// its old ones; one VM's failure doesn't stop the others.
+    pub async fn apply_snapshot_policies(&self, now: chrono::DateTime<chrono::Utc>) -> Result<()> {
+        let mut names: Vec<&String> = self.config.snapshot_policies.keys().collect();
+        names.sort();
+
+        let mut failed = Vec::new();
+        for name in names {
+            let policy = &self.config.snapshot_policies[name];
+            let result = async {
+                let existing: Vec<String> = self.snapshots(name).await?.into_iter().map(|s| s.name).collect();
+                let plan = retention::plan(policy, &existing, now);
+                for snapshot in &plan.create {
+                    self.create_snapshot(name, snapshot, Some("Automatic snapshot by vmtools daemon"), false).await?;
+                }
+                for snapshot in &plan.delete {
+                    self.delete_snapshot(name, snapshot).await?;
+                }
+                Ok::<_, VmError>(())
+            }.await;
+            if let Err(e) = result {
+                eprintln!("{} Snapshot policy of VM '{}' failed: {}", "✗".red(), name, e);
+                failed.push(name.as_str());
+            }
+        }
+
+        if !failed.is_empty() {
+            return Err(VmError::OperationError(format!("Snapshot policies failed for {}", failed.join(", "))));
+        }
+        Ok(())
+    }
+
+    /// Polls a running VM's health checks until they all pass
+    async fn wait_healthy(&self, name: &str, timeout: Duration) -> Result<()> {
+        if self.health_checks(name).await?.is_empty() {
+            return Ok(());
+        }
+        println!("⏳ Waiting for VM '{}' to pass its health checks...", name);
+
+        let deadline = std::time::Instant::now() + timeout;
+        loop {
+            let results = self.check_health(name).await?;
+            if results.iter().all(|result| result.passed) {
+                println!("✓ VM '{}' is healthy", name);
+                return Ok(());
+            }
+            if std::time::Instant::now() >= deadline {
+                let failing: Vec<&str> = results.iter()
+                    .filter(|result| !result.passed)
+                    .map(|result| result.check.as_str())
+                    .collect();
+                return Err(VmError::Timeout(format!(
+                    "VM '{}' did not pass {} within {}s", name, failing.join(", "), timeout.as_secs()
+                )));
+            }
+            sleep(Duration::from_secs(2)).await;
+        }
+    }
+
+    /// Records the current time as the VM's last start in its metadata. A VM booting
+    /// its installer for the first time gets the disk first in its saved definition;
+    /// the running VM keeps the ISO first until it shuts down, so installer reboots
+    /// still come back to it.
+    async fn record_start(&self, name: &str) {
+        let result = async {
+            let mut xml = self.backend.get_inactive_domain_xml(name).await?;
+            let mut metadata = VmMetadata::parse(&xml).unwrap_or_default();
+            metadata.last_started = Some(chrono::Utc::now().timestamp() as u64);
+            if metadata.iso_first_boot {
+                let mut order = vec!["hd".to_string()];
+                order.extend(DomainSpec::parse(&xml)?.boot_order.into_iter().filter(|dev| dev != "hd"));
+                xml = domain::set_boot_order(&xml, &order)?;
+                metadata.iso_first_boot = false;
+                println!("💿 Booting the installer ISO; after the VM shuts down it starts from disk");
+            }
+            self.backend.define_domain(&domain::set_metadata(&xml, &metadata)?).await
+        }.await;
+        // The VM is up either way; a missing timestamp is not worth failing the start over
+        if let Err(e) = result {
+            tracing::warn!("Could not record the start time of VM '{}': {}", name, e);
+        }
+    }
+    
+    /// Metadata for a VM being created now
+    fn creation_metadata(template: Option<&str>) -> VmMetadata {
+        VmMetadata {
+            created_at: Some(chrono::Utc::now().timestamp() as u64),
+            template: template
+                .filter(|name| utils::validate_label("Template name", name).is_ok())
+                .map(str::to_string),
+            ..Default::default()
+        }
+    }
+    
+    /// Creates the configured storage directories and checks each can hold disk images
+    pub fn init_storage(&self) -> Result<()> {
+        println!("🗄️  Preparing storage directories...");
+        let directories = [
+            ("storage.vm_images_path", &self.config.storage.vm_images_path),
+            ("storage.iso_path", &self.config.storage.iso_path),
+            ("storage.backup_path", &self.config.storage.backup_path),
+        ];
+        for (key, path) in directories {
+            self.prepare_directory(path, key)?;
+            println!("✓ {} ({})", path.display(), key);
+        }
+        println!("✅ Storage is ready");
+        Ok(())
+    }
+
+    /// Reports host settings that differ from what suits VMs and, with `apply`,
+    /// writes the recommended values; those needing a module reload are only explained
+    pub fn tune_host(&self, apply: bool) -> Result<()> {
+        println!("🔍 Checking host settings...");
+        let settings = host::inspect();
+        if settings.is_empty() {
+            println!("No tunable settings found under /proc and /sys");
+            return Ok(());
+        }
+        let deviating: Vec<_> = settings.iter().filter(|setting| setting.deviates()).collect();
+        for setting in &settings {
+            if setting.deviates() {
+                println!("{} {}: {} (recommended {}): {}", "⚠️ ".yellow(), setting.name.bold(),
+                         setting.current, setting.recommended.join(" or "), setting.reason);
+            } else {
+                println!("✓ {}: {}", setting.name, setting.current);
+            }
+        }
+        if deviating.is_empty() {
+            println!("✅ Host settings suit VMs");
+            return Ok(());
+        }
+        if !apply {
+            println!("💡 Apply them with: vmtools host tune --apply");
+            return Ok(());
+        }
+
+        for setting in deviating {
+            match &setting.manual {
+                Some(manual) => println!("💡 {}: {}", setting.name, manual),
+                None => {
+                    setting.apply()?;
+                    println!("🔧 Set {} to {}", setting.name, setting.recommended[0]);
+                }
+            }
+        }
+        println!("✅ Host tuned; values set under /proc and /sys last until the next reboot, \
+                  so add them to /etc/sysctl.d or a boot script to keep them");
+        Ok(())
+    }
+
+    /// A failed start, explained when the guest was refused one of its files
+    fn start_error(name: &str, error: VmError) -> VmError {
+        if matches!(error, VmError::PermissionDenied(_)) {
+            return error;
+        }
+        match storage::explain_denial(&error.to_string(), SecurityModule::detect()) {
+            Some(fix) => VmError::PermissionDenied(format!("VM '{}' could not start: {}", name, fix)),
+            None => error,
+        }
+    }
+
+    /// Labels an image outside the images directory so guests may open it; files
+    /// inside it go by the directory's label. A file that cannot be labelled is
+    /// only a warning, since libvirt relabels disks itself when it may.
+    async fn label_image(&self, path: &std::path::Path, read_only: bool) {
+        if path.starts_with(&self.config.storage.vm_images_path) || !path.exists() {
+            return;
+        }
+        match storage::label_image(self.runner.as_ref(), path, read_only).await {
+            Ok(Some(label)) => println!("{} labelled {} {}", "Storage:".cyan(), path.display(), label),
+            Ok(None) => {}
+            Err(e) => eprintln!("{} {}", "⚠️ ".yellow(), e),
+        }
+    }
+
+    /// Readies one storage directory, showing a label warning when there is one
+    fn prepare_directory(&self, path: &std::path::Path, key: &str) -> Result<()> {
+        if let Some(warning) = storage::init_directory(path, key)? {
+            eprintln!("{} {}", "⚠️ ".yellow(), warning);
+        }
+        Ok(())
+    }
+
+    pub async fn start_vm(&self, name: &str) -> Result<()> {
+        self.boot_vm(name).await?;
+        self.record_start(name).await;
+        Ok(())
+    }
+
+    /// Starts `name`, from `boot_iso` when given, then attaches to its console.
+    /// Notifications and webhooks hear about the start now, not when the session
+    /// ends; a failed start attaches nothing.
+    pub async fn start_vm_with_console(&self, name: &str, boot_iso: Option<&std::path::Path>) -> Result<()> {
+        let started = std::time::Instant::now();
+        let result = match boot_iso {
+            Some(iso) => self.start_vm_from_iso(name, iso).await,
+            None => self.start_vm(name).await,
+        };
+        self.notify_operation("start", Some(name), &result, started.elapsed()).await;
+        result?;
+        self.connect_console(name, None, false).await
+    }
+    
+    /// Spinner for the steps of a longer operation, each named by the message
+    fn step_progress() -> ProgressBar {
+        let pb = ProgressBar::new_spinner();
+        pb.set_style(Self::step_style());
+        pb.enable_steady_tick(Duration::from_millis(120));
+        pb
+    }
+
+    fn step_style() -> ProgressStyle {
+        ProgressStyle::default_spinner()
+            .template("{spinner:.green} [{elapsed_precise}] {msg}")
+            .unwrap()
+    }
+
+    /// Bar of bytes copied, with an ETA
+    fn copy_style() -> ProgressStyle {
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta}) {msg}")
+            .unwrap()
+    }
+
+    /// Turns `pb` into a bar of bytes copied for a disk copy to report into
+    fn copy_progress(pb: &ProgressBar) -> impl Fn(u64, u64) + Send + Sync + '_ {
+        pb.set_style(Self::copy_style());
+        move |copied, total| {
+            pb.set_length(total);
+            pb.set_position(copied);
+        }
+    }
+
+    /// Copies each (source, target) image pair at the same time, `pb` showing the
+    /// combined bytes. Waits for every copy to end; if any failed, the new images
+    /// are removed and the first error returned.
+    async fn clone_disks(&self, pairs: &[(std::path::PathBuf, std::path::PathBuf)], pb: &ProgressBar) -> Result<()> {
+        pb.set_style(Self::copy_style());
+        let totals = Arc::new(std::sync::Mutex::new(vec![(0u64, 0u64); pairs.len()]));
+        let mut tasks = tokio::task::JoinSet::new();
+        for (index, (source, target)) in pairs.iter().cloned().enumerate() {
+            let (backend, pb, totals) = (self.backend.clone(), pb.clone(), totals.clone());
+            tasks.spawn(async move {
+                let progress = move |copied, total| {
+                    let mut totals = totals.lock().unwrap();
+                    totals[index] = (copied, total);
+                    pb.set_length(totals.iter().map(|(_, total)| total).sum());
+                    pb.set_position(totals.iter().map(|(copied, _)| copied).sum());
+                };
+                backend.clone_disk(&source, &target, &progress).await
+            });
+        }
+
+        let mut failure = None;
+        while let Some(joined) = tasks.join_next().await {
+            let result = joined.unwrap_or_else(|e| Err(VmError::CommandError(format!("Disk copy failed: {}", e))));
+            if let Err(e) = result {
+                failure.get_or_insert(e);
+            }
+        }
+        if let Some(e) = failure {
+            pb.abandon();
+            for (_, target) in pairs {
+                let _ = tokio::fs::remove_file(target).await;
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+    
+    /// Starts a VM and waits for it to come up, without touching its definition
+    async fn boot_vm(&self, name: &str) -> Result<()> {
+        println!("Starting VM '{}'...", name.green());
+        
+        // Validate VM name to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(name)?;
+        
+        let pb = ProgressBar::new_spinner();
+        pb.set_style(ProgressStyle::default_spinner()
+            .template("{spinner:.green} {msg}")
+            .unwrap());
+        pb.set_message("Starting virtual machine...");
+        
+        if let Err(e) = self.backend.start_domain(name).await {
+            pb.finish_and_clear();
+            return Err(Self::start_error(name, e));
+        }
+        
+        // Wait for VM to fully start
+        for _ in 0..30 {
+            pb.tick();
+            sleep(Duration::from_secs(1)).await;
+            
+            let state = self.backend.get_domain_state(name).await?;
+            if state == VmState::Running {
+                pb.finish_with_message(format!("✓ VM '{}' started successfully", name));
+                return Ok(());
+            }
+        }
+        
+        pb.finish_with_message(format!("⚠ VM '{}' may still be starting", name));
+        Ok(())
+    }
+    
+    /// Boots once from `iso`, then restores the saved definition so later boots are unchanged
+    pub async fn start_vm_from_iso(&self, name: &str, iso: &std::path::Path) -> Result<()> {
+        // Validate VM name to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(name)?;
+        
+        if !iso.is_file() {
+            return Err(VmError::InvalidInput(format!("ISO {} does not exist", iso.display())));
+        }
+        if self.backend.get_domain_state(name).await? == VmState::Running {
+            return Err(VmError::VmAlreadyRunning(name.to_string()));
+        }
+        
+        let original = self.backend.get_inactive_domain_xml(name).await?;
+        let spec = DomainSpec::parse(&original)?;
+        let mut order = vec!["cdrom".to_string()];
+        order.extend(spec.boot_order.into_iter().filter(|dev| dev != "cdrom"));
+        
+        let iso = iso.canonicalize()?;
+        let once = domain::with_cdrom(&original, &iso.to_string_lossy())?;
+        self.backend.define_domain(&domain::set_boot_order(&once, &order)?).await?;
+        println!("Booting '{}' once from {}", name.green(), iso.display());
+        
+        let started = self.boot_vm(name).await;
+        
+        // The running VM keeps the ISO; only the next boot goes back to normal
+        self.backend.define_domain(&original).await?;
+        println!("✓ Restored the saved boot configuration of VM '{}'", name);
+        if started.is_ok() {
+            self.record_start(name).await;
+        }
+        
+        started
+    }
+    
+    /// Where the VM's definition has drifted from what `template` would generate
+    pub async fn diff_template(&self, name: &str, template_name: &str) -> Result<Vec<SpecDifference>> {
+        // Validate VM name to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(name)?;
+        
+        let template = self.config.get_template(template_name)
+            .ok_or_else(|| VmError::InvalidInput(format!("Template '{}' not found", template_name)))?;
+        let actual = DomainSpec::parse(&self.backend.get_domain_xml(name).await?)?;
+        
+        // Generate with the VM's own disk so only real settings can differ
+        let disk = DiskSource::File(actual.disks.first()
+            .map(|disk| std::path::PathBuf::from(&disk.path))
+            .unwrap_or_else(|| self.config.storage.vm_images_path.join(format!("{}.qcow2", name))));
+        let disk_bus = match actual.disks.first() {
+            Some(disk) if disk.bus == "scsi" => DiskBus::VirtioScsi,
+            _ => DiskBus::Virtio,
+        };
+        // The installer CD-ROM comes from create --iso rather than the template
+        let devices = DeviceOptions {
+            iso: actual.cdrom.as_ref().map(|_| ""),
+            network: &self.config.network.default_network,
+            desktop: self.config.desktop.clone(),
+            graphics: self.config.graphics.clone(),
+            disk_bus,
+            net_queues: template.cpus,
+            cpu_model: Some(self.config.defaults.cpu_model.clone()),
+        };
+        let xml = self.generate_vm_xml(name, template, &disk, &devices, None)?;
+        
+        Ok(actual.diff(&DomainSpec::parse(&xml)?))
+    }
+    
+    /// The VM's domain XML; `inactive` gives the persistent definition instead of the live one
+    pub async fn export_xml(&self, name: &str, inactive: bool) -> Result<String> {
+        // Validate VM name to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(name)?;
+        
+        if inactive {
+            self.backend.get_inactive_domain_xml(name).await
+        } else {
+            self.backend.get_domain_xml(name).await
+        }
+    }
+    
+    /// Defines a VM from an exported XML file, optionally renamed and with new UUID/MACs
+    pub async fn import_xml(&self, path: &std::path::Path, rename: Option<&str>, regenerate_ids: bool) -> Result<()> {
+        let mut xml = std::fs::read_to_string(path)
+            .map_err(|e| VmError::InvalidInput(format!("Cannot read {}: {}", path.display(), e)))?;
+        
+        if let Some(name) = rename {
+            xml = domain::set_name(&xml, name)?;
+        }
+        if regenerate_ids {
+            xml = domain::regenerate_ids(&xml);
+        }
+        
+        let spec = DomainSpec::parse(&xml)?;
+        // Validate VM name to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(&spec.name)?;
+        println!("Importing VM '{}' from {}...", spec.name.green(), path.display());
+        
+        if self.backend.domain_exists(&spec.name).await? {
+            return Err(VmError::VmAlreadyExists(spec.name));
+        }
+        
+        // libvirt would update the VM that owns the UUID instead of defining a new one
+        if !spec.uuid.is_empty() {
+            let existing = self.backend.list_domains(true, true).await?;
+            if let Some(owner) = existing.iter().find(|vm| vm.uuid == spec.uuid) {
+                return Err(VmError::VmAlreadyExists(format!(
+                    "UUID {} belongs to '{}'; import with --regenerate-ids", spec.uuid, owner.name
+                )));
+            }
+        }
+        
+        for disk in &spec.disks {
+            if !std::path::Path::new(&disk.path).exists() {
+                println!("{} Disk {} does not exist", "Warning:".yellow(), disk.path);
+            }
+        }
+        
+        let xml = self.with_unique_macs(&xml).await?;
+        self.backend.define_domain(&xml).await?;
+        println!("✓ VM '{}' imported", spec.name);
+        
+        Ok(())
+    }
+    
+    /// Brings a domain defined outside vmtools (virt-install, virt-manager, plain
+    /// virsh) under management: records vmtools metadata and adds what other
+    /// commands rely on, reporting what it cannot change
+    pub async fn adopt_vm(&self, name: &str, template: Option<&str>, tags: &[String]) -> Result<()> {
+        if let Err(e) = utils::validate_vm_name(name) {
+            return Err(VmError::InvalidInput(format!(
+                "{}; rename the domain first with: virsh domrename {} <new-name>", e, name
+            )));
+        }
+        if let Some(template) = template {
+            utils::validate_label("Template name", template)?;
+            if self.config.get_template(template).is_none() {
+                return Err(VmError::InvalidInput(format!("Template '{}' not found", template)));
+            }
+        }
+        for tag in tags {
+            utils::validate_label("Tag", tag)?;
+        }
+        println!("Adopting VM '{}'...", name.green());
+        
+        let xml = self.backend.get_inactive_domain_xml(name).await?;
+        let spec = DomainSpec::parse(&xml)?;
+        
+        let mut metadata = match VmMetadata::parse(&xml) {
+            Some(existing) => {
+                println!("{} VM '{}' already has vmtools metadata; updating it", "Info:".cyan(), name);
+                existing
+            }
+            None => VmMetadata {
+                // The oldest disk is the best record of when the VM came to be
+                created_at: Some(spec.disks.iter()
+                    .filter_map(|disk| std::fs::metadata(&disk.path).ok()?.created().ok())
+                    .min()
+                    .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map_or_else(|| chrono::Utc::now().timestamp() as u64, |age| age.as_secs())),
+                adopted: true,
+                ..Default::default()
+            },
+        };
+        if let Some(template) = template {
+            metadata.template = Some(template.to_string());
+        }
+        for tag in tags {
+            if !metadata.tags.contains(tag) {
+                metadata.tags.push(tag.clone());
+            }
+        }
+        if metadata.os_variant.is_none() && self.backend.get_domain_state(name).await? == VmState::Running {
+            let osinfo = self.backend.agent_command(name, &serde_json::json!({ "execute": "guest-get-osinfo" })).await;
+            metadata.os_variant = osinfo.ok().as_ref().and_then(OsVariant::from_agent).map(|variant| variant.short_id);
+        }
+        let mut updated = domain::set_metadata(&xml, &metadata)?;
+        
+        // Identity fixes and guest checks go through the QEMU guest agent
+        let add_agent = !xml.contains("name='org.qemu.guest_agent.0'");
+        if add_agent {
+            println!("🔧 Adding QEMU guest agent channel");
+            if !xml.contains("<controller type='virtio-serial'") {
+                updated = domain::add_device(&updated, "<controller type='virtio-serial' index='0'/>")?;
+            }
+            updated = domain::add_device(&updated, "<channel type='unix'>
+      <target type='virtio' name='org.qemu.guest_agent.0'/>
+    </channel>")?;
+        }
+        
+        self.backend.define_domain(&updated).await?;
+        
+        // Report what vmtools cannot normalize on its own
+        for disk in spec.disks.iter().filter(|disk| disk.format != "qcow2") {
+            println!("{} Disk {} is {}; snapshots and backups need qcow2 (qemu-img convert -O qcow2)",
+                     "Warning:".yellow(), disk.path, disk.format);
+        }
+        if xml.contains("<disk type='block'") || xml.contains("<disk type=\"block\"") {
+            println!("{} Block device disks are left out of disk usage, clones and backups", "Warning:".yellow());
+        }
+        if spec.networks.is_empty() && xml.contains("<interface ") {
+            println!("{} Interfaces not on a libvirt network are skipped by fix-network", "Warning:".yellow());
+        }
+        
+        println!("✓ VM '{}' adopted", name);
+        if let Some(template) = &metadata.template {
+            println!("  Template: {}", template);
+        }
+        if !metadata.tags.is_empty() {
+            println!("  Tags: {}", metadata.tags.join(", "));
+        }
+        if let Some(os_variant) = &metadata.os_variant {
+            println!("  OS: {}", os_variant);
+        }
+        if add_agent && self.backend.get_domain_state(name).await? == VmState::Running {
+            println!("💡 Restart the VM for the guest agent channel to take effect");
+        }
+        
+        Ok(())
+    }
+    
+    /// Sets a new SPICE/VNC console password, generating one when `password` is `None`;
+    /// only a generated password is printed
+    pub async fn rotate_console_password(&self, name: &str, password: Option<&str>) -> Result<()> {
+        // Validate VM name to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(name)?;
+        
+        let vnc = self.backend.get_inactive_domain_xml(name).await?.contains("<graphics type='vnc'");
+        let max_length = if vnc { VNC_PASSWORD_LENGTH } else { SPICE_PASSWORD_LENGTH };
+        
+        let (password, generated) = match password {
+            Some(password) => {
+                if password.is_empty() || password.contains(['\'', '"', '<', '>', '&']) {
+                    return Err(VmError::InvalidInput("Password must be non-empty and free of XML special characters".to_string()));
+                }
+                if vnc && password.len() > VNC_PASSWORD_LENGTH {
+                    return Err(VmError::InvalidInput(format!(
+                        "VNC passwords are limited to {} characters", VNC_PASSWORD_LENGTH
+                    )));
+                }
+                (password.to_string(), false)
+            }
+            None => (utils::generate_password(max_length), true),
+        };
+        
+        self.apply_console_password(name, Some(&password)).await?;
+        println!("✓ Console password of VM '{}' set", name);
+        // A password read from stdin stays off the terminal and out of logs
+        if generated {
+            println!("  Password: {}", password.bold());
+        }
+        Ok(())
+    }
+    
+    /// Sets the password of a guest account by editing the disks of the stopped VM,
+    /// generating one when `password` is `None`
+    pub async fn reset_guest_password(&self, name: &str, user: &str, password: Option<&str>) -> Result<()> {
+        // Validate VM name to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(name)?;
+        
+        utils::validate_guest_user(user)?;
+        if self.backend.get_domain_state(name).await? != VmState::Stopped {
+            return Err(VmError::InvalidInput(format!(
+                "VM '{}' must be shut off; its disks can't be edited while in use", name
+            )));
+        }
+        self.ensure_no_managed_save(name).await?;
+        
+        let (password, generated) = match password {
+            Some(password) if password.is_empty() || password.contains(['\n', '\r']) => {
+                return Err(VmError::InvalidInput("Password must be a single non-empty line".to_string()));
+            }
+            Some(password) => (password.to_string(), false),
+            None => (utils::generate_password(16), true),
+        };
+        
+        println!("Resetting password of '{}' on VM '{}'...", user.green(), name);
+        self.backend.set_guest_password(name, user, &password).await?;
+        println!("🔑 Password of '{}' on VM '{}' reset", user, name);
+        // A password the user chose stays off the terminal and out of logs
+        if generated {
+            println!("  Password: {}", password.bold());
+        }
+        Ok(())
+    }
+    
+    /// Refuses to edit the disks of a VM with a managed save. It reports "shut off",
+    /// but resuming restores memory that no longer matches the disks.
+    async fn ensure_no_managed_save(&self, name: &str) -> Result<()> {
+        if self.backend.list_managed_saves().await?.iter().any(|saved| saved == name) {
+            return Err(VmError::InvalidInput(format!(
+                "VM '{}' has a saved state; start it and shut it down before editing its disks", name
+            )));
+        }
+        Ok(())
+    }
+    
+    /// Removes the console password, leaving the console unauthenticated
+    pub async fn clear_console_password(&self, name: &str) -> Result<()> {
+        // Validate VM name to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(name)?;
+        
+        self.apply_console_password(name, None).await?;
+        println!("✓ Console password of VM '{}' removed", name);
+        println!("{} Anyone who can reach the console port can now use it", "Warning:".yellow());
+        Ok(())
+    }
+    
+    /// Writes the password into the persistent definition and, when running, the live console
+    async fn apply_console_password(&self, name: &str, password: Option<&str>) -> Result<()> {
+        let xml = self.backend.get_inactive_domain_xml(name).await?;
+        self.backend.define_domain(&domain::set_graphics_password(&xml, password)?).await?;
+        
+        if self.backend.get_domain_state(name).await? == VmState::Running {
+            let live = domain::set_graphics_password(&self.backend.get_domain_xml(name).await?, password)?;
+            for graphics in domain::graphics_elements(&live) {
+                self.backend.update_device(name, &graphics).await?;
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Sets the balloon target of a running VM and/or its persistent balloon options
+    pub async fn adjust_balloon(
+        &self,
+        name: &str,
+        target: Option<u64>,
+        autodeflate: Option<bool>,
+        free_page_reporting: Option<bool>,
+    ) -> Result<()> {
+        // Validate VM name to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(name)?;
+        
+        let configure = autodeflate.is_some() || free_page_reporting.is_some();
+        if target.is_none() && !configure {
+            return Err(VmError::InvalidInput(
+                "Nothing to change; give a target size in MB or --autodeflate / --free-page-reporting".to_string()
+            ));
+        }
+        
+        let xml = self.backend.get_inactive_domain_xml(name).await?;
+        let spec = DomainSpec::parse(&xml)?;
+        if spec.balloon.is_none() {
+            return Err(VmError::InvalidInput(format!("VM '{}' has no virtio memory balloon", name)));
+        }
+        let running = self.backend.get_domain_state(name).await? == VmState::Running;
+        
+        if let Some(target) = target {
+            utils::validate_memory(target)?;
+            if target > spec.memory {
+                return Err(VmError::InvalidInput(format!(
+                    "Balloon target {} MB exceeds the VM's {} MB; use 'vmtools set' to raise it", target, spec.memory
+                )));
+            }
+            if !running {
+                return Err(VmError::VmNotRunning(name.to_string()));
+            }
+        }
+        
+        if configure {
+            self.backend.define_domain(&domain::set_balloon_options(&xml, autodeflate, free_page_reporting)?).await?;
+            let on_off = |enabled: bool| if enabled { "on" } else { "off" };
+            if let Some(enabled) = autodeflate {
+                println!("✓ Balloon autodeflate {} for VM '{}'", on_off(enabled), name);
+            }
+            if let Some(enabled) = free_page_reporting {
+                println!("✓ Free page reporting {} for VM '{}'", on_off(enabled), name);
+            }
+            if running {
+                println!("  Takes effect on the next boot");
+            }
+        }
+        
+        if let Some(target) = target {
+            self.backend.set_memory_target(name, target).await?;
+            println!("✓ Balloon target of VM '{}' set to {} MB of {} MB", name, target, spec.memory);
+        }
+        
+        Ok(())
+    }
+    
+    /// What `apply_manifest` would change, VM by VM; VMs that already match are left out
+    pub async fn plan_manifest(&self, manifest: &Manifest) -> Result<Vec<ManifestChange>> {
+        let mut changes = Vec::new();
+        
+        for vm in &manifest.vms {
+            let exists = self.backend.domain_exists(&vm.name).await?;
+            if vm.state == DesiredState::Absent {
+                if exists {
+                    changes.push(ManifestChange::Delete { vm: vm.name.clone() });
+                }
+                continue;
+            }
+            
+            let template = self.manifest_template(vm)?;
+            if !exists {
+                changes.push(ManifestChange::Create { vm: vm.name.clone() });
+                continue;
+            }
+            
+            let spec = DomainSpec::parse(&self.backend.get_inactive_domain_xml(&vm.name).await?)?;
+            let mut resources = Vec::new();
+            if template.memory != spec.memory {
+                resources.push(SpecDifference {
+                    field: "memory".to_string(),
+                    expected: format!("{} MB", template.memory),
+                    actual: format!("{} MB", spec.memory),
+                });
+            }
+            if template.cpus != spec.cpus {
+                resources.push(SpecDifference {
+                    field: "cpus".to_string(),
+                    expected: template.cpus.to_string(),
+                    actual: spec.cpus.to_string(),
+                });
+            }
+            if !resources.is_empty() {
+                if self.backend.get_domain_state(&vm.name).await? == VmState::Stopped {
+                    changes.push(ManifestChange::Modify { vm: vm.name.clone(), differences: resources });
+                } else {
+                    changes.push(ManifestChange::Drift {
+                        vm: vm.name.clone(),
+                        differences: resources,
+                        reason: "VM is running; stop it and apply again to resize".to_string(),
+                    });
+                }
+            }
+            
+            if !vm.networks.is_empty() && vm.networks != spec.networks {
+                changes.push(ManifestChange::Drift {
+                    vm: vm.name.clone(),
+                    differences: vec![SpecDifference {
+                        field: "networks".to_string(),
+                        expected: vm.networks.join(", "),
+                        actual: spec.networks.join(", "),
+                    }],
+                    reason: "interfaces of existing VMs are not changed".to_string(),
+                });
+            }
+        }
+        
+        Ok(changes)
+    }
+    
+    /// Reconciles VMs with `manifest`: creates missing ones, resizes stopped ones
+    /// whose memory or vCPUs differ, trashes ones marked absent, and reports
+    /// drift it cannot fix
+    pub async fn apply_manifest(&self, manifest: &Manifest) -> Result<()> {
+        let changes = self.plan_manifest(manifest).await?;
+        if changes.is_empty() {
+            println!("✓ All {} VMs match the manifest", manifest.vms.len());
+            return Ok(());
+        }
+        
+        let (mut created, mut modified, mut deleted, mut drifted) = (0, 0, 0, 0);
+        for change in &changes {
+            match change {
+                ManifestChange::Create { vm } => {
+                    let vm = manifest.vms.iter().find(|spec| spec.name == *vm)
+                        .ok_or_else(|| VmError::VmNotFound(vm.clone()))?;
+                    self.create_manifest_vm(vm).await?;
+                    created += 1;
+                }
+                ManifestChange::Modify { vm, .. } => {
+                    let spec = manifest.vms.iter().find(|spec| spec.name == *vm)
+                        .ok_or_else(|| VmError::VmNotFound(vm.clone()))?;
+                    let template = self.manifest_template(spec)?;
+                    self.set_resources(vm, Some(template.memory), Some(template.cpus)).await?;
+                    modified += 1;
+                }
+                ManifestChange::Delete { vm } => {
+                    self.delete_vm(vm, true).await?;
+                    deleted += 1;
+                }
+                ManifestChange::Drift { vm, differences, reason } => {
+                    println!("{} VM '{}' has drifted: {}", "⚠".yellow(), vm, reason);
+                    for difference in differences {
+                        println!("    {}: {} (manifest: {})", difference.field, difference.actual, difference.expected);
+                    }
+                    drifted += 1;
+                }
+            }
+        }
+        
+        println!("Apply complete: {} created, {} modified, {} deleted, {} drifted", created, modified, deleted, drifted);
+        Ok(())
+    }
+    
+    /// A manifest entry's hardware: its template, or `[defaults]`, with its own overrides
+    fn manifest_template(&self, vm: &VmManifest) -> Result<VmTemplate> {
+        let mut template = match &vm.template {
+            Some(name) => self.config.get_template(name)
+                .ok_or_else(|| VmError::InvalidInput(format!("Template '{}' of VM '{}' not found", name, vm.name)))?
+                .clone(),
+            None => {
+                let defaults = &self.config.defaults;
+                Self::default_template(defaults.memory, defaults.cpus, defaults.disk_size)
+            }
+        };
+        if let Some(memory) = vm.memory {
+            utils::validate_memory(memory)?;
+            template.memory = memory;
+        }
+        if let Some(cpus) = vm.cpus {
+            utils::validate_cpus(cpus)?;
+            template.cpus = cpus;
+        }
+        if let Some(disk_size) = vm.disk_size {
+            template.disk_size = disk_size;
+        }
+        Ok(template)
+    }
+    
+    /// Creates a manifest entry's VM, with a cloud-init seed as its CD-ROM if it has one
+    async fn create_manifest_vm(&self, vm: &VmManifest) -> Result<()> {
+        let template = self.manifest_template(vm)?;
+        let mut options = CreateOptions {
+            memory: template.memory,
+            cpus: template.cpus,
+            disk_size: template.disk_size,
+            template: vm.template.clone(),
+            networks: vm.networks.clone(),
+            base_image: vm.image.clone(),
+            ..Default::default()
+        };
+        
+        if let Some(cloud_init) = &vm.cloud_init {
+            let seed = self.config.storage.vm_images_path.join(format!("{}-cidata.iso", vm.name));
+            cloud_init.build_seed(self.runner.as_ref(), &vm.name, &seed, &self.config.system.temp_dir).await?;
+            println!("{} cloud-init seed written to {}", "Cloud-init:".cyan(), seed.display());
+            options.iso_path = Some(seed.to_string_lossy().into_owned());
+        }
+        
+        self.create_from_template(&vm.name, template, &options).await
+    }
+    
+    /// Limits the average inbound/outbound rate of the interface with MAC address `mac`,
+    /// in the definition and, when the VM is running, live. Rates take units such as
+    /// `100mbit`; `0` lifts a limit and a direction left out keeps its current one.
+    pub async fn limit_nic(&self, name: &str, mac: &str, inbound: Option<&str>, outbound: Option<&str>) -> Result<()> {
+        // Validate VM name to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(name)?;
+        
+        if inbound.is_none() && outbound.is_none() {
+            return Err(VmError::InvalidInput("Nothing to change; pass --inbound and/or --outbound".to_string()));
+        }
+        let inbound = inbound.map(utils::parse_bandwidth).transpose()?;
+        let outbound = outbound.map(utils::parse_bandwidth).transpose()?;
+        
+        let xml = self.backend.get_inactive_domain_xml(name).await?;
+        let current = domain::interface_bandwidth(&xml, mac)?;
+        let bandwidth = Bandwidth {
+            inbound: inbound.unwrap_or(current.inbound),
+            outbound: outbound.unwrap_or(current.outbound),
+        };
+        
+        self.backend.define_domain(&domain::set_interface_bandwidth(&xml, mac, bandwidth)?).await?;
+        if self.backend.get_domain_state(name).await? == VmState::Running {
+            self.backend.set_interface_bandwidth(name, mac, bandwidth.inbound, bandwidth.outbound).await?;
+        }
+        
+        let rate = |kib: u64| if kib == 0 {
+            "unlimited".to_string()
+        } else {
+            format!("{}/s", utils::format_bytes(kib * 1024))
+        };
+        println!("✓ Bandwidth of {} on VM '{}' set", mac, name);
+        println!("  Inbound:  {}", rate(bandwidth.inbound));
+        println!("  Outbound: {}", rate(bandwidth.outbound));
+        
+        Ok(())
+    }
+    
+    /// Sets a VM's CPU weight (`shares`, default 1024) and caps each vCPU at `quota`
+    /// (e.g. `50%`), in the definition and, when the VM is running, live.
+    /// `0` resets either setting; one left out keeps its current value.
+    pub async fn limit_cpu(&self, name: &str, shares: Option<u64>, quota: Option<&str>) -> Result<()> {
+        // Validate VM name to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(name)?;
+        
+        if shares.is_none() && quota.is_none() {
+            return Err(VmError::InvalidInput("Nothing to change; pass --shares and/or --quota".to_string()));
+        }
+        if let Some(shares) = shares {
+            if shares != 0 && !(2..=262_144).contains(&shares) {
+                return Err(VmError::InvalidInput(format!("--shares must be between 2 and 262144, got {}", shares)));
+            }
+        }
+        let quota = quota.map(|quota| {
+            quota.trim().trim_end_matches('%').parse::<u32>().ok()
+                .filter(|percent| *percent <= 100)
+                .ok_or_else(|| VmError::InvalidInput(format!(
+                    "Invalid quota '{}'; use a percentage of each vCPU from 1% to 100%, or 0 to uncap", quota
+                )))
+        }).transpose()?;
+        
+        let xml = self.backend.get_inactive_domain_xml(name).await?;
+        let current = domain::cpu_tune(&xml);
+        let tune = CpuTune {
+            shares: shares.unwrap_or(current.shares),
+            // 100% of every vCPU is no cap at all
+            quota: quota.map(|percent| if percent == 100 { 0 } else { percent }).unwrap_or(current.quota),
+        };
+        
+        self.backend.define_domain(&domain::set_cpu_tune(&xml, tune)?).await?;
+        if self.backend.get_domain_state(name).await? == VmState::Running {
+            let live_quota = if tune.quota == 0 { -1 } else { (domain::CPU_PERIOD * tune.quota as u64 / 100) as i64 };
+            let live_shares = if tune.shares == 0 { 1024 } else { tune.shares };
+            self.backend.set_cpu_tune(name, live_shares, domain::CPU_PERIOD, live_quota).await?;
+        }
+        
+        println!("✓ CPU limits of VM '{}' set", name);
+        println!("  Shares: {}", if tune.shares == 0 { "1024 (default)".to_string() } else { tune.shares.to_string() });
+        println!("  Quota:  {}", if tune.quota == 0 { "uncapped".to_string() } else { format!("{}% per vCPU", tune.quota) });
+        
+        Ok(())
+    }
+    
+    /// Changes the memory (MiB) and/or vCPU count of a stopped VM's definition
+    pub async fn set_resources(&self, name: &str, memory: Option<u64>, cpus: Option<u32>) -> Result<()> {
+        // Validate VM name to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(name)?;
+        
+        if memory.is_none() && cpus.is_none() {
+            return Err(VmError::InvalidInput("Nothing to change; pass --memory and/or --cpus".to_string()));
+        }
+        if let Some(memory) = memory {
+            utils::validate_memory(memory)?;
+        }
+        if let Some(cpus) = cpus {
+            utils::validate_cpus(cpus)?;
+        }
+        
+        if self.backend.get_domain_state(name).await? != VmState::Stopped {
+            return Err(VmError::InvalidVmState(format!(
+                "VM '{}' must be stopped to change its resources; run 'vmtools stop {}' first", name, name
+            )));
+        }
+        
+        let mut xml = self.backend.get_inactive_domain_xml(name).await?;
+        let spec = DomainSpec::parse(&xml)?;
+        if let Some(memory) = memory {
+            xml = domain::set_memory(&xml, memory)?;
+        }
+        if let Some(cpus) = cpus {
+            xml = domain::set_cpus(&xml, cpus)?;
+        }
+        self.backend.define_domain(&xml).await?;
+        
+        println!("✓ Updated VM '{}'", name);
+        if let Some(memory) = memory {
+            println!("  Memory: {} MB → {} MB", spec.memory, memory);
+        }
+        if let Some(cpus) = cpus {
+            println!("  CPUs: {} → {}", spec.cpus, cpus);
+        }
+        
+        Ok(())
+    }
+    
+    /// Replaces the boot device order; takes effect on the next boot
+    pub async fn set_boot_order(&self, name: &str, order: &[String]) -> Result<()> {
+        // Validate VM name to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(name)?;
+        
+        for (i, dev) in order.iter().enumerate() {
+            if !BOOT_DEVICES.contains(&dev.as_str()) {
+                return Err(VmError::InvalidInput(format!(
+                    "Unknown boot device '{}'. Use {}", dev, BOOT_DEVICES.join(", ")
+                )));
+            }
+            if order[..i].contains(dev) {
+                return Err(VmError::InvalidInput(format!("Boot device '{}' is listed twice", dev)));
+            }
+        }
+        if order.is_empty() {
+            return Err(VmError::InvalidInput("Boot order must list at least one device".to_string()));
+        }
+        
+        let xml = self.backend.get_inactive_domain_xml(name).await?;
+        if order.iter().any(|dev| dev == "cdrom") && DomainSpec::parse(&xml)?.cdrom.is_none() {
+            println!("{} VM '{}' has no CD-ROM drive; 'cdrom' will be skipped", "Warning:".yellow(), name);
+        }
+        
+        self.backend.define_domain(&domain::set_boot_order(&xml, order)?).await?;
+        println!("✓ Boot order of VM '{}' set to {}", name, order.join(", "));
+        
+        if self.backend.get_domain_state(name).await? == VmState::Running {
+            println!("  Takes effect on the next boot");
+        }
+        
+        Ok(())
+    }
+    
+    pub async fn stop_vm(&self, name: &str, force: bool) -> Result<()> {
+        let action = if force { "Force stopping" } else { "Stopping" };
+        println!("{} VM '{}'...", action, name.red());
+        
+        // Validate VM name to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(name)?;
+        
+        if force {
+            self.backend.destroy_domain(name).await?;
+        } else {
+            self.backend.shutdown_domain(name).await?;
+        }
+        
+        println!("✓ VM '{}' stopped successfully", name);
+        Ok(())
+    }
+    
+    /// Detailed information about a single VM
+    pub async fn info(&self, name: &str) -> Result<VmInfo> {
+        // Validate VM name to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(name)?;
+        
+        let mut info = self.backend.get_domain_info(name).await?;
+        self.load_metadata(&mut info).await;
+        self.load_health(std::slice::from_mut(&mut info)).await;
+        if info.state == VmState::Running {
+            // An agent that is configured but not running would hold status up for the full agent timeout
+            info.guest = tokio::time::timeout(Duration::from_secs(2), self.guest_info(name)).await
+                .ok().and_then(|guest| guest.ok());
+        }
+        info.graphics = self.graphics(name, info.state == VmState::Running).await;
+        Ok(info)
+    }
+    
+    /// The consoles in the VM's live definition; for a running VM, QEMU's
+    /// query-spice and query-vnc tell whether a viewer is attached
+    async fn graphics(&self, name: &str, running: bool) -> Vec<GraphicsInfo> {
+        let Ok(xml) = self.backend.get_domain_xml(name).await else {
+            return Vec::new();
+        };
+        let mut consoles: Vec<GraphicsInfo> = domain::graphics_elements(&xml).iter()
+            .filter_map(|element| GraphicsInfo::parse(element))
+            .collect();
+        if !running {
+            return consoles;
+        }
+        for console in consoles.iter_mut() {
+            let (command, viewers) = match console.kind.as_str() {
+                "spice" => ("query-spice", "channels"),
+                "vnc" => ("query-vnc", "clients"),
+                _ => continue,
+            };
+            let query = serde_json::json!({ "execute": command });
+            let reply = tokio::time::timeout(Duration::from_secs(2), self.backend.monitor_command(name, &query)).await;
+            if let Ok(Ok(reply)) = reply {
+                console.connected = reply.get(viewers).and_then(|v| v.as_array()).map(|v| !v.is_empty());
+            }
+        }
+        consoles
+    }
+    
+    pub async fn create_vm(&self, name: &str, options: &CreateOptions) -> Result<()> {
+        let template = if let Some(template_name) = &options.template {
+            self.config.get_template(template_name)
+                .ok_or_else(|| VmError::InvalidInput(format!("Template '{}' not found", template_name)))?
+                .clone()
+        } else {
+            Self::default_template(options.memory, options.cpus, options.disk_size)
+        };
+        self.create_from_template(name, template, options).await
+    }
+    
+    /// Prepares a disk image in place (packages, commands, SSH keys), e.g. a golden
+    /// image before VMs are created from it. Images a running VM uses are refused.
+    pub async fn customize_image(&self, image: &std::path::Path, customization: &Customization) -> Result<()> {
+        if customization.is_empty() {
+            return Err(VmError::InvalidInput(
+                "Nothing to do; pass --install, --run-command and/or --ssh-inject".to_string()
+            ));
+        }
+        customization.validate()?;
+        let image = image.canonicalize()
+            .map_err(|e| VmError::InvalidInput(format!("Image {}: {}", image.display(), e)))?;
+        
+        for vm in self.backend.list_domains(false, false).await? {
+            let in_use = vm.disk_usage.iter()
+                .any(|disk| std::path::Path::new(&disk.path).canonicalize().is_ok_and(|path| path == image));
+            if in_use {
+                return Err(VmError::InvalidInput(format!(
+                    "{} is in use by running VM '{}'", image.display(), vm.name
+                )));
+            }
+        }
+        // Overlays record the base's blocks as unchanged, so editing it corrupts them
+        for (vm, chain) in self.disk_chains("").await? {
+            match chain.iter().position(|path| *path == image) {
+                Some(0) => self.ensure_no_managed_save(&vm).await?,
+                Some(_) => return Err(VmError::InvalidInput(format!(
+                    "{} is a backing file of VM '{}'; flatten that VM with 'vmtools disk pull' first", image.display(), vm
+                ))),
+                None => {}
+            }
+        }
+        
+        println!("Customizing image {}...", image.display().to_string().green());
+        self.backend.customize_image(&image, customization).await?;
+        println!("✓ Image {} customized", image.display());
+        Ok(())
+    }
+    
+    /// Creates a VM around a disk image built elsewhere (packer, virt-builder, ...),
+    /// copied into the images directory or, with `link`, used as a backing file.
+    /// `os_variant` names the guest like virt-install does (`win11`, `ubuntu24.04`, ...);
+    /// Windows variants get the `windows` template's hardware.
+    pub async fn import_disk(
+        &self,
+        name: &str,
+        image: &std::path::Path,
+        os_variant: Option<&str>,
+        memory: Option<u64>,
+        cpus: Option<u32>,
+        link: bool,
+    ) -> Result<()> {
+        if !image.is_file() {
+            return Err(VmError::InvalidInput(format!("Disk image not found: {}", image.display())));
+        }
+        
+        let os_variant = os_variant.map(str::parse::<OsVariant>).transpose().map_err(VmError::InvalidInput)?;
+        let defaults = &self.config.defaults;
+        let mut template = Self::default_template(defaults.memory, defaults.cpus, defaults.disk_size);
+        if os_variant.as_ref().is_some_and(|variant| variant.defaults().windows) {
+            template = match self.config.get_template("windows") {
+                Some(windows) => windows.clone(),
+                None => VmTemplate {
+                    os_type: "windows".to_string(),
+                    features: vec!["acpi".to_string(), "apic".to_string(), "hyperv".to_string()],
+                    ..template
+                },
+            };
+        }
+        if let Some(memory) = memory {
+            template.memory = memory;
+        }
+        if let Some(cpus) = cpus {
+            template.cpus = cpus;
+        }
+        utils::validate_memory(template.memory)?;
+        utils::validate_cpus(template.cpus)?;
+        
+        let options = CreateOptions {
+            memory: template.memory,
+            cpus: template.cpus,
+            base_image: Some(image.to_path_buf()),
+            link_base_image: link,
+            os_variant,
+            ..Default::default()
+        };
+        self.create_from_template(name, template, &options).await?;
+        
+        if link {
+            println!("💡 The VM reads from {}; keep it in place and unchanged", image.display());
+        }
+        Ok(())
+    }
+    
+    /// The hardware of VMs created without a template
+    fn default_template(memory: u64, cpus: u32, disk_size: u64) -> VmTemplate {
+        VmTemplate {
+            memory,
+            cpus,
+            disk_size,
+            os_type: "linux".to_string(),
+            arch: "x86_64".to_string(),
+            machine_type: "pc-q35-7.0".to_string(),
+            boot_order: vec!["hd".to_string(), "cdrom".to_string()],
+            features: vec!["acpi".to_string(), "apic".to_string()],
+            iothreads: 1,
+            user_data_path: None,
+            network_config: None,
+            extends: None,
+        }
+    }
+    
+    /// Creates a VM with `template`'s hardware; `options` supplies everything else
+    async fn create_from_template(&self, name: &str, mut template: VmTemplate, options: &CreateOptions) -> Result<()> {
+        let iso_path = options.iso_path.as_deref();
+        let disk_size = options.disk_size;
+        println!("Creating VM '{}'...", name.green());
+        
+        // Validate VM name to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(name)?;
+        
+        // Check if VM already exists
+        if self.backend.domain_exists(name).await? {
+            return Err(VmError::VmAlreadyExists(name.to_string()));
         }
 
-        // Check available networks and select the best one
-        let available_networks = self.libvirt.list_networks().await?;
+        if self.is_lxc() {
+            return Err(VmError::InvalidInput(format!(
+                "{} manages containers; use --type container --rootfs <dir>", self.config.libvirt.uri
+            )));
+        }
+        
+        let selected_network = match options.networks.first() {
+            Some(primary) => {
+                self.check_networks(&options.networks).await?;
+                println!("{} Using network: {}", "Network:".cyan(), primary.green());
+                primary.clone()
+            }
+            None => self.select_network().await?,
+        };
+        let graphics = self.graphics_options(options)?;
+        let kernel_boot = options.kernel_boot.as_ref().map(Self::resolve_kernel_boot).transpose()?;
+        
+        let os_variant = match (&options.os_variant, iso_path) {
+            (Some(variant), _) => Some(variant.clone()),
+            (None, Some(iso)) => match osinfo::detect_media(self.runner.as_ref(), std::path::Path::new(iso)).await {
+                Ok(Some(variant)) => {
+                    println!("{} detected {} on the ISO", "OS:".cyan(), variant);
+                    Some(variant)
+                }
+                Ok(None) => None,
+                Err(e) => {
+                    eprintln!("{} Could not detect the guest OS: {}", "⚠️ ".yellow(), e);
+                    None
+                }
+            },
+            (None, None) => None,
+        };
+        let os_defaults = os_variant.as_ref().map(OsVariant::defaults).unwrap_or_default();
+        if os_defaults.virtio != VirtioSupport::Builtin && options.disk_bus == DiskBus::VirtioScsi {
+            return Err(VmError::InvalidInput(format!(
+                "{} has no virtio drivers in its installer, so it can't boot from --disk-bus virtio-scsi",
+                os_variant.as_ref().map_or_else(String::new, |variant| variant.to_string())
+            )));
+        }
+        
+        if let Some(arch) = &options.arch {
+            template.arch = arch.clone();
+        }
+        if let Some(iothreads) = options.iothreads {
+            if iothreads > 64 {
+                return Err(VmError::InvalidInput(format!(
+                    "--iothreads must be between 0 and 64, got {}", iothreads
+                )));
+            }
+            template.iothreads = iothreads;
+        }
+        
+        // KVM can only run guests of the host's own architecture
+        if template.arch != arch::host_arch() && !options.emulated {
+            return Err(VmError::InvalidInput(format!(
+                "{} guests cannot use KVM on this {} host; add --emulated to run them under TCG",
+                template.arch, arch::host_arch()
+            )));
+        }
+        if let Some(queues) = options.net_queues {
+            if !(1..=256).contains(&queues) {
+                return Err(VmError::InvalidInput(format!(
+                    "--net-queues must be between 1 and 256, got {}", queues
+                )));
+            }
+        }
+        let (emulation, cpu_model) = if options.emulated {
+            if matches!(options.cpu_model, Some(CpuModel::HostPassthrough | CpuModel::HostModel)) {
+                return Err(VmError::InvalidInput(
+                    "Emulated guests cannot use the host CPU; pass a named --cpu-model".to_string()
+                ));
+            }
+            let profile = ArchProfile::for_arch(&template.arch)?;
+            println!("{} {} guest emulated with {}", "Emulation:".cyan(), template.arch, profile.emulator.display());
+            (Some(profile), options.cpu_model.clone())
+        } else {
+            let cpu_model = options.cpu_model.clone().unwrap_or_else(|| self.config.defaults.cpu_model.clone());
+            self.fit_to_host(&mut template, &cpu_model).await?;
+            (None, Some(cpu_model))
+        };
+        
+        // An unusable image directory fails here, not halfway through qemu-img
+        if options.disk.is_none() || template.cloud_init()?.is_some() {
+            self.prepare_directory(&self.config.storage.vm_images_path, "storage.vm_images_path")?;
+        }
+
+        // The template's cloud-init seed takes the CD-ROM unless an ISO already has it
+        let seed = match template.cloud_init()? {
+            Some(_) if iso_path.is_some() => {
+                println!("{} template seed skipped; the CD-ROM holds {}", "Cloud-init:".cyan(), iso_path.unwrap_or_default());
+                None
+            }
+            Some(cloud_init) => {
+                let seed = self.config.storage.vm_images_path.join(format!("{}-cidata.iso", name));
+                cloud_init.build_seed(self.runner.as_ref(), name, &seed, &self.config.system.temp_dir).await?;
+                println!("{} template seed written to {}", "Cloud-init:".cyan(), seed.display());
+                Some(seed.to_string_lossy().into_owned())
+            }
+            None => None,
+        };
+        
+        let pb = Self::step_progress();
+        pb.set_message("Creating disk image...");
+        
+        // Create disk image
+        let disk = match (&options.disk, &options.base_image) {
+            (Some(_), Some(_)) => {
+                return Err(VmError::InvalidInput("--disk cannot be combined with a base image".to_string()));
+            }
+            (Some(source), None) => self.resolve_disk_source(source, Some((name, disk_size * 1024 * 1024 * 1024))).await?,
+            (None, Some(image)) => {
+                if !image.is_file() {
+                    return Err(VmError::InvalidInput(format!("Disk image not found: {}", image.display())));
+                }
+                pb.set_message("Importing disk image...");
+                if options.link_base_image {
+                    self.label_image(image, true).await;
+                }
+                let disk_path = self.config.storage.vm_images_path.join(format!("{}.qcow2", name));
+                self.backend.import_disk(image, &disk_path, options.link_base_image, &Self::copy_progress(&pb)).await?;
+                DiskSource::File(disk_path)
+            }
+            (None, None) => {
+                let disk_path = self.config.storage.vm_images_path.join(format!("{}.qcow2", name));
+                self.backend.create_disk(&disk_path, disk_size * 1024 * 1024 * 1024).await?;
+                DiskSource::File(disk_path)
+            }
+        };
+        
+        pb.set_style(Self::step_style());
+        pb.set_message("Generating VM configuration...");
+        
+        // Generate XML configuration
+        let devices = DeviceOptions {
+            iso: iso_path.or(seed.as_deref()),
+            network: &selected_network,
+            desktop: DesktopConfig {
+                usb_redirect: options.usb_redirect.unwrap_or(self.config.desktop.usb_redirect),
+                audio: options.audio.unwrap_or(self.config.desktop.audio),
+            },
+            graphics,
+            disk_bus: options.disk_bus,
+            // Queue pairs are a virtio-net feature
+            net_queues: match os_defaults.virtio {
+                VirtioSupport::Builtin => options.net_queues.unwrap_or(template.cpus),
+                _ => 1,
+            },
+            cpu_model,
+        };
+        let mut xml_config = self.generate_vm_xml(name, &template, &disk, &devices, emulation.as_ref())?;
+        if emulation.is_none() {
+            xml_config = osinfo::fit(&xml_config, &os_defaults)?;
+        }
+        for network in options.networks.iter().skip(1) {
+            xml_config = domain::add_device(&xml_config, &format!(r#"<interface type='network'>
+      <mac address='{}'/>
+      <source network='{}'/>
+      <model type='virtio'/>
+    </interface>"#, utils::generate_mac_address(), network))?;
+        }
+        if let Some(boot) = &kernel_boot {
+            xml_config = domain::set_kernel_boot(&xml_config, boot)?;
+        }
+        
+        pb.set_message("Registering VM with libvirt...");
+        
+        // An installer ISO with a blank disk boots first, until the first start
+        let mut metadata = Self::creation_metadata(options.template.as_deref());
+        metadata.os_variant = os_variant.as_ref().map(|variant| variant.short_id.clone());
+        if iso_path.is_some() && options.base_image.is_none() && options.disk.is_none() && kernel_boot.is_none() {
+            let mut order = vec!["cdrom".to_string()];
+            order.extend(DomainSpec::parse(&xml_config)?.boot_order.into_iter().filter(|dev| dev != "cdrom"));
+            xml_config = domain::set_boot_order(&xml_config, &order)?;
+            metadata.iso_first_boot = true;
+        }
+        
+        // Define the domain
+        let xml_config = self.with_unique_macs(&xml_config).await?;
+        self.backend.define_domain(&domain::set_metadata(&xml_config, &metadata)?).await?;
+        
+        pb.set_message("VM created successfully");
+        pb.finish_with_message(format!("✓ VM '{}' created successfully", name));
+        
+        println!("VM Configuration:");
+        println!("  Memory: {}MB", template.memory);
+        println!("  CPUs: {}", template.cpus);
+        println!("  Disk: {}GB", template.disk_size);
+        println!("  Disk Path: {}", disk);
+        
+        if let Some(iso) = iso_path {
+            println!("  ISO: {}", iso);
+        }
+        if let Some(variant) = &os_variant {
+            println!("  OS: {}", variant);
+            if os_defaults.virtio == VirtioSupport::Separate {
+                println!("💡 Disks are SATA and the NIC is {} until the guest has virtio drivers; \
+                          install them from virtio-win.iso, then run: vmtools optimize {} --apply",
+                         os_defaults.nic_model, name);
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Creates an LXC container whose root filesystem is the directory `rootfs`
+    pub async fn create_container(&self, name: &str, memory: u64, cpus: u32, rootfs: &std::path::Path) -> Result<()> {
+        println!("Creating container '{}'...", name.green());
+        
+        // Validate VM name to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(name)?;
+        
+        if !self.is_lxc() {
+            return Err(VmError::InvalidInput(format!(
+                "Containers need an LXC connection, but libvirt.uri is {}.\n  \
+                 Use: vmtools config --set libvirt.uri=lxc:///", self.config.libvirt.uri
+            )));
+        }
+        
+        if !rootfs.is_dir() {
+            return Err(VmError::InvalidInput(format!("Root filesystem {} is not a directory", rootfs.display())));
+        }
+        if !rootfs.join("sbin/init").exists() {
+            println!("{} {} has no /sbin/init; the container will not boot until one is installed",
+                     "Warning:".yellow(), rootfs.display());
+        }
+        
+        if self.backend.domain_exists(name).await? {
+            return Err(VmError::VmAlreadyExists(name.to_string()));
+        }
+        
+        let network = self.select_network().await?;
+        let xml = format!(r#"<domain type='lxc'>
+  <name>{}</name>
+  <uuid>{}</uuid>
+  <memory unit='MiB'>{}</memory>
+  <currentMemory unit='MiB'>{}</currentMemory>
+  <vcpu placement='static'>{}</vcpu>
+  <os>
+    <type>exe</type>
+    <init>/sbin/init</init>
+  </os>
+  <clock offset='utc'/>
+  <on_poweroff>destroy</on_poweroff>
+  <on_reboot>restart</on_reboot>
+  <on_crash>destroy</on_crash>
+  <devices>
+    <filesystem type='mount' accessmode='passthrough'>
+      <source dir='{}'/>
+      <target dir='/'/>
+    </filesystem>
+    <interface type='network'>
+      <mac address='{}'/>
+      <source network='{}'/>
+    </interface>
+    <console type='pty'/>
+  </devices>
+</domain>"#,
+            name,
+            uuid::Uuid::new_v4(),
+            memory,
+            memory,
+            cpus,
+            rootfs.display(),
+            utils::generate_mac_address(),
+            network
+        );
+        
+        let xml = self.with_unique_macs(&xml).await?;
+        self.backend.define_domain(&xml).await?;
+        
+        println!("✓ Container '{}' created", name);
+        println!("  Memory: {}MB", memory);
+        println!("  CPUs: {}", cpus);
+        println!("  Root filesystem: {}", rootfs.display());
+        Ok(())
+    }
+    
+    fn is_lxc(&self) -> bool {
+        self.config.libvirt.uri.starts_with("lxc:")
+    }
+    
+    /// Picks the configured default network, or the first active one
+    /// Fails unless every network in `networks` exists and is active
+    async fn check_networks(&self, networks: &[String]) -> Result<()> {
+        let available = self.backend.list_networks().await?;
+        for network in networks {
+            match available.iter().find(|(name, _, _, _)| name == network) {
+                Some((_, true, _, _)) => {}
+                Some(_) => return Err(VmError::NetworkError(format!(
+                    "Network '{}' is not active; start it with: virsh net-start {}", network, network
+                ))),
+                None => return Err(VmError::NetworkError(format!("Network '{}' does not exist", network))),
+            }
+        }
+        Ok(())
+    }
+    
+    async fn select_network(&self) -> Result<String> {
+        let available_networks = self.backend.list_networks().await?;
         let active_networks: Vec<String> = available_networks.iter()
             .filter(|(_, active, _, _)| *active)
             .map(|(name, _, _, _)| name.clone())
             .collect();
-        
+        
+        let selected_network = if active_networks.contains(&self.config.network.default_network) {
+            println!("{} Using default network: {}", 
+                     "Network:".cyan(), self.config.network.default_network.green());
+            self.config.network.default_network.clone()
+        } else if let Some(first_network) = active_networks.first() {
+            println!("{} Default network '{}' not available, using: {}", 
+                     "Network:".yellow(), 
+                     self.config.network.default_network,
+                     first_network.green());
+            first_network.clone()
+        } else {
+            return Err(VmError::NetworkError(
+                "No active virtual networks found. Please start a network first:\n  virsh net-start default\n  or create a new network.".to_string()
+            ));
+        };
+        
+        println!("{} Available networks: {}", 
+                 "Info:".cyan(), 
+                 active_networks.join(", "));
+        
+        Ok(selected_network)
+    }
+    
+    /// Deletes the VM and its disks, or moves both to the trash; asking the
+    /// user first is up to the caller
+    pub async fn delete_vm(&self, name: &str, to_trash: bool) -> Result<()> {
+        // Validate VM name to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(name)?;
+        
+        println!("Deleting VM '{}'...", name.red());
+        
+        // The definition, not the VM's name, says where its disks are
+        let images = self.disk_images(&self.backend.get_inactive_domain_xml(name).await?).await?;
+        let thin_volumes = self.thin_volume_disks(name).await?;
+        
+        // Removing (or trashing) an image other VMs' overlays sit on would break them
+        let shared = self.images_used_by_others(name).await?;
+        for image in &images {
+            if let Some(user) = shared.get(&Self::image_key(&image.disk.path)) {
+                return Err(VmError::InvalidInput(format!(
+                    "{} of VM '{}' is a backing file of VM '{}'; flatten that VM with 'vmtools disk pull' first",
+                    image.disk.path, name, user
+                )));
+            }
+        }
+        // LUNs are made and removed on the storage appliance
+        let luns: Vec<&DiskImage> = images.iter()
+            .filter(|image| image.volume.is_some() && !std::path::Path::new(&image.disk.path).is_file())
+            .collect();
+        
+        // Stop VM if running
+        let state = self.backend.get_domain_state(name).await?;
+        if state == VmState::Running {
+            self.backend.destroy_domain(name).await?;
+        }
+        
+        if to_trash {
+            let xml = self.backend.get_inactive_domain_xml(name).await?;
+            self.backend.undefine_domain(name).await?;
+            
+            let disk_paths: Vec<String> = images.iter()
+                .filter(|image| !luns.iter().any(|lun| lun.disk.path == image.disk.path))
+                .map(|image| image.disk.path.clone())
+                .collect();
+            let pool_volumes = luns.iter()
+                .filter_map(|lun| lun.volume.clone())
+                .map(|(pool, volume)| TrashedVolume { pool, volume })
+                .collect();
+            let entry = self.trash().store(name, &xml, &disk_paths, thin_volumes.clone(), pool_volumes).await?;
+            
+            println!("✓ VM '{}' moved to trash: {}", name, entry.dir.display());
+            for device in &thin_volumes {
+                println!("{} Volume {} stays in place for the restore", "Info:".cyan(), device.display());
+            }
+            for lun in &luns {
+                println!("{} Volume {} stays in place for the restore", "Info:".cyan(), lun.disk.path);
+            }
+            println!("💡 Restore it with: vmtools undelete {}", name);
+            self.purge_trash().await;
+            return Ok(());
+        }
+        
+        // Undefine the domain
+        self.backend.undefine_domain(name).await?;
+        
+        // Delete disk files; those in a storage pool through the pool, so it doesn't list them any more
+        for image in &images {
+            let result = match &image.volume {
+                Some(_) if luns.iter().any(|lun| lun.disk.path == image.disk.path) => {
+                    println!("{} Volume {} stays; remove the LUN on the storage side", "Info:".cyan(), image.disk.path);
+                    continue;
+                }
+                Some((pool, volume)) => self.backend.delete_volume(pool, volume).await,
+                None => tokio::fs::remove_file(&image.disk.path).await.map_err(VmError::from),
+            };
+            if let Err(e) = result {
+                tracing::warn!("Failed to delete disk {}: {}", image.disk.path, e);
+            }
+        }
+        for device in &thin_volumes {
+            if let Err(e) = self.backend.remove_thin_volume(device).await {
+                tracing::warn!("Failed to remove volume {}: {}", device.display(), e);
+            }
+        }
+        
+        println!("✓ VM '{}' deleted successfully", name);
+        Ok(())
+    }
+    
+    /// Restores the most recently trashed VM with the given name
+    pub async fn undelete_vm(&self, name: &str) -> Result<()> {
+        // Validate VM name to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(name)?;
+        
+        if self.backend.domain_exists(name).await? {
+            return Err(VmError::VmAlreadyExists(name.to_string()));
+        }
+        
+        let trash = self.trash();
+        let entry = trash.latest(name).await?;
+        
+        println!("Restoring VM '{}' deleted at {}...", name.green(), entry.deleted_at.format("%Y-%m-%d %H:%M:%S"));
+        
+        let xml = trash.restore(&entry).await?;
+        self.backend.define_domain(&xml).await?;
+        trash.remove(&entry).await?;
+        
+        println!("✓ VM '{}' restored with {} disk(s)", name, entry.disks.len());
+        Ok(())
+    }
+    
+    /// VMs currently held in the trash, newest first; expired entries are purged first
+    pub async fn trash_entries(&self) -> Result<Vec<TrashEntry>> {
+        self.purge_trash().await;
+        self.trash().entries().await
+    }
+    
+    fn trash(&self) -> Trash {
+        Trash::new(&self.config.storage.backup_path, self.config.storage.trash_retention_days)
+    }
+    
+    /// Permanently removes trash entries past the retention period
+    async fn purge_trash(&self) {
+        match self.trash().purge_expired(self.backend.as_ref()).await {
+            Ok(purged) => {
+                for entry in purged {
+                    println!("🗑  Purged expired trash entry for '{}'", entry.name);
+                }
+            }
+            Err(e) => tracing::warn!("Failed to purge expired trash entries: {}", e),
+        }
+    }
+    
+    pub async fn clone_vm(&self, source: &str, target: &str) -> Result<()> {
+        println!("Cloning VM '{}' to '{}'...", source.blue(), target.green());
+        
+        // Validate VM names to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(source)?;
+        utils::validate_vm_name(target)?;
+        
+        if self.backend.domain_exists(target).await? {
+            return Err(VmError::VmAlreadyExists(target.to_string()));
+        }
+        
+        let pb = Self::step_progress();
+        pb.set_message("Reading source VM configuration...");
+        
+        let source_xml = self.backend.get_domain_xml(source).await?;
+        if source_xml.contains("<domain type='lxc'") {
+            pb.abandon();
+            return Err(VmError::InvalidInput(format!(
+                "'{}' is a container; copy its root filesystem and use create --type container instead", source
+            )));
+        }
+        
+        let source_info = self.backend.get_domain_info(source).await?;
+        // A running QEMU keeps writing while the disks are copied, and neither a
+        // reflink nor qemu-img info -U waits for its lock, so the copy would be torn
+        if source_info.state != VmState::Stopped {
+            pb.abandon();
+            return Err(VmError::InvalidInput(format!(
+                "VM '{}' is {}; shut it down before cloning it", source, source_info.state.label()
+            )));
+        }
+        let volumes = self.thin_volume_disks(source).await?;
+        if volumes.iter().any(|device| storage::zvol_name(device).is_err()) {
+            pb.abandon();
+            return Err(VmError::InvalidInput(format!(
+                "'{}' runs from LVM volumes; cloning supports image files and zvols", source
+            )));
+        }
+        
+        pb.set_message("Cloning disk images...");
+        
+        // Clone disk images; a zvol becomes a `zfs clone` sharing its blocks
+        let source_disks: Vec<domain::DomainDisk> = self.disk_images(&source_xml).await?.into_iter()
+            .map(|image| image.disk)
+            .collect();
+        let mut copies = Vec::new();
+        let target_disk = match volumes.first() {
+            Some(zvol) => {
+                if volumes.len() > 1 {
+                    pb.println(format!("⚠️  Only the system zvol {} is cloned", zvol.display()));
+                }
+                DiskSource::Block(self.backend.clone_zvol(zvol, target).await?)
+            }
+            None => {
+                // The system disk keeps the usual `<vm>.qcow2`; the others are named
+                // after their target device so each keeps its place in the clone
+                for (index, disk) in source_disks.iter().enumerate() {
+                    let file = match index {
+                        0 => format!("{}.qcow2", target),
+                        _ => format!("{}-{}.qcow2", target, disk.target),
+                    };
+                    copies.push((disk, self.image_path_beside(&disk.path, &file)));
+                }
+                let pairs: Vec<_> = copies.iter()
+                    .map(|(disk, path)| (std::path::PathBuf::from(&disk.path), path.clone()))
+                    .collect();
+                self.clone_disks(&pairs, &pb).await?;
+                DiskSource::File(copies.first()
+                    .map(|(_, path)| path.clone())
+                    .unwrap_or_else(|| self.config.storage.vm_images_path.join(format!("{}.qcow2", target))))
+            }
+        };
+        pb.set_style(Self::step_style());
+        
+        pb.set_message("Creating new VM configuration...");
+        
+        // Detect available networks
+        let networks = self.backend.list_networks().await?;
+        let active_networks: Vec<String> = networks.iter()
+            .filter(|(_, active, _, _)| *active)
+            .map(|(name, _, _, _)| name.clone())
+            .collect();
+            
         let selected_network = if active_networks.contains(&self.config.network.default_network) {
-            println!("{} Using default network: {}", 
-                     "Network:".cyan(), self.config.network.default_network.green());
+            println!("📡 Using configured network: {}", self.config.network.default_network.green());
             self.config.network.default_network.clone()
         } else if let Some(first_network) = active_networks.first() {
-            println!("{} Default network '{}' not available, using: {}", 
-                     "Network:".yellow(), 
+            println!("⚠️  Configured network '{}' not available, using: {}", 
                      self.config.network.default_network,
                      first_network.green());
             first_network.clone()
         } else {
-            return Err(VmError::NetworkError(
-                "No active virtual networks found. Please start a network first:\n  virsh net-start default\n  or create a new network.".to_string()
-            ));
+            return Err(VmError::NetworkError(
+                "No active networks available for VM creation".to_string()
+            ));
+        };
+        
+        // Create new XML with updated paths and UUID
+        let template = VmTemplate {
+            memory: source_info.memory,
+            cpus: source_info.cpus,
+            disk_size: source_info.disk_usage.first().map(|d| d.size / (1024 * 1024 * 1024)).unwrap_or(20),
+            os_type: "linux".to_string(),
+            arch: "x86_64".to_string(),
+            machine_type: "pc-q35-7.0".to_string(),
+            boot_order: vec!["hd".to_string()],
+            features: vec!["acpi".to_string(), "apic".to_string()],
+            iothreads: 1,
+            user_data_path: None,
+            network_config: None,
+            extends: None,
+        };
+        
+        let devices = DeviceOptions {
+            iso: None,
+            network: &selected_network,
+            desktop: self.config.desktop.clone(),
+            graphics: self.config.graphics.clone(),
+            disk_bus: match copies.first() {
+                Some((disk, _)) if disk.bus == "scsi" => DiskBus::VirtioScsi,
+                _ => DiskBus::Virtio,
+            },
+            net_queues: template.cpus,
+            cpu_model: Some(self.config.defaults.cpu_model.clone()),
+        };
+        let mut xml_config = self.generate_vm_xml(target, &template, &target_disk, &devices, None)?;
+        for (disk, path) in copies.iter().skip(1) {
+            xml_config = domain::add_device(&xml_config, &format!(r#"<disk type='file' device='disk'>
+      <driver name='qemu' type='qcow2'/>
+      <source file='{}'/>
+      <target dev='{}' bus='{}'/>
+    </disk>"#, path.display(), disk.target, disk.bus))?;
+        }
+        let xml_config = self.with_unique_macs(&xml_config).await?;
+        self.backend.define_domain(&domain::set_metadata(&xml_config, &Self::creation_metadata(None))?).await?;
+        
+        pb.finish_with_message(format!("✓ VM '{}' cloned successfully", target));
+        Ok(())
+    }
+    
+    /// Defines `name` in the shape of `source`: the same memory, vCPUs, tuning and
+    /// devices, with fresh IDs and an empty disk of the same size in place of each
+    /// of its disks. With `iso` the new VM boots that installer first.
+    pub async fn create_like(&self, name: &str, source: &str, iso: Option<&str>) -> Result<()> {
+        utils::validate_vm_name(name)?;
+        utils::validate_vm_name(source)?;
+        println!("Creating VM '{}' like '{}'...", name.green(), source.blue());
+        
+        if self.backend.domain_exists(name).await? {
+            return Err(VmError::VmAlreadyExists(name.to_string()));
+        }
+        let source_xml = self.backend.get_inactive_domain_xml(source).await?;
+        if source_xml.contains("<domain type='lxc'") {
+            return Err(VmError::InvalidInput(format!(
+                "'{}' is a container; use create --type container instead", source
+            )));
+        }
+        let spec = DomainSpec::parse(&source_xml)?;
+        let images = self.disk_images(&source_xml).await?;
+        // Block devices and network disks can't be recreated empty
+        if source_xml.matches("device='disk'").count() != images.len() {
+            return Err(VmError::InvalidInput(format!(
+                "'{}' has disks on block devices or network storage; --like supports VMs on image files", source
+            )));
+        }
+        if let Some(iso) = iso {
+            if !std::path::Path::new(iso).exists() {
+                return Err(VmError::InvalidInput(format!("ISO file not found: {}", iso)));
+            }
+        }
+        
+        let mut xml = self.with_unique_macs(&domain::regenerate_ids(&domain::set_name(&source_xml, name)?)).await?;
+        xml = domain::drop_nvram(&xml);
+        // Same naming as clones: `<vm>.qcow2` for the system disk, the target device for the others
+        let mut created = Vec::new();
+        // A failure on any disk, or in the definition, takes the images made so far along
+        let result = async {
+            for (index, DiskImage { disk, .. }) in images.iter().enumerate() {
+                let file = match index {
+                    0 => format!("{}.qcow2", name),
+                    _ => format!("{}-{}.qcow2", name, disk.target),
+                };
+                let path = self.image_path_beside(&disk.path, &file);
+                let size = utils::get_image_info(self.runner.as_ref(), &disk.path).await
+                    .map_err(|e| VmError::OperationError(format!("Cannot read the size of {}: {}", disk.path, e)))?
+                    .virtual_size;
+                if path.exists() {
+                    return Err(VmError::InvalidInput(format!("Disk image {} already exists", path.display())));
+                }
+                self.backend.create_disk(&path, size).await?;
+                println!("  {} {} ({}, empty)", disk.target, path.display(), utils::format_bytes(size));
+                xml = domain::set_disk_image(&xml, &disk.target, &path.to_string_lossy())?;
+                created.push(path);
+            }
+
+            let source_metadata = VmMetadata::parse(&source_xml).unwrap_or_default();
+            let mut metadata = Self::creation_metadata(source_metadata.template.as_deref());
+            metadata.os_variant = source_metadata.os_variant;
+            if let Some(iso) = iso {
+                xml = domain::with_cdrom(&xml, iso)?;
+                let mut order = vec!["cdrom".to_string()];
+                order.extend(DomainSpec::parse(&xml)?.boot_order.into_iter().filter(|dev| dev != "cdrom"));
+                xml = domain::set_boot_order(&xml, &order)?;
+                metadata.iso_first_boot = true;
+            }
+
+            self.backend.define_domain(&domain::set_metadata(&xml, &metadata)?).await
+        }.await;
+        if let Err(e) = result {
+            for path in &created {
+                std::fs::remove_file(path).ok();
+            }
+            return Err(e);
+        }
+        println!("✓ VM '{}' created with {}MB, {} vCPUs and {} empty disk(s) like '{}'",
+                 name, spec.memory, spec.cpus, created.len(), source);
+        Ok(())
+    }
+    
+    /// Takes a snapshot; with `memory` the running VM's RAM is saved too, so
+    /// reverting resumes the guest exactly where it was
+    pub async fn create_snapshot(&self, name: &str, snapshot: &str, description: Option<&str>, memory: bool) -> Result<()> {
+        // Validate names to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(name)?;
+        utils::validate_vm_name(snapshot)?;
+        
+        if self.snapshots(name).await?.iter().any(|s| s.name == snapshot) {
+            return Err(VmError::InvalidInput(format!("Snapshot '{}' already exists for VM '{}'", snapshot, name)));
+        }
+        if memory && self.backend.get_domain_state(name).await? != VmState::Running {
+            return Err(VmError::InvalidInput(format!(
+                "VM '{}' is not running; memory snapshots need a running VM", name
+            )));
+        }
+        
+        let thin_volumes = self.thin_volume_disks(name).await?;
+        if !thin_volumes.is_empty() {
+            if memory {
+                return Err(VmError::InvalidInput(format!(
+                    "VM '{}' runs from LVM/ZFS volumes, whose snapshots hold disk state only", name
+                )));
+            }
+            println!("Creating volume snapshot '{}' of VM '{}'...", snapshot.green(), name);
+            for device in &thin_volumes {
+                self.backend.snapshot_thin_volume(device, snapshot).await?;
+            }
+            println!("✓ Snapshot '{}' created", snapshot);
+            return Ok(());
+        }
+        
+        if memory {
+            println!("Creating snapshot '{}' of VM '{}' with memory state...", snapshot.green(), name);
+        } else {
+            println!("Creating snapshot '{}' of VM '{}'...", snapshot.green(), name);
+        }
+        self.backend.create_snapshot(name, snapshot, description, memory).await?;
+        println!("✓ Snapshot '{}' created", snapshot);
+        Ok(())
+    }
+    
+    /// Snapshots of a VM in creation order
+    pub async fn snapshots(&self, name: &str) -> Result<Vec<SnapshotInfo>> {
+        utils::validate_vm_name(name)?;
+        
+        // Every thin volume of the VM gets each snapshot, so the first one speaks for all
+        match self.thin_volume_disks(name).await?.first() {
+            Some(device) => {
+                let kind = if storage::zvol_name(device).is_ok() { "ZFS" } else { "LVM thin" };
+                Ok(self.backend.list_thin_snapshots(device).await?
+                    .into_iter()
+                    .map(|(snapshot, created)| SnapshotInfo {
+                        name: snapshot,
+                        created,
+                        state: VmState::Stopped,
+                        current: false,
+                        description: Some(format!("{} snapshot (disk only)", kind)),
+                    })
+                    .collect())
+            }
+            None => self.backend.list_snapshots(name).await,
+        }
+    }
+    
+    /// Space each snapshot and image file of a VM's disk chains takes on the host.
+    /// Disks qemu-img can't open (RBD, block devices) are left out.
+    pub async fn snapshot_usage(&self, name: &str) -> Result<Vec<SnapshotUsage>> {
+        utils::validate_vm_name(name)?;
+        
+        let spec = DomainSpec::parse(&self.backend.get_inactive_domain_xml(name).await?)?;
+        let mut chains = Vec::new();
+        for disk in &spec.disks {
+            match utils::get_backing_chain(self.runner.as_ref(), &disk.path).await {
+                Ok(chain) => chains.push(chain),
+                Err(e) => tracing::warn!("Failed to inspect {}: {}", disk.path, e),
+            }
+        }
+        Ok(SnapshotUsage::from_chains(&chains))
+    }
+    
+    /// Block disks of a VM that are LVs (`/dev/<vg>/<lv>`) or zvols; their snapshots are
+    /// taken with LVM or ZFS, as libvirt can only snapshot qcow2 images internally
+    async fn thin_volume_disks(&self, name: &str) -> Result<Vec<std::path::PathBuf>> {
+        let xml = self.backend.get_inactive_domain_xml(name).await?;
+        Ok(xml.split("<disk type='block' device='disk'>")
+            .skip(1)
+            .filter_map(|block| domain::attribute(&block[..block.find("</disk>").unwrap_or(block.len())], "<source dev='"))
+            .map(std::path::PathBuf::from)
+            .filter(|device| storage::lv_name(device).is_ok() || storage::zvol_name(device).is_ok())
+            .collect())
+    }
+    
+    pub async fn revert_snapshot(&self, name: &str, snapshot: &str) -> Result<()> {
+        utils::validate_vm_name(name)?;
+        let target = self.find_snapshot(name, snapshot).await?;
+        
+        let thin_volumes = self.thin_volume_disks(name).await?;
+        if !thin_volumes.is_empty() {
+            if self.backend.get_domain_state(name).await? != VmState::Stopped {
+                return Err(VmError::InvalidInput(format!(
+                    "Stop VM '{}' first; its LVM/ZFS volumes can't be rolled back while in use", name
+                )));
+            }
+            println!("Reverting VM '{}' to volume snapshot '{}'...", name, snapshot.green());
+            for device in &thin_volumes {
+                self.backend.revert_thin_volume(device, snapshot).await?;
+            }
+            println!("✓ VM '{}' reverted to '{}'", name, snapshot);
+            return Ok(());
+        }
+        
+        println!("Reverting VM '{}' to snapshot '{}'...", name, snapshot.green());
+        self.backend.revert_snapshot(name, snapshot).await?;
+        println!("✓ VM '{}' reverted to '{}'", name, snapshot);
+        if target.state == VmState::Running {
+            println!("💡 The snapshot holds memory state; the VM resumed where it was taken");
+        }
+        Ok(())
+    }
+    
+    pub async fn delete_snapshot(&self, name: &str, snapshot: &str) -> Result<()> {
+        utils::validate_vm_name(name)?;
+        self.find_snapshot(name, snapshot).await?;
+        
+        let thin_volumes = self.thin_volume_disks(name).await?;
+        for device in &thin_volumes {
+            self.backend.delete_thin_snapshot(device, snapshot).await?;
+        }
+        if thin_volumes.is_empty() {
+            self.backend.delete_snapshot(name, snapshot).await?;
+        }
+        println!("✓ Snapshot '{}' of VM '{}' deleted", snapshot, name);
+        Ok(())
+    }
+    
+    async fn find_snapshot(&self, name: &str, snapshot: &str) -> Result<SnapshotInfo> {
+        self.snapshots(name).await?
+            .into_iter()
+            .find(|s| s.name == snapshot)
+            .ok_or_else(|| VmError::InvalidInput(format!("Snapshot '{}' not found for VM '{}'", snapshot, name)))
+    }
+    
+    /// libvirt credentials for commands that shell out to host tools directly
+    fn privileges(&self) -> Result<&Privileges> {
+        self.backend.privileges().ok_or_else(|| VmError::OperationError(
+            "This command requires the libvirt backend".to_string()
+        ))
+    }
+    
+    /// Inserts `iso` into the VM's CD-ROM drive, or ejects the media when `None`
+    pub async fn change_media(&self, name: &str, iso: Option<&std::path::Path>) -> Result<()> {
+        // Validate VM name to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(name)?;
+        
+        let spec = DomainSpec::parse(&self.backend.get_domain_xml(name).await?)?;
+        let cdrom = spec.cdrom
+            .ok_or_else(|| VmError::InvalidInput(format!("VM '{}' has no CD-ROM drive", name)))?;
+        
+        match iso {
+            Some(iso) => {
+                if !iso.is_file() {
+                    return Err(VmError::InvalidInput(format!("ISO {} does not exist", iso.display())));
+                }
+                let iso = iso.canonicalize()?;
+                self.backend.change_media(name, &cdrom.target, Some(&iso.to_string_lossy())).await?;
+                println!("✓ Inserted {} into {} of VM '{}'", iso.display(), cdrom.target, name);
+            }
+            None => {
+                if cdrom.media.is_none() {
+                    println!("{} CD-ROM drive of VM '{}' is already empty", "Info:".cyan(), name);
+                    return Ok(());
+                }
+                self.backend.change_media(name, &cdrom.target, None).await?;
+                println!("✓ Ejected media from {} of VM '{}'", cdrom.target, name);
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Redraws a VM's state and resource use every `interval`; with `log`, each
+    /// sample is also appended there. Keys pause or resume the VM, snapshot it
+    /// or quit (see `MonitorKey`); the terminal is restored however it ends.
+    pub async fn monitor_vm(&self, name: &str, mut log: Option<MetricsLog>, interval: Duration) -> Result<()> {
+        // Validate VM name to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(name)?;
+        
+        let keys = terminal::KeyInput::enable();
+        let mut message: Option<String> = None;
+        let mut previous: Option<(DomainStats, std::time::Instant)> = None;
+        loop {
+            let vm_info = self.backend.get_domain_info(name).await?;
+            // Counters from live statistics, where the backend has them
+            let stats = self.backend.domain_stats().await.ok()
+                .and_then(|all| all.into_iter().find(|stats| stats.name == name));
+            let now = std::time::Instant::now();
+            let usage = match (&previous, &stats) {
+                (Some((before, at)), Some(after)) => Some(VmUsage::between(before, after, now - *at)),
+                _ => None,
+            };
+            previous = stats.map(|stats| (stats, now));
+            
+            print!("\x1B[2J\x1B[1;1H"); // Clear screen
+            println!("{}", format!("VM Monitor: {} | {}", name, chrono::Local::now().format("%Y-%m-%d %H:%M:%S")).bold());
+            println!("{}", "═".repeat(60));
+            println!("State: {}", vm_info.state);
+            
+            if let Some(usage) = &usage {
+                println!("CPU Usage: {:.1}%", usage.cpu_percent);
+                println!("Memory Usage: {}/{}",
+                         utils::format_bytes(usage.memory_used * 1024),
+                         utils::format_bytes(usage.memory * 1024));
+                println!("Disk I/O: {}/s read, {}/s written",
+                         utils::format_bytes(usage.disk_read_rate),
+                         utils::format_bytes(usage.disk_write_rate));
+                println!("Network: {}/s in, {}/s out",
+                         utils::format_bytes(usage.net_rx_rate),
+                         utils::format_bytes(usage.net_tx_rate));
+            } else {
+                if let Some(cpu_usage) = vm_info.cpu_usage {
+                    println!("CPU Usage: {:.1}%", cpu_usage);
+                }
+                
+                if let Some(memory_usage) = vm_info.memory_usage {
+                    println!("Memory Usage: {:.1}% ({}/{}MB)", 
+                             memory_usage,
+                             (vm_info.memory as f64 * memory_usage / 100.0) as u64,
+                             vm_info.memory);
+                }
+            }
+            
+            if let Some(uptime) = vm_info.uptime {
+                println!("Uptime: {}", utils::format_duration(uptime));
+            }
+            
+            if let Some(log) = log.as_mut() {
+                log.append(&MonitorSample {
+                    timestamp: chrono::Utc::now(),
+                    name: name.to_string(),
+                    state: vm_info.state.label().to_string(),
+                    cpu_percent: usage.as_ref().map(|u| u.cpu_percent).or(vm_info.cpu_usage),
+                    memory_used: usage.as_ref().map(|u| u.memory_used),
+                    memory: usage.as_ref().map_or(vm_info.memory * 1024, |u| u.memory),
+                    disk_read_rate: usage.as_ref().map(|u| u.disk_read_rate),
+                    disk_write_rate: usage.as_ref().map(|u| u.disk_write_rate),
+                    net_rx_rate: usage.as_ref().map(|u| u.net_rx_rate),
+                    net_tx_rate: usage.as_ref().map(|u| u.net_tx_rate),
+                    uptime: vm_info.uptime,
+                })?;
+            }
+            
+            println!();
+            if let Some(message) = message.take() {
+                println!("{}", message);
+            }
+            println!("{}", "q quit · p pause/resume · s snapshot".dimmed());
+            
+            // Watch for keys until the next redraw is due
+            let due = tokio::time::Instant::now() + interval;
+            while tokio::time::Instant::now() < due {
+                let Some(key) = keys.key().and_then(MonitorKey::from_key) else {
+                    sleep(Duration::from_millis(100).min(due - tokio::time::Instant::now())).await;
+                    continue;
+                };
+                if key == MonitorKey::Quit {
+                    return Ok(());
+                }
+                message = Some(match self.monitor_action(name, key).await {
+                    Ok(done) => format!("✓ {}", done).green().to_string(),
+                    Err(e) => format!("✗ {}", e).red().to_string(),
+                });
+                break;
+            }
+        }
+    }
+    
+    /// Carries out a `vmtools monitor` key other than quit, returning what was done
+    pub async fn monitor_action(&self, name: &str, key: MonitorKey) -> Result<String> {
+        match key {
+            MonitorKey::Quit => Ok("Stopped monitoring".to_string()),
+            MonitorKey::TogglePause => match self.backend.get_domain_state(name).await? {
+                VmState::Running => {
+                    self.backend.pause_domain(name).await?;
+                    Ok(format!("Paused VM '{}'", name))
+                }
+                VmState::Paused => {
+                    self.backend.resume_domain(name).await?;
+                    Ok(format!("Resumed VM '{}'", name))
+                }
+                _ => Err(VmError::VmNotRunning(name.to_string())),
+            },
+            MonitorKey::Snapshot => {
+                let snapshot = format!("monitor-{}", chrono::Local::now().format("%Y%m%d-%H%M%S"));
+                self.create_snapshot(name, &snapshot, Some("Taken from vmtools monitor"), false).await?;
+                Ok(format!("Snapshot '{}' created", snapshot))
+            }
+        }
+    }
+    
+    /// Attaches the terminal to the VM's serial console until the `console.escape`
+    /// key is pressed, saving its output as an asciinema recording when `record`
+    /// is given. With `force` a session already holding the console is cut off.
+    /// An interactive session reconnects when the VM restarts underneath it.
+    pub async fn connect_console(&self, name: &str, record: Option<&std::path::Path>, force: bool) -> Result<()> {
+        // Validate VM name to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(name)?;
+        
+        let escape = &self.config.console.escape;
+        let options = ConsoleOptions { record, escape: ConsoleConfig::escape_byte(escape)?, force };
+        let keys = format!("Ctrl+{}", escape.trim_start_matches('^'));
+        if let Some(path) = record {
+            if path.exists() {
+                return Err(VmError::InvalidInput(format!("{} already exists", path.display())));
+            }
+            println!("Connecting to console of VM '{}', recording to {} ({} to detach)...",
+                     name.cyan(), path.display().to_string().green(), keys);
+            self.backend.connect_console(name, options).await?;
+            println!("✓ Session saved to {}", path.display());
+            println!("💡 Replay it with: asciinema play {}", path.display());
+            return Ok(());
+        }
+        
+        println!("Connecting to console of VM '{}' ({} to detach)...", name.cyan(), keys);
+        self.backend.connect_console(name, options).await?;
+        // Detaching leaves the VM running; a session that ended with the VM
+        // gone picks up again once it is back
+        while self.backend.get_domain_state(name).await? != VmState::Running {
+            println!("\n⏳ VM '{}' went down, waiting for it to come back (Ctrl+C to stop)...", name);
+            let waited = std::time::Instant::now();
+            while self.backend.get_domain_state(name).await? != VmState::Running {
+                if waited.elapsed() >= CONSOLE_RECONNECT_WAIT {
+                    println!("VM '{}' stayed down; console closed", name);
+                    return Ok(());
+                }
+                sleep(Duration::from_secs(1)).await;
+            }
+            println!("↻ Reconnecting to console of VM '{}'...", name.cyan());
+            // Whatever held the console went with the old QEMU process
+            self.backend.connect_console(name, ConsoleOptions { force: false, ..options }).await?;
+        }
+        Ok(())
+    }
+    
+    /// Sends a raw QMP command to a running VM's monitor and returns its result.
+    /// `command` is the JSON QMP takes (`{"execute": ..., "arguments": ...}`) or
+    /// just a command name for one without arguments.
+    pub async fn qmp(&self, name: &str, command: &str) -> Result<serde_json::Value> {
+        let command = self.passthrough_command(name, command, "query-status").await?;
+        self.backend.monitor_command(name, &command).await
+    }
+    
+    /// Sends a raw command to a running VM's QEMU guest agent and returns its
+    /// result; like `qmp`, `command` is JSON or a bare command name
+    pub async fn guest_agent(&self, name: &str, command: &str) -> Result<serde_json::Value> {
+        let command = self.passthrough_command(name, command, "guest-info").await?;
+        self.backend.agent_command(name, &command).await
+    }
+    
+    /// The guest's OS, kernel and agent version, from its guest agent
+    pub async fn guest_info(&self, name: &str) -> Result<GuestInfo> {
+        let info = self.backend.agent_command(name, &serde_json::json!({ "execute": "guest-info" })).await?;
+        let osinfo = self.backend.agent_command(name, &serde_json::json!({ "execute": "guest-get-osinfo" })).await.ok();
+        Ok(GuestInfo::from_replies(&info, osinfo.as_ref()))
+    }
+    
+    /// Parses a command for `qmp` or `guest_agent` once the VM is known to be running
+    async fn passthrough_command(&self, name: &str, command: &str, example: &str) -> Result<serde_json::Value> {
+        utils::validate_vm_name(name)?;
+        let command = command.trim();
+        let command: serde_json::Value = if command.starts_with('{') {
+            serde_json::from_str(command)
+                .map_err(|e| VmError::InvalidInput(format!("Command is not valid JSON: {}", e)))?
+        } else {
+            serde_json::json!({ "execute": command })
+        };
+        if !command.get("execute").is_some_and(|execute| execute.is_string()) {
+            return Err(VmError::InvalidInput(format!(
+                "A command needs an \"execute\" name, e.g. {{\"execute\": \"{}\"}}", example
+            )));
+        }
+        if !matches!(self.backend.get_domain_state(name).await?, VmState::Running | VmState::Paused) {
+            return Err(VmError::VmNotRunning(name.to_string()));
+        }
+        Ok(command)
+    }
+    
+    /// Fills in what a disk needs before it goes into a definition: cluster details and
+    /// the cephx secret for RBD images, the volume format for pool volumes. With `new`
+    /// (name, bytes), a missing pool volume is created and `lvm:` allocates a thin LV
+    /// of that name.
+    async fn resolve_disk_source(&self, source: &DiskSource, new: Option<(&str, u64)>) -> Result<DiskSource> {
+        // The qemu backend only runs image files
+        if self.config.backend.kind == BackendKind::Qemu && !matches!(source, DiskSource::File(_)) {
+            return Err(VmError::InvalidInput(format!("{} disks need the libvirt backend", source)));
+        }
+        let rbd = match source {
+            DiskSource::File(path) => {
+                self.label_image(path, false).await;
+                return Ok(source.clone());
+            }
+            DiskSource::Block(_) => return Ok(source.clone()),
+            DiskSource::Volume { pool, volume, .. } => {
+                return self.resolve_volume(pool, volume, new.map(|(_, size)| size)).await;
+            }
+            DiskSource::Lvm { vg, thin_pool } => {
+                let (lv, size) = new.ok_or_else(|| VmError::InvalidInput(
+                    "A new thin LV needs a size (--size)".to_string()
+                ))?;
+                let device = self.backend.create_thin_volume(vg, thin_pool, lv, size).await?;
+                println!("{} Created thin LV {} ({})", "Storage:".cyan(), device.display().to_string().green(),
+                         utils::format_bytes(size));
+                return Ok(DiskSource::Block(device));
+            }
+            DiskSource::Zfs { dataset } => {
+                let (zvol, size) = new.ok_or_else(|| VmError::InvalidInput(
+                    "A new zvol needs a size (--size)".to_string()
+                ))?;
+                let zfs = &self.config.zfs;
+                let device = self.backend.create_zvol(dataset, zvol, size, &zfs.volblocksize, &zfs.compression).await?;
+                println!("{} Created zvol {} ({}, volblocksize {}, compression {})", "Storage:".cyan(),
+                         device.display().to_string().green(), utils::format_bytes(size), zfs.volblocksize, zfs.compression);
+                return Ok(DiskSource::Block(device));
+            }
+            DiskSource::Rbd(rbd) => rbd,
+        };
+
+        let ceph = &self.config.ceph;
+        let auth = if ceph.user.is_empty() {
+            None
+        } else {
+            let usage = SecretUsage::Ceph(ceph.secret_usage());
+            let secret = self.find_secret(&usage).await?.ok_or_else(|| VmError::InvalidInput(format!(
+                "No libvirt secret holds the key of client.{}.\n  \
+                 Create one with: ceph auth get-key client.{} | vmtools secret create ceph '{}'",
+                ceph.user, ceph.user, usage.id()
+            )))?;
+            Some((ceph.user.clone(), secret.uuid))
         };
-        
-        if !active_networks.is_empty() {
-            println!("{} Available networks: {}", 
-                     "Info:".cyan(), 
-                     active_networks.join(", "));
+
+        Ok(DiskSource::Rbd(Box::new(RbdImage {
+            monitors: ceph.monitors.clone(),
+            auth,
+            ..(**rbd).clone()
+        })))
+    }
+
+    async fn resolve_volume(&self, pool: &str, volume: &str, create_size: Option<u64>) -> Result<DiskSource> {
+        let info = self.backend.list_pools().await?.into_iter()
+            .find(|info| info.name == pool)
+            .ok_or_else(|| VmError::InvalidInput(format!("No storage pool '{}'", pool)))?;
+        if !info.active {
+            return Err(VmError::InvalidInput(format!("Storage pool '{}' is not running", pool)));
         }
-        
-        // Get template or use defaults
-        let template = if let Some(template_name) = template_name {
-            self.config.get_template(template_name)
-                .ok_or_else(|| VmError::InvalidInput(format!("Template '{}' not found", template_name)))?
-                .clone()
-        } else {
-            VmTemplate {
-                memory,
-                cpus,
-                disk_size,
-                os_type: "linux".to_string(),
-                arch: "x86_64".to_string(),
-                machine_type: "pc-q35-7.0".to_string(),
-                boot_order: vec!["hd".to_string(), "cdrom".to_string()],
-                features: vec!["acpi".to_string(), "apic".to_string()],
+
+        let existing = self.backend.list_volumes(pool).await?.into_iter().find(|v| v.name == volume);
+        let format = match (existing, create_size) {
+            (Some(existing), _) => existing.format,
+            (None, Some(size)) if info.can_create_volumes() => {
+                self.backend.create_volume(pool, volume, size).await?;
+                println!("{} Created volume {} in pool {}", "Storage:".cyan(), volume.green(), pool);
+                "qcow2".to_string()
             }
+            (None, _) => return Err(VmError::InvalidInput(format!(
+                "Pool '{}' has no volume '{}' (see vmtools pool volumes {})", pool, volume, pool
+            ))),
         };
+
+        Ok(DiskSource::Volume { pool: pool.to_string(), volume: volume.to_string(), format })
+    }
+
+    pub async fn pools(&self) -> Result<Vec<PoolInfo>> {
+        self.backend.list_pools().await
+    }
+
+    pub async fn volumes(&self, pool: &str) -> Result<Vec<VolumeInfo>> {
+        storage::validate_pool_name(pool)?;
+        self.backend.list_volumes(pool).await
+    }
+
+    /// IQNs of the targets the iSCSI portal `host` offers
+    pub async fn discover_iscsi(&self, host: &str) -> Result<Vec<String>> {
+        storage::validate_pool_field("iSCSI portal", host)?;
+        self.backend.discover_iscsi_targets(host).await
+    }
+
+    /// Adds a pool mounting the NFS export `host:export` under the images directory
+    pub async fn add_nfs_pool(&self, name: &str, host: &str, export: &str) -> Result<()> {
+        storage::validate_pool_name(name)?;
+        storage::validate_pool_field("NFS server", host)?;
+        storage::validate_pool_field("NFS export", export)?;
+        if !export.starts_with('/') {
+            return Err(VmError::InvalidInput(format!("NFS export must be an absolute path, got '{}'", export)));
+        }
+
+        let mount_point = self.config.storage.vm_images_path.join(name);
+        self.backend.define_pool(&storage::nfs_pool_xml(name, host, export, &mount_point)).await?;
+        println!("✅ Pool '{}' mounts {}:{} at {}", name.green(), host, export, mount_point.display());
+        Ok(())
+    }
+
+    /// Adds a pool for the LUNs of an iSCSI target. With `chap_user`, the password must
+    /// already be stored as an iscsi secret whose usage is the target IQN.
+    pub async fn add_iscsi_pool(&self, name: &str, host: &str, target: &str, chap_user: Option<&str>) -> Result<()> {
+        storage::validate_pool_name(name)?;
+        storage::validate_pool_field("iSCSI portal", host)?;
+        storage::validate_pool_field("iSCSI target", target)?;
+        if let Some(user) = chap_user {
+            storage::validate_pool_field("CHAP user", user)?;
+            let usage = SecretUsage::Iscsi(target.to_string());
+            if self.find_secret(&usage).await?.is_none() {
+                return Err(VmError::InvalidInput(format!(
+                    "No CHAP secret for {}.\n  Create one with: vmtools secret create iscsi {}", target, target
+                )));
+            }
+        }
+
+        self.backend.define_pool(&storage::iscsi_pool_xml(name, host, target, chap_user)).await?;
+        println!("✅ Pool '{}' logs in to {} on {}", name.green(), target, host);
+        Ok(())
+    }
+
+    /// Removes a pool definition; its data stays on the server. Refuses while a VM
+    /// still uses one of its volumes.
+    pub async fn remove_pool(&self, name: &str) -> Result<()> {
+        storage::validate_pool_name(name)?;
+        let marker = format!("<source pool='{}'", name);
+        for vm in self.backend.list_domains(true, true).await? {
+            if self.backend.get_inactive_domain_xml(&vm.name).await?.contains(&marker) {
+                return Err(VmError::InvalidInput(format!("VM '{}' uses a volume of pool '{}'", vm.name, name)));
+            }
+        }
+
+        self.backend.delete_pool(name).await?;
+        println!("🗑️  Removed pool '{}'", name);
+        Ok(())
+    }
+
+    /// Adds `source` to a VM as a virtio data disk, hot-plugging it when the VM runs.
+    /// `size_gb` creates a missing pool volume or a new thin LV (named `<vm>-<target>`).
+    /// Returns the target it was attached as (vdb, vdc, ...).
+    pub async fn attach_disk(&self, name: &str, source: &DiskSource, size_gb: Option<u64>) -> Result<String> {
+        utils::validate_vm_name(name)?;
+        if let Some(size) = size_gb {
+            utils::validate_disk_size(size)?;
+        }
+
+        let xml = self.backend.get_inactive_domain_xml(name).await?;
+        let target = ('b'..='z')
+            .map(|letter| format!("vd{}", letter))
+            .find(|dev| !xml.contains(&format!("<target dev='{}'", dev)))
+            .ok_or_else(|| VmError::InvalidInput(format!("VM '{}' has no free virtio disk slot", name)))?;
+        let lv = format!("{}-{}", name, target);
+        let source = self.resolve_disk_source(source, size_gb.map(|size| (lv.as_str(), size * 1024 * 1024 * 1024))).await?;
+        let device = format!(r#"<disk type='{}' device='disk'>
+      <driver name='qemu' type='{}'{} discard='unmap'/>
+      {}
+      <target dev='{}' bus='virtio'/>
+    </disk>"#, source.disk_type(), source.format(), source.driver_tuning(), source.source_xml(), target);
+
+        if self.backend.get_domain_state(name).await? == VmState::Running {
+            self.backend.attach_device(name, &device).await?;
+        }
+        self.backend.define_domain(&domain::add_device(&xml, &device)?).await?;
+
+        println!("✅ Attached {} to VM '{}' as {}", source.to_string().cyan(), name, target.green());
+        Ok(target)
+    }
+
+    /// Merges a running VM's disk overlays (external snapshots, linked clone layers)
+    /// down into the base image and switches the VM to it. `disk` picks one target
+    /// such as `vda`; by default every disk with a backing file is committed.
+    /// Refuses when another VM still reads from an image that would change.
+    pub async fn commit_disk(&self, name: &str, disk: Option<&str>) -> Result<()> {
+        // Validate VM name to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(name)?;
         
-        let pb = ProgressBar::new(100);
-        pb.set_style(ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos:>7}/{len:7} {msg}")
-            .unwrap());
-        
-        pb.set_message("Creating disk image...");
-        pb.set_position(10);
-        
-        // Create disk image
-        let disk_path = self.config.storage.vm_images_path.join(format!("{}.qcow2", name));
-        utils::create_qcow2_image(&disk_path, disk_size * 1024 * 1024 * 1024).await?;
-        
-        pb.set_message("Generating VM configuration...");
-        pb.set_position(40);
-        
-        // Generate XML configuration
-        let xml_config = self.generate_vm_xml(name, &template, &disk_path, iso_path, &selected_network)?;
-        
-        pb.set_message("Registering VM with libvirt...");
-        pb.set_position(70);
-        
-        // Define the domain
-        self.libvirt.define_domain(&xml_config).await?;
-        
-        pb.set_message("VM created successfully");
-        pb.finish_with_message(format!("✓ VM '{}' created successfully", name));
+        if self.backend.get_domain_state(name).await? != VmState::Running {
+            return Err(VmError::InvalidInput(format!(
+                "VM '{}' is not running; start it to commit live, or flatten it offline with 'vmtools disk pull'", name
+            )));
+        }
         
-        println!("VM Configuration:");
-        println!("  Memory: {}MB", template.memory);
-        println!("  CPUs: {}", template.cpus);
-        println!("  Disk: {}GB", template.disk_size);
-        println!("  Disk Path: {}", disk_path.display());
+        let disks = self.chained_disks(name, disk).await?;
+        if disks.is_empty() {
+            println!("✓ No disk of VM '{}' has a backing file", name);
+            return Ok(());
+        }
         
-        if let Some(iso) = iso_path {
-            println!("  ISO: {}", iso);
+        // Committing into an image other VMs read through would corrupt those VMs
+        let shared = self.images_used_by_others(name).await?;
+        for (target, path) in &disks {
+            for image in utils::get_backing_chain(self.runner.as_ref(), path).await?.iter().skip(1) {
+                if let Some(user) = shared.get(&Self::image_key(&image.filename)) {
+                    return Err(VmError::InvalidInput(format!(
+                        "{} of VM '{}' sits on {}, which VM '{}' also uses; use 'vmtools disk pull' instead",
+                        target, name, image.filename, user
+                    )));
+                }
+            }
         }
         
+        for (target, _) in &disks {
+            println!("🔄 Committing {} of VM '{}' into its base image...", target.cyan(), name);
+            self.backend.block_commit(name, target).await?;
+            println!("✓ {} now runs from its base image", target);
+        }
+        println!("💡 The merged overlay files are no longer used and can be removed");
         Ok(())
     }
     
-    pub async fn delete_vm(&self, name: &str, force: bool) -> Result<()> {
+    /// Copies the backing data into a VM's disks so they stand alone: live through
+    /// libvirt when the VM runs, with `qemu-img rebase` when it is stopped.
+    /// Backing images are left untouched, so this is safe for linked clones.
+    pub async fn pull_disk(&self, name: &str, disk: Option<&str>) -> Result<()> {
         // Validate VM name to prevent path traversal attacks (CWE-22)
         utils::validate_vm_name(name)?;
         
-        if !force {
-            print!("Are you sure you want to delete VM '{}'? [y/N]: ", name);
-            use std::io::{self, Write};
-            io::stdout().flush().unwrap();
-            
-            let mut input = String::new();
-            io::stdin().read_line(&mut input).unwrap();
-            
-            if !input.trim().to_lowercase().starts_with('y') {
-                println!("Operation cancelled");
-                return Ok(());
-            }
-        }
-        
-        println!("Deleting VM '{}'...", name.red());
+        let running = match self.backend.get_domain_state(name).await? {
+            VmState::Running => true,
+            VmState::Stopped => false,
+            state => return Err(VmError::InvalidInput(format!(
+                "VM '{}' is {}; start or stop it first", name, state.label()
+            ))),
+        };
         
-        // Stop VM if running
-        let state = self.libvirt.get_domain_state(name).await?;
-        if state == VmState::Running {
-            self.libvirt.destroy_domain(name).await?;
+        let disks = self.chained_disks(name, disk).await?;
+        if disks.is_empty() {
+            println!("✓ No disk of VM '{}' has a backing file", name);
+            return Ok(());
         }
         
-        // Get VM info to find disk files
-        let vm_info = self.libvirt.get_domain_info(name).await?;
-        
-        // Undefine the domain
-        self.libvirt.undefine_domain(name).await?;
-        
-        // Delete disk files
-        for disk in &vm_info.disk_usage {
-            if let Err(e) = tokio::fs::remove_file(&disk.path).await {
-                eprintln!("Warning: Failed to delete disk {}: {}", disk.path, e);
+        for (target, path) in &disks {
+            println!("🔄 Pulling backing data into {} of VM '{}'...", target.cyan(), name);
+            if running {
+                self.backend.block_pull(name, target).await?;
+            } else {
+                utils::flatten_image(self.runner.as_ref(), std::path::Path::new(path)).await?;
             }
+            println!("✓ {} no longer depends on a backing file", target);
         }
-        
-        println!("✓ VM '{}' deleted successfully", name);
         Ok(())
     }
     
-    pub async fn clone_vm(&self, source: &str, target: &str) -> Result<()> {
-        println!("Cloning VM '{}' to '{}'...", source.blue(), target.green());
-        
-        // Validate VM names to prevent path traversal attacks (CWE-22)
-        utils::validate_vm_name(source)?;
-        utils::validate_vm_name(target)?;
+    /// Returns the definition of a new VM with every MAC address another VM already
+    /// has, or that repeats within it, swapped for a fresh one, so guests never
+    /// share one on a network
+    async fn with_unique_macs(&self, xml: &str) -> Result<String> {
+        let mut taken = std::collections::HashSet::new();
+        for vm in self.backend.list_domains(true, true).await? {
+            let Ok(other) = self.backend.get_domain_xml(&vm.name).await else {
+                continue;
+            };
+            taken.extend(domain::interfaces(&other).into_iter().map(|(mac, _, _)| mac.to_ascii_lowercase()));
+        }
+        let (xml, swapped) = domain::avoid_macs(xml, &taken);
+        for (mac, fresh) in swapped {
+            println!("  MAC address {} is already in use; using {}", mac, fresh);
+        }
+        Ok(xml)
+    }
+    
+    /// Images the other VMs read, their own disks and every backing file below them,
+    /// each mapped to a VM using it
+    async fn images_used_by_others(&self, name: &str) -> Result<std::collections::HashMap<std::path::PathBuf, String>> {
+        let mut used = std::collections::HashMap::new();
+        for (vm, chain) in self.disk_chains(name).await? {
+            for image in chain {
+                used.insert(image, vm.clone());
+            }
+        }
+        Ok(used)
+    }
+    
+    /// The backing chain of every disk of every VM but `except`, as canonical paths,
+    /// the disk first. Only image files have a chain to follow; RBD images and block
+    /// devices are skipped. An image file whose chain can't be read is an error, as
+    /// it may sit on any of the images in question.
+    async fn disk_chains(&self, except: &str) -> Result<Vec<(String, Vec<std::path::PathBuf>)>> {
+        let mut chains = Vec::new();
+        for vm in self.backend.list_domains(true, false).await? {
+            if vm.name == except {
+                continue;
+            }
+            for disk in &vm.disk_usage {
+                if !tokio::fs::metadata(&disk.path).await.is_ok_and(|metadata| metadata.is_file()) {
+                    continue;
+                }
+                let chain = utils::get_backing_chain(self.runner.as_ref(), &disk.path).await
+                    .map_err(|e| VmError::OperationError(format!(
+                        "Cannot tell which images {} of VM '{}' sits on: {}", disk.path, vm.name, e
+                    )))?;
+                chains.push((vm.name.clone(), chain.iter().map(|image| Self::image_key(&image.filename)).collect()));
+            }
+        }
+        Ok(chains)
+    }
+    
+    /// Canonical form of an image path, so relative backing file names match
+    fn image_key(path: &str) -> std::path::PathBuf {
+        std::path::Path::new(path).canonicalize().unwrap_or_else(|_| path.into())
+    }
+    
+    /// The image files behind the disks of the definition `xml`, in definition order:
+    /// files it names, each looked up in the storage pools, and the pool volumes it names
+    async fn disk_images(&self, xml: &str) -> Result<Vec<DiskImage>> {
+        let mut images = Vec::new();
+        for disk in DomainSpec::parse(xml)?.disks {
+            // Outside every pool, or no pools to look in
+            let volume = self.backend.volume_at(std::path::Path::new(&disk.path)).await.unwrap_or(None);
+            images.push(DiskImage { disk, volume });
+        }
+        for (target, bus, pool, volume) in domain::volume_disks(xml) {
+            let info = self.backend.list_volumes(&pool).await?.into_iter()
+                .find(|info| info.name == volume)
+                .ok_or_else(|| VmError::InvalidInput(format!("Pool '{}' has no volume '{}'", pool, volume)))?;
+            images.push(DiskImage {
+                disk: domain::DomainDisk { target, bus, path: info.path, format: info.format, iothread: None },
+                volume: Some((pool, volume)),
+            });
+        }
+        images.sort_by_key(|image| xml.find(&format!("<target dev='{}'", image.disk.target)));
+        Ok(images)
+    }
+    
+    /// Where a new image named `file` goes: next to the image file `source`, so it
+    /// lands on the same storage; in `storage.vm_images_path` when `source` is a
+    /// device rather than a file
+    fn image_path_beside(&self, source: &str, file: &str) -> std::path::PathBuf {
+        let source = std::path::Path::new(source);
+        match source.parent() {
+            Some(dir) if source.is_file() => dir.join(file),
+            _ => self.config.storage.vm_images_path.join(file),
+        }
+    }
+    
+    /// Target and path of the disk `disk`, or of every disk with a backing file
+    async fn chained_disks(&self, name: &str, disk: Option<&str>) -> Result<Vec<(String, String)>> {
+        let spec = DomainSpec::parse(&self.backend.get_domain_xml(name).await?)?;
         
-        if self.libvirt.domain_exists(target).await? {
-            return Err(VmError::VmAlreadyExists(target.to_string()));
+        if let Some(target) = disk {
+            return spec.disks.into_iter()
+                .find(|d| d.target == target)
+                .map(|d| vec![(d.target, d.path)])
+                .ok_or_else(|| VmError::InvalidInput(format!("VM '{}' has no disk '{}'", name, target)));
         }
         
-        let pb = ProgressBar::new(100);
-        pb.set_style(ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos:>7}/{len:7} {msg}")
-            .unwrap());
+        let mut chained = Vec::new();
+        for d in spec.disks {
+            if utils::get_backing_chain(self.runner.as_ref(), &d.path).await?.len() > 1 {
+                chained.push((d.target, d.path));
+            }
+        }
+        Ok(chained)
+    }
+    
+    /// Benchmarks a VM's storage with `qemu-img bench`, once per cache/AIO combination.
+    /// Reads go against the disk itself; with `bench.write` writes go to a scratch image
+    /// in the same directory, so the VM's data is never touched. With `guest`, fio also
+    /// runs inside the running VM through the guest agent.
+    pub async fn bench_disk(
+        &self,
+        name: &str,
+        disk: Option<&str>,
+        caches: &[String],
+        aios: &[String],
+        bench: &utils::ImageBench,
+        guest: bool,
+    ) -> Result<Vec<BenchResult>> {
+        // Validate VM name to prevent path traversal attacks (CWE-22)
+        utils::validate_vm_name(name)?;
         
-        pb.set_message("Reading source VM configuration...");
-        pb.set_position(20);
+        let spec = DomainSpec::parse(&self.backend.get_inactive_domain_xml(name).await?)?;
+        let target = match disk {
+            Some(target) => spec.disks.iter().find(|d| d.target == target)
+                .ok_or_else(|| VmError::InvalidInput(format!("VM '{}' has no disk '{}'", name, target)))?,
+            None => spec.disks.first()
+                .ok_or_else(|| VmError::InvalidInput(format!("VM '{}' has no disks", name)))?,
+        };
+        let path = std::path::Path::new(&target.path);
+        if guest && self.backend.get_domain_state(name).await? != VmState::Running {
+            return Err(VmError::InvalidInput(format!("VM '{}' must be running for --guest", name)));
+        }
         
-        let source_info = self.libvirt.get_domain_info(source).await?;
+        let scratch = path.with_file_name(format!(".vmtools-bench-{}.qcow2", uuid::Uuid::new_v4()));
+        if bench.write {
+            self.backend.create_disk(&scratch, 1024 * 1024 * 1024).await?;
+        }
         
-        pb.set_message("Cloning disk images...");
-        pb.set_position(60);
+        let mut results = Vec::new();
+        let outcome = async {
+            for cache in caches {
+                for aio in aios {
+                    let mut run = bench.clone();
+                    let mut jobs = vec![(format!("{} read", target.target), path, target.format.as_str(), false)];
+                    if bench.write {
+                        jobs.push(("scratch write".to_string(), scratch.as_path(), "qcow2", true));
+                    }
+                    for (label, image, format, write) in jobs {
+                        println!("⏱  {} (cache={}, aio={})...", label, cache, aio);
+                        run.write = write;
+                        let seconds = utils::bench_image(self.runner.as_ref(), image, format, cache, aio, &run).await?;
+                        results.push(Self::bench_result(label, Some(cache), Some(aio), &run, seconds));
+                    }
+                }
+            }
+            Ok::<_, VmError>(())
+        }.await;
+        if bench.write {
+            let _ = tokio::fs::remove_file(&scratch).await;
+        }
+        outcome?;
         
-        // Clone disk images
-        for disk in &source_info.disk_usage {
-            let target_path_str = self.config.storage.vm_images_path.join(format!("{}.qcow2", target));
-            utils::clone_qcow2_image(disk.path.clone(), target_path_str.to_string_lossy().to_string()).await?;
+        if guest {
+            for mode in ["randread", "randwrite"] {
+                println!("⏱  guest {} (fio)...", mode);
+                results.push(self.guest_fio(name, mode, bench).await?);
+            }
         }
+        Ok(results)
+    }
+    
+    fn bench_result(target: String, cache: Option<&str>, aio: Option<&str>, bench: &utils::ImageBench, seconds: f64) -> BenchResult {
+        let iops = bench.requests as f64 / seconds.max(f64::EPSILON);
+        BenchResult {
+            target,
+            cache: cache.map(str::to_string),
+            aio: aio.map(str::to_string),
+            iops,
+            throughput: iops * bench.block_size as f64,
+            // Little's law: requests in flight divided by completion rate
+            latency_us: bench.depth as f64 / iops * 1_000_000.0,
+        }
+    }
+    
+    /// Runs a 10 second fio job inside the guest, which must have fio installed
+    async fn guest_fio(&self, name: &str, mode: &str, bench: &utils::ImageBench) -> Result<BenchResult> {
+        const FILE: &str = "/var/tmp/vmtools-bench.fio";
+        let block_size = format!("--bs={}", bench.block_size);
+        let depth = format!("--iodepth={}", bench.depth);
+        let rw = format!("--rw={}", mode);
+        let args = [
+            "--name=vmtools", &format!("--filename={}", FILE), "--size=256M", rw.as_str(),
+            block_size.as_str(), depth.as_str(), "--ioengine=libaio", "--direct=1",
+            "--runtime=10", "--time_based", "--output-format=json",
+        ];
         
-        pb.set_message("Creating new VM configuration...");
-        pb.set_position(80);
+        let run = self.guest_exec_output(name, "/usr/bin/fio", &args, 60).await;
+        let _ = self.guest_exec(name, "/bin/rm", &["-f", FILE]).await;
+        let (code, stdout) = run?;
+        if code != 0 {
+            return Err(VmError::OperationError(format!(
+                "fio exited with {} in VM '{}'; is fio installed in the guest?", code, name
+            )));
+        }
         
-        // Detect available networks
-        let networks = self.libvirt.list_networks().await?;
-        let active_networks: Vec<String> = networks.iter()
-            .filter(|(_, active, _, _)| *active)
-            .map(|(name, _, _, _)| name.clone())
+        let report: serde_json::Value = serde_json::from_str(&stdout)?;
+        let job = &report["jobs"][0][if mode == "randread" { "read" } else { "write" }];
+        Ok(BenchResult {
+            target: format!("guest {}", mode),
+            cache: None,
+            aio: None,
+            iops: job["iops"].as_f64().unwrap_or(0.0),
+            throughput: job["bw_bytes"].as_f64().unwrap_or(0.0),
+            latency_us: job["clat_ns"]["mean"].as_f64().unwrap_or(0.0) / 1000.0,
+        })
+    }
+    
+    /// Machine types, CPU models, vCPU limit and firmware of the host's hypervisor
+    pub async fn capabilities(&self) -> Result<HostCapabilities> {
+        self.backend.capabilities().await
+    }
+
+    /// Stores `value` in a new libvirt secret for `usage`, returning its UUID.
+    /// Disks and pools then reference the secret instead of carrying the key.
+    pub async fn create_secret(&self, usage: &SecretUsage, value: &[u8], description: Option<&str>) -> Result<String> {
+        if value.is_empty() {
+            return Err(VmError::InvalidInput("The secret value is empty".to_string()));
+        }
+        if self.find_secret(usage).await?.is_some() {
+            return Err(VmError::InvalidInput(format!(
+                "A {} secret for '{}' already exists", usage.kind(), usage.id()
+            )));
+        }
+
+        let uuid = self.backend.create_secret(&usage.secret_xml(description), value).await?;
+        println!("🔑 Created {} secret for '{}': {}", usage.kind(), usage.id(), uuid.cyan());
+        Ok(uuid)
+    }
+
+    pub async fn secrets(&self) -> Result<Vec<SecretInfo>> {
+        self.backend.list_secrets().await
+    }
+
+    /// The secret stored for `usage`, if any
+    pub async fn find_secret(&self, usage: &SecretUsage) -> Result<Option<SecretInfo>> {
+        Ok(self.backend.list_secrets().await?.into_iter().find(|secret| &secret.usage == usage))
+    }
+
+    /// Deletes a secret by UUID or by its usage id (e.g. `client.admin secret`)
+    pub async fn delete_secret(&self, id: &str) -> Result<()> {
+        let secrets = self.backend.list_secrets().await?;
+        let matches: Vec<&SecretInfo> = secrets.iter()
+            .filter(|secret| secret.uuid == id || secret.usage.id() == id)
             .collect();
-            
-        let selected_network = if active_networks.contains(&self.config.network.default_network) {
-            println!("📡 Using configured network: {}", self.config.network.default_network.green());
-            self.config.network.default_network.clone()
-        } else if let Some(first_network) = active_networks.first() {
-            println!("⚠️  Configured network '{}' not available, using: {}", 
-                     self.config.network.default_network,
-                     first_network.green());
-            first_network.clone()
-        } else {
-            return Err(VmError::NetworkError(
-                "No active networks available for VM creation".to_string()
-            ));
+        let secret = match matches.as_slice() {
+            [secret] => *secret,
+            [] => return Err(VmError::InvalidInput(format!("No secret '{}'", id))),
+            _ => return Err(VmError::InvalidInput(format!(
+                "'{}' names secrets of several types; delete it by UUID", id
+            ))),
         };
+
+        self.backend.delete_secret(&secret.uuid).await?;
+        println!("🗑️  Deleted {} secret '{}'", secret.usage.kind(), secret.usage.id());
+        Ok(())
+    }
+    
+    /// What each running VM used over `interval`, from two samples of its counters
+    pub async fn vm_usage(&self, interval: Duration, sort: TopSort) -> Result<Vec<VmUsage>> {
+        let before = self.backend.domain_stats().await?;
+        let started = std::time::Instant::now();
+        sleep(interval).await;
+        let after = self.backend.domain_stats().await?;
         
-        // Create new XML with updated paths and UUID
-        let target_disk_path = self.config.storage.vm_images_path.join(format!("{}.qcow2", target));
-        let template = VmTemplate {
-            memory: source_info.memory,
-            cpus: source_info.cpus,
-            disk_size: source_info.disk_usage.first().map(|d| d.size / (1024 * 1024 * 1024)).unwrap_or(20),
-            os_type: "linux".to_string(),
-            arch: "x86_64".to_string(),
-            machine_type: "pc-q35-7.0".to_string(),
-            boot_order: vec!["hd".to_string()],
-            features: vec!["acpi".to_string(), "apic".to_string()],
+        let mut usage = VmUsage::compare(&before, &after, started.elapsed());
+        match sort {
+            TopSort::Cpu => usage.sort_by(|a, b| b.cpu_percent.total_cmp(&a.cpu_percent)),
+            TopSort::Memory => usage.sort_by_key(|u| std::cmp::Reverse(u.memory_used)),
+            TopSort::Disk => usage.sort_by_key(|u| std::cmp::Reverse(u.disk_rate())),
+            TopSort::Net => usage.sort_by_key(|u| std::cmp::Reverse(u.net_rate())),
+        }
+        Ok(usage)
+    }
+    
+    /// Counts VMs by state, sums what the running ones are given and use, and
+    /// looks for trouble: an inactive default network, QEMU processes still
+    /// holding the disk locks of VMs that are not running, full or down pools
+    pub async fn host_summary(&self) -> Result<HostSummary> {
+        let vms = self.backend.list_domains(true, true).await?;
+        let mut states: Vec<(VmState, usize)> = Vec::new();
+        for vm in &vms {
+            match states.iter_mut().find(|(state, _)| *state == vm.state) {
+                Some((_, count)) => *count += 1,
+                None => states.push((vm.state.clone(), 1)),
+            }
+        }
+        states.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+
+        let running: Vec<&VmInfo> = vms.iter().filter(|vm| vm.state == VmState::Running).collect();
+        let usage = if running.is_empty() {
+            Vec::new()
+        } else {
+            self.vm_usage(SUMMARY_SAMPLE, TopSort::Cpu).await.unwrap_or_default()
         };
+
+        let mut issues = Vec::new();
+        let networks = self.backend.list_networks().await?;
+        let default_network = &self.config.network.default_network;
+        match networks.iter().find(|(name, _, _, _)| name == default_network) {
+            Some((_, true, _, _)) => {}
+            Some(_) => issues.push(format!(
+                "Default network '{}' is inactive; start it with: virsh net-start {}", default_network, default_network
+            )),
+            None => issues.push(format!(
+                "Default network '{}' does not exist; pick another with: vmtools config --set network.default_network <name>",
+                default_network
+            )),
+        }
+        for (pid, name) in host::qemu_processes() {
+            if vms.iter().any(|vm| vm.name == name && vm.state == VmState::Stopped) {
+                issues.push(format!(
+                    "QEMU process {} of stopped VM '{}' still holds its disk locks; stop it with: kill {}", pid, name, pid
+                ));
+            }
+        }
+        let pools = self.backend.list_pools().await.unwrap_or_default();
+        for pool in &pools {
+            if !pool.active {
+                issues.push(format!("Storage pool '{}' is inactive", pool.name));
+            } else if pool.capacity > 0 && pool.allocation * 100 / pool.capacity >= POOL_FULL_PERCENT {
+                issues.push(format!(
+                    "Storage pool '{}' is {}% full", pool.name, pool.allocation * 100 / pool.capacity
+                ));
+            }
+        }
+
+        Ok(HostSummary {
+            states,
+            memory_allocated: running.iter().map(|vm| vm.memory).sum(),
+            vcpus_allocated: running.iter().map(|vm| vm.cpus).sum(),
+            memory_used: usage.iter().map(|vm| vm.memory_used).sum::<u64>() / 1024,
+            cpu_percent: usage.iter().map(|vm| vm.cpu_percent).sum(),
+            host: utils::get_host_info(&self.config).await.ok(),
+            pools,
+            issues,
+        })
+    }
+    
+    /// Disk usage for every VM, largest on-disk footprint first
+    pub async fn disk_usage(&self) -> Result<Vec<VmDiskUsage>> {
+        let vms = self.backend.list_domains(true, false).await?;
         
-        let xml_config = self.generate_vm_xml(target, &template, &target_disk_path, None, &selected_network)?;
-        self.libvirt.define_domain(&xml_config).await?;
+        let mut report = Vec::new();
+        for vm in &vms {
+            let mut usage = VmDiskUsage {
+                name: vm.name.clone(),
+                disks: vm.disk_usage.len(),
+                virtual_size: 0,
+                actual_size: 0,
+                backing_size: 0,
+                snapshots: 0,
+                snapshot_state: 0,
+            };
+            
+            for disk in &vm.disk_usage {
+                let chain = match utils::get_backing_chain(self.runner.as_ref(), &disk.path).await {
+                    Ok(chain) => chain,
+                    Err(e) => {
+                        tracing::warn!("Failed to inspect {}: {}", disk.path, e);
+                        continue;
+                    }
+                };
+                
+                if let Some((top, backing)) = chain.split_first() {
+                    usage.virtual_size += top.virtual_size;
+                    usage.actual_size += top.actual_size;
+                    usage.backing_size += backing.iter().map(|image| image.actual_size).sum::<u64>();
+                    for snapshot in chain.iter().flat_map(|image| &image.snapshots) {
+                        usage.snapshots += 1;
+                        usage.snapshot_state += snapshot.vm_state_size;
+                    }
+                }
+            }
+            
+            report.push(usage);
+        }
         
-        pb.finish_with_message(format!("✓ VM '{}' cloned successfully", target));
-        Ok(())
+        report.sort_by_key(|usage| std::cmp::Reverse(usage.actual_size + usage.backing_size));
+        Ok(report)
     }
     
-    pub async fn monitor_vm(&self, name: &str) -> Result<()> {
-        // Validate VM name to prevent path traversal attacks (CWE-22)
-        utils::validate_vm_name(name)?;
+    /// Streams domain lifecycle events as text or JSON lines until interrupted
+    pub async fn watch_events(&self, vm: Option<&str>, json: bool) -> Result<()> {
+        if let Some(name) = vm {
+            utils::validate_vm_name(name)?;
+        }
         
-        println!("Monitoring VM '{}' (Press Ctrl+C to exit)...", name.cyan());
+        let mut stream = self.backend.subscribe_events(vm)?;
         
-        loop {
-            let vm_info = self.libvirt.get_domain_info(name).await?;
-            
-            print!("\x1B[2J\x1B[1;1H"); // Clear screen
-            println!("{}", format!("VM Monitor: {} | {}", name, chrono::Local::now().format("%Y-%m-%d %H:%M:%S")).bold());
-            println!("{}", "═".repeat(60));
-            println!("State: {}", vm_info.state);
+        if !json {
+            match vm {
+                Some(name) => println!("Watching events for VM '{}' (Press Ctrl+C to exit)...", name.cyan()),
+                None => println!("Watching events for all VMs (Press Ctrl+C to exit)..."),
+            }
+        }
+        
+        while let Some(event) = stream.next().await? {
+            self.webhooks.dispatch(self.runner.as_ref(), &WebhookPayload::from_event(&event)).await;
             
-            if let Some(cpu_usage) = vm_info.cpu_usage {
-                println!("CPU Usage: {:.1}%", cpu_usage);
+            if json {
+                println!("{}", serde_json::to_string(&event)?);
+            } else {
+                let kind = match event.kind {
+                    EventKind::Started | EventKind::Resumed => event.kind.to_string().green(),
+                    EventKind::Crashed => event.kind.to_string().red().bold(),
+                    EventKind::Stopped | EventKind::Shutdown => event.kind.to_string().red(),
+                    EventKind::Suspended => event.kind.to_string().yellow(),
+                    _ => event.kind.to_string().cyan(),
+                };
+                println!("{} {:<20} {:<10} {}", event.timestamp.bright_black(), event.domain, kind, event.detail);
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Libvirt networks as (name, active, bridge, autostart)
+    pub async fn networks(&self) -> Result<Vec<(String, bool, String, bool)>> {
+        self.backend.list_networks().await
+    }
+    
+    /// Defines, starts and autostarts network `name` from `[network_templates.<template>]`,
+    /// with `ipv6` (a /64) also addressed over DHCPv6 and router advertisements
+    pub async fn create_network(&self, name: &str, template_name: &str, ipv6: Option<&str>) -> Result<()> {
+        let template = self.config.network_templates.get(template_name).ok_or_else(|| {
+            let mut known: Vec<&str> = self.config.network_templates.keys().map(String::as_str).collect();
+            known.sort_unstable();
+            VmError::InvalidInput(if known.is_empty() {
+                format!("No network template '{}': add [network_templates.{}] to the config", template_name, template_name)
+            } else {
+                format!("No network template '{}'; known: {}", template_name, known.join(", "))
+            })
+        })?;
+        let ipv6: Option<Ipv6Subnet> = ipv6.map(str::parse).transpose()?;
+        let xml = network::network_xml(name, template, ipv6.as_ref())?;
+        if self.backend.list_networks().await?.iter().any(|(network, _, _, _)| network == name) {
+            return Err(VmError::InvalidInput(format!("Network '{}' already exists", name)));
+        }
+
+        self.backend.define_network(&xml).await?;
+        let mode = match template.mode {
+            NetworkMode::Nat => "NAT",
+            NetworkMode::Isolated => "isolated",
+        };
+        println!("✅ Network '{}' ({}) is up on {} from template '{}'", name.green(), mode, template.subnet, template_name);
+        if let Some(subnet) = &ipv6 {
+            let (start, end) = subnet.dhcp_range();
+            println!("   IPv6: {}/64, DHCPv6 leases {} to {}", subnet.network, start, end);
+        }
+        if let Some(domain) = &template.domain {
+            println!("   Guests resolve as <name>.{}", domain);
+        }
+        Ok(())
+    }
+    
+    /// Port forwards of every VM, with the address each guest has now; guests
+    /// that are off or have no lease yet get `None`. Forwards libvirt's own rules
+    /// reject, as they do on NAT networks, name the bridge they are rejected on.
+    pub async fn firewall_rules(&self) -> Result<Vec<FirewallRule>> {
+        let mut rules = Vec::new();
+        for vm in self.backend.list_domains(true, true).await? {
+            let xml = self.backend.get_inactive_domain_xml(&vm.name).await?;
+            let metadata = VmMetadata::parse(&xml).unwrap_or_default();
+            if metadata.port_forwards.is_empty() {
+                continue;
             }
-            
-            if let Some(memory_usage) = vm_info.memory_usage {
-                println!("Memory Usage: {:.1}% ({}/{}MB)", 
-                         memory_usage,
-                         (vm_info.memory as f64 * memory_usage / 100.0) as u64,
-                         vm_info.memory);
+            let address = if vm.state == VmState::Running {
+                self.backend.get_domain_info(&vm.name).await?.network_info.iter()
+                    .find_map(|net| net.ip_address.as_deref()?.parse().ok())
+            } else {
+                None
+            };
+            for spec in &metadata.port_forwards {
+                let forward = spec.parse().map_err(VmError::InvalidInput)?;
+                rules.push(FirewallRule { vm: vm.name.clone(), forward, address, rejected_on: None });
             }
-            
-            if let Some(uptime) = vm_info.uptime {
-                println!("Uptime: {}", utils::format_duration(uptime));
+        }
+
+        if rules.iter().any(|rule| rule.address.is_some()) {
+            if let Some(host_rules) = self.backend.host_filter_rules().await {
+                for rule in &mut rules {
+                    rule.rejected_on = rule.address.and_then(|address| firewall::rejecting_bridge(&host_rules, address));
+                }
             }
-            
-            sleep(Duration::from_secs(2)).await;
         }
+        Ok(rules)
     }
-    
-    pub async fn connect_console(&self, name: &str) -> Result<()> {
-        // Validate VM name to prevent path traversal attacks (CWE-22)
+
+    /// Rebuilds the host's vmtools nftables table from the VMs' port forwards and
+    /// their guests' current addresses
+    pub async fn sync_firewall(&self) -> Result<Vec<FirewallRule>> {
+        let rules = self.firewall_rules().await?;
+        self.backend.apply_firewall(&firewall::ruleset(&rules)).await?;
+        Ok(rules)
+    }
+
+    /// Forwards a host port to the VM, following the guest's address as its lease
+    /// changes; the forward is kept in the VM's metadata
+    pub async fn allow_port(&self, name: &str, forward: PortForward) -> Result<()> {
         utils::validate_vm_name(name)?;
-        
-        println!("Connecting to console of VM '{}'...", name.cyan());
-        self.libvirt.connect_console(name).await
+
+        let xml = self.backend.get_inactive_domain_xml(name).await?;
+        let mut metadata = VmMetadata::parse(&xml).unwrap_or_default();
+        if !metadata.port_forwards.contains(&forward.to_string()) {
+            let mut rules = self.firewall_rules().await?;
+            rules.push(FirewallRule { vm: name.to_string(), forward, address: None, rejected_on: None });
+            firewall::check_conflicts(&rules)?;
+            metadata.port_forwards.push(forward.to_string());
+            self.backend.define_domain(&domain::set_metadata(&xml, &metadata)?).await?;
+        }
+
+        let rules = self.sync_firewall().await?;
+        let rule = rules.iter().find(|rule| rule.vm == name && rule.forward == forward);
+        if let Some(rejection) = rule.and_then(FirewallRule::rejection) {
+            eprintln!("{} {}", "⚠️ ".yellow(), rejection);
+        }
+        match rule.and_then(|rule| rule.address) {
+            Some(address) => println!("✅ Port {}/{} on this host goes to VM '{}' at {}:{}",
+                                      forward.host_port, forward.protocol, name.green(), address, forward.guest_port),
+            None => println!("✅ Port {}/{} goes to VM '{}' once it has an address; 'vmtools daemon' keeps it pointed at the current one",
+                             forward.host_port, forward.protocol, name.green()),
+        }
+        Ok(())
     }
-    
-    pub async fn list_networks(&self) -> Result<()> {
-        let networks = self.libvirt.list_networks().await?;
-        
-        println!("{:<20} {:<12} {:<15} {:<10}", 
-                 "NAME".bold(), "STATE".bold(), "BRIDGE".bold(), "AUTOSTART".bold());
-        println!("{}", "─".repeat(60));
-        
-        for (name, active, bridge, autostart) in networks {
-            let state = if active { "ACTIVE".green() } else { "INACTIVE".red() };
-            let autostart_str = if autostart { "Yes".green() } else { "No".red() };
-            
-            println!("{:<20} {:<12} {:<15} {:<10}",
-                     name, state, bridge, autostart_str);
+
+    /// Stops forwarding a host port to the VM
+    pub async fn remove_port(&self, name: &str, forward: PortForward) -> Result<()> {
+        utils::validate_vm_name(name)?;
+
+        let xml = self.backend.get_inactive_domain_xml(name).await?;
+        let mut metadata = VmMetadata::parse(&xml).unwrap_or_default();
+        let spec = forward.to_string();
+        if !metadata.port_forwards.contains(&spec) {
+            return Err(VmError::InvalidInput(format!("VM '{}' has no port forward {}", name, spec)));
         }
-        
+        metadata.port_forwards.retain(|existing| *existing != spec);
+        self.backend.define_domain(&domain::set_metadata(&xml, &metadata)?).await?;
+
+        self.sync_firewall().await?;
+        println!("✅ Port {}/{} no longer goes to VM '{}'", forward.host_port, forward.protocol, name.green());
+        Ok(())
+    }
+
+    /// `sync_firewall` for the daemon: applies the rules only when they changed since
+    /// `applied`, the last ruleset it applied, and never touches a host without forwards
+    async fn sync_firewall_if_changed(&self, applied: &mut Option<String>) -> Result<()> {
+        let rules = self.firewall_rules().await?;
+        if rules.is_empty() && applied.is_none() {
+            return Ok(());
+        }
+        let ruleset = firewall::ruleset(&rules);
+        if applied.as_deref() == Some(ruleset.as_str()) {
+            return Ok(());
+        }
+
+        self.backend.apply_firewall(&ruleset).await?;
+        for rejection in rules.iter().filter_map(FirewallRule::rejection) {
+            eprintln!("{} {}", "⚠️ ".yellow(), rejection);
+        }
+        let targets: Vec<String> = rules.iter()
+            .filter_map(|rule| Some(format!("{} → {}:{}", rule.forward.host_port, rule.address?, rule.forward.guest_port)))
+            .collect();
+        println!("🔥 Firewall rules updated: {}", if targets.is_empty() { "no forwards".to_string() } else { targets.join(", ") });
+        *applied = Some(ruleset);
         Ok(())
     }
     
     pub async fn set_config(&self, key: &str, value: &str) -> Result<()> {
-        let mut config = self.config.clone();
+        let mut config = self.loaded_config.clone();
         config.set_value(key, value)?;
         config.save()?;
         println!("✓ Configuration updated: {} = {}", key, value);
         Ok(())
     }
     
+    /// Stores a sensitive setting encrypted; later loads decrypt it transparently
+    pub async fn encrypt_config(&self, key: &str, keyring: bool) -> Result<()> {
+        let mut config = self.loaded_config.clone();
+        let count = config.seal_value(key, keyring, self.runner.as_ref()).await?;
+        if count == 0 {
+            println!("{} Nothing to encrypt in {}", "Info:".cyan(), key);
+            return Ok(());
+        }
+        config.save()?;
+        if keyring {
+            println!("✓ Moved {} value(s) of {} to the keyring", count, key);
+        } else {
+            println!("✓ Encrypted {} value(s) of {} with the age key at {}",
+                     count, key, vault::identity_path()?.display());
+        }
+        Ok(())
+    }
+    
     pub async fn get_config(&self, key: &str) -> Result<()> {
         let value = self.config.get_value(key)?;
         println!("{} = {}", key, value);
         Ok(())
     }
     
+    /// Swaps a machine type the host's QEMU lacks for the newest one of its family
+    /// and checks the vCPU count and CPU model against what the host supports.
+    /// Hosts that can't report capabilities keep the template as it is.
+    async fn fit_to_host(&self, template: &mut VmTemplate, cpu_model: &CpuModel) -> Result<()> {
+        let capabilities = match self.backend.capabilities().await {
+            Ok(capabilities) if !capabilities.machines.is_empty() => capabilities,
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                tracing::debug!("Host capabilities unavailable: {}", e);
+                return Ok(());
+            }
+        };
+        
+        match capabilities.best_machine(&template.machine_type) {
+            Some(machine) if machine != template.machine_type => {
+                println!("{} Machine type {} is not available on this host; using {}",
+                         "Info:".cyan(), template.machine_type, machine.green());
+                template.machine_type = machine;
+            }
+            Some(_) => {}
+            None => return Err(VmError::InvalidInput(format!(
+                "Machine type '{}' is not supported by this host's QEMU; see 'vmtools capabilities'",
+                template.machine_type
+            ))),
+        }
+        
+        if let Some(max) = capabilities.max_vcpus.filter(|max| template.cpus > *max) {
+            return Err(VmError::InvalidInput(format!(
+                "{} vCPUs requested but this host supports at most {}", template.cpus, max
+            )));
+        }
+        
+        let usable = match cpu_model {
+            CpuModel::HostPassthrough => capabilities.cpu_modes.iter().any(|mode| mode == "host-passthrough"),
+            CpuModel::HostModel => capabilities.cpu_modes.iter().any(|mode| mode == "host-model"),
+            CpuModel::Named(model) => capabilities.cpu_models.contains(model),
+        };
+        if !usable {
+            return Err(VmError::InvalidInput(format!(
+                "CPU model '{}' is not usable on this host; see 'vmtools capabilities'", cpu_model
+            )));
+        }
+        Ok(())
+    }
+    
+    /// Absolute paths for a direct kernel boot; libvirt resolves nothing relative
+    fn resolve_kernel_boot(boot: &KernelBoot) -> Result<KernelBoot> {
+        let absolute = |path: &std::path::Path| path.canonicalize()
+            .map_err(|e| VmError::InvalidInput(format!("{}: {}", path.display(), e)));
+        Ok(KernelBoot {
+            kernel: absolute(&boot.kernel)?,
+            initrd: boot.initrd.as_deref().map(absolute).transpose()?,
+            cmdline: boot.cmdline.clone(),
+        })
+    }
+    
     fn generate_vm_xml(
         &self,
         name: &str,
         template: &VmTemplate,
-        disk_path: &std::path::Path,
-        iso_path: Option<&str>,
-        network: &str,
+        disk: &DiskSource,
+        devices: &DeviceOptions,
+        emulation: Option<&ArchProfile>,
     ) -> Result<String> {
+        if let Some(profile) = emulation.filter(|profile| profile.machine == "virt") {
+            return Ok(Self::generate_virt_xml(name, template, disk, devices, profile));
+        }
+        
         let uuid = uuid::Uuid::new_v4();
         
-        let mut xml = format!(r#"<domain type='kvm'>
+        // TCG can't pass the host CPU through, so emulated guests get a generic model
+        let (domain_type, cpu, emulator) = match emulation {
+            Some(profile) => (
+                "qemu",
+                format!("<cpu mode='custom' match='exact'>\n    <model fallback='allow'>{}</model>\n  </cpu>",
+                        Self::emulated_cpu_model(profile, devices)),
+                profile.emulator.display().to_string(),
+            ),
+            None => (
+                "kvm",
+                Self::cpu_element(devices.cpu_model.as_ref().unwrap_or(&CpuModel::HostPassthrough)),
+                "/usr/bin/qemu-system-x86_64".to_string(),
+            ),
+        };
+        
+        let mut xml = format!(r#"<domain type='{}'>
   <name>{}</name>
   <uuid>{}</uuid>
   <memory unit='MiB'>{}</memory>
   <currentMemory unit='MiB'>{}</currentMemory>
-  <vcpu placement='static'>{}</vcpu>
+  <vcpu placement='static'>{}</vcpu>{}
   <os>
     <type arch='{}' machine='{}'>{}</type>
     <boot dev='hd'/>
@@ -542,7 +4816,7 @@ impl VmManager {
     <acpi/>
     <apic/>
   </features>
-  <cpu mode='host-passthrough' check='none'/>
+  {}
   <clock offset='utc'>
     <timer name='rtc' tickpolicy='catchup'/>
     <timer name='pit' tickpolicy='delay'/>
@@ -552,33 +4826,34 @@ impl VmManager {
   <on_reboot>restart</on_reboot>
   <on_crash>destroy</on_crash>
   <devices>
-    <emulator>/usr/bin/qemu-system-x86_64</emulator>
-    <disk type='file' device='disk'>
-      <driver name='qemu' type='qcow2'/>
-      <source file='{}'/>
-      <target dev='vda' bus='virtio'/>
-      <address type='pci' domain='0x0000' bus='0x04' slot='0x00' function='0x0'/>
-    </disk>"#,
+    <emulator>{}</emulator>
+    {}"#,
+            domain_type,
             name,
             uuid,
             template.memory,
             template.memory,
             template.cpus,
+            Self::iothreads_element(template.iothreads),
             template.arch,
             template.machine_type,
             template.os_type,
-            disk_path.display()
+            cpu,
+            emulator,
+            Self::system_disk(disk, devices.disk_bus, template)
         );
         
-        if let Some(iso) = iso_path {
+        if let Some(iso) = devices.iso {
+            // sd* names are shared between SATA and SCSI, so step past a SCSI system disk
+            let target = if devices.disk_bus == DiskBus::VirtioScsi { "sdb" } else { "sda" };
             xml.push_str(&format!(r#"
     <disk type='file' device='cdrom'>
       <driver name='qemu' type='raw'/>
       <source file='{}'/>
-      <target dev='sda' bus='sata'/>
+      <target dev='{}' bus='sata'/>
       <readonly/>
       <address type='drive' controller='0' bus='0' target='0' unit='0'/>
-    </disk>"#, iso));
+    </disk>"#, iso, target));
         }
         
         xml.push_str(&format!(r#"
@@ -597,7 +4872,7 @@ impl VmManager {
     <interface type='network'>
       <mac address='{}'/>
       <source network='{}'/>
-      <model type='virtio'/>
+      <model type='virtio'/>{}
       <address type='pci' domain='0x0000' bus='0x01' slot='0x00' function='0x0'/>
     </interface>
     <serial type='pty'>
@@ -613,14 +4888,8 @@ impl VmManager {
     </input>
     <input type='mouse' bus='ps2'/>
     <input type='keyboard' bus='ps2'/>
-    <graphics type='spice' autoport='yes'>
-      <listen type='address'/>
-      <image compression='off'/>
-    </graphics>
-    <sound model='ich9'>
-      <address type='pci' domain='0x0000' bus='0x00' slot='0x1b' function='0x0'/>
-    </sound>
-    <video>
+    {}
+    {}<video>
       <model type='qxl' ram='65536' vram='65536' vgamem='16384' heads='1' primary='yes'/>
       <address type='pci' domain='0x0000' bus='0x00' slot='0x01' function='0x0'/>
     </video>
@@ -634,12 +4903,260 @@ impl VmManager {
   </devices>
 </domain>"#,
             utils::generate_mac_address(),
-            network
+            devices.network,
+            Self::net_driver(devices.net_queues),
+            self.graphics_device(&devices.graphics),
+            self.desktop_devices(&devices.desktop)
         ));
         
         Ok(xml)
     }
     
+    /// The system disk, plus its controller when it sits on virtio-scsi
+    fn system_disk(disk: &DiskSource, bus: DiskBus, template: &VmTemplate) -> String {
+        let iothread = Self::iothread_attribute(template.iothreads, 0);
+        match bus {
+            DiskBus::Virtio => format!(r#"<disk type='{}' device='disk'>
+      <driver name='qemu' type='{}'{}{}/>
+      {}
+      <target dev='vda' bus='virtio'/>
+      <address type='pci' domain='0x0000' bus='0x04' slot='0x00' function='0x0'/>
+    </disk>"#, disk.disk_type(), disk.format(), disk.driver_tuning(), iothread, disk.source_xml()),
+            // One request queue per vCPU lets every vCPU submit I/O without contention;
+            // SCSI disks share the controller's I/O thread
+            DiskBus::VirtioScsi => format!(r#"<controller type='scsi' index='0' model='virtio-scsi'>
+      <driver queues='{}'{}/>
+    </controller>
+    <disk type='{}' device='disk'>
+      <driver name='qemu' type='{}'{} discard='unmap'/>
+      {}
+      <target dev='sda' bus='scsi'/>
+      <address type='drive' controller='0' bus='0' target='0' unit='0'/>
+    </disk>"#, template.cpus, iothread, disk.disk_type(), disk.format(), disk.driver_tuning(), disk.source_xml()),
+        }
+    }
+    
+    /// `<cpu>` element for a KVM guest
+    fn cpu_element(model: &CpuModel) -> String {
+        match model {
+            CpuModel::HostPassthrough => "<cpu mode='host-passthrough' check='none'/>".to_string(),
+            CpuModel::HostModel => "<cpu mode='host-model' check='partial'/>".to_string(),
+            // forbid: refuse to start rather than quietly run without the model's features
+            CpuModel::Named(model) => format!(
+                "<cpu mode='custom' match='exact' check='partial'>\n    <model fallback='forbid'>{}</model>\n  </cpu>",
+                model
+            ),
+        }
+    }
+    
+    /// CPU model name for a TCG guest: a named `--cpu-model`, or the architecture's default
+    fn emulated_cpu_model<'a>(profile: &'a ArchProfile, devices: &'a DeviceOptions) -> &'a str {
+        match &devices.cpu_model {
+            Some(CpuModel::Named(model)) => model,
+            _ => profile.cpu_model,
+        }
+    }
+    
+    /// `<iothreads>` element for the domain, empty when disks use the main loop
+    fn iothreads_element(iothreads: u32) -> String {
+        if iothreads > 0 {
+            format!("\n  <iothreads>{}</iothreads>", iothreads)
+        } else {
+            String::new()
+        }
+    }
+    
+    /// `iothread=` driver attribute for the `index`th disk, spreading disks round-robin
+    fn iothread_attribute(iothreads: u32, index: u32) -> String {
+        if iothreads > 0 {
+            format!(" iothread='{}'", index % iothreads + 1)
+        } else {
+            String::new()
+        }
+    }
+    
+    /// Interface `<driver>` line; a single queue is virtio-net's default and needs none
+    fn net_driver(queues: u32) -> String {
+        if queues > 1 {
+            format!("\n      <driver queues='{}'/>", queues)
+        } else {
+            String::new()
+        }
+    }
+    
+    /// The `<graphics>` device for the configured console type
+    fn graphics_device(&self, graphics: &GraphicsConfig) -> String {
+        if self.config.defaults.graphics == "vnc" {
+            return format!(r#"<graphics type='vnc' autoport='yes' listen='{0}'>
+      <listen type='address' address='{0}'/>
+    </graphics>"#, graphics.listen);
+        }
+        
+        let mut attributes = format!("autoport='yes' listen='{}'", graphics.listen);
+        if graphics.tls {
+            attributes.push_str(" defaultMode='secure'");
+            if let Some(port) = graphics.tls_port {
+                attributes.push_str(&format!(" tlsPort='{}'", port));
+            }
+        }
+        format!(r#"<graphics type='spice' {}>
+      <listen type='address' address='{}'/>
+      <image compression='off'/>
+    </graphics>"#, attributes, graphics.listen)
+    }
+    
+    /// Sound card, audio backend and USB redirection channels for desktops;
+    /// the SPICE-only parts are left out for VNC consoles
+    fn desktop_devices(&self, desktop: &DesktopConfig) -> String {
+        let spice = self.config.defaults.graphics != "vnc";
+        let mut xml = String::new();
+        
+        if desktop.audio != AudioBackend::None {
+            let backend = match desktop.audio {
+                AudioBackend::Spice if !spice => String::new(),
+                audio => format!("\n    <audio id='1' type='{}'/>", audio),
+            };
+            xml.push_str(&format!(r#"<sound model='ich9'>
+      <audio id='1'/>
+      <address type='pci' domain='0x0000' bus='0x00' slot='0x1b' function='0x0'/>
+    </sound>{}
+    "#, backend));
+        }
+        
+        if spice {
+            for _ in 0..desktop.usb_redirect {
+                xml.push_str("<redirdev bus='usb' type='spicevmc'/>\n    ");
+            }
+        }
+        
+        xml
+    }
+    
+    /// Console settings for a new VM: `[graphics]` config overridden by create flags
+    fn graphics_options(&self, options: &CreateOptions) -> Result<GraphicsConfig> {
+        let mut graphics = self.config.graphics.clone();
+        if let Some(listen) = &options.listen {
+            graphics.listen = listen.clone();
+        }
+        graphics.tls |= options.tls || options.tls_port.is_some();
+        graphics.tls_port = options.tls_port.or(graphics.tls_port);
+        if let Some(dir) = &options.x509_dir {
+            graphics.x509_dir = Some(dir.clone());
+        }
+        
+        let address: std::net::IpAddr = graphics.listen.parse()
+            .map_err(|_| VmError::InvalidInput(format!("Invalid listen address: {}", graphics.listen)))?;
+        
+        let kind = if self.config.defaults.graphics == "vnc" { "vnc" } else { "spice" };
+        if graphics.tls {
+            for warning in utils::graphics_tls_problems(kind, graphics.x509_dir.as_deref()) {
+                println!("{} {}", "Warning:".yellow(), warning);
+            }
+        } else if !address.is_loopback() {
+            println!("{} The {} console will listen on {} without TLS; anyone on the network can reach it",
+                     "Warning:".yellow(), kind, address);
+        }
+        
+        Ok(graphics)
+    }
+    
+    /// Headless `virt` machine definition for emulated aarch64/riscv64 guests
+    fn generate_virt_xml(
+        name: &str,
+        template: &VmTemplate,
+        disk: &DiskSource,
+        devices: &DeviceOptions,
+        profile: &ArchProfile,
+    ) -> String {
+        let iso_path = devices.iso;
+        let mut os = format!("<type arch='{}' machine='{}'>hvm</type>", profile.arch, profile.machine);
+        match &profile.firmware {
+            Some(Firmware::Loader(path)) => {
+                os.push_str(&format!("\n    <loader readonly='yes' type='pflash'>{}</loader>", path.display()));
+            }
+            Some(Firmware::Kernel(path)) => {
+                os.push_str(&format!("\n    <kernel>{}</kernel>", path.display()));
+            }
+            None => {}
+        }
+        if iso_path.is_some() {
+            os.push_str("\n    <boot dev='cdrom'/>");
+        }
+        os.push_str("\n    <boot dev='hd'/>");
+        
+        // The CD-ROM always sits on virtio-scsi; share the controller with a SCSI system disk
+        let (disk, controller, cdrom_target) = match devices.disk_bus {
+            DiskBus::Virtio => (
+                format!(r#"<disk type='{}' device='disk'>
+      <driver name='qemu' type='{}'{}{}/>
+      {}
+      <target dev='vda' bus='virtio'/>
+    </disk>"#, disk.disk_type(), disk.format(), disk.driver_tuning(),
+                        Self::iothread_attribute(template.iothreads, 0), disk.source_xml()),
+                "\n    <controller type='scsi' index='0' model='virtio-scsi'/>",
+                "sda",
+            ),
+            DiskBus::VirtioScsi => (Self::system_disk(disk, DiskBus::VirtioScsi, template), "", "sdb"),
+        };
+        
+        let cdrom = iso_path.map(|iso| format!(r#"{}
+    <disk type='file' device='cdrom'>
+      <driver name='qemu' type='raw'/>
+      <source file='{}'/>
+      <target dev='{}' bus='scsi'/>
+      <readonly/>
+    </disk>"#, controller, iso, cdrom_target)).unwrap_or_default();
+        
+        format!(r#"<domain type='qemu'>
+  <name>{}</name>
+  <uuid>{}</uuid>
+  <memory unit='MiB'>{}</memory>
+  <currentMemory unit='MiB'>{}</currentMemory>
+  <vcpu placement='static'>{}</vcpu>{}
+  <os>
+    {}
+  </os>
+  <cpu mode='custom' match='exact'>
+    <model fallback='allow'>{}</model>
+  </cpu>
+  <clock offset='utc'/>
+  <on_poweroff>destroy</on_poweroff>
+  <on_reboot>restart</on_reboot>
+  <on_crash>destroy</on_crash>
+  <devices>
+    <emulator>{}</emulator>
+    {}{}
+    <interface type='network'>
+      <mac address='{}'/>
+      <source network='{}'/>
+      <model type='virtio'/>{}
+    </interface>
+    <serial type='pty'/>
+    <console type='pty'>
+      <target type='serial'/>
+    </console>
+    <rng model='virtio'>
+      <backend model='random'>/dev/urandom</backend>
+    </rng>
+  </devices>
+</domain>"#,
+            name,
+            uuid::Uuid::new_v4(),
+            template.memory,
+            template.memory,
+            template.cpus,
+            Self::iothreads_element(template.iothreads),
+            os,
+            profile.cpu_model,
+            profile.emulator.display(),
+            disk,
+            cdrom,
+            utils::generate_mac_address(),
+            devices.network,
+            Self::net_driver(devices.net_queues)
+        )
+    }
+    
     /// Detects and fixes network mismatches for a VM
     pub async fn fix_network_issues(&self, name: &str, auto_fix: bool) -> Result<()> {
         println!("🔍 Analyzing network configuration for VM '{}'...", name.cyan());
@@ -648,7 +5165,7 @@ impl VmManager {
         utils::validate_vm_name(name)?;
         
         // Detect network mismatches
-        let mismatches = utils::detect_network_mismatches(name).await?;
+        let mismatches = utils::detect_network_mismatches(name, self.privileges()?).await?;
         
         if mismatches.is_empty() {
             println!("✅ No network issues detected for VM '{}'", name.green());
@@ -677,7 +5194,7 @@ impl VmManager {
         
         if auto_fix {
             println!("\n🔧 Attempting to auto-fix network issues...");
-            let fixes = utils::auto_fix_network_mismatches(name, &mismatches).await?;
+            let fixes = utils::auto_fix_network_mismatches(name, &mismatches, self.privileges()?).await?;
             
             if fixes.is_empty() {
                 println!("❌ No automatic fixes could be applied");
@@ -707,6 +5224,10 @@ impl VmManager {
                     utils::NetworkIssueType::InvalidNetworkReference => {
                         println!("  • Update network: virsh edit {} (change <source network='...'/>)", name);
                     },
+                    utils::NetworkIssueType::MissingNatRules => {
+                        println!("  • Restore libvirt's NAT rules for {}: sudo systemctl reload libvirtd (or virtnetworkd)",
+                                 mismatch.suggested_config.network);
+                    },
                     _ => {
                         println!("  • Check libvirt documentation for {}", mismatch.issue_type);
                     }
@@ -717,15 +5238,95 @@ impl VmManager {
         Ok(())
     }
     
-    /// Optimizes VM configuration based on libvirt environment
-    pub async fn optimize_vm_config(&self, name: &str) -> Result<()> {
+    /// Follows the path of the guest's packets out, one hop at a time: the VM and its
+    /// network, the bridge and gateway, its DHCP lease, forwarding and NAT on the
+    /// host, then the gateway and DNS from inside the guest through its agent.
+    /// Every step is run, so the first failure shows where packets die.
+    pub async fn diagnose_network(&self, name: &str) -> Result<Vec<DiagnosisStep>> {
+        utils::validate_vm_name(name)?;
+        let privileges = self.privileges()?;
+
+        let info = self.backend.get_domain_info(name).await?;
+        let mut steps = Vec::new();
+        if info.state != VmState::Running {
+            steps.push(DiagnosisStep::fail("VM is running", info.state.label(), format!("vmtools start {}", name)));
+            return Ok(steps);
+        }
+        steps.push(DiagnosisStep::pass("VM is running", ""));
+
+        // Interfaces on a host bridge never touch libvirt's DHCP or NAT
+        let Some(net) = info.network_info.iter().find(|net| net.network != "-" && net.network != net.bridge) else {
+            steps.push(DiagnosisStep::skip("VM is on a libvirt network", "its interfaces use host bridges, which the host's own network serves"));
+            return Ok(steps);
+        };
+        let networks = self.backend.list_networks().await?;
+        let Some((_, active, bridge, _)) = networks.iter().find(|(network, _, _, _)| *network == net.network) else {
+            steps.push(DiagnosisStep::fail(
+                format!("Network '{}' is active", net.network), "no such network",
+                format!("vmtools fix-network {} --auto moves the VM to an existing network", name),
+            ));
+            return Ok(steps);
+        };
+        if !active {
+            steps.push(DiagnosisStep::fail(
+                format!("Network '{}' is active", net.network), "inactive",
+                format!("virsh net-start {}", net.network),
+            ));
+            return Ok(steps);
+        }
+        steps.push(DiagnosisStep::pass(format!("Network '{}' is active", net.network), format!("interface {}", net.mac_address)));
+
+        let xml = privileges.runner().output(&privileges.virsh_read(&["net-dumpxml", &net.network])?).await
+            .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+            .unwrap_or_default();
+        steps.extend(diagnosis::host_steps(privileges, &net.network, &xml, bridge, &net.mac_address).await);
+
+        let agent_steps = ["Guest reaches the gateway", "Guest resolves names"];
+        let gateway = diagnosis::network_ipv4(&xml).map(|(gateway, _)| gateway);
+        if self.backend.agent_command(name, &serde_json::json!({ "execute": "guest-ping" })).await.is_err() {
+            steps.extend(agent_steps.map(|check| DiagnosisStep::skip(check, "no guest agent answers")));
+            return Ok(steps);
+        }
+        match gateway {
+            Some(gateway) => {
+                let ping = format!("ping -c 1 -W 2 {}", gateway);
+                steps.push(match self.guest_exec_output(name, "/bin/sh", &["-c", &ping], 10).await {
+                    Ok((0, _)) => DiagnosisStep::pass(agent_steps[0], gateway.to_string()),
+                    Ok(_) => DiagnosisStep::fail(
+                        agent_steps[0], format!("no reply from {}", gateway),
+                        "Check the guest's address and default route: ip addr; ip route",
+                    ),
+                    Err(e) => DiagnosisStep::skip(agent_steps[0], format!("could not run ping in the guest: {}", e)),
+                });
+            }
+            None => steps.push(DiagnosisStep::skip(agent_steps[0], "the network has no IPv4 gateway")),
+        }
+        let lookup = format!("getent hosts {host} || nslookup {host}", host = DIAGNOSIS_HOST);
+        steps.push(match self.guest_exec_output(name, "/bin/sh", &["-c", &lookup], 10).await {
+            Ok((0, _)) => DiagnosisStep::pass(agent_steps[1], DIAGNOSIS_HOST),
+            Ok(_) => DiagnosisStep::fail(
+                agent_steps[1], format!("{} did not resolve", DIAGNOSIS_HOST),
+                format!("The guest should ask dnsmasq on {}; check its /etc/resolv.conf",
+                        gateway.map_or_else(|| "the gateway".to_string(), |gateway| gateway.to_string())),
+            ),
+            Err(e) => DiagnosisStep::skip(agent_steps[1], format!("could not run a lookup in the guest: {}", e)),
+        });
+        Ok(steps)
+    }
+    
+    /// Optimizes VM configuration based on libvirt environment: reports network
+    /// observations, performance devices the definition lacks and settings worth
+    /// changing on this host, plus the tuning `profile` calls for. With `apply` the
+    /// changes are shown as a diff of the definition and made once confirmed, or
+    /// right away with `yes`.
+    pub async fn optimize_vm_config(&self, name: &str, apply: bool, profile: Option<Profile>, confirm: Confirm<'_>) -> Result<()> {
         println!("🚀 Optimizing VM configuration for '{}'...", name.cyan());
         
         // Validate VM name to prevent path traversal attacks (CWE-22)
         utils::validate_vm_name(name)?;
         
         // Check if VM is running (can't optimize running VM)
-        let state = self.libvirt.get_domain_state(name).await?;
+        let state = self.backend.get_domain_state(name).await?;
         if state == VmState::Running {
             return Err(VmError::InvalidVmState(
                 "Cannot optimize running VM. Please stop the VM first.".to_string()
@@ -733,10 +5334,12 @@ impl VmManager {
         }
         
         // Get current VM configuration
-        let vm_info = self.libvirt.get_domain_info(name).await?;
+        let vm_info = self.backend.get_domain_info(name).await?;
         
-        // Check network configuration
-        self.fix_network_issues(name, false).await?;
+        // Network mismatches are checked against libvirt's networks; the direct backend has only user networking
+        if self.backend.privileges().is_some() {
+            self.fix_network_issues(name, false).await?;
+        }
         
         // Check for excessive network interfaces
         if vm_info.network_info.len() > 2 {
@@ -748,7 +5351,7 @@ impl VmManager {
         }
         
         // Check available networks and suggest optimization
-        let networks = self.libvirt.list_networks().await?;
+        let networks = self.backend.list_networks().await?;
         let active_networks: Vec<String> = networks.iter()
             .filter(|(_, active, _, _)| *active)
             .map(|(name, _, _, _)| name.clone())
@@ -768,109 +5371,193 @@ impl VmManager {
             }
         }
         
+        let xml = self.backend.get_inactive_domain_xml(name).await?;
+        let capabilities = self.backend.capabilities().await.unwrap_or_default();
+        let host = HostResources::detect();
+        
+        // The guest OS recorded at creation or adoption decides what it can run
+        let os_variant = VmMetadata::parse(&xml)
+            .and_then(|metadata| metadata.os_variant)
+            .and_then(|id| id.parse::<OsVariant>().ok());
+        let os_defaults = os_variant.as_ref().map(OsVariant::defaults).unwrap_or_default();
+        let profile = match (profile, &os_variant) {
+            (None, Some(variant)) if os_defaults.windows => {
+                println!("💡 {} guest: including the windows profile", variant);
+                Some(Profile::Windows)
+            }
+            (profile, _) => profile,
+        };
+        let mut optimizations = optimize::suggestions(&xml, &capabilities, &host, profile)?;
+        if os_defaults.virtio == VirtioSupport::Unavailable {
+            optimizations.retain(|optimization| {
+                !matches!(optimization, Optimization::VirtioDisk { .. } | Optimization::VirtioNic { .. })
+            });
+        }
+        let virtio_switch = optimizations.iter()
+            .any(|optimization| matches!(optimization, Optimization::VirtioDisk { .. } | Optimization::VirtioNic { .. }));
+        if profile == Some(Profile::Windows) && virtio_switch {
+            println!("⚠️  Windows has no virtio drivers built in and won't boot from a virtio disk without them");
+            println!("💡 Install them first: vmtools media {} insert virtio-win.iso", name);
+            println!("   (from https://fedorapeople.org/groups/virt/virtio-win/direct-downloads/), then run virtio-win-guest-tools.exe");
+        }
+        let memory = DomainSpec::parse(&xml)?.memory;
+        if optimizations.contains(&Optimization::Hugepages) && host.free_hugepages < memory * 1024 {
+            // 2 MiB pages, the default size on x86
+            let pages = (memory * 1024 - host.free_hugepages).div_ceil(2048);
+            println!("⚠️  The VM needs {} MiB of huge pages but only {} MiB are free", memory, host.free_hugepages / 1024);
+            println!("💡 Reserve {} more before starting it: sudo sysctl vm.nr_hugepages=$(( $(cat /proc/sys/vm/nr_hugepages) + {} ))",
+                     pages, pages);
+        }
+        if optimizations.is_empty() {
+            println!("✓ Devices and settings are already optimal");
+        } else if apply {
+            let mut updated = xml.clone();
+            for optimization in &optimizations {
+                println!("🔧 {}", optimization);
+                updated = optimization.apply(&updated)?;
+            }
+            
+            println!();
+            for line in optimize::diff(&xml, &updated).lines() {
+                match line.chars().next() {
+                    Some('+') => println!("{}", line.green()),
+                    Some('-') => println!("{}", line.red()),
+                    Some('@') => println!("{}", line.cyan()),
+                    _ => println!("{}", line),
+                }
+            }
+            
+            if !confirm(&format!("Apply these changes to VM '{}'?", name), false) {
+                println!("Operation cancelled");
+                return Ok(());
+            }
+            
+            self.backend.define_domain(&updated).await?;
+            println!("✅ Applied {} optimization(s) to VM '{}'", optimizations.len(), name);
+            return Ok(());
+        } else {
+            println!("💡 Suggested optimizations:");
+            for optimization in &optimizations {
+                println!("  • {}", optimization);
+            }
+            println!("💡 Review and apply them with: vmtools optimize {} --apply", name);
+        }
+        
         println!("✅ VM configuration analysis complete");
         Ok(())
     }
     
-    /// Fixes clipboard integration by adding SPICE agent channels and clipboard support
+    /// Fixes clipboard integration: adds the virtio-serial controller, the vdagent
+    /// channel matching the graphics type and a guest agent channel to the
+    /// persistent definition, then checks spice-vdagent in a running guest
     pub async fn fix_clipboard_integration(&self, name: &str) -> Result<()> {
         println!("📋 Fixing clipboard integration for VM '{}'...", name.cyan());
         
         // Validate VM name to prevent path traversal attacks (CWE-22)
         utils::validate_vm_name(name)?;
         
-        // Check if VM is running
-        let state = self.libvirt.get_domain_state(name).await?;
-        if state == VmState::Running {
-            return Err(VmError::InvalidVmState(
-                "Cannot modify VM configuration while running. Please stop the VM first.".to_string()
-            ));
+        let xml_content = self.backend.get_inactive_domain_xml(name).await?;
+        let mut updated_xml = xml_content.clone();
+        
+        if !xml_content.contains("<controller type='virtio-serial'") {
+            println!("🔧 Adding virtio-serial controller");
+            updated_xml = domain::add_device(&updated_xml, "<controller type='virtio-serial' index='0'/>")?;
         }
         
-        // Get current VM XML configuration
-        let xml_content = self.libvirt.get_domain_xml(name).await?;
+        // SPICE carries the clipboard itself; other displays need QEMU's built-in vdagent
+        let spice = xml_content.contains("<graphics type='spice'");
+        if !xml_content.contains("name='com.redhat.spice.0'") {
+            if spice {
+                println!("🔧 Adding SPICE agent channel");
+                updated_xml = domain::add_device(&updated_xml, "<channel type='spicevmc'>
+      <target type='virtio' name='com.redhat.spice.0'/>
+    </channel>")?;
+            } else {
+                println!("🔧 Adding qemu-vdagent channel");
+                updated_xml = domain::add_device(&updated_xml, "<channel type='qemu-vdagent'>
+      <source>
+        <clipboard copypaste='yes'/>
+        <mouse mode='client'/>
+      </source>
+      <target type='virtio' name='com.redhat.spice.0'/>
+    </channel>")?;
+            }
+        }
         
-        // Check if SPICE agent channel already exists
-        if xml_content.contains("spicevmc") && xml_content.contains("clipboard copypaste") {
-            println!("✅ Clipboard integration already configured for VM '{}'", name);
-            return Ok(());
+        if updated_xml.contains("<clipboard copypaste='no'/>") {
+            println!("🔧 Enabling copy and paste in the graphics configuration");
+            updated_xml = updated_xml.replace("<clipboard copypaste='no'/>", "<clipboard copypaste='yes'/>");
         }
         
-        println!("🔧 Adding SPICE agent channel and clipboard support...");
+        // Lets us check spice-vdagent from the host, now and in future runs
+        let has_agent = xml_content.contains("name='org.qemu.guest_agent.0'");
+        if !has_agent {
+            println!("🔧 Adding QEMU guest agent channel");
+            updated_xml = domain::add_device(&updated_xml, "<channel type='unix'>
+      <target type='virtio' name='org.qemu.guest_agent.0'/>
+    </channel>")?;
+        }
         
-        let mut updated_xml = xml_content.clone();
+        let running = self.backend.get_domain_state(name).await? == VmState::Running;
+        if updated_xml != xml_content {
+            self.backend.define_domain(&updated_xml).await?;
+            println!("✅ Clipboard devices added to VM '{}'", name);
+            if running {
+                println!("💡 Restart the VM for the new devices to take effect");
+            }
+        } else {
+            println!("✅ Clipboard devices already configured for VM '{}'", name);
+        }
         
-        // Add SPICE agent channel if not present
-        if !xml_content.contains("spicevmc") {
-            // Find existing channel and add SPICE agent channel after it
-            if let Some(pos) = xml_content.find("</channel>") {
-                let insert_pos = xml_content[..pos].rfind('\n').unwrap_or(pos) + 1;
-                let indent = "    "; // Adjust indentation as needed
-                
-                let spice_channel = format!(
-                    "{}    <channel type='spicevmc'>\n\
-                     {}      <target type='virtio' name='com.redhat.spice.0'/>\n\
-                     {}      <address type='virtio-serial' controller='0' bus='0' port='2'/>\n\
-                     {}    </channel>\n",
-                    indent, indent, indent, indent
-                );
-                
-                updated_xml.insert_str(insert_pos, &spice_channel);
-            }
-        }
-        
-        // Add clipboard support to graphics section
-        if !xml_content.contains("clipboard copypaste") {
-            if let Some(graphics_start) = updated_xml.find("<graphics type='spice'") {
-                if let Some(graphics_end) = updated_xml[graphics_start..].find("</graphics>") {
-                    let graphics_end_abs = graphics_start + graphics_end;
-                    
-                    // Check if there's already image compression line
-                    if let Some(img_pos) = updated_xml[graphics_start..graphics_end_abs].rfind("</image>") {
-                        let img_pos_abs = graphics_start + img_pos + "</image>".len();
-                        let clipboard_config = "\n      <clipboard copypaste='yes'/>";
-                        updated_xml.insert_str(img_pos_abs, clipboard_config);
-                    } else {
-                        // Add before closing graphics tag
-                        let clipboard_config = "      <clipboard copypaste='yes'/>\n    ";
-                        updated_xml.insert_str(graphics_end_abs, clipboard_config);
-                    }
-                }
+        if !running || !has_agent {
+            println!("📝 Ensure spice-vdagent and qemu-guest-agent are installed in the guest");
+            return Ok(());
+        }
+        
+        match self.guest_exec(name, "/usr/bin/pgrep", &["-x", "spice-vdagent"]).await {
+            Ok(0) => println!("✅ spice-vdagent is running in the guest"),
+            Ok(_) => {
+                println!("⚠️  spice-vdagent is not running in the guest");
+                println!("💡 Install it with 'apt install spice-vdagent' or 'dnf install spice-vdagent' and log in again");
             }
+            Err(e) => println!("⚠️  Could not check the guest through the agent: {}", e),
         }
         
-        // Apply the updated configuration
-        if updated_xml != xml_content {
-            // Save to temporary file
-            let temp_file = format!("/tmp/{}_clipboard_fix.xml", name);
-            std::fs::write(&temp_file, &updated_xml)
-                .map_err(|e| VmError::LibvirtError(format!("Failed to write XML file: {}", e)))?;
-            
-            // Apply the configuration
-            let output = tokio::process::Command::new("sudo")
-                .args(&["virsh", "define", &temp_file])
-                .output()
-                .await
-                .map_err(|e| VmError::CommandError(format!("Failed to apply VM configuration: {}", e)))?;
+        Ok(())
+    }
+    
+    /// Runs a program in the guest through the guest agent and returns its exit code
+    async fn guest_exec(&self, name: &str, path: &str, args: &[&str]) -> Result<i64> {
+        self.guest_exec_output(name, path, args, 20).await.map(|(code, _)| code)
+    }
+    
+    /// Runs a program in the guest, waiting up to `timeout_secs`, and returns its
+    /// exit code and standard output
+    async fn guest_exec_output(&self, name: &str, path: &str, args: &[&str], timeout_secs: u64) -> Result<(i64, String)> {
+        let started = self.backend.agent_command(name, &serde_json::json!({
+            "execute": "guest-exec",
+            "arguments": { "path": path, "arg": args, "capture-output": true }
+        })).await?;
+        let pid = started.get("pid").and_then(|p| p.as_i64())
+            .ok_or_else(|| VmError::OperationError("guest-exec returned no PID".to_string()))?;
+        
+        for _ in 0..timeout_secs * 4 {
+            let status = self.backend.agent_command(name, &serde_json::json!({
+                "execute": "guest-exec-status",
+                "arguments": { "pid": pid }
+            })).await?;
             
-            if !output.status.success() {
-                return Err(VmError::CommandError(format!(
-                    "Failed to apply clipboard configuration: {}", 
-                    String::from_utf8_lossy(&output.stderr)
-                )));
+            if status.get("exited").and_then(|e| e.as_bool()) == Some(true) {
+                let stdout = status.get("out-data").and_then(|d| d.as_str())
+                    .and_then(|data| base64::engine::general_purpose::STANDARD.decode(data).ok())
+                    .map(|data| String::from_utf8_lossy(&data).into_owned())
+                    .unwrap_or_default();
+                return Ok((status.get("exitcode").and_then(|c| c.as_i64()).unwrap_or(-1), stdout));
             }
-            
-            // Clean up temporary file
-            let _ = std::fs::remove_file(&temp_file);
-            
-            println!("✅ Clipboard integration configured successfully");
-            println!("💡 Please restart the VM for changes to take effect");
-            println!("📝 Note: Ensure spice-vdagent is installed in the guest OS for full functionality");
-        } else {
-            println!("✅ Clipboard integration already properly configured");
+            sleep(Duration::from_millis(250)).await;
         }
         
-        Ok(())
+        Err(VmError::Timeout(format!("{} did not finish in the guest", path)))
     }
 
     /// Fixes VM identity issues for cloned VMs
@@ -883,12 +5570,12 @@ impl VmManager {
         let hostname = new_hostname.unwrap_or(name);
         
         // Check if VM exists
-        if !self.libvirt.domain_exists(name).await? {
+        if !self.backend.domain_exists(name).await? {
             return Err(VmError::VmNotFound(name.to_string()));
         }
         
         // Get VM state
-        let state = self.libvirt.get_domain_state(name).await?;
+        let state = self.backend.get_domain_state(name).await?;
         
         if state == VmState::Running {
             println!("⚠️  VM is currently running. Identity changes require guest OS access.");
@@ -920,7 +5607,7 @@ impl VmManager {
             println!("   4. Clear /var/lib/dhcp/dhclient.leases");
             println!();
             println!("⚠️  Common issues with cloned VMs:");
-            println!("   • DHCP hostname conflicts (showing '{}' instead of '{}')", "Hunter-Seeker", hostname);
+            println!("   • DHCP hostname conflicts (showing 'Hunter-Seeker' instead of '{}')", hostname);
             println!("   • SSH host key conflicts (same keys as original VM)");
             println!("   • Machine ID conflicts (/etc/machine-id)");
             println!();
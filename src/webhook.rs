@@ -0,0 +1,125 @@
+use serde::Serialize;
+
+use crate::{
+    config::WebhookConfig,
+    error::{VmError, Result},
+    events::DomainEvent,
+    runner::{CommandRunner, Invocation},
+};
+
+/// JSON body POSTed to configured webhook URLs
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum WebhookPayload {
+    /// A domain changed state
+    Lifecycle {
+        timestamp: String,
+        vm: String,
+        event: String,
+        detail: String,
+    },
+    /// A vmtools operation finished
+    Operation {
+        timestamp: String,
+        vm: Option<String>,
+        operation: String,
+        success: bool,
+        message: Option<String>,
+    },
+}
+
+impl WebhookPayload {
+    pub fn from_event(event: &DomainEvent) -> Self {
+        WebhookPayload::Lifecycle {
+            timestamp: event.timestamp.clone(),
+            vm: event.domain.clone(),
+            event: event.kind.to_string(),
+            detail: event.detail.clone(),
+        }
+    }
+
+    pub fn operation(operation: &str, vm: Option<&str>, result: std::result::Result<(), String>) -> Self {
+        WebhookPayload::Operation {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            vm: vm.map(|v| v.to_string()),
+            operation: operation.to_string(),
+            success: result.is_ok(),
+            message: result.err(),
+        }
+    }
+
+    /// Name matched against `webhooks.events` filters
+    fn event_name(&self) -> &str {
+        match self {
+            WebhookPayload::Lifecycle { event, .. } => event,
+            WebhookPayload::Operation { .. } => "operation",
+        }
+    }
+}
+
+/// Sends payloads to every configured webhook URL
+pub struct WebhookDispatcher {
+    config: WebhookConfig,
+}
+
+impl WebhookDispatcher {
+    pub fn new(config: &WebhookConfig) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.config.urls.is_empty()
+    }
+
+    fn wants(&self, payload: &WebhookPayload) -> bool {
+        self.config.events.is_empty()
+            || self.config.events.iter().any(|e| e.eq_ignore_ascii_case(payload.event_name()))
+    }
+
+    /// Delivers the payload to all URLs with curl run through `runner`; delivery
+    /// failures are logged, never fatal
+    pub async fn dispatch(&self, runner: &dyn CommandRunner, payload: &WebhookPayload) {
+        if !self.is_enabled() || !self.wants(payload) {
+            return;
+        }
+
+        let body = match serde_json::to_string(payload) {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::warn!("Failed to serialize webhook payload: {}", e);
+                return;
+            }
+        };
+
+        for url in &self.config.urls {
+            if let Err(e) = post_json(runner, url, &body, self.config.timeout).await {
+                tracing::warn!("Webhook delivery to {} failed: {}", url, e);
+            }
+        }
+    }
+}
+
+async fn post_json(runner: &dyn CommandRunner, url: &str, body: &str, timeout: u64) -> Result<()> {
+    let timeout = timeout.to_string();
+    let post = Invocation::new("curl")
+        .args([
+            "-sS", "-f",
+            "-X", "POST",
+            "-H", "Content-Type: application/json",
+            "--max-time", &timeout,
+            "--data-binary", "@-",
+            url,
+        ])
+        .stdin(body);
+    let output = runner.output(&post)
+        .await
+        .map_err(|e| VmError::NetworkError(format!("Failed to execute curl: {}", e)))?;
+
+    if !output.status.success() {
+        return Err(VmError::NetworkError(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+
+    Ok(())
+}